@@ -0,0 +1,144 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversions between Fastly's request and response types and those of
+//! sxg_rs, via the `http` crate, as `http_server` does for hyper's types.
+
+use anyhow::{Error, Result};
+use fastly::http::{HeaderName, HeaderValue};
+use fastly::{Request, Response};
+use std::convert::TryInto;
+use sxg_rs::http::{HeaderFields, HttpRequest, HttpResponse};
+
+fn header_fields<'a>(
+    names: impl Iterator<Item = &'a HeaderName>,
+    values: impl Fn(&'a HeaderName) -> Vec<&'a HeaderValue>,
+) -> Result<HeaderFields> {
+    let mut fields: HeaderFields = vec![];
+    for name in names {
+        for value in values(name) {
+            let value = value.to_str().map_err(|_| {
+                Error::msg(format!(r#"Header "{}" contains non-ASCII value."#, name))
+            })?;
+            fields.push((name.as_str().to_string(), value.to_string()))
+        }
+    }
+    Ok(fields)
+}
+
+/// Returns the header fields of the request, with repeated headers as
+/// separate fields.
+pub fn request_header_fields(req: &Request) -> Result<HeaderFields> {
+    header_fields(req.get_header_names(), |name| {
+        req.get_header_all(name).collect()
+    })
+}
+
+/// Returns the header fields of the response, with repeated headers as
+/// separate fields.
+pub fn response_header_fields(rsp: &Response) -> Result<HeaderFields> {
+    header_fields(rsp.get_header_names(), |name| {
+        rsp.get_header_all(name).collect()
+    })
+}
+
+pub fn to_fastly_request(req: HttpRequest) -> Result<Request> {
+    let req: ::http::request::Request<Vec<u8>> = req.try_into()?;
+    let req = req.map(fastly::Body::from);
+    Ok(req.try_into()?)
+}
+
+pub fn to_fastly_response(rsp: HttpResponse) -> Result<Response> {
+    let rsp: ::http::response::Response<Vec<u8>> = rsp.try_into()?;
+    let rsp: ::http::response::Response<fastly::Body> = rsp.map(From::<Vec<u8>>::from);
+    Ok(rsp.into())
+}
+
+pub fn from_fastly_response(rsp: Response) -> Result<HttpResponse> {
+    let rsp: ::http::response::Response<fastly::Body> = rsp.into();
+    let rsp = rsp.map(|body| body.into_bytes());
+    rsp.try_into()
+}
+
+// Fastly's types are backed by host calls, so these tests run only on
+// wasm32-wasi under Viceroy; see the README.
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use sxg_rs::http::Method;
+
+    // Fastly doesn't preserve the order of distinct header names.
+    fn sorted(mut fields: HeaderFields) -> HeaderFields {
+        fields.sort();
+        fields
+    }
+
+    #[test]
+    fn request_header_fields_keeps_repeats() {
+        let mut req = Request::get("https://example.com/");
+        req.append_header("accept", "text/html");
+        req.append_header("x-repeated", "a");
+        req.append_header("x-repeated", "b");
+        assert_eq!(
+            sorted(request_header_fields(&req).unwrap()),
+            vec![
+                ("accept".to_string(), "text/html".to_string()),
+                ("x-repeated".to_string(), "a".to_string()),
+                ("x-repeated".to_string(), "b".to_string()),
+            ]
+        );
+    }
+    #[test]
+    fn response_header_fields_rejects_non_ascii() {
+        let mut rsp = Response::new();
+        rsp.set_header("x-bytes", HeaderValue::from_bytes(b"\xff").unwrap());
+        assert!(response_header_fields(&rsp).is_err());
+    }
+    #[test]
+    fn converts_request() {
+        let req = to_fastly_request(HttpRequest {
+            body: b"hello".to_vec(),
+            headers: vec![("content-type".into(), "text/plain".into())],
+            method: Method::Post,
+            url: "https://example.com/path?q".into(),
+        })
+        .unwrap();
+        assert_eq!(req.get_method_str(), "POST");
+        assert_eq!(req.get_url_str(), "https://example.com/path?q");
+        assert_eq!(req.get_header_str("content-type"), Some("text/plain"));
+        assert_eq!(req.into_body_bytes(), b"hello");
+    }
+    #[test]
+    fn round_trips_response() {
+        let original = HttpResponse {
+            body: b"<h1>Hi</h1>".to_vec(),
+            headers: vec![
+                ("content-type".into(), "text/html".into()),
+                ("link".into(), "<a.css>;rel=preload".into()),
+                ("link".into(), "<b.js>;rel=preload".into()),
+            ],
+            status: 404,
+        };
+        let rsp = to_fastly_response(original.clone()).unwrap();
+        assert_eq!(rsp.get_status().as_u16(), 404);
+        assert_eq!(
+            sorted(response_header_fields(&rsp).unwrap()),
+            original.headers
+        );
+        let converted = from_fastly_response(rsp).unwrap();
+        assert_eq!(converted.status, 404);
+        assert_eq!(sorted(converted.headers), original.headers);
+        assert_eq!(converted.body, original.body);
+    }
+}
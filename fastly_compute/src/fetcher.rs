@@ -12,10 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::convert;
 use anyhow::{Error, Result};
 use async_trait::async_trait;
-use fastly::{Request as FastlyRequest, Response as FastlyResponse};
-use std::convert::TryInto;
 use sxg_rs::{
     fetcher::Fetcher,
     http::{HttpRequest, HttpResponse},
@@ -41,15 +40,9 @@ impl FastlyFetcher {
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl Fetcher for FastlyFetcher {
     async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
-        let request: ::http::request::Request<Vec<u8>> = request.try_into()?;
-        let request = request.map(fastly::Body::from);
-        let request: FastlyRequest = request.try_into()?;
-        let response: FastlyResponse = request
+        let response = convert::to_fastly_request(request)?
             .send(self.backend_name)
             .map_err(|e| Error::new(e).context("Failed to fetch from backend."))?;
-
-        let response: ::http::response::Response<fastly::Body> = response.into();
-        let response = response.map(|body| body.into_bytes());
-        response.try_into()
+        convert::from_fastly_response(response)
     }
 }
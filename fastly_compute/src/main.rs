@@ -12,13 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod convert;
 mod fetcher;
 mod storage;
 
 use anyhow::{Error, Result};
 use fastly::{http::Url, Error as FastlyError, Request, Response};
 use fetcher::FastlyFetcher;
-use std::convert::TryInto;
 use sxg_rs::{
     crypto::CertificateChain,
     headers::{AcceptLevel, Headers, VIA_SXGRS},
@@ -36,7 +36,7 @@ async fn create_worker() -> SxgWorker {
     let preissued = (dict.get("cert-pem"), dict.get("issuer-pem"));
     if let (Some(cert_pem), Some(issuer_pem)) = preissued {
         let certificate = CertificateChain::from_pem_files(&[&cert_pem, &issuer_pem]).unwrap();
-        worker.add_certificate(certificate);
+        worker.add_certificate(certificate).unwrap();
     }
     let runtime = sxg_rs::runtime::Runtime {
         storage: Box::new(storage::FastlyStorage::new("config")),
@@ -54,37 +54,11 @@ async fn get_req_header_fields(
     req: &Request,
     accept_filter: AcceptLevel,
 ) -> Result<HeaderFields> {
-    let mut fields: Vec<(String, String)> = vec![];
-    for name in req.get_header_names() {
-        for value in req.get_header_all(name) {
-            let value = value.to_str().map_err(|_| {
-                Error::msg(format!(r#"Header "{}" contains non-ASCII value."#, name))
-            })?;
-            fields.push((name.as_str().to_string(), value.to_string()))
-        }
-    }
-    worker.transform_request_headers(fields, accept_filter)
+    worker.transform_request_headers(convert::request_header_fields(req)?, accept_filter)
 }
 
 async fn get_rsp_header_fields(worker: &SxgWorker, rsp: &Response) -> Result<Headers> {
-    let mut fields: Vec<(String, String)> = vec![];
-    for name in rsp.get_header_names() {
-        for value in rsp.get_header_all(name) {
-            let value = value.to_str().map_err(|_| {
-                Error::msg(format!(r#"Header "{}" contains non-ASCII value."#, name))
-            })?;
-            fields.push((name.as_str().to_string(), value.to_string()))
-        }
-    }
-    worker.transform_payload_headers(fields)
-}
-
-pub fn sxg_rs_response_to_fastly_response(
-    rsp: sxg_rs::http::HttpResponse,
-) -> anyhow::Result<fastly::Response> {
-    let rsp: ::http::response::Response<Vec<u8>> = rsp.try_into()?;
-    let rsp: ::http::response::Response<fastly::Body> = rsp.map(From::<Vec<u8>>::from);
-    Ok(rsp.into())
+    worker.transform_payload_headers(convert::response_header_fields(rsp)?)
 }
 
 /// The *name* of the host in Fastly service configuration.
@@ -107,6 +81,7 @@ async fn generate_sxg_response(
     payload: Response,
 ) -> Result<Response> {
     let payload_headers = get_rsp_header_fields(worker, &payload).await?;
+    let status_code = payload.get_status().as_u16();
     let payload_body = payload.into_body_bytes();
     let cert_origin = cert_origin.ascii_serialization();
     let runtime = sxg_rs::runtime::Runtime {
@@ -122,17 +97,18 @@ async fn generate_sxg_response(
             payload_body: &payload_body,
             payload_headers,
             skip_process_link: false,
-            status_code: 200,
+            status_code,
             fallback_url: fallback_url.as_str(),
             cert_origin: &cert_origin,
             // The fastly crate provides only read access to dictionaries, so
             // header integrities cannot be cached. However, I believe the
             // subresource_fetcher will go through the cache.
             header_integrity_cache: sxg_rs::http_cache::NullCache {},
+            mice_record_size: None,
         },
     );
     let sxg = sxg.await?;
-    sxg_rs_response_to_fastly_response(sxg)
+    convert::to_fastly_response(sxg)
 }
 
 async fn handle_request(worker: &SxgWorker, req: &Request) -> Result<Response> {
@@ -149,13 +125,11 @@ async fn handle_request(worker: &SxgWorker, req: &Request) -> Result<Response> {
         .serve_preset_content(&runtime, req.get_url_str())
         .await;
     match preset_content {
-        Some(PresetContent::Direct(response)) => {
-            return sxg_rs_response_to_fastly_response(response)
-        }
+        Some(PresetContent::Direct(response)) => return convert::to_fastly_response(response),
         Some(PresetContent::ToBeSigned { url, payload, .. }) => {
             fallback_url = Url::parse(&url).map_err(Error::new)?;
             (_, cert_origin) = worker.get_fallback_url_and_cert_origin(req.get_url())?;
-            sxg_payload = sxg_rs_response_to_fastly_response(payload)?;
+            sxg_payload = convert::to_fastly_response(payload)?;
             get_req_header_fields(worker, req, AcceptLevel::AcceptsSxg).await?;
         }
         None => {
@@ -0,0 +1,216 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The DNS resolver used by the backend, subresource, and OCSP fetchers,
+//! configured by the `dns` section of config.yaml. The resolver is built once
+//! at startup, so changes to this section take effect only after a restart.
+
+use anyhow::Result;
+use hyper_trust_dns::TrustDnsResolver;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use trust_dns_resolver::config::{
+    LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts,
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    // Servers queried over plain UDP and TCP, such as '8.8.8.8:53'.
+    pub nameservers: Vec<SocketAddr>,
+    // A DNS-over-HTTPS endpoint, queried in addition to `nameservers`.
+    pub dns_over_https: Option<DohConfig>,
+    // Which address families to look up, and which to prefer.
+    pub ip_version: IpVersion,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DohConfig {
+    // The name the endpoint's certificate is validated against, such as
+    // 'dns.google'. Queries are sent to https://{tls_name}/dns-query.
+    pub tls_name: String,
+    // The IP addresses of the endpoint, such as ['8.8.8.8', '8.8.4.4'];
+    // these are needed to avoid resolving tls_name itself.
+    pub addresses: Vec<IpAddr>,
+    #[serde(default = "default_https_port")]
+    pub port: u16,
+}
+
+fn default_https_port() -> u16 {
+    443
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IpVersion {
+    Ipv4Only,
+    Ipv6Only,
+    Ipv4AndIpv6,
+    Ipv4ThenIpv6,
+    Ipv6ThenIpv4,
+}
+
+impl Default for IpVersion {
+    fn default() -> Self {
+        IpVersion::Ipv4ThenIpv6
+    }
+}
+
+impl From<IpVersion> for LookupIpStrategy {
+    fn from(version: IpVersion) -> Self {
+        match version {
+            IpVersion::Ipv4Only => LookupIpStrategy::Ipv4Only,
+            IpVersion::Ipv6Only => LookupIpStrategy::Ipv6Only,
+            IpVersion::Ipv4AndIpv6 => LookupIpStrategy::Ipv4AndIpv6,
+            IpVersion::Ipv4ThenIpv6 => LookupIpStrategy::Ipv4thenIpv6,
+            IpVersion::Ipv6ThenIpv4 => LookupIpStrategy::Ipv6thenIpv4,
+        }
+    }
+}
+
+/// Reads the `dns` section of config.yaml; the rest is parsed by `SxgWorker`.
+pub fn load(config_yaml: &str) -> Result<DnsConfig> {
+    #[derive(Deserialize)]
+    struct File {
+        #[serde(default)]
+        dns: DnsConfig,
+    }
+    Ok(serde_yaml::from_str::<File>(config_yaml)?.dns)
+}
+
+/// Returns a resolver for `config`. If no servers are configured, the
+/// resolver uses Google Public DNS, as `TrustDnsResolver::default()` does.
+pub fn resolver(config: &DnsConfig) -> TrustDnsResolver {
+    let mut servers = NameServerConfigGroup::new();
+    for addr in &config.nameservers {
+        servers.merge(NameServerConfigGroup::from_ips_clear(
+            &[addr.ip()],
+            addr.port(),
+            true,
+        ));
+    }
+    if let Some(doh) = &config.dns_over_https {
+        servers.merge(NameServerConfigGroup::from_ips_https(
+            &doh.addresses,
+            doh.port,
+            doh.tls_name.clone(),
+            true,
+        ));
+    }
+    let resolver_config = if servers.is_empty() {
+        ResolverConfig::default()
+    } else {
+        ResolverConfig::from_parts(None, vec![], servers)
+    };
+    let mut options = ResolverOpts::default();
+    options.ip_strategy = config.ip_version.into();
+    TrustDnsResolver::with_config_and_options(resolver_config, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{client::connect::dns::Name, service::Service};
+    use std::net::UdpSocket;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Starts a DNS server on localhost that answers every query with an A
+    // record for 192.0.2.1, and returns its address and the number of
+    // queries it has received.
+    fn stub_resolver() -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let count = queries.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+                count.fetch_add(1, Ordering::SeqCst);
+                let query = &buf[..len];
+                // The question follows the 12-byte header: the name as
+                // length-prefixed labels ending with an empty one, then the
+                // 2-byte type and class.
+                let mut end = 12;
+                while query[end] != 0 {
+                    end += 1 + query[end] as usize;
+                }
+                let question = &query[12..end + 5];
+                let mut response = query[..2].to_vec();
+                // A recursive answer with 1 question and 1 answer.
+                response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+                response.extend_from_slice(question);
+                // The question's name, type A, class IN, TTL 60, then the IP.
+                response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                response.extend_from_slice(&[192, 0, 2, 1]);
+                let _ = socket.send_to(&response, peer);
+            }
+        });
+        (addr, queries)
+    }
+    #[test]
+    fn load_config() {
+        let config = load(
+            r#"
+html_host: example.com
+dns:
+  nameservers: ["10.0.0.53:53", "[2001:db8::53]:5353"]
+  dns_over_https:
+    tls_name: dns.google
+    addresses: ["8.8.8.8"]
+  ip_version: ipv4_only
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.nameservers,
+            vec![
+                "10.0.0.53:53".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::53]:5353".parse().unwrap(),
+            ]
+        );
+        let doh = config.dns_over_https.unwrap();
+        assert_eq!(doh.tls_name, "dns.google");
+        assert_eq!(doh.addresses, vec!["8.8.8.8".parse::<IpAddr>().unwrap()]);
+        assert_eq!(doh.port, 443);
+        assert_eq!(config.ip_version, IpVersion::Ipv4Only);
+    }
+    #[test]
+    fn load_config_without_dns() {
+        let config = load("html_host: example.com").unwrap();
+        assert!(config.nameservers.is_empty());
+        assert!(config.dns_over_https.is_none());
+        assert_eq!(config.ip_version, IpVersion::Ipv4ThenIpv6);
+    }
+    #[tokio::test]
+    async fn resolves_through_configured_nameserver() {
+        let (addr, queries) = stub_resolver();
+        let mut resolver = resolver(&DnsConfig {
+            nameservers: vec![addr],
+            ip_version: IpVersion::Ipv4Only,
+            ..Default::default()
+        });
+        futures::future::poll_fn(|cx| resolver.poll_ready(cx))
+            .await
+            .unwrap();
+        let addrs: Vec<SocketAddr> = resolver
+            .call(Name::from_str("backend.example").unwrap())
+            .await
+            .unwrap()
+            .collect();
+        assert_eq!(addrs, vec!["192.0.2.1:0".parse().unwrap()]);
+        assert!(queries.load(Ordering::SeqCst) > 0);
+    }
+}
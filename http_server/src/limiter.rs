@@ -0,0 +1,126 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits the number of SXG signings in flight, so that a burst of requests
+//! degrades to unsigned responses instead of queueing indefinitely.
+
+use anyhow::{anyhow, Result};
+use prometheus::IntGauge;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct SigningLimiter {
+    in_flight: AtomicUsize,
+    // Mirrors `in_flight`, for the metrics endpoint.
+    gauge: IntGauge,
+}
+
+// Releases its slot when dropped, including when the signing future is
+// cancelled because the client disconnected.
+struct Permit<'a>(&'a SigningLimiter);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.0.gauge.dec();
+    }
+}
+
+impl SigningLimiter {
+    pub fn new(gauge: IntGauge) -> Self {
+        SigningLimiter {
+            in_flight: AtomicUsize::new(0),
+            gauge,
+        }
+    }
+    /// Returns the number of signings in flight.
+    pub fn depth(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+    // The limit is passed per call rather than at construction, so that it
+    // follows the config when the worker is reloaded.
+    fn try_acquire(&self, max: Option<usize>) -> Option<Permit<'_>> {
+        let max = max.unwrap_or(usize::MAX);
+        self.in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()?;
+        self.gauge.inc();
+        Some(Permit(self))
+    }
+    /// Runs `sign` if fewer than `max` signings are in flight; otherwise
+    /// returns an error immediately, so that the caller can serve the
+    /// response unsigned. If `max` is `None`, there is no limit.
+    pub async fn run<T>(
+        &self,
+        max: Option<usize>,
+        sign: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let _permit = self.try_acquire(max).ok_or_else(|| {
+            anyhow!(
+                "Too many concurrent SXG signings; the limit is {}.",
+                max.unwrap_or_default()
+            )
+        })?;
+        sign.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn new_limiter() -> SigningLimiter {
+        SigningLimiter::new(IntGauge::new("in_flight", "help").unwrap())
+    }
+    #[tokio::test]
+    async fn releases_after_signing() {
+        let limiter = new_limiter();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let first = limiter.run(Some(1), async {
+            rx.await?;
+            Ok(())
+        });
+        let second = async {
+            tokio::task::yield_now().await;
+            assert_eq!(limiter.depth(), 1);
+            assert_eq!(limiter.gauge.get(), 1);
+            assert!(limiter.run(Some(1), async { Ok(()) }).await.is_err());
+            tx.send(()).unwrap();
+        };
+        let (first, ()) = tokio::join!(first, second);
+        first.unwrap();
+        assert_eq!(limiter.depth(), 0);
+        assert_eq!(limiter.gauge.get(), 0);
+        assert!(limiter.run(Some(1), async { Ok(()) }).await.is_ok());
+    }
+    #[tokio::test]
+    async fn releases_when_cancelled() {
+        let limiter = new_limiter();
+        let sign = limiter.run(Some(1), futures::future::pending::<Result<()>>());
+        // Polls once to acquire the slot, then drops the future.
+        assert!(futures::poll!(Box::pin(sign)).is_pending());
+        assert_eq!(limiter.depth(), 0);
+        assert_eq!(limiter.gauge.get(), 0);
+    }
+    #[tokio::test]
+    async fn unlimited() {
+        let limiter = new_limiter();
+        assert!(limiter.run(None, async { Ok(()) }).await.is_ok());
+    }
+}
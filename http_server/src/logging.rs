@@ -0,0 +1,164 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structured logs, with a span per request carrying a request ID, so that
+//! the logs of subresource and OCSP fetches can be correlated with the
+//! request that caused them.
+
+use clap::ArgEnum;
+use hyper::{body::HttpBody, Body, Response};
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::Span;
+
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+/// Installs the global subscriber, writing to stdout in the given format.
+pub fn init(format: LogFormat) {
+    let builder = tracing_subscriber::fmt();
+    match format {
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Returns the span for handling a request, with a newly generated request ID.
+pub fn request_span(client_ip: IpAddr, url: &http::Uri) -> Span {
+    let request_id = format!("{:016x}", rand::random::<u64>());
+    tracing::info_span!("request", %request_id, %client_ip, %url)
+}
+
+// Returns whether the response was signed, served unsigned, or failed.
+fn outcome(resp: &Response<Body>) -> &'static str {
+    let is_sxg = resp
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.starts_with("application/signed-exchange"));
+    if is_sxg {
+        "signed"
+    } else if resp.status().is_server_error() {
+        "error"
+    } else {
+        "unsigned"
+    }
+}
+
+/// Emits the one log line summarizing the response to a request. `error` is
+/// the reason the response is unsigned, if any.
+pub fn log_response(resp: &Response<Body>, error: Option<&str>, duration: Duration) {
+    tracing::info!(
+        outcome = outcome(resp),
+        status = resp.status().as_u16(),
+        duration_ms = duration.as_millis() as u64,
+        payload_size = resp.body().size_hint().exact(),
+        error,
+        "response"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::instrument::Instrument;
+    use tracing::span::{Attributes, Id};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    // The name and fields of each span or event.
+    type Records = Arc<Mutex<Vec<(String, Vec<(String, String)>)>>>;
+
+    #[derive(Default)]
+    struct Fields(Vec<(String, String)>);
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push((field.name().to_string(), value.to_string()));
+        }
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    struct Capture(Records);
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            let name = attrs.metadata().name().to_string();
+            self.0.lock().unwrap().push((name, fields.0));
+        }
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(("event".to_string(), fields.0));
+        }
+    }
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[tokio::test]
+    async fn request_produces_span_and_summary() {
+        let records = Records::default();
+        let subscriber = tracing_subscriber::registry().with(Capture(records.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let url: http::Uri = "/index.html".parse().unwrap();
+        let span = request_span("1.2.3.4".parse().unwrap(), &url);
+        async {
+            let resp = Response::builder()
+                .header("content-type", "application/signed-exchange;v=b3")
+                .body(Body::from("sxg"))
+                .unwrap();
+            log_response(&resp, None, Duration::from_millis(5));
+        }
+        .instrument(span)
+        .await;
+
+        let records = records.lock().unwrap();
+        let (name, fields) = &records[0];
+        assert_eq!(name, "request");
+        assert_eq!(field(fields, "request_id").unwrap().len(), 16);
+        assert_eq!(field(fields, "client_ip"), Some("1.2.3.4"));
+        assert_eq!(field(fields, "url"), Some("/index.html"));
+        let (name, fields) = &records[1];
+        assert_eq!(name, "event");
+        assert_eq!(field(fields, "outcome"), Some("signed"));
+        assert_eq!(field(fields, "status"), Some("200"));
+        assert_eq!(field(fields, "duration_ms"), Some("5"));
+        assert_eq!(field(fields, "payload_size"), Some("3"));
+        assert_eq!(field(fields, "error"), None);
+    }
+    #[test]
+    fn outcomes() {
+        let unsigned = Response::new(Body::empty());
+        assert_eq!(outcome(&unsigned), "unsigned");
+        let error = Response::builder().status(502).body(Body::empty()).unwrap();
+        assert_eq!(outcome(&error), "error");
+    }
+}
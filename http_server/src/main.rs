@@ -12,9 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::{anyhow, Result};
+mod client_auth;
+mod dns;
+mod key_source;
+mod limiter;
+mod logging;
+mod metrics;
+#[cfg(feature = "redis")]
+mod redis_storage;
+mod shutdown;
+
+use anyhow::{anyhow, Error, Result};
 use async_trait::async_trait;
 use clap::Parser;
+use client_auth::ClientAuth;
 use fs2::FileExt;
 use futures::{stream, StreamExt};
 use hyper::{
@@ -24,8 +35,7 @@ use hyper::{
     Body, Request, Response, StatusCode,
 };
 use hyper_reverse_proxy::ReverseProxy;
-use hyper_trust_dns::{RustlsHttpsConnector, TrustDnsResolver};
-use lru::LruCache;
+use hyper_trust_dns::TrustDnsResolver;
 use rand::{
     distributions::{Distribution, Uniform},
     SeedableRng,
@@ -35,22 +45,23 @@ use std::convert::TryInto;
 use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::net::{IpAddr, SocketAddr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use sxg_rs::{
     acme,
     crypto::CertificateChain,
-    fetcher::Fetcher,
-    headers::AcceptLevel,
-    http::{HttpRequest, HttpResponse, Method},
-    http_cache::HttpCache,
-    process_html::ProcessHtmlOption,
+    fetcher::{retry_fetcher::RetryFetcher, Fetcher},
+    http::{HeaderFields, HttpRequest, HttpResponse, Method},
+    http_cache::InMemoryCache,
+    signature::rust_signer::RustSigner,
     storage::Storage,
-    PresetContent, SxgWorker, MAX_PAYLOAD_SIZE,
+    RequestAction, SxgWorker, MAX_PAYLOAD_SIZE, SIGNED_HOST_HEADER,
 };
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::RwLock;
 use tools::Artifact;
+use tracing::Instrument;
 use url::Url;
 
 // TODO: Add readme, explaining how to create credentials & config.yaml and how to run.
@@ -75,6 +86,14 @@ struct Args {
     #[clap(short, long, default_value = "/tmp/sxg-rs")]
     directory: PathBuf,
 
+    /// The URL of a Redis server, such as redis://127.0.0.1:6379/, to store
+    /// ACME and OCSP state in instead of --directory, so that replicas share
+    /// it. While Redis can't be reached, storage is skipped, and OCSP
+    /// responses are fetched from the CA. Needs the redis feature.
+    #[cfg(feature = "redis")]
+    #[clap(long)]
+    redis_url: Option<String>,
+
     /// Path to config.yaml.
     #[clap(short, long, default_value = "http_server/config.yaml")]
     config: PathBuf,
@@ -87,6 +106,12 @@ struct Args {
     #[clap(short, long, default_value = "credentials/issuer.pem")]
     issuer: PathBuf,
 
+    /// Path to the TLS-encoded SignedCertificateTimestampList (RFC 6962) of
+    /// --cert, to include in the cert-chain if the CA doesn't embed SCTs in
+    /// the certificate.
+    #[clap(long)]
+    sct_list: Option<PathBuf>,
+
     /// The config generated by `gen-config` containing ACME account
     /// information. If it exists, this server will manage certificate
     /// acquisition and renewal. TODO: Instructions on what to forward.
@@ -97,36 +122,282 @@ struct Args {
     /// Maximum number of entries in the header integrity cache. Each entry will be about 1KB.
     #[clap(long, default_value = "2000")]
     header_integrity_cache_size: std::num::NonZeroUsize,
+
+    /// Number of seconds after which a header integrity cache entry expires.
+    #[clap(long, default_value = "3600")]
+    header_integrity_cache_ttl_seconds: u64,
+
+    /// Maximum size in bytes of a backend response to buffer for signing.
+    /// Larger responses are streamed unsigned. Values above 8000000 are
+    /// lowered to 8000000, the limit of the Google SXG cache.
+    #[clap(long, default_value = "8000000")]
+    max_payload_size: usize,
+
+    /// Maximum size in bytes of the body of a request whose response may be
+    /// signed, which is buffered to fetch the page. Larger requests are
+    /// rejected with 413 Payload Too Large, without reading the rest of the
+    /// body. Requests proxied unsigned are streamed whatever their size.
+    #[clap(long, default_value = "8000000")]
+    max_request_body_size: usize,
+
+    /// Negotiate HTTP/2 with the backend via ALPN, falling back to HTTP/1.1
+    /// if the backend doesn't support it. With the native_tls feature, only
+    /// HTTP/2 is offered, so the backend must support it.
+    #[clap(long)]
+    backend_http2: bool,
+
+    /// Number of seconds after which connecting to the backend, or to the
+    /// origin of an OCSP or ACME fetch, is aborted.
+    #[clap(long, default_value = "10")]
+    backend_connect_timeout_seconds: u64,
+
+    /// Number of seconds to wait for the response headers of the backend, or
+    /// of an OCSP or ACME fetch, after which the request fails. A backend
+    /// request that times out is answered with 504 Gateway Timeout.
+    #[clap(long, default_value = "60")]
+    backend_read_timeout_seconds: u64,
+
+    /// Include the full error chain in the body of error responses. Only for
+    /// local debugging; by default, the body is empty and only a one-line
+    /// summary is sent, in the sxg-rs-error header.
+    #[clap(long)]
+    verbose_errors: bool,
+
+    /// Number of seconds after which a subresource or OCSP fetch attempt is
+    /// aborted.
+    #[clap(long, default_value = "10")]
+    fetch_timeout_seconds: u64,
+
+    /// Maximum number of attempts for a subresource or OCSP fetch.
+    #[clap(long, default_value = "3")]
+    fetch_max_attempts: u32,
+
+    /// Number of milliseconds to wait before the first retry of a failed
+    /// fetch. Each subsequent retry waits twice as long.
+    #[clap(long, default_value = "100")]
+    fetch_retry_delay_ms: u64,
+
+    /// Don't fetch the OCSP responses of the certificates in the background.
+    /// By default, they are fetched once the worker is initialized, and again
+    /// whenever half of their validity has elapsed, so that requests for the
+    /// cert URL don't wait on the CA.
+    #[clap(long)]
+    no_ocsp_prefetch: bool,
+
+    /// The bind address (ip:port) of the admin server exposing Prometheus
+    /// metrics at /metrics, such as 127.0.0.1:9090. If not set, metrics are
+    /// not served.
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// The path of the health check, e.g. for a load balancer. It responds
+    /// 200 if every host has an unexpired certificate whose OCSP in
+    /// storage is unexpired, and otherwise 503 with the reason in the
+    /// body. Requests to it aren't proxied to the backend.
+    #[clap(long, default_value = "/healthz")]
+    health_check_path: String,
+
+    /// Take the client IP, which is forwarded to the backend and logged, from
+    /// the rightmost entry of the X-Forwarded-For request header, rather than
+    /// from the connection. Only set this behind a load balancer that appends
+    /// to X-Forwarded-For, as otherwise clients can choose their IP.
+    #[clap(long)]
+    trust_forwarded_for: bool,
+
+    /// An IP of the routing layer that may choose the host to sign requests
+    /// for, with an x-sxg-signed-host request header, when
+    /// trust_signed_host_header is set in config.yaml. Repeat it for each IP.
+    /// If none are set, only loopback connections may. The header is removed
+    /// from requests over any other connection.
+    #[clap(long)]
+    signed_host_source: Vec<IpAddr>,
+
+    /// The User-Agent of the requests that sxg-rs makes itself: fetches of
+    /// pages to sign from the backend, and subresource, OCSP and ACME
+    /// fetches. Requests proxied unsigned keep the User-Agent of the client.
+    /// Each request also gets a `Via: 1.1 sxg-rs` header.
+    #[clap(long, default_value = concat!("sxg-rs/", env!("CARGO_PKG_VERSION")))]
+    user_agent: http::HeaderValue,
+
+    /// The format of the per-request logs written to stdout.
+    #[clap(arg_enum, long, default_value = "pretty")]
+    log_format: logging::LogFormat,
+
+    /// Where to read the SXG private key from: private_key_base64 in
+    /// config.yaml ('file'), a PEM in the SXG_PRIVATE_KEY environment
+    /// variable ('env'), or a PEM on stdin ('stdin').
+    #[clap(arg_enum, long, default_value = "file")]
+    key_source: key_source::KeySource,
+
+    /// Number of seconds to wait for in-flight requests to complete after
+    /// receiving SIGTERM or SIGINT, before exiting anyway.
+    #[clap(long, default_value = "30")]
+    shutdown_timeout_seconds: u64,
 }
 
-type HttpsClient = hyper::Client<
-    hyper_rustls::HttpsConnector<hyper::client::connect::HttpConnector<TrustDnsResolver>>,
->;
+type HttpConnector = hyper::client::connect::HttpConnector<TrustDnsResolver>;
+
+// The connector of all the clients below: rustls with the webpki roots, or
+// with the native_tls feature, the platform's TLS library and trust store.
+#[cfg(not(feature = "native_tls"))]
+type HttpsConnector = hyper_trust_dns::RustlsHttpsConnector;
+#[cfg(feature = "native_tls")]
+type HttpsConnector = hyper_tls::HttpsConnector<HttpConnector>;
+
+type HttpsClient = hyper::Client<HttpsConnector>;
+
+// The arguments of the process. Tests get those of tests::args instead, which
+// point the server at a backend of their own.
+#[cfg(not(test))]
+fn args() -> Args {
+    Args::parse()
+}
+#[cfg(test)]
+use tests::args;
 
 lazy_static::lazy_static! {
-    static ref ARGS: Args = Args::parse();
+    static ref ARGS: Args = args();
+
+    // The signer for --key-source env or stdin, which is read once at startup.
+    static ref KEY_SOURCE_SIGNER: Option<RustSigner> =
+        key_source::load(ARGS.key_source).unwrap_or_else(|e| {
+            eprintln!("{e:#}");
+            std::process::exit(1)
+        });
+
+    // Shared by all the clients below; configured by the dns section of
+    // config.yaml.
+    static ref RESOLVER: TrustDnsResolver =
+        load_resolver(&ARGS.config).unwrap_or_else(|e| {
+            eprintln!("{e:#}");
+            std::process::exit(1)
+        });
+
+    // The certificate presented by all the clients below; configured by the
+    // client_auth section of config.yaml.
+    static ref CLIENT_AUTH: Option<ClientAuth> =
+        load_client_auth(&ARGS.config).unwrap_or_else(|e| {
+            eprintln!("{e:#}");
+            std::process::exit(1)
+        });
 
     static ref HTTPS_CLIENT: HttpsClient =
-        hyper::Client::builder().build::<_, hyper::Body>(TrustDnsResolver::default().into_rustls_webpki_https_connector());
+        client_builder(false).build::<_, hyper::Body>(https_connector(false));
 
-    static ref PROXY_CLIENT: ReverseProxy<RustlsHttpsConnector> =
+    static ref PROXY_CLIENT: ReverseProxy<HttpsConnector> =
         ReverseProxy::new(
-            hyper::Client::builder().build::<_, hyper::Body>(TrustDnsResolver::default().into_rustls_webpki_https_connector()));
+            client_builder(false).build::<_, hyper::Body>(https_connector(false)));
+
+    // Used for backend requests instead of PROXY_CLIENT when --backend-http2
+    // is set.
+    static ref HTTP2_BACKEND_CLIENT: HttpsClient =
+        client_builder(true).build::<_, hyper::Body>(https_connector(true));
 
     // WORKER must be mutable so that add_acme_certificates_from_storage can be
-    // called after challenge verification via WORKER.serve_preset_content().
-    // An RwLock is used instead of a Mutex to allow concurrent response
-    // handling. tokio::RwLock is write-preferring, so the write isn't starved.
-    static ref WORKER: RwLock<SxgWorker> = RwLock::new(
-        SxgWorker::new(&fs::read_to_string(&ARGS.config).unwrap()).unwrap());
+    // called after challenge verification via WORKER.serve_preset_content(),
+    // and so that a reload can swap it. Readers clone the Arc out with
+    // current_worker() rather than holding the guard, because tokio::RwLock
+    // is write-preferring: a guard held while signing, whose SelfFetcher
+    // reads WORKER again, would deadlock with a queued reload.
+    // It is forced at the start of main(), so a bad config fails at startup.
+    static ref WORKER: RwLock<Arc<SxgWorker>> = RwLock::new(Arc::new(
+        load_worker(&ARGS.config).unwrap_or_else(|e| {
+            eprintln!("{e:#}");
+            std::process::exit(1)
+        })));
+
+    static ref METRICS: metrics::Metrics = metrics::Metrics::new().unwrap();
+
+    static ref SIGNING_LIMITER: limiter::SigningLimiter =
+        limiter::SigningLimiter::new(METRICS.signings_in_flight());
+
+    static ref HEADER_INTEGRITY: InMemoryCache = InMemoryCache::new(
+        ARGS.header_integrity_cache_size.get(),
+        Duration::from_secs(ARGS.header_integrity_cache_ttl_seconds));
+}
+
+#[cfg(feature = "redis")]
+lazy_static::lazy_static! {
+    // Set by --redis-url. It is shared, so that requests reuse its connection.
+    static ref REDIS_STORAGE: Option<redis_storage::RedisStorage> =
+        ARGS.redis_url.as_deref().map(|url| {
+            redis_storage::RedisStorage::new(url).unwrap_or_else(|e| {
+                eprintln!("{e:#}");
+                std::process::exit(1)
+            })
+        });
+}
 
-    static ref HEADER_INTEGRITY: LruHttpCache = LruHttpCache(Mutex::new(LruCache::new(ARGS.header_integrity_cache_size)));
+// The error for a request body over the size limit, so that it can be
+// answered with 413 rather than 500.
+#[derive(Debug)]
+struct BodyTooLarge {
+    max_size: usize,
 }
 
-async fn req_to_vec_body(request: Request<Body>) -> Result<Request<Vec<u8>>> {
-    let (parts, body) = request.into_parts();
-    let body = hyper::body::to_bytes(body).await?.to_vec();
-    Ok(Request::from_parts(parts, body))
+impl std::fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The body exceeds the limit of {} bytes.", self.max_size)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+// The error for a backend that didn't respond in time, so that it can be
+// answered with 504 rather than 502.
+#[derive(Debug)]
+struct BackendTimeout {
+    timeout: Duration,
+}
+
+impl std::fmt::Display for BackendTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The backend didn't respond within {:?}.", self.timeout)
+    }
+}
+
+impl std::error::Error for BackendTimeout {}
+
+// Awaits `response`, failing with BackendTimeout if it takes over `timeout`.
+async fn with_timeout<T>(
+    timeout: Duration,
+    response: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::time::timeout(timeout, response)
+        .await
+        .map_err(|_| Error::new(BackendTimeout { timeout }))?
+}
+
+fn backend_read_timeout() -> Duration {
+    Duration::from_secs(ARGS.backend_read_timeout_seconds)
+}
+
+// Returns the declared Content-Length, if it or the body's size hint is known.
+fn declared_size(headers: &http::HeaderMap, body: &Body) -> Option<u64> {
+    headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse().ok())
+        .or_else(|| Some(body.size_hint().lower()).filter(|&size| size > 0))
+}
+
+// Buffers the request body, failing with BodyTooLarge as soon as it exceeds
+// max_size: before reading, if the Content-Length is too large, or else once
+// the chunks read so far add up to more than max_size.
+async fn req_to_vec_body(request: Request<Body>, max_size: usize) -> Result<Request<Vec<u8>>> {
+    let (parts, mut body) = request.into_parts();
+    let too_large = || Error::new(BodyTooLarge { max_size });
+    if matches!(declared_size(&parts.headers, &body), Some(size) if size > max_size as u64) {
+        return Err(too_large());
+    }
+    let mut buf = vec![];
+    while let Some(data) = body.data().await {
+        let data = data?;
+        if buf.len() + data.len() > max_size {
+            return Err(too_large());
+        }
+        buf.extend_from_slice(&data);
+    }
+    Ok(Request::from_parts(parts, buf))
 }
 
 #[derive(Debug)]
@@ -135,41 +406,46 @@ enum Payload {
     Streamed(Response<Body>),
 }
 
-// If body length is <= MAX_PAYLOAD_SIZE, returns it buffered in memory. Else,
-// returns a Body that streams the full response.
-async fn resp_to_vec_body(response: Response<Body>) -> Result<Payload> {
+// If body length is <= max_size, returns it buffered in memory. Else, returns a
+// Body that streams the full response. At most max_size bytes (plus the
+// remainder of the chunk that crossed it) are buffered. The whole payload must
+// be buffered before signing, because each MICE record proof depends on all of
+// the records after it.
+async fn resp_to_vec_body(response: Response<Body>, max_size: usize) -> Result<Payload> {
     let (parts, mut body) = response.into_parts();
-    if matches!(body.size_hint().upper(), Some(size) if size <= MAX_PAYLOAD_SIZE.try_into().unwrap_or(u64::MAX))
+    if matches!(declared_size(&parts.headers, &body), Some(size) if size > max_size as u64) {
+        // Known to be too large; stream it without buffering any of it.
+        return Ok(Payload::Streamed(Response::from_parts(parts, body)));
+    }
+    if matches!(body.size_hint().upper(), Some(size) if size <= max_size.try_into().unwrap_or(u64::MAX))
     {
-        Ok(Payload::InMemory(Response::from_parts(
+        Ok(in_memory(
             parts,
             hyper::body::to_bytes(body).await?.to_vec(),
-        )))
+        ))
     } else {
-        let mut buf = Vec::with_capacity(
-            body.size_hint()
-                .lower()
-                .try_into()
-                .unwrap_or(MAX_PAYLOAD_SIZE),
-        );
+        let mut buf = Vec::with_capacity(std::cmp::min(
+            body.size_hint().lower().try_into().unwrap_or(max_size),
+            max_size,
+        ));
         let mut extra = vec![];
-        while buf.len() <= MAX_PAYLOAD_SIZE {
+        while buf.len() <= max_size {
             if let Some(data) = body.data().await {
-                // Not yet MAX_PAYLOAD_SIZE and more data available.
+                // Not yet max_size and more data available.
                 let data = data?;
-                let needed = std::cmp::min(data.len(), MAX_PAYLOAD_SIZE - buf.len());
+                let needed = std::cmp::min(data.len(), max_size - buf.len());
                 buf.extend_from_slice(&data[..needed]);
                 extra.extend_from_slice(&data[needed..]);
-            } else if !extra.is_empty() {
-                // No more data, but the final chunk pushed us over MAX_PAYLOAD_SIZE.
-                break;
+                if !extra.is_empty() {
+                    // This chunk pushed us over max_size.
+                    break;
+                }
             } else {
-                // No more data and we're within MAX_PAYLOAD_SIZE.
-                return Ok(Payload::InMemory(Response::from_parts(parts, buf)));
+                // No more data and we're within max_size.
+                return Ok(in_memory(parts, buf));
             }
         }
-        // We're over MAX_PAYLOAD_SIZE. Additional data may be available in
-        // body, depending on how the while loop exited.
+        // We're over max_size. Additional data may be available in body.
         Ok(Payload::Streamed(Response::from_parts(
             parts,
             Body::wrap_stream(
@@ -183,16 +459,54 @@ async fn resp_to_vec_body(response: Response<Body>) -> Result<Payload> {
     }
 }
 
-struct HttpsFetcher<'a>(&'a HttpsClient);
+// Returns the fully buffered `body`. A chunked Transfer-Encoding of the
+// backend is removed, since it framed the body on the backend's connection;
+// hyper frames the buffered body with a Content-Length instead, and the
+// signed headers get the Content-Length of the MICE-encoded payload.
+fn in_memory(mut parts: http::response::Parts, body: Vec<u8>) -> Payload {
+    parts.headers.remove(http::header::TRANSFER_ENCODING);
+    Payload::InMemory(Response::from_parts(parts, body))
+}
+
+// The maximum payload size to buffer for signing. Larger payloads are served
+// unsigned.
+fn payload_size_limit() -> usize {
+    std::cmp::min(ARGS.max_payload_size, MAX_PAYLOAD_SIZE)
+}
+
+// The Via entry of this hop, per RFC 9110 section 7.6.3.
+const VIA: &str = "1.1 sxg-rs";
+
+// Appends this hop to any Via of an outgoing request. The SIGNED_HOST_HEADER
+// is only meant for sxg-rs, so it is removed.
+fn add_proxy_headers(headers: &mut http::HeaderMap) {
+    headers.append(http::header::VIA, http::HeaderValue::from_static(VIA));
+    headers.remove(SIGNED_HOST_HEADER);
+}
+
+// Identifies sxg-rs as the client of a request that it makes itself, rather
+// than on behalf of a client, by replacing the User-Agent with `user_agent`.
+fn set_user_agent(headers: &mut http::HeaderMap, user_agent: &http::HeaderValue) {
+    headers.insert(http::header::USER_AGENT, user_agent.clone());
+}
+
+// Fetches with the client, identifying as the given User-Agent.
+struct HttpsFetcher<'a>(&'a HttpsClient, &'a http::HeaderValue);
 
 #[async_trait]
 impl Fetcher for HttpsFetcher<'_> {
+    #[tracing::instrument(name = "https_fetch", skip_all, fields(url = %request.url))]
     async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
         let request: Request<Vec<u8>> = request.try_into()?;
-        let request: Request<Body> = request.map(|b| b.into());
+        let mut request: Request<Body> = request.map(|b| b.into());
+        set_user_agent(request.headers_mut(), self.1);
+        add_proxy_headers(request.headers_mut());
 
-        let response: Response<Body> = self.0.request(request).await?;
-        match resp_to_vec_body(response).await? {
+        let response: Response<Body> = with_timeout(backend_read_timeout(), async {
+            Ok(self.0.request(request).await?)
+        })
+        .await?;
+        match resp_to_vec_body(response, payload_size_limit()).await? {
             Payload::InMemory(payload) => payload.try_into(),
             _ => Err(anyhow!("Response too large")),
         }
@@ -203,71 +517,110 @@ struct SelfFetcher {
     client_ip: IpAddr,
 }
 
+// Returns the signer for the key from --key-source.
+fn sxg_signer(worker: &SxgWorker) -> Result<RustSigner> {
+    match &*KEY_SOURCE_SIGNER {
+        Some(signer) => Ok(signer.clone()),
+        None => worker.create_rust_signer(),
+    }
+}
+
+// Wraps the fetcher with the timeout and retry policy from ARGS.
+fn with_retries<F: Fetcher>(fetcher: F) -> RetryFetcher<F> {
+    RetryFetcher::new(
+        fetcher,
+        Duration::from_secs(ARGS.fetch_timeout_seconds),
+        ARGS.fetch_max_attempts,
+        Duration::from_millis(ARGS.fetch_retry_delay_ms),
+    )
+}
+
 // Fetches without `Accept: application/signed-exchange;v=b3`, because the
 // HeaderIntegrityFetcher expects unsigned responses.
 #[async_trait]
 impl Fetcher for SelfFetcher {
+    #[tracing::instrument(name = "self_fetch", skip_all, fields(url = %request.url))]
     async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
-        let (response, _) = handle(self.client_ip, request).await;
-        match resp_to_vec_body(response).await? {
+        let mut request: Request<Vec<u8>> = request.try_into()?;
+        set_user_agent(request.headers_mut(), &ARGS.user_agent);
+        let (parts, body) = request.into_parts();
+        let request = Request::from_parts(parts, vec![]).try_into()?;
+        let (response, _) = handle(self.client_ip, request, body.into()).await;
+        match resp_to_vec_body(response, payload_size_limit()).await? {
             Payload::InMemory(payload) => payload.try_into(),
             _ => Err(anyhow!("Response too large")),
         }
     }
 }
 
-struct LruHttpCache(Mutex<LruCache<String, HttpResponse>>);
-
-#[async_trait]
-impl HttpCache for &LruHttpCache {
-    async fn get(&self, url: &str) -> Result<HttpResponse> {
-        match self.0.lock().await.get(url) {
-            Some(resp) => Ok(resp.clone()),
-            None => Err(anyhow!("No cache entry found for {}", url)),
-        }
-    }
-    async fn put(&self, url: &str, response: &HttpResponse) -> Result<()> {
-        match self.0.lock().await.put(url.into(), response.clone()) {
-            Some(_) => Ok(()),
-            None => Err(anyhow!("Error storing cache entry for {}", url)),
-        }
-    }
+// Returns the worker at this time, without holding the lock of WORKER, so
+// that it may be swapped while a request is being handled with it.
+async fn current_worker() -> Arc<SxgWorker> {
+    WORKER.read().await.clone()
 }
 
+#[tracing::instrument(skip_all, fields(url = %fallback_url))]
 async fn generate_sxg_response(
     client_ip: IpAddr,
+    host: &str,
     fallback_url: &str,
     payload: Arc<HttpResponse>,
+    req_headers: &HeaderFields,
 ) -> Result<Response<Body>> {
-    let worker = WORKER.read().await;
-    let payload = worker.process_html(payload, ProcessHtmlOption { is_sxg: true });
+    // Preset content such as the cert-chain is served directly by
+    // handle_impl, so only signings count against the limit.
+    let max_concurrent_signings = current_worker().await.config().max_concurrent_signings;
+    SIGNING_LIMITER
+        .run(max_concurrent_signings, async {
+            let start = Instant::now();
+            let resp =
+                generate_sxg_response_impl(client_ip, host, fallback_url, payload, req_headers)
+                    .await;
+            METRICS.observe_sxg_generation(metrics::outcome(&resp), start.elapsed());
+            resp
+        })
+        .await
+}
 
-    let cert_origin = Url::parse(fallback_url)?.origin().ascii_serialization();
+async fn generate_sxg_response_impl(
+    client_ip: IpAddr,
+    host: &str,
+    fallback_url: &str,
+    payload: Arc<HttpResponse>,
+    req_headers: &HeaderFields,
+) -> Result<Response<Body>> {
+    let workers = current_worker().await;
+    let worker = workers.for_host(host);
     let subresource_fetcher = SelfFetcher { client_ip };
     let runtime = sxg_rs::runtime::Runtime {
         now: SystemTime::now(),
-        fetcher: Box::new(subresource_fetcher),
-        sxg_signer: Box::new(worker.create_rust_signer()?),
+        fetcher: Box::new(with_retries(subresource_fetcher)),
+        storage: storage(),
+        sxg_signer: Box::new(sxg_signer(worker)?),
         ..Default::default()
     };
     let sxg = worker
-        .create_signed_exchange(
+        .sign_payload(
             &runtime,
-            sxg_rs::CreateSignedExchangeParams {
-                payload_body: &payload.body,
-                payload_headers: worker.transform_payload_headers(payload.headers.clone())?,
-                skip_process_link: false,
-                status_code: 200,
-                fallback_url,
-                cert_origin: &cert_origin,
-                header_integrity_cache: &*HEADER_INTEGRITY,
-            },
+            fallback_url,
+            payload,
+            req_headers,
+            &*HEADER_INTEGRITY,
         )
         .await?;
     let sxg: Response<Vec<u8>> = sxg.try_into()?;
     Ok(sxg.map(Body::from))
 }
 
+// Returns the storage of --redis-url, or else of --directory.
+fn storage() -> Box<dyn Storage> {
+    #[cfg(feature = "redis")]
+    if let Some(storage) = &*REDIS_STORAGE {
+        return Box::new(storage.clone());
+    }
+    Box::new(FileStorage(ARGS.directory.clone()))
+}
+
 /// Persistent storage mechanism for OCSP responses & ACME certs. Takes a path
 /// to a directory where it will create files for them.
 pub struct FileStorage(PathBuf);
@@ -300,181 +653,614 @@ impl Storage for FileStorage {
         }
     }
     async fn write(&self, k: &str, v: &str) -> Result<()> {
+        static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
         let path = self.0.join(k);
-        let mut f = File::create(path)?;
-        // Don't do any early returns (e.g. `?`) between lock and unlock.
-        f.lock_exclusive()?;
-        let ret = write!(f, "{}", v);
-        let _ = f.unlock();
+        // Write to a temporary file and rename it over the destination, so
+        // that a crash mid-write can't leave a truncated file behind. Readers
+        // see either the old or the new content.
+        let tmp_path = self.0.join(format!(
+            ".{k}.{}.{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let ret = (|| -> std::io::Result<()> {
+            let mut f = File::create(&tmp_path)?;
+            write!(f, "{}", v)?;
+            f.sync_all()?;
+            fs::rename(&tmp_path, &path)
+        })();
+        if ret.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
         ret.map_err(|e| anyhow!("error writing file {k}: {e}"))
     }
 }
 
-async fn serve_preset_content(url: &str) -> Option<PresetContent> {
-    let ocsp_fetcher = HttpsFetcher(&HTTPS_CLIENT);
-    // Using a Storage impl that persists across restarts (and between
-    // replicas, if using a networked filesystem), per
-    // https://gist.github.com/sleevi/5efe9ef98961ecfb4da8 rule #1.
-    let worker = WORKER.read().await;
+// Records the outcome of OCSP fetches. Any fetch means the OCSP in storage was
+// missing or due for an update.
+struct OcspMetricsFetcher<F: Fetcher> {
+    inner: F,
+    fetched: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl<F: Fetcher> Fetcher for OcspMetricsFetcher<F> {
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
+        self.fetched.store(true, Ordering::SeqCst);
+        let response = self.inner.fetch(request).await;
+        METRICS.inc_ocsp_fetch(metrics::outcome(&response));
+        response
+    }
+}
+
+// The wait before retrying a failed OCSP prefetch.
+const OCSP_PREFETCH_RETRY_DELAY: Duration = Duration::from_secs(60);
+// The longest wait between OCSP prefetches, so that certificates added by a
+// reload are prefetched soon after.
+const OCSP_PREFETCH_MAX_DELAY: Duration = Duration::from_secs(3600);
+
+// Fetches the OCSP of each of the worker's certificates into storage, unless
+// it is already there and not past its recommended update time. Returns how
+// long to wait before calling this again.
+async fn prefetch_ocsp(
+    worker: &SxgWorker,
+    fetcher: Box<dyn Fetcher>,
+    storage: Box<dyn Storage>,
+) -> Duration {
+    let runtime = sxg_rs::runtime::Runtime {
+        now: SystemTime::now(),
+        fetcher,
+        storage,
+        ..Default::default()
+    };
+    if let Err(e) = worker.update_oscp_in_storage(&runtime).await {
+        println!("OCSP prefetch failed; retrying: {e:#}");
+    }
+    worker
+        .next_ocsp_update_time(&runtime)
+        .await
+        .and_then(|next| next.duration_since(SystemTime::now()).ok())
+        .unwrap_or(OCSP_PREFETCH_RETRY_DELAY)
+        .clamp(OCSP_PREFETCH_RETRY_DELAY, OCSP_PREFETCH_MAX_DELAY)
+}
+
+async fn prefetch_ocsp_periodically() {
+    loop {
+        let ocsp_fetcher = OcspMetricsFetcher {
+            inner: with_retries(HttpsFetcher(&HTTPS_CLIENT, &ARGS.user_agent)),
+            fetched: Arc::new(AtomicBool::new(false)),
+        };
+        let worker = current_worker().await;
+        let delay = prefetch_ocsp(&worker, Box::new(ocsp_fetcher), storage()).await;
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Routes `req` with `worker`, whose preset content such as the cert-chain is
+// served with the OCSP from storage, or else from the CA.
+#[tracing::instrument(skip_all, fields(url = %req.url))]
+async fn route_request(worker: &SxgWorker, req: &HttpRequest) -> Result<RequestAction> {
+    let fetched = Arc::new(AtomicBool::new(false));
+    let ocsp_fetcher = OcspMetricsFetcher {
+        inner: with_retries(HttpsFetcher(&HTTPS_CLIENT, &ARGS.user_agent)),
+        fetched: fetched.clone(),
+    };
     // Use a MockSigner if the Rust signer is not ready because ACME hasn't completed.
-    let sxg_signer: Box<dyn sxg_rs::signature::Signer> = match worker.create_rust_signer() {
+    let sxg_signer: Box<dyn sxg_rs::signature::Signer> = match sxg_signer(worker) {
         Ok(s) => Box::new(s),
         Err(_) => Box::new(sxg_rs::signature::mock_signer::MockSigner),
     };
+    // Using a Storage impl that persists across restarts (and between
+    // replicas, if using a networked filesystem), per
+    // https://gist.github.com/sleevi/5efe9ef98961ecfb4da8 rule #1.
     let runtime = sxg_rs::runtime::Runtime {
         now: SystemTime::now(),
         fetcher: Box::new(ocsp_fetcher),
-        storage: Box::new(FileStorage(ARGS.directory.clone())),
+        storage: storage(),
         sxg_signer,
         ..Default::default()
     };
-    worker.serve_preset_content(&runtime, url).await
+    let action = worker
+        .route_request(&runtime, &req.method, &req.url, &req.headers)
+        .await;
+    let is_cert_url = request_path(&req.url).starts_with(&worker.config().cert_url_dirname);
+    let fetched = fetched.load(Ordering::SeqCst);
+    let served_cert = matches!(
+        &action,
+        Ok(RequestAction::Respond(HttpResponse {
+            status: 200 | 304,
+            ..
+        }))
+    );
+    if is_cert_url && (fetched || served_cert) {
+        METRICS.observe_ocsp_cache(!fetched);
+    }
+    action
+}
+
+// Returns the path of a request for `url`, which is absolute in HTTP/2.
+fn request_path(url: &str) -> String {
+    Url::parse("https://localhost/")
+        .and_then(|base| base.join(url))
+        .map(|url| url.path().to_string())
+        .unwrap_or_default()
+}
+
+// Returns a connector for https and http URLs, which negotiates HTTP/2 via
+// ALPN if `http2` is true.
+fn https_connector(http2: bool) -> HttpsConnector {
+    let mut http = RESOLVER.clone().into_http_connector();
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(Duration::from_secs(
+        ARGS.backend_connect_timeout_seconds,
+    )));
+    wrap_tls(http, http2, CLIENT_AUTH.as_ref()).unwrap_or_else(|e| {
+        eprintln!("{e:#}");
+        std::process::exit(1)
+    })
+}
+
+#[cfg(not(feature = "native_tls"))]
+fn wrap_tls(http: HttpConnector, http2: bool, auth: Option<&ClientAuth>) -> Result<HttpsConnector> {
+    let tls = client_auth::rustls_config(client_auth::webpki_roots(), auth)?;
+    let builder = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls)
+        .https_or_http()
+        .enable_http1();
+    Ok(if http2 {
+        builder.enable_http2().wrap_connector(http)
+    } else {
+        builder.wrap_connector(http)
+    })
+}
+
+#[cfg(feature = "native_tls")]
+fn wrap_tls(http: HttpConnector, http2: bool, auth: Option<&ClientAuth>) -> Result<HttpsConnector> {
+    // The client speaks only the protocol that client_builder forces, so a
+    // server must not be allowed to pick the other.
+    let alpn: &[&str] = if http2 { &["h2"] } else { &["http/1.1"] };
+    let mut builder = native_tls::TlsConnector::builder();
+    builder.request_alpns(alpn);
+    if let Some(auth) = auth {
+        let identity =
+            native_tls::Identity::from_pkcs8(auth.cert_pem.as_bytes(), auth.key_pem.as_bytes())
+                .map_err(|e| Error::new(e).context("error loading client_auth into native TLS"))?;
+        builder.identity(identity);
+    }
+    let tls = builder
+        .build()
+        .map_err(|e| Error::new(e).context("error initializing native TLS"))?;
+    Ok(hyper_tls::HttpsConnector::from((
+        http,
+        tokio_native_tls::TlsConnector::from(tls),
+    )))
+}
+
+// Returns the builder of a client using `https_connector(http2)`. hyper-tls
+// doesn't tell hyper which protocol ALPN negotiated, so HTTP/2 is forced
+// instead.
+fn client_builder(http2: bool) -> hyper::client::Builder {
+    let mut builder = hyper::Client::builder();
+    if cfg!(feature = "native_tls") {
+        builder.http2_only(http2);
+    }
+    builder
+}
+
+// https://datatracker.ietf.org/doc/html/draft-ietf-httpbis-http2bis-07#section-8.3.1
+// requires that a request's :authority pseudo-header equals its Host header,
+// else servers such as https://www.google.com respond with a 400.
+// hyper::Client derives :authority from the request URI, and
+// http::header::HeaderMap can't hold ":authority" as a key, so this sets the
+// URI to the absolute backend URL and the Host header to its authority.
+fn http2_backend_request(
+    client_ip: IpAddr,
+    backend: &str,
+    mut request: Request<Body>,
+) -> Result<Request<Body>> {
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |p| p.as_str())
+        .to_string();
+    let uri: hyper::Uri = Url::parse(backend)?.join(&path)?.as_str().parse()?;
+    let authority: http::HeaderValue = uri
+        .authority()
+        .ok_or_else(|| anyhow!("Backend URL {} has no authority", backend))?
+        .as_str()
+        .try_into()?;
+    *request.uri_mut() = uri;
+    let headers = request.headers_mut();
+    headers.insert(http::header::HOST, authority);
+    headers.append("x-forwarded-for", client_ip.to_string().try_into()?);
+    Ok(request)
+}
+
+async fn fetch_backend(client_ip: IpAddr, request: Request<Body>) -> Result<Response<Body>> {
+    let start = Instant::now();
+    let response = fetch_backend_impl(client_ip, request).await;
+    METRICS.observe_backend_fetch(metrics::outcome(&response), start.elapsed());
+    response
+}
+
+async fn fetch_backend_impl(
+    client_ip: IpAddr,
+    mut request: Request<Body>,
+) -> Result<Response<Body>> {
+    add_proxy_headers(request.headers_mut());
+    with_timeout(backend_read_timeout(), async {
+        if ARGS.backend_http2 {
+            let request = http2_backend_request(client_ip, &ARGS.backend, request)?;
+            Ok(HTTP2_BACKEND_CLIENT.request(request).await?)
+        } else {
+            PROXY_CLIENT
+                .call(client_ip, &ARGS.backend, request)
+                .await
+                .map_err(|e| anyhow!("{:?}", e))
+        }
+    })
+    .await
 }
 
-// TODO: Dedupe with PresetContent.
 enum HandleAction {
     Respond(Response<Body>),
-    Sign { url: String, payload: HttpResponse },
+    // Sign `payload`, or if that fails, serve `fallback` unsigned.
+    Sign {
+        host: String,
+        url: String,
+        payload: Arc<HttpResponse>,
+        fallback: Arc<HttpResponse>,
+    },
+    // The request isn't eligible for signing, per its method, per
+    // signed_paths and unsigned_paths, or per an invalid SIGNED_HOST_HEADER.
+    ProxyUnsigned,
 }
 
-// TODO: Figure out how to enable http2 client support.  It's disabled
-// currently, because when testing on https://www.google.com with http2
-// enabled, I got a 400. My guess why:
-// https://datatracker.ietf.org/doc/html/draft-ietf-httpbis-http2bis-07#section-8.3.1
-// requires that a request's :authority pseudo-header equals its Host header.
-// I guess hyper::Client doesn't synthesize :authority from the Host header.
-// We can't work around this because http::header::HeaderMap panics with
-// InvalidHeaderName when given ":authority" as a key.
-async fn handle_impl(client_ip: IpAddr, req: HttpRequest) -> Result<HandleAction> {
-    let worker = WORKER.read().await;
-    let fallback_url: String;
-    let sxg_payload;
-    let req_url =
-        url::Url::parse(&format!("https://{}/", worker.config().html_host))?.join(&req.url)?;
-    match serve_preset_content(&format!("{}", req_url)).await {
-        Some(PresetContent::Direct(response)) => {
+// Removes SIGNED_HOST_HEADER from a request over a connection from
+// `remote_ip`, unless that is one of `sources`, or if there are none, a
+// loopback address, so that clients can't choose the host to sign for.
+fn strip_untrusted_signed_host(
+    remote_ip: IpAddr,
+    headers: &mut http::HeaderMap,
+    sources: &[IpAddr],
+) {
+    let trusted = if sources.is_empty() {
+        remote_ip.is_loopback()
+    } else {
+        sources.contains(&remote_ip)
+    };
+    if !trusted {
+        headers.remove(SIGNED_HOST_HEADER);
+    }
+}
+
+// Returns the action for `req`, whose body is `body`. Only a request whose
+// response may be signed has its body read, into a buffer within
+// --max-request-body-size that replaces `body`.
+async fn handle_impl(client_ip: IpAddr, req: HttpRequest, body: &mut Body) -> Result<HandleAction> {
+    let workers = current_worker().await;
+    // A router that names a host that isn't configured gets the response
+    // unsigned, as for any other request that isn't eligible for signing.
+    let host = match workers.request_host(&req.headers, &req.url) {
+        Ok(host) => host,
+        Err(_) => return Ok(HandleAction::ProxyUnsigned),
+    };
+    let worker = workers.for_host(&host);
+    if matches!(req.method, Method::Get | Method::Head)
+        && request_path(&req.url) == ARGS.health_check_path
+    {
+        let runtime = sxg_rs::runtime::Runtime {
+            now: SystemTime::now(),
+            storage: storage(),
+            ..Default::default()
+        };
+        return Ok(HandleAction::Respond(
+            health_response(&workers, &runtime).await,
+        ));
+    }
+    match route_request(worker, &req).await? {
+        RequestAction::Respond(response) => {
             let response: Response<Vec<u8>> = response.try_into()?;
-            return Ok(HandleAction::Respond(response.map(Body::from)));
-        }
-        Some(PresetContent::ToBeSigned { url, payload, .. }) => {
-            fallback_url = url;
-            let payload: Response<Vec<u8>> = payload.try_into()?;
-            sxg_payload = payload.map(Body::from);
-            worker.transform_request_headers(req.headers, AcceptLevel::AcceptsSxg)?;
+            Ok(HandleAction::Respond(response.map(Body::from)))
         }
-        None => {
+        RequestAction::ProxyUnsigned => Ok(HandleAction::ProxyUnsigned),
+        RequestAction::Sign {
+            fallback_url,
+            payload,
+            fallback,
+        } => Ok(HandleAction::Sign {
+            host,
+            url: fallback_url,
+            payload: Arc::new(payload),
+            fallback: Arc::new(fallback),
+        }),
+        RequestAction::Fetch {
+            fallback_url,
+            backend_path,
+            backend_headers,
+        } => {
             // TODO: Reduce the amount of conversion needed between request/response/header types.
-            let backend_url = url::Url::parse(&ARGS.backend)?.join(&req.url)?;
-            fallback_url = worker
-                .get_fallback_url_and_cert_origin(&backend_url)?
-                .0
-                .to_string();
-            let req_headers =
-                worker.transform_request_headers(req.headers, AcceptLevel::PrefersSxg)?;
-            let mut request = Request::builder()
-                .method(match req.method {
-                    Method::Get => "GET",
-                    Method::Post => "POST",
-                })
-                .uri(req.url);
-            for (key, value) in req_headers {
+            let mut request = Request::builder().uri(backend_path);
+            for (key, value) in backend_headers {
                 request = request.header(key, value);
             }
-            let request = request.body(req.body.into())?;
-            sxg_payload = PROXY_CLIENT
-                .call(client_ip, &ARGS.backend, request)
-                .await
-                .map_err(|e| anyhow!("{:?}", e))?;
+            let request = req_to_vec_body(
+                request.body(std::mem::take(body))?,
+                ARGS.max_request_body_size,
+            )
+            .await?;
+            // Kept, so that the request can still be proxied unsigned if the
+            // fetch fails.
+            *body = Body::from(request.body().clone());
+            let mut request = request.map(Body::from);
+            set_user_agent(request.headers_mut(), &ARGS.user_agent);
+            let payload = fetch_backend(client_ip, request).await?;
+            let payload = resp_to_vec_body(payload, payload_size_limit()).await?;
+            Ok(match payload {
+                Payload::InMemory(payload) => sign_or_respond(worker, host, fallback_url, payload)?,
+                Payload::Streamed(payload) => HandleAction::Respond(payload),
+            })
         }
     }
-    let sxg_payload = resp_to_vec_body(sxg_payload).await?;
-    Ok(match sxg_payload {
-        Payload::InMemory(payload) => HandleAction::Sign {
-            url: fallback_url,
-            payload: payload.try_into()?,
-        },
-        Payload::Streamed(payload) => HandleAction::Respond(payload),
+}
+
+// Returns the action for the buffered `payload`: signing it, or if the worker
+// doesn't sign its status, such as that of a backend error, or its
+// content-type or a body that mismatches it, serving it unsigned as it is.
+fn sign_or_respond(
+    worker: &SxgWorker,
+    host: String,
+    url: String,
+    payload: Response<Vec<u8>>,
+) -> Result<HandleAction> {
+    let payload: Arc<HttpResponse> = Arc::new(payload.try_into()?);
+    if !worker.should_sign_payload(&payload) {
+        return Ok(HandleAction::Respond(unsigned_response(worker, payload)?));
+    }
+    Ok(HandleAction::Sign {
+        host,
+        url,
+        payload: payload.clone(),
+        fallback: payload,
     })
 }
 
-async fn proxy_unsigned(client_ip: IpAddr, req: HttpRequest) -> Result<Response<Body>> {
+// Returns the buffered backend response to serve unsigned, with its HTML
+// processed as for an unsigned page.
+fn unsigned_response(worker: &SxgWorker, payload: Arc<HttpResponse>) -> Result<Response<Body>> {
+    let payload: Response<Vec<u8>> = worker.unsigned_response(payload).try_into()?;
+    Ok(payload.map(Body::from))
+}
+
+// Responds 200 if `worker` can sign, else 503 with the reason. OCSP is only
+// read from storage, so that an outage of the CA doesn't delay the check.
+async fn health_response(worker: &SxgWorker, runtime: &sxg_rs::runtime::Runtime) -> Response<Body> {
+    let (status, body) = match worker.check_health(runtime).await {
+        Ok(()) => (StatusCode::OK, "OK\n".to_string()),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, format!("{e:#}\n")),
+    };
+    let mut resp = Response::new(Body::from(body));
+    *resp.status_mut() = status;
+    let headers = resp.headers_mut();
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("text/plain"),
+    );
+    headers.insert(
+        http::header::CACHE_CONTROL,
+        http::HeaderValue::from_static("no-store"),
+    );
+    resp
+}
+
+// Fetches `req`, whose body is `body`, from the backend to serve unsigned.
+async fn proxy_unsigned(client_ip: IpAddr, req: HttpRequest, body: Body) -> Result<Response<Body>> {
     let req: Request<Vec<u8>> = req.try_into()?;
-    let req = req.map(Body::from);
-    let payload = PROXY_CLIENT
-        .call(client_ip, &ARGS.backend, req)
-        .await
-        .map_err(|e| anyhow!("{:?}", e))?;
-    Ok(match resp_to_vec_body(payload).await? {
-        Payload::InMemory(payload) => {
-            let payload: HttpResponse = payload.try_into()?;
-            let worker = WORKER.read().await;
-            let payload =
-                worker.process_html(Arc::new(payload), ProcessHtmlOption { is_sxg: false });
-            let payload = Arc::try_unwrap(payload).unwrap_or_else(|p| (*p).clone());
-            let payload: Response<Vec<u8>> = payload.try_into()?;
-            payload.map(Body::from)
-        }
+    let req = req.map(|_| body);
+    let payload = fetch_backend(client_ip, req).await?;
+    unsigned_payload(&*current_worker().await, payload).await
+}
+
+// Returns the backend response to serve unsigned. A partial response to a
+// Range request is passed through as is, because processing its HTML would
+// change the bytes that its Content-Range refers to.
+async fn unsigned_payload(worker: &SxgWorker, payload: Response<Body>) -> Result<Response<Body>> {
+    if payload.status() == StatusCode::PARTIAL_CONTENT {
+        return Ok(payload);
+    }
+    let payload = resp_to_vec_body(payload, payload_size_limit()).await?;
+    Ok(match payload {
+        Payload::InMemory(payload) => unsigned_response(worker, Arc::new(payload.try_into()?))?,
         Payload::Streamed(payload) => payload,
     })
 }
 
+// Reduces the error to its outermost message, as one line of printable ASCII
+// that is valid as a header value.
+fn error_summary(err: impl core::fmt::Display) -> String {
+    const MAX_LEN: usize = 256;
+    format!("{err}")
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c == ' ' || c.is_ascii_graphic() {
+                c
+            } else {
+                '?'
+            }
+        })
+        .take(MAX_LEN)
+        .collect()
+}
+
 fn set_error_header(err: impl core::fmt::Display, mut resp: Response<Body>) -> Response<Body> {
-    if let Ok(val) = format!("{err}").try_into() {
+    if let Ok(val) = error_summary(err).try_into() {
         resp.headers_mut().insert("sxg-rs-error", val);
     }
     resp
 }
 
-fn error_body(err: impl core::fmt::Display) -> Response<Body> {
-    let mut resp = Response::new(Body::from(format!("{err}")));
-    *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-    resp
+// Returns an error response with an empty body, so that error details don't
+// leak into pages seen by users or crawlers. If `verbose` is true, the body
+// contains the full error chain instead.
+fn error_response(
+    status: StatusCode,
+    err: impl core::fmt::Display,
+    verbose: bool,
+) -> Response<Body> {
+    let body = if verbose {
+        Body::from(format!("{err:#}"))
+    } else {
+        Body::empty()
+    };
+    let mut resp = Response::new(body);
+    *resp.status_mut() = status;
+    set_error_header(err, resp)
+}
+
+// Returns the response for when neither a signed nor unsigned response could
+// be generated from the backend.
+fn error_body(err: impl core::fmt::Display, verbose: bool) -> Response<Body> {
+    error_response(StatusCode::BAD_GATEWAY, err, verbose)
+}
+
+// Like error_body, but answers a backend timeout with 504.
+fn backend_error_body(err: Error, verbose: bool) -> Response<Body> {
+    if err.is::<BackendTimeout>() {
+        error_response(StatusCode::GATEWAY_TIMEOUT, err, verbose)
+    } else {
+        error_body(err, verbose)
+    }
+}
+
+// Returns the unsigned payload to serve in place of an SXG that failed to
+// generate, plus the error message for why it wasn't signed. If the fallback is
+// disabled by config, returns an error response instead.
+fn unsigned_fallback(
+    worker: &SxgWorker,
+    payload: Arc<HttpResponse>,
+    err: impl core::fmt::Display,
+    verbose_errors: bool,
+) -> (Response<Body>, Option<String>) {
+    if !worker.config().fallback_to_unsigned {
+        return (error_body(err, verbose_errors), None);
+    }
+    let payload: Result<Response<Vec<u8>>> = worker.unsigned_response(payload).try_into();
+    match payload {
+        Ok(payload) => (payload.map(Body::from), Some(format!("{err}"))),
+        Err(e) => (error_body(e, verbose_errors), None),
+    }
 }
 
-// Returns the maybe-signed response, plus an optional string containing an
-// error message for why it wasn't signed.
-async fn handle(client_ip: IpAddr, req: HttpRequest) -> (Response<Body>, Option<String>) {
-    match handle_impl(client_ip, req.clone()).await {
+// Returns the maybe-signed response to `req`, whose body is `body` rather than
+// `req.body`, plus an optional string containing an error message for why it
+// wasn't signed.
+async fn handle(
+    client_ip: IpAddr,
+    req: HttpRequest,
+    mut body: Body,
+) -> (Response<Body>, Option<String>) {
+    match handle_impl(client_ip, req.clone(), &mut body).await {
         Ok(HandleAction::Respond(resp)) => (resp, None),
-        Ok(HandleAction::Sign { url, payload }) => {
-            let payload = Arc::new(payload);
-            match generate_sxg_response(client_ip, &url, payload.clone()).await {
+        Ok(HandleAction::ProxyUnsigned) => match proxy_unsigned(client_ip, req, body).await {
+            Ok(resp) => (resp, None),
+            Err(e) => (backend_error_body(e, ARGS.verbose_errors), None),
+        },
+        Ok(HandleAction::Sign {
+            host,
+            url,
+            payload,
+            fallback,
+        }) => {
+            match generate_sxg_response(client_ip, &host, &url, payload, &req.headers).await {
                 Ok(resp) => (resp, None),
                 Err(e) => {
-                    let worker = WORKER.read().await;
-                    let payload = worker.process_html(payload, ProcessHtmlOption { is_sxg: false });
-                    let payload = Arc::try_unwrap(payload).unwrap_or_else(|p| (*p).clone());
-                    let payload: Result<Response<Vec<u8>>> = payload.try_into();
-                    match payload {
-                        Ok(payload) => (payload.map(Body::from), Some(format!("{e}"))),
-                        Err(e) => (error_body(e), None),
+                    // The SXG, including its MICE encoding, is complete
+                    // before any of it is sent, so a failure never truncates
+                    // the response. The buffered payload is served instead,
+                    // without fetching the backend again.
+                    let workers = current_worker().await;
+                    let worker = workers.for_host(&host);
+                    let (resp, e) = unsigned_fallback(worker, fallback, e, ARGS.verbose_errors);
+                    if e.is_some() {
+                        METRICS.inc_unsigned_fallback();
                     }
+                    (resp, e)
                 }
             }
         }
-        Err(e) => match proxy_unsigned(client_ip, req).await {
+        Err(e) if e.is::<BodyTooLarge>() => (
+            error_response(StatusCode::PAYLOAD_TOO_LARGE, e, ARGS.verbose_errors),
+            None,
+        ),
+        // Retrying unsigned would wait on the backend again.
+        Err(e) if e.is::<BackendTimeout>() => (backend_error_body(e, ARGS.verbose_errors), None),
+        Err(e) => match proxy_unsigned(client_ip, req, body).await {
             Ok(resp) => (resp, Some(format!("{e}"))),
-            Err(e) => (error_body(e), None),
+            Err(e) => (backend_error_body(e, ARGS.verbose_errors), None),
         },
     }
 }
 
+// Returns the IP of the client of `req`, which arrived over a connection from
+// `remote_ip`. If `trust_forwarded_for`, that is the rightmost entry of
+// X-Forwarded-For, as appended by the load balancer; the entries to its left
+// were sent by the client, so may be spoofed.
+fn client_ip(remote_ip: IpAddr, headers: &http::HeaderMap, trust_forwarded_for: bool) -> IpAddr {
+    if !trust_forwarded_for {
+        return remote_ip;
+    }
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .last()
+        .and_then(|value| value.to_str().ok()?.rsplit(',').next())
+        .and_then(|entry| {
+            let entry = entry.trim();
+            // The entry may include a port, such as 1.2.3.4:5678 or [::1]:80.
+            entry
+                .parse()
+                .or_else(|_| entry.parse::<SocketAddr>().map(|addr| addr.ip()))
+                .ok()
+        })
+        .unwrap_or(remote_ip)
+}
+
 async fn handle_or_error(
+    remote_ip: IpAddr,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, http::Error> {
+    strip_untrusted_signed_host(remote_ip, req.headers_mut(), &ARGS.signed_host_source);
+    let client_ip = client_ip(remote_ip, req.headers(), ARGS.trust_forwarded_for);
+    let span = logging::request_span(client_ip, req.uri());
+    handle_or_error_impl(client_ip, req).instrument(span).await
+}
+
+async fn handle_or_error_impl(
     client_ip: IpAddr,
     req: Request<Body>,
 ) -> Result<Response<Body>, http::Error> {
-    println!("{client_ip} {}", req.uri());
-    let req: Result<Request<Vec<u8>>> = req_to_vec_body(req).await;
-    let req: Result<HttpRequest> = req.and_then(|r| r.try_into());
+    let start = Instant::now();
+    // The body is left to handle, which streams it unless it buffers it to
+    // sign the response.
+    let (parts, body) = req.into_parts();
+    let req: Result<HttpRequest> = Request::from_parts(parts, vec![]).try_into();
     let req: HttpRequest = match req {
         Ok(req) => req,
         Err(e) => {
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from(format!("{:?}", e)));
+            let resp = error_response(StatusCode::INTERNAL_SERVER_ERROR, e, ARGS.verbose_errors);
+            logging::log_response(&resp, None, start.elapsed());
+            return Ok(resp);
         }
     };
-    let (resp, e) = handle(client_ip, req).await;
+    let is_head = req.method == Method::Head;
+    let (mut resp, e) = handle(client_ip, req, body).await;
+    logging::log_response(&resp, e.as_deref(), start.elapsed());
+    if is_head {
+        *resp.body_mut() = Body::empty();
+    }
     Ok(match e {
         Some(e) => set_error_header(e, resp),
         None => resp,
@@ -486,7 +1272,7 @@ async fn handle_or_error(
 // - Ok(false) if the user didn't configure ACME; the caller should add certs
 // - Err() if ACME failed; the caller should print a message and add backup certs
 async fn initialize_acme() -> Result<bool> {
-    let ocsp_fetcher = HttpsFetcher(&HTTPS_CLIENT);
+    let ocsp_fetcher = HttpsFetcher(&HTTPS_CLIENT, &ARGS.user_agent);
     let artifact_str = std::fs::read_to_string(&ARGS.artifact)?;
 
     let artifact: Artifact = serde_yaml::from_str(&artifact_str)?;
@@ -504,7 +1290,7 @@ async fn initialize_acme() -> Result<bool> {
     let mut runtime = sxg_rs::runtime::Runtime {
         now: SystemTime::now(),
         fetcher: Box::new(ocsp_fetcher),
-        storage: Box::new(FileStorage(ARGS.directory.clone())),
+        storage: storage(),
         acme_signer: Box::new(acme_signer),
         ..Default::default()
     };
@@ -539,17 +1325,136 @@ async fn initialize_acme() -> Result<bool> {
                 tokio::time::sleep(delay).await;
             }
             None => {
-                // The ACME workflow is complete.
-                let mut worker = WORKER.write().await;
+                // The ACME workflow is complete. The certificates are read
+                // into a copy, so that WORKER isn't locked while storage is.
+                let mut worker = SxgWorker::clone(&*current_worker().await);
                 worker.add_acme_certificates_from_storage(&runtime).await?;
+                *WORKER.write().await = Arc::new(worker);
                 return Ok(true);
             }
         }
     }
 }
 
+/// Reads config.yaml into a new worker, without any certificates.
+fn load_worker(path: &Path) -> Result<SxgWorker> {
+    let config = fs::read_to_string(path)
+        .map_err(|e| anyhow!("error reading config {}: {e}", path.display()))?;
+    SxgWorker::new(&config)
+        .map_err(|e| e.context(format!("error parsing config {}", path.display())))
+}
+
+fn load_resolver(path: &Path) -> Result<TrustDnsResolver> {
+    let config = fs::read_to_string(path)
+        .map_err(|e| anyhow!("error reading config {}: {e}", path.display()))?;
+    let config = dns::load(&config)
+        .map_err(|e| e.context(format!("error parsing dns in config {}", path.display())))?;
+    Ok(dns::resolver(&config))
+}
+
+fn load_client_auth(path: &Path) -> Result<Option<ClientAuth>> {
+    let config = fs::read_to_string(path)
+        .map_err(|e| anyhow!("error reading config {}: {e}", path.display()))?;
+    client_auth::load(&config).map_err(|e| {
+        e.context(format!(
+            "error parsing client_auth in config {}",
+            path.display()
+        ))
+    })
+}
+
+/// Reads the cert and issuer PEM files into a certificate chain.
+fn load_certificate(
+    cert: &Path,
+    issuer: &Path,
+    sct_list: Option<&Path>,
+) -> Result<CertificateChain> {
+    let read = |path: &Path| {
+        fs::read_to_string(path).map_err(|e| anyhow!("error reading {}: {e}", path.display()))
+    };
+    let certificate = CertificateChain::from_pem_files(&[&read(cert)?, &read(issuer)?])
+        .map_err(|e| e.context("error parsing --cert and --issuer"))?;
+    match sct_list {
+        Some(path) => {
+            let sct_list =
+                fs::read(path).map_err(|e| anyhow!("error reading {}: {e}", path.display()))?;
+            certificate
+                .with_sct_list(&sct_list)
+                .map_err(|e| e.context(format!("error parsing {}", path.display())))
+        }
+        None => Ok(certificate),
+    }
+}
+
+/// Adds the certificate of each of the config's `hosts`, read from its
+/// `cert_file` and `issuer_file`.
+fn add_host_certificates(worker: &mut SxgWorker) -> Result<()> {
+    for (host, host_config) in worker.config().hosts.clone() {
+        let (cert, issuer) = match (&host_config.cert_file, &host_config.issuer_file) {
+            (Some(cert), Some(issuer)) => (cert, issuer),
+            _ => {
+                return Err(anyhow!(
+                    "hosts.{host} in config needs both cert_file and issuer_file"
+                ))
+            }
+        };
+        let certificate = load_certificate(Path::new(cert), Path::new(issuer), None)
+            .map_err(|e| e.context(format!("error loading certificate of hosts.{host}")))?;
+        worker.add_certificate_for_host(&host, certificate)?;
+    }
+    Ok(())
+}
+
+/// Re-reads config.yaml and the certificates into a new worker, and swaps it
+/// in for WORKER. ACME certificates are read from storage; if there are none,
+/// --cert and --issuer are used. On error, WORKER is left unchanged.
+async fn reload_worker() -> Result<()> {
+    let mut worker = load_worker(&ARGS.config)?;
+    let runtime = sxg_rs::runtime::Runtime {
+        storage: storage(),
+        ..Default::default()
+    };
+    let acme_status = worker.add_acme_certificates_from_storage(&runtime).await;
+    if acme_status.is_err() || worker.latest_certificate_basename().is_none() {
+        worker.add_certificate(load_certificate(
+            &ARGS.cert,
+            &ARGS.issuer,
+            ARGS.sct_list.as_deref(),
+        )?)?;
+    }
+    add_host_certificates(&mut worker)?;
+    *WORKER.write().await = Arc::new(worker);
+    Ok(())
+}
+
+/// Reloads the worker on each SIGHUP, e.g. to pick up rotated certificates.
+#[cfg(unix)]
+async fn reload_on_sighup() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            println!("Unable to listen for SIGHUP: {e}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match reload_worker().await {
+            Ok(()) => println!("Reloaded config and certificates."),
+            Err(e) => println!("Reload failed; keeping the previous worker: {e:#}"),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    logging::init(ARGS.log_format);
+    lazy_static::initialize(&WORKER);
+    lazy_static::initialize(&RESOLVER);
+    lazy_static::initialize(&CLIENT_AUTH);
+    lazy_static::initialize(&KEY_SOURCE_SIGNER);
+    #[cfg(feature = "redis")]
+    lazy_static::initialize(&REDIS_STORAGE);
     let _ = fs::create_dir(&ARGS.directory);
     let addr: SocketAddr = ARGS.bind_addr.parse().expect("Could not parse ip:port.");
 
@@ -567,28 +1472,55 @@ async fn main() {
                 );
             }
             let mut worker = WORKER.write().await;
-            worker.add_certificate(
-                CertificateChain::from_pem_files(&[
-                    &fs::read_to_string(&ARGS.cert).unwrap(),
-                    &fs::read_to_string(&ARGS.issuer).unwrap(),
-                ])
-                .unwrap(),
-            );
+            let certificate = load_certificate(&ARGS.cert, &ARGS.issuer, ARGS.sct_list.as_deref())
+                .and_then(|certificate| Arc::make_mut(&mut *worker).add_certificate(certificate));
+            if let Err(e) = certificate {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+        }
+        if let Err(e) = add_host_certificates(Arc::make_mut(&mut *WORKER.write().await)) {
+            eprintln!("{e:#}");
+            std::process::exit(1);
         }
 
         println!("Worker initialized; now able to generate SXGs.");
+
+        if !ARGS.no_ocsp_prefetch {
+            tokio::spawn(prefetch_ocsp_periodically());
+        }
     });
 
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup());
+
+    if let Some(metrics_addr) = &ARGS.metrics_addr {
+        match metrics::bind(&*METRICS, metrics_addr) {
+            Ok((metrics_addr, metrics_server)) => {
+                println!("Serving metrics on http://{metrics_addr}/metrics");
+                tokio::spawn(metrics_server);
+            }
+            Err(e) => {
+                eprintln!("Could not bind --metrics-addr: {e:#}");
+                std::process::exit(1);
+            }
+        }
+    }
+
     let make_svc = make_service_fn(|conn: &AddrStream| {
         let remote_addr = conn.remote_addr().ip();
         async move { Ok::<_, http::Error>(service_fn(move |req| handle_or_error(remote_addr, req))) }
     });
 
-    let server = Server::bind(&addr).serve(make_svc);
+    let (shutdown_signal, shutdown_started) = shutdown::notify_on(shutdown::signal());
+    let server = Server::bind(&addr)
+        .serve(make_svc)
+        .with_graceful_shutdown(shutdown_signal);
 
     println!("Listening on http://{}", addr);
 
-    if let Err(e) = server.await {
+    let timeout = Duration::from_secs(ARGS.shutdown_timeout_seconds);
+    if let Err(e) = shutdown::drain(server, shutdown_started, timeout).await {
         println!("server error: {}", e);
     }
 }
@@ -599,6 +1531,642 @@ mod tests {
     use assert_matches::assert_matches;
     use hyper::{body::Bytes, Body, Response};
 
+    fn new_worker(fallback_to_unsigned: bool) -> SxgWorker {
+        SxgWorker::new(&format!(
+            r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+fallback_to_unsigned: {fallback_to_unsigned}
+forward_request_headers: []
+html_host: example.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+            "#
+        ))
+        .unwrap()
+    }
+    fn upstream_payload() -> Arc<HttpResponse> {
+        Arc::new(HttpResponse {
+            body: b"unsigned body".to_vec(),
+            headers: vec![("content-type".into(), "text/plain".into())],
+            status: 200,
+        })
+    }
+    lazy_static::lazy_static! {
+        // The runtime of the tests through `handle`. It outlives each test,
+        // so that the connections of PROXY_CLIENT to the backend stay usable.
+        static ref RUNTIME: tokio::runtime::Runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // Held by each test through `handle`, since they replace WORKER.
+        static ref WORKER_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+
+        // Notified when the backend gets a request for /subresource.html,
+        // which it answers once SUBRESOURCE_GATE has a permit.
+        static ref SUBRESOURCE_REQUESTED: tokio::sync::Notify = tokio::sync::Notify::new();
+        static ref SUBRESOURCE_GATE: tokio::sync::Semaphore = tokio::sync::Semaphore::new(0);
+    }
+    // The config of the workers of the tests through `handle`.
+    const HANDLE_CONFIG: &str = r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: example.org
+private_key_base64: "Q714JzYPYxqPwajTtDPs5ho+SUk3TlknvXzaqihejIo="
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+    "#;
+    // The ARGS of the tests: the backend is `serve_backend` on RUNTIME, and
+    // the config is HANDLE_CONFIG.
+    pub(super) fn args() -> Args {
+        let dir = std::env::temp_dir().join(format!("sxg-rs-handle-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config.yaml");
+        fs::write(&config, HANDLE_CONFIG).unwrap();
+        let _runtime = RUNTIME.enter();
+        let make_svc =
+            make_service_fn(|_| async { Ok::<_, http::Error>(service_fn(serve_backend)) });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let backend = format!("http://{}", server.local_addr());
+        RUNTIME.spawn(server);
+        Args::parse_from([
+            "http_server",
+            "--backend",
+            backend.as_str(),
+            "--config",
+            config.to_str().unwrap(),
+            "--directory",
+            dir.to_str().unwrap(),
+            "--max-request-body-size",
+            "64",
+        ])
+    }
+    // Serves an HTML page naming the method and path of `req`, with the
+    // User-Agent and Via that it received echoed in x-user-agent and x-via,
+    // and the size of its body in x-body-size. /preloading.html preloads
+    // /subresource.html, which is served per SUBRESOURCE_GATE.
+    async fn serve_backend(mut req: Request<Body>) -> Result<Response<Body>, http::Error> {
+        let body_size = hyper::body::to_bytes(req.body_mut()).await.unwrap().len();
+        let echo = |name| {
+            let values: Vec<_> = req.headers().get_all(name).iter().collect();
+            format!("{:?}", values)
+        };
+        let mut resp = Response::builder();
+        match req.uri().path() {
+            "/preloading.html" => {
+                resp = resp.header("link", "</subresource.html>;rel=preload;as=document");
+            }
+            "/subresource.html" => {
+                SUBRESOURCE_REQUESTED.notify_one();
+                SUBRESOURCE_GATE.acquire().await.unwrap().forget();
+            }
+            _ => (),
+        }
+        resp.header("content-type", "text/html")
+            .header("x-user-agent", echo(http::header::USER_AGENT))
+            .header("x-via", echo(http::header::VIA))
+            .header("x-body-size", body_size)
+            .body(Body::from(format!("<p>{} {}</p>", req.method(), req.uri())))
+    }
+    // Returns a worker for HANDLE_CONFIG, with SIGNING_CERT_PEM, whose key it
+    // has, after `configure` changes the config without validating it.
+    fn signing_worker(configure: impl FnOnce(&mut sxg_rs::config::Config)) -> SxgWorker {
+        let mut config = sxg_rs::config::Config::new(HANDLE_CONFIG).unwrap();
+        configure(&mut config);
+        let mut worker = SxgWorker::from_parsed(config);
+        worker
+            .add_certificate(
+                CertificateChain::from_pem_files(&[SIGNING_CERT_PEM, SIGNING_CERT_PEM]).unwrap(),
+            )
+            .unwrap();
+        worker
+    }
+    // A request for a page of the backend that prefers an SXG.
+    fn page_request() -> HttpRequest {
+        HttpRequest {
+            body: vec![],
+            headers: vec![
+                ("accept".into(), "application/signed-exchange;v=b3".into()),
+                ("host".into(), "example.org".into()),
+            ],
+            method: Method::Get,
+            url: "/page.html".into(),
+        }
+    }
+    // Returns the response of `handle` to `req` with WORKER set to `worker`,
+    // with its body buffered, plus the error for why it wasn't signed.
+    fn handle_with(worker: SxgWorker, mut req: HttpRequest) -> (Response<Bytes>, Option<String>) {
+        let body: Body = std::mem::take(&mut req.body).into();
+        RUNTIME.block_on(async {
+            let _lock = WORKER_LOCK.lock().await;
+            *WORKER.write().await = Arc::new(worker);
+            let (resp, e) = handle("127.0.0.1".parse().unwrap(), req, body).await;
+            let (parts, body) = resp.into_parts();
+            let body = hyper::body::to_bytes(body).await.unwrap();
+            (Response::from_parts(parts, body), e)
+        })
+    }
+    #[test]
+    fn reload_while_signing() {
+        let mut req = page_request();
+        req.url = "/preloading.html".into();
+        RUNTIME.block_on(async {
+            let _lock = WORKER_LOCK.lock().await;
+            *WORKER.write().await = Arc::new(signing_worker(|_| ()));
+            let signing = tokio::spawn(handle("127.0.0.1".parse().unwrap(), req, Body::empty()));
+            // The signing is fetching the preloaded subresource through
+            // SelfFetcher, which handles it with WORKER too.
+            SUBRESOURCE_REQUESTED.notified().await;
+            // A reload swaps the worker without waiting for the signing.
+            tokio::time::timeout(Duration::from_secs(5), async {
+                *WORKER.write().await = Arc::new(signing_worker(|_| ()));
+            })
+            .await
+            .unwrap();
+            SUBRESOURCE_GATE.add_permits(1);
+            let (resp, _) = tokio::time::timeout(Duration::from_secs(5), signing)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers()[http::header::CONTENT_TYPE],
+                "application/signed-exchange;v=b3"
+            );
+        });
+    }
+    // Responds like https://www.google.com does when :authority and Host differ.
+    async fn serve_h2c_checking_authority() -> SocketAddr {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, http::Error>(service_fn(|req: Request<Body>| async move {
+                let authority = req.uri().authority().map(|a| a.as_str().to_string());
+                let host = req.headers().get(http::header::HOST).cloned();
+                let status = match (authority, host) {
+                    (Some(authority), Some(host)) if host == authority.as_str() => StatusCode::OK,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+                Response::builder().status(status).body(Body::empty())
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into())
+            .http2_only(true)
+            .serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+    #[tokio::test]
+    async fn http2_backend_request_sets_matching_authority() {
+        let addr = serve_h2c_checking_authority().await;
+        let client = hyper::Client::builder()
+            .http2_only(true)
+            .build_http::<Body>();
+        let backend = format!("http://{}", addr);
+        let request = Request::builder()
+            .uri("/page.html?q=1")
+            .header("host", "public.example")
+            .body(Body::empty())
+            .unwrap();
+        let request = http2_backend_request("1.2.3.4".parse().unwrap(), &backend, request).unwrap();
+        assert_eq!(
+            request.uri().to_string(),
+            format!("{}/page.html?q=1", backend)
+        );
+        assert_eq!(request.headers()["host"], addr.to_string());
+        assert_eq!(request.headers()["x-forwarded-for"], "1.2.3.4");
+        let resp = client.request(request).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Without the rewrite, the Host header disagrees with :authority.
+        let request = Request::builder()
+            .uri(format!("{}/page.html", backend))
+            .header("host", "public.example")
+            .body(Body::empty())
+            .unwrap();
+        let resp = client.request(request).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+    #[test]
+    fn proxy_headers_append_via() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::USER_AGENT, "Mozilla/5.0".try_into().unwrap());
+        headers.insert(http::header::VIA, "1.1 cdn.example".try_into().unwrap());
+        headers.insert(SIGNED_HOST_HEADER, "blog.example.com".try_into().unwrap());
+        add_proxy_headers(&mut headers);
+        assert_eq!(headers[http::header::USER_AGENT], "Mozilla/5.0");
+        let via: Vec<_> = headers.get_all(http::header::VIA).iter().collect();
+        assert_eq!(via, ["1.1 cdn.example", VIA]);
+        assert!(!headers.contains_key(SIGNED_HOST_HEADER));
+        set_user_agent(&mut headers, &"sxg-rs/test".try_into().unwrap());
+        assert_eq!(headers[http::header::USER_AGENT], "sxg-rs/test");
+    }
+    #[test]
+    fn only_fetches_to_sign_have_configured_user_agent() {
+        // A request proxied unsigned is the client's.
+        let request = Request::builder()
+            .uri("/page.html")
+            .header("user-agent", "Mozilla/5.0")
+            .body(Body::empty())
+            .unwrap();
+        let resp = RUNTIME
+            .block_on(fetch_backend_impl("127.0.0.1".parse().unwrap(), request))
+            .unwrap();
+        assert_eq!(resp.headers()["x-user-agent"], r#"["Mozilla/5.0"]"#);
+        assert_eq!(resp.headers()["x-via"], r#"["1.1 sxg-rs"]"#);
+
+        // The fetch of a page to sign is the signer's own. Its payload is
+        // served unsigned, as the worker can't sign.
+        let worker = signing_worker(|config| config.private_key_base64 = None);
+        let mut req = page_request();
+        req.headers
+            .push(("user-agent".into(), "Mozilla/5.0".into()));
+        let (resp, _) = handle_with(worker, req);
+        assert_eq!(
+            resp.headers()["x-user-agent"],
+            format!(r#"["sxg-rs/{}"]"#, env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(resp.headers()["x-via"], r#"["1.1 sxg-rs"]"#);
+    }
+    #[tokio::test]
+    async fn backend_receives_configured_user_agent() {
+        // A backend that echoes the User-Agent and Via it received.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, http::Error>(service_fn(|req: Request<Body>| async move {
+                let header = |name| {
+                    let values: Vec<_> = req.headers().get_all(name).iter().collect();
+                    format!("{:?}", values)
+                };
+                let body = format!(
+                    "{} {}",
+                    header(http::header::USER_AGENT),
+                    header(http::header::VIA)
+                );
+                Response::builder().body(Body::from(body))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        let mut request = Request::builder()
+            .uri(format!("http://{addr}/"))
+            .header("user-agent", "Mozilla/5.0")
+            .body(Body::empty())
+            .unwrap();
+        set_user_agent(request.headers_mut(), &"my-agent/1.0".try_into().unwrap());
+        add_proxy_headers(request.headers_mut());
+        let resp = hyper::Client::new().request(request).await.unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, r#"["my-agent/1.0"] ["1.1 sxg-rs"]"#);
+    }
+    #[tokio::test]
+    async fn range_request_is_proxied_unsigned() {
+        // A backend that serves byte ranges of an HTML document.
+        const DOCUMENT: &[u8] = b"<html><head></head><body>hello</body></html>";
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, http::Error>(service_fn(|req: Request<Body>| async move {
+                let response = Response::builder().header("content-type", "text/html");
+                match req.headers().get(http::header::RANGE) {
+                    Some(range) if range == "bytes=6-11" => response
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .header(
+                            http::header::CONTENT_RANGE,
+                            format!("bytes 6-11/{}", DOCUMENT.len()),
+                        )
+                        .body(Body::from(&DOCUMENT[6..12])),
+                    _ => response.body(Body::from(DOCUMENT)),
+                }
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        // The request headers that proxy_unsigned forwards to the backend.
+        let req = HttpRequest {
+            body: vec![],
+            headers: vec![("range".into(), "bytes=6-11".into())],
+            method: Method::Get,
+            url: format!("http://{addr}/"),
+        };
+        let req: Request<Vec<u8>> = req.try_into().unwrap();
+        let payload = hyper::Client::new()
+            .request(req.map(Body::from))
+            .await
+            .unwrap();
+        let mut resp = unsigned_payload(&new_worker(true), payload).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            resp.headers()[http::header::CONTENT_RANGE],
+            format!("bytes 6-11/{}", DOCUMENT.len()).as_str()
+        );
+        assert_eq!(
+            hyper::body::to_bytes(resp.body_mut()).await.unwrap(),
+            &b"<head>"[..]
+        );
+    }
+    #[test]
+    fn default_user_agent_has_version() {
+        let args = Args::parse_from(["http_server", "--backend", "https://backend"]);
+        assert_eq!(
+            args.user_agent,
+            format!("sxg-rs/{}", env!("CARGO_PKG_VERSION")).as_str()
+        );
+    }
+    #[tokio::test]
+    async fn backend_timeout_responds_504() {
+        // A backend that takes far longer than the timeout to respond.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, http::Error>(service_fn(|_: Request<Body>| async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                Response::builder().body(Body::empty())
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        let client = hyper::Client::new();
+        let start = Instant::now();
+        let err = with_timeout(Duration::from_millis(100), async {
+            Ok(client.get(format!("http://{addr}/").parse()?).await?)
+        })
+        .await
+        .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(err.is::<BackendTimeout>());
+        let resp = backend_error_body(err, false);
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            resp.headers()["sxg-rs-error"],
+            "The backend didn't respond within 100ms."
+        );
+        let resp = backend_error_body(anyhow!("Connection refused"), false);
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    }
+    #[test]
+    fn unsigned_fallback_on_signer_error() {
+        let worker = signing_worker(|config| config.private_key_base64 = None);
+        let (resp, e) = handle_with(worker, page_request());
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "text/html");
+        assert_eq!(resp.body(), "<p>GET /page.html</p>");
+        assert_eq!(e.as_deref(), Some("Config private_key_base64 is not set"));
+    }
+    #[test]
+    fn unsigned_fallback_on_mice_error() {
+        // Bypasses the validation of the config, which rejects this size.
+        let worker = signing_worker(|config| config.mice_record_size = Some(3));
+        let (resp, e) = handle_with(worker, page_request());
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "text/html");
+        assert_eq!(resp.body(), "<p>GET /page.html</p>");
+        assert_eq!(
+            e.as_deref(),
+            Some("The MICE record size 3 is not a power of two.")
+        );
+    }
+    #[test]
+    fn post_is_proxied_unsigned_and_get_is_signed() {
+        let (resp, e) = handle_with(signing_worker(|_| ()), page_request());
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()["content-type"],
+            "application/signed-exchange;v=b3"
+        );
+        assert_eq!(e, None);
+        let payload = SxgWorker::unwrap_sxg(resp.body()).unwrap();
+        assert_eq!(payload.body, b"<p>GET /page.html</p>");
+
+        // The same request as a POST, which is never signed.
+        let post = HttpRequest {
+            body: b"q=1".to_vec(),
+            method: Method::Post,
+            ..page_request()
+        };
+        let (resp, e) = handle_with(signing_worker(|_| ()), post);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "text/html");
+        assert_eq!(resp.body(), "<p>POST /page.html</p>");
+        assert_eq!(e, None);
+    }
+    #[test]
+    fn only_bodies_to_sign_are_limited() {
+        // Beyond the --max-request-body-size of ARGS.
+        let body = vec![b'a'; 100];
+        let post = HttpRequest {
+            body: body.clone(),
+            method: Method::Post,
+            ..page_request()
+        };
+        let (resp, e) = handle_with(signing_worker(|_| ()), post);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["x-body-size"], "100");
+        assert_eq!(e, None);
+
+        let get = HttpRequest {
+            body,
+            ..page_request()
+        };
+        let (resp, e) = handle_with(signing_worker(|_| ()), get);
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            resp.headers()["sxg-rs-error"],
+            "The body exceeds the limit of 64 bytes."
+        );
+        assert_eq!(e, None);
+    }
+    #[tokio::test]
+    async fn unsigned_fallback_of_multiple_mice_records() {
+        let worker = new_worker(true);
+        // Spans several records of the default MICE record size.
+        let body: Vec<u8> = (0..=u8::MAX).cycle().take(3 * 16384 + 1).collect();
+        let payload = Arc::new(HttpResponse {
+            body: body.clone(),
+            headers: vec![("content-type".into(), "application/octet-stream".into())],
+            status: 200,
+        });
+        let error = anyhow!("Failed to create SXG.");
+        let (mut resp, e) = unsigned_fallback(&worker, payload, error, false);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[http::header::CONTENT_TYPE],
+            "application/octet-stream"
+        );
+        assert_eq!(hyper::body::to_bytes(resp.body_mut()).await.unwrap(), body);
+        assert!(e.is_some());
+    }
+    #[tokio::test]
+    async fn backend_error_is_proxied_unsigned() {
+        let worker = new_worker(true);
+        let payload = |status: u16| {
+            Response::builder()
+                .status(status)
+                .header("content-type", "text/plain")
+                .body(b"backend body".to_vec())
+                .unwrap()
+        };
+        let action = |status| {
+            sign_or_respond(
+                &worker,
+                "example.com".into(),
+                "https://example.com/".into(),
+                payload(status),
+            )
+            .unwrap()
+        };
+        match action(500) {
+            HandleAction::Respond(mut resp) => {
+                assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+                assert_eq!(
+                    hyper::body::to_bytes(resp.body_mut()).await.unwrap(),
+                    &b"backend body"[..]
+                );
+            }
+            _ => panic!("A 500 response should be proxied unsigned"),
+        }
+        assert!(matches!(
+            action(200),
+            HandleAction::Sign { payload, .. } if payload.status == 200
+        ));
+        assert!(matches!(action(404), HandleAction::Sign { .. }));
+    }
+    #[tokio::test]
+    async fn unsignable_content_type_is_proxied_unsigned() {
+        let worker = SxgWorker::new(
+            r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+fallback_to_unsigned: false
+forward_request_headers: []
+html_host: example.com
+reserved_path: ".sxg"
+signable_content_types: ["text/html"]
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+            "#,
+        )
+        .unwrap();
+        let payload = Response::builder()
+            .header("content-type", "application/pdf")
+            .body(b"%PDF-1.7".to_vec())
+            .unwrap();
+        let action = sign_or_respond(
+            &worker,
+            "example.com".into(),
+            "https://example.com/".into(),
+            payload,
+        )
+        .unwrap();
+        match action {
+            // Served as is, even without the fallback to unsigned, which is
+            // only for signing failures.
+            HandleAction::Respond(mut resp) => {
+                assert_eq!(resp.status(), StatusCode::OK);
+                assert_eq!(resp.headers()["content-type"], "application/pdf");
+                assert!(!resp.headers().contains_key("sxg-rs-error"));
+                assert_eq!(
+                    hyper::body::to_bytes(resp.body_mut()).await.unwrap(),
+                    &b"%PDF-1.7"[..]
+                );
+            }
+            _ => panic!("An unsignable content-type should be proxied unsigned"),
+        }
+    }
+    #[tokio::test]
+    async fn unsigned_fallback_beyond_signing_limit() {
+        let worker = new_worker(true);
+        let limiter = limiter::SigningLimiter::new(
+            prometheus::IntGauge::new("signings_in_flight", "help").unwrap(),
+        );
+        let (tx, rx) = tokio::sync::watch::channel(false);
+        let worker = &worker;
+        let sign = &|| {
+            let mut rx = rx.clone();
+            limiter.run(Some(2), async move {
+                while !*rx.borrow() {
+                    rx.changed().await?;
+                }
+                Ok(Response::new(Body::from("signed body")))
+            })
+        };
+        let requests = futures::future::join_all((0..5).map(move |_| async move {
+            match sign().await {
+                Ok(resp) => (resp, None),
+                Err(e) => unsigned_fallback(worker, upstream_payload(), e, false),
+            }
+        }));
+        let release = async {
+            tokio::task::yield_now().await;
+            assert_eq!(limiter.depth(), 2);
+            tx.send(true).unwrap();
+        };
+        let (responses, ()) = tokio::join!(requests, release);
+        let mut bodies = vec![];
+        for (mut resp, e) in responses {
+            assert_eq!(resp.status(), StatusCode::OK);
+            bodies.push((hyper::body::to_bytes(resp.body_mut()).await.unwrap(), e));
+        }
+        let signed = bodies.iter().filter(|(b, _)| b == &b"signed body"[..]);
+        assert_eq!(signed.count(), 2);
+        let unsigned: Vec<_> = bodies
+            .iter()
+            .filter(|(b, _)| b == &b"unsigned body"[..])
+            .collect();
+        assert_eq!(unsigned.len(), 3);
+        for (_, e) in unsigned {
+            assert_eq!(
+                e.as_deref(),
+                Some("Too many concurrent SXG signings; the limit is 2.")
+            );
+        }
+        assert_eq!(limiter.depth(), 0);
+    }
+    #[test]
+    fn unsigned_fallback_disabled() {
+        let worker = new_worker(false);
+        let (resp, e) = unsigned_fallback(&worker, upstream_payload(), anyhow!("error"), false);
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(resp.headers()["sxg-rs-error"], "error");
+        assert!(e.is_none());
+    }
+    #[tokio::test]
+    async fn error_body_puts_summary_in_header() {
+        let error = anyhow!("backend said:\n<html>oops</html>").context("Failed to fetch");
+        let resp = error_body(&error, false);
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(resp.headers()["sxg-rs-error"], "Failed to fetch");
+        assert!(hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+    #[tokio::test]
+    async fn error_body_verbose() {
+        let error = anyhow!("backend said:\n<html>oops</html>").context("Failed to fetch");
+        let resp = error_body(&error, true);
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(resp.headers()["sxg-rs-error"], "Failed to fetch");
+        assert_eq!(
+            hyper::body::to_bytes(resp.into_body()).await.unwrap(),
+            "Failed to fetch: backend said:\n<html>oops</html>"
+        );
+    }
+    #[test]
+    fn error_summary_is_valid_header() {
+        assert_eq!(
+            error_summary("bad\tbyte \u{7f}é\nsecond line"),
+            "bad?byte ??"
+        );
+        assert_eq!(error_summary("").len(), 0);
+        assert_eq!(error_summary("x".repeat(1000)).len(), 256);
+    }
+    #[tokio::test]
+    async fn wraps_tls_for_http1_and_http2() {
+        for http2 in [false, true] {
+            let mut http = TrustDnsResolver::default().into_http_connector();
+            http.enforce_http(false);
+            assert!(wrap_tls(http, http2, None).is_ok());
+        }
+    }
     #[tokio::test]
     async fn resp_to_vec_body_one_chunk() {
         let (sender, body) = Body::channel();
@@ -609,7 +2177,7 @@ mod tests {
 
         let resp = Response::new(body);
         assert_matches!(
-            resp_to_vec_body(resp).await,
+            resp_to_vec_body(resp, MAX_PAYLOAD_SIZE).await,
             Ok(Payload::InMemory(r)) if r.body() == b"hello");
         handler.await.unwrap();
     }
@@ -624,7 +2192,7 @@ mod tests {
 
         let resp = Response::new(body);
         assert_matches!(
-            resp_to_vec_body(resp).await,
+            resp_to_vec_body(resp, MAX_PAYLOAD_SIZE).await,
             Ok(Payload::InMemory(r)) if r.body() == b"hellobye");
         handler.await.unwrap();
     }
@@ -643,7 +2211,7 @@ mod tests {
 
         let resp = Response::new(body);
         assert_matches!(
-            resp_to_vec_body(resp).await,
+            resp_to_vec_body(resp, MAX_PAYLOAD_SIZE).await,
             Ok(Payload::InMemory(r)) if r.body().len() == MAX_PAYLOAD_SIZE);
         handler.await.unwrap();
     }
@@ -659,7 +2227,7 @@ mod tests {
         });
 
         let resp = Response::new(body);
-        match resp_to_vec_body(resp).await {
+        match resp_to_vec_body(resp, MAX_PAYLOAD_SIZE).await {
             Ok(Payload::Streamed(mut r)) => {
                 assert_eq!(
                     hyper::body::to_bytes(r.body_mut()).await.unwrap().len(),
@@ -670,4 +2238,439 @@ mod tests {
         }
         handler.await.unwrap();
     }
+    #[tokio::test]
+    async fn resp_to_vec_body_oversized_content_length() {
+        // The body never completes, so reading it would hang.
+        let (_sender, body) = Body::channel();
+        let resp = Response::builder()
+            .header("content-length", MAX_PAYLOAD_SIZE + 1)
+            .body(body)
+            .unwrap();
+        let payload = tokio::time::timeout(
+            Duration::from_secs(5),
+            resp_to_vec_body(resp, MAX_PAYLOAD_SIZE),
+        )
+        .await
+        .unwrap();
+        assert_matches!(payload, Ok(Payload::Streamed(_)));
+    }
+    #[tokio::test]
+    async fn req_to_vec_body_within_max() {
+        let req = Request::new(Body::from("hello"));
+        assert_eq!(req_to_vec_body(req, 5).await.unwrap().body(), b"hello");
+    }
+    #[tokio::test]
+    async fn req_to_vec_body_oversized_content_length() {
+        let (_sender, body) = Body::channel();
+        let req = Request::builder()
+            .header("content-length", "6")
+            .body(body)
+            .unwrap();
+        let err = tokio::time::timeout(Duration::from_secs(5), req_to_vec_body(req, 5))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(err.is::<BodyTooLarge>());
+    }
+    #[tokio::test]
+    async fn req_to_vec_body_chunked_over_max() {
+        let consumed = Arc::new(AtomicUsize::new(0));
+        let counter = consumed.clone();
+        let body = Body::wrap_stream(stream::iter(0..10).map(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, std::io::Error>(Bytes::from_static(b"abc"))
+        }));
+        let err = req_to_vec_body(Request::new(body), 5).await.unwrap_err();
+        assert!(err.is::<BodyTooLarge>());
+        assert_eq!(err.to_string(), "The body exceeds the limit of 5 bytes.");
+        // Stopped at the second chunk, which crossed the limit.
+        assert_eq!(consumed.load(Ordering::SeqCst), 2);
+    }
+    #[test]
+    fn load_worker_from_path() {
+        let dir = std::env::temp_dir().join(format!("sxg-rs-load-worker-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let missing = load_worker(&path).err().unwrap();
+        assert!(format!("{missing:#}").contains("config.yaml"));
+        fs::write(&path, "html_host: [").unwrap();
+        let malformed = load_worker(&path).err().unwrap();
+        assert!(format!("{malformed:#}").starts_with("error parsing config"));
+        fs::write(
+            &path,
+            r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: example.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+            "#,
+        )
+        .unwrap();
+        let worker = load_worker(&path).unwrap();
+        assert_eq!(worker.config().html_host, "example.com");
+        assert!(worker.latest_certificate_basename().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn client_ip_from_forwarded_for() {
+        let remote_ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let headers = |values: &[&str]| {
+            let mut headers = http::HeaderMap::new();
+            for value in values {
+                headers.append("x-forwarded-for", value.parse().unwrap());
+            }
+            headers
+        };
+        let forwarded = headers(&["6.6.6.6", "1.2.3.4, 5.6.7.8"]);
+        assert_eq!(client_ip(remote_ip, &forwarded, false), remote_ip);
+        assert_eq!(
+            client_ip(remote_ip, &forwarded, true),
+            "5.6.7.8".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(client_ip(remote_ip, &headers(&[]), true), remote_ip);
+        assert_eq!(
+            client_ip(remote_ip, &headers(&["1.2.3.4:5678"]), true),
+            "1.2.3.4".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            client_ip(remote_ip, &headers(&["[2001:db8::1]:80"]), true),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            client_ip(remote_ip, &headers(&["1.2.3.4, unknown"]), true),
+            remote_ip
+        );
+    }
+    #[test]
+    fn strips_untrusted_signed_host() {
+        let stripped = |remote_ip: &str, sources: &[IpAddr]| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert(SIGNED_HOST_HEADER, "blog.example.com".parse().unwrap());
+            strip_untrusted_signed_host(remote_ip.parse().unwrap(), &mut headers, sources);
+            !headers.contains_key(SIGNED_HOST_HEADER)
+        };
+        let router: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(!stripped("127.0.0.1", &[]));
+        assert!(!stripped("::1", &[]));
+        assert!(stripped("10.0.0.1", &[]));
+        assert!(!stripped("10.0.0.1", &[router]));
+        assert!(stripped("10.0.0.2", &[router]));
+        assert!(stripped("127.0.0.1", &[router]));
+    }
+    #[test]
+    fn add_host_certificates_from_files() {
+        let dir = std::env::temp_dir().join(format!("sxg-rs-host-certs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let cert = dir.join("cert.pem");
+        fs::write(&cert, OCSP_CERT_PEM).unwrap();
+        let config = |issuer: &str| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+hosts:
+  blog.example.com:
+    cert_file: "{}"
+    {issuer}
+html_host: example.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#,
+                cert.display()
+            )
+        };
+        let mut worker = SxgWorker::new(&config("")).unwrap();
+        assert!(add_host_certificates(&mut worker).is_err());
+
+        let issuer = format!("issuer_file: \"{}\"", cert.display());
+        let mut worker = SxgWorker::new(&config(&issuer)).unwrap();
+        add_host_certificates(&mut worker).unwrap();
+        let basename = CertificateChain::from_pem_files(&[OCSP_CERT_PEM, OCSP_CERT_PEM])
+            .unwrap()
+            .basename;
+        assert_eq!(
+            worker
+                .for_host("blog.example.com")
+                .latest_certificate_basename(),
+            Some(basename.as_str())
+        );
+        assert!(worker
+            .for_host("example.com")
+            .latest_certificate_basename()
+            .is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn file_storage_persists_across_restart() {
+        let dir = std::env::temp_dir().join(format!("sxg-rs-file-storage-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let storage = FileStorage(dir.clone());
+        assert_eq!(storage.read("OCSP").await.unwrap(), None);
+        storage.write("OCSP", "first").await.unwrap();
+        storage.write("OCSP", "second").await.unwrap();
+        // A new FileStorage over the same directory simulates a restart.
+        let storage = FileStorage(dir.clone());
+        assert_eq!(
+            storage.read("OCSP").await.unwrap().as_deref(),
+            Some("second")
+        );
+        // No temporary files are left behind.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    // Copied from sxg_rs::utils::tests; its private key is that of
+    // HANDLE_CONFIG.
+    const SIGNING_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIBsTCCAVegAwIBAgIURjAk4mIwIrynNtqMB0D31hlnvXAwCgYIKoZIzj0EAwIw
+MjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYT
+AlVTMB4XDTI2MTAxNDA1MjA1NVoXDTI3MDExMjA1MjA1NVowMjEUMBIGA1UEAwwL
+ZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYTAlVTMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEWpxFASoBrkOKVcdKTW60fF5l91wI2eodenO15EPf
+2EFIpof5jftxHxVn+LrB+pkC48BvjY4HZ6sml5Htdhr3JaNLMEkwEAYKKwYBBAHW
+eQIBFgQCBQAwFgYDVR0RBA8wDYILZXhhbXBsZS5vcmcwHQYDVR0OBBYEFBvUyZ+a
+zqqbRGOCuh+Y1wp8osr8MAoGCCqGSM49BAMCA0gAMEUCIG3/Z3UMSubVvWgJII3n
+dJGHumYz1sL0dBzX683gSnjiAiEAkOG6V6xs3CUNyTmelFSTeCnUtB587KcVzYKn
+tM4oz6g=
+-----END CERTIFICATE-----
+    ";
+    // Copied from sxg_rs::utils::tests; its AIA names http://ocsp.example.org
+    // as the OCSP responder.
+    const OCSP_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIBzzCCAXSgAwIBAgIUB4Q0H3ITrTypt0eFBiqC/1DtfdwwCgYIKoZIzj0EAwIw
+MjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYT
+AlVTMB4XDTI2MTAxNDA1MzE0N1oXDTI3MDExMjA1MzE0N1owMjEUMBIGA1UEAwwL
+ZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYTAlVTMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEWpxFASoBrkOKVcdKTW60fF5l91wI2eodenO15EPf
+2EFIpof5jftxHxVn+LrB+pkC48BvjY4HZ6sml5Htdhr3JaNoMGYwEAYKKwYBBAHW
+eQIBFgQCBQAwMwYIKwYBBQUHAQEEJzAlMCMGCCsGAQUFBzABhhdodHRwOi8vb2Nz
+cC5leGFtcGxlLm9yZzAdBgNVHQ4EFgQUG9TJn5rOqptEY4K6H5jXCnyiyvwwCgYI
+KoZIzj0EAwIDSQAwRgIhAPt5DZ88OjwWzB3shPuht8u4HJW+3PbfnKoEJul/oG7I
+AiEAllEzHsf9jHkco5Kz91seZK3JpDYTdeb8r9FHQSjbOVg=
+-----END CERTIFICATE-----
+    ";
+    // Records the URLs requested of it.
+    struct MockOcspResponder(Arc<std::sync::Mutex<Vec<String>>>);
+    #[async_trait]
+    impl Fetcher for MockOcspResponder {
+        async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
+            self.0.lock().unwrap().push(request.url);
+            Ok(HttpResponse {
+                body: b"mock ocsp".to_vec(),
+                headers: vec![("content-type".into(), "application/ocsp-response".into())],
+                status: 200,
+            })
+        }
+    }
+    #[tokio::test]
+    async fn prefetch_ocsp_populates_storage() {
+        let dir = std::env::temp_dir().join(format!("sxg-rs-ocsp-prefetch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let certificate =
+            || CertificateChain::from_pem_files(&[OCSP_CERT_PEM, OCSP_CERT_PEM]).unwrap();
+        let mut worker = new_worker(false);
+        worker.add_certificate(certificate()).unwrap();
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+
+        let delay = prefetch_ocsp(
+            &worker,
+            Box::new(MockOcspResponder(requests.clone())),
+            Box::new(FileStorage(dir.clone())),
+        )
+        .await;
+        assert_eq!(*requests.lock().unwrap(), vec!["http://ocsp.example.org"]);
+        // The mock response has no nextUpdate, so it is due in a day, later
+        // than the longest wait.
+        assert_eq!(delay, OCSP_PREFETCH_MAX_DELAY);
+
+        // Before any request, the OCSP is in storage, so none is fetched.
+        let runtime = sxg_rs::runtime::Runtime {
+            now: SystemTime::now(),
+            storage: Box::new(FileStorage(dir.clone())),
+            ..Default::default()
+        };
+        assert_eq!(
+            sxg_rs::ocsp::read_unexpired_ocsp_from_storage(&certificate(), &runtime)
+                .await
+                .as_deref(),
+            Some(&b"mock ocsp"[..])
+        );
+        prefetch_ocsp(
+            &worker,
+            Box::new(MockOcspResponder(requests.clone())),
+            Box::new(FileStorage(dir.clone())),
+        )
+        .await;
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn health_check_of_ocsp_freshness() {
+        let mut worker = new_worker(false);
+        worker
+            .add_certificate(
+                CertificateChain::from_pem_files(&[OCSP_CERT_PEM, OCSP_CERT_PEM]).unwrap(),
+            )
+            .unwrap();
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut runtime = sxg_rs::runtime::Runtime {
+            // 2026-11-01, before the certificate expires.
+            now: std::time::UNIX_EPOCH + Duration::from_secs(1793491200),
+            fetcher: Box::new(MockOcspResponder(requests.clone())),
+            ..Default::default()
+        };
+        let health = |resp: Response<Body>| async move {
+            let status = resp.status();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        };
+        let (status, body) = health(health_response(&worker, &runtime).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("missing or expired"), "{body}");
+        // The health check doesn't fetch OCSP itself.
+        assert!(requests.lock().unwrap().is_empty());
+
+        worker.update_oscp_in_storage(&runtime).await.unwrap();
+        let (status, body) = health(health_response(&worker, &runtime).await).await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "OK\n"));
+
+        // The mock response has no nextUpdate, so it expires in 6 days.
+        runtime.now += Duration::from_secs(3600 * 24 * 7);
+        let (status, body) = health(health_response(&worker, &runtime).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("missing or expired"), "{body}");
+    }
+    #[tokio::test]
+    async fn prefetch_ocsp_retries_on_failure() {
+        let dir = std::env::temp_dir().join(format!("sxg-rs-ocsp-retry-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let mut worker = new_worker(false);
+        worker
+            .add_certificate(
+                CertificateChain::from_pem_files(&[OCSP_CERT_PEM, OCSP_CERT_PEM]).unwrap(),
+            )
+            .unwrap();
+        let delay = prefetch_ocsp(
+            &worker,
+            Box::new(sxg_rs::fetcher::NULL_FETCHER),
+            Box::new(FileStorage(dir.clone())),
+        )
+        .await;
+        assert_eq!(delay, OCSP_PREFETCH_RETRY_DELAY);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn signs_chunked_backend_response() {
+        const CHUNKS: [&str; 3] = ["<h1>Chunked</h1>", "<p>without a ", "Content-Length</p>"];
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, http::Error>(service_fn(|_: Request<Body>| async {
+                // A stream of unknown length is sent chunked.
+                let body = Body::wrap_stream(stream::iter(CHUNKS).map(Ok::<_, std::io::Error>));
+                Response::builder()
+                    .header("content-type", "text/html")
+                    .body(body)
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        let resp = hyper::Client::new()
+            .get(format!("http://{addr}/").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["transfer-encoding"], "chunked");
+        assert!(!resp.headers().contains_key("content-length"));
+
+        let payload = match resp_to_vec_body(resp, MAX_PAYLOAD_SIZE).await.unwrap() {
+            Payload::InMemory(payload) => payload,
+            Payload::Streamed(_) => panic!("the chunked body was not buffered"),
+        };
+        assert_eq!(payload.body(), CHUNKS.concat().as_bytes());
+        assert!(!payload.headers().contains_key("transfer-encoding"));
+        let payload: HttpResponse = payload.try_into().unwrap();
+
+        let mut worker = new_worker(true);
+        worker
+            .add_certificate(
+                CertificateChain::from_pem_files(&[OCSP_CERT_PEM, OCSP_CERT_PEM]).unwrap(),
+            )
+            .unwrap();
+        let runtime = sxg_rs::runtime::Runtime {
+            now: SystemTime::now(),
+            sxg_signer: Box::new(sxg_rs::signature::mock_signer::MockSigner),
+            ..Default::default()
+        };
+        let sxg = worker
+            .create_signed_exchange(
+                &runtime,
+                sxg_rs::CreateSignedExchangeParams {
+                    fallback_url: "https://example.com/",
+                    cert_origin: "https://example.com",
+                    payload_body: &payload.body,
+                    payload_headers: worker
+                        .transform_payload_headers(payload.headers.clone())
+                        .unwrap(),
+                    skip_process_link: false,
+                    status_code: payload.status,
+                    header_integrity_cache: sxg_rs::http_cache::NullCache {},
+                    mice_record_size: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert!(!sxg
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("transfer-encoding")));
+        let parts = sxg_rs::sxg::parse(&sxg.body).unwrap();
+        let signed_headers = sxg_rs::sxg::parse_signed_headers(parts.signed_headers).unwrap();
+        assert!(!signed_headers.contains_key(&b"transfer-encoding"[..]));
+        assert_eq!(
+            signed_headers.get(&b"content-length"[..]),
+            Some(&parts.payload_body.len().to_string().as_bytes())
+        );
+        assert_eq!(
+            SxgWorker::unwrap_sxg(&sxg.body).unwrap().body,
+            CHUNKS.concat().as_bytes()
+        );
+    }
+    #[tokio::test]
+    async fn resp_to_vec_body_large_is_bounded() {
+        const CHUNK_SIZE: usize = 1_000_000;
+        const CHUNK_COUNT: usize = 20;
+        let consumed = Arc::new(AtomicUsize::new(0));
+        let counter = consumed.clone();
+        let body = Body::wrap_stream(stream::iter(0..CHUNK_COUNT).map(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, std::io::Error>(Bytes::from(vec![0; CHUNK_SIZE]))
+        }));
+
+        let resp = Response::new(body);
+        match resp_to_vec_body(resp, MAX_PAYLOAD_SIZE).await {
+            Ok(Payload::Streamed(mut r)) => {
+                // Only the chunks up to and including the one that crossed
+                // the limit were read before deciding to stream.
+                assert_eq!(
+                    consumed.load(Ordering::SeqCst),
+                    MAX_PAYLOAD_SIZE / CHUNK_SIZE + 1
+                );
+                assert_eq!(
+                    hyper::body::to_bytes(r.body_mut()).await.unwrap().len(),
+                    CHUNK_SIZE * CHUNK_COUNT
+                );
+            }
+            _ => panic!("resp does not match Payload::Streamed"),
+        }
+    }
 }
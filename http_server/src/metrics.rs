@@ -0,0 +1,230 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prometheus metrics, served at `/metrics` on a separate admin address.
+
+use anyhow::Result;
+use hyper::{
+    server::{conn::AddrStream, Server},
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, StatusCode,
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+pub struct Metrics {
+    registry: Registry,
+    sxg_generation: IntCounterVec,
+    sxg_generation_duration: HistogramVec,
+    unsigned_fallback: IntCounter,
+    ocsp_cache: IntCounterVec,
+    ocsp_fetch: IntCounterVec,
+    backend_fetch_duration: HistogramVec,
+    signings_in_flight: IntGauge,
+}
+
+/// Returns the value of the `outcome` label for the result.
+pub fn outcome<T, E>(result: &Result<T, E>) -> &'static str {
+    match result {
+        Ok(_) => "success",
+        Err(_) => "error",
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let sxg_generation = IntCounterVec::new(
+            Opts::new("sxg_generation_total", "Number of SXGs generated."),
+            &["outcome"],
+        )?;
+        let sxg_generation_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "sxg_generation_duration_seconds",
+                "Time to generate an SXG, including subresource fetches.",
+            ),
+            &["outcome"],
+        )?;
+        let unsigned_fallback = IntCounter::new(
+            "sxg_unsigned_fallback_total",
+            "Number of unsigned responses served because SXG generation failed.",
+        )?;
+        let ocsp_cache = IntCounterVec::new(
+            Opts::new(
+                "ocsp_cache_total",
+                "Number of cert-chain requests served from the OCSP in storage (hit) or \
+                 requiring a fetch from the CA (miss).",
+            ),
+            &["result"],
+        )?;
+        let ocsp_fetch = IntCounterVec::new(
+            Opts::new("ocsp_fetch_total", "Number of OCSP fetches from the CA."),
+            &["outcome"],
+        )?;
+        let backend_fetch_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "backend_fetch_duration_seconds",
+                "Time until the backend responds with headers.",
+            ),
+            &["outcome"],
+        )?;
+        let signings_in_flight = IntGauge::new(
+            "sxg_signings_in_flight",
+            "Number of SXG signings in progress, as limited by max_concurrent_signings.",
+        )?;
+        registry.register(Box::new(sxg_generation.clone()))?;
+        registry.register(Box::new(sxg_generation_duration.clone()))?;
+        registry.register(Box::new(unsigned_fallback.clone()))?;
+        registry.register(Box::new(ocsp_cache.clone()))?;
+        registry.register(Box::new(ocsp_fetch.clone()))?;
+        registry.register(Box::new(backend_fetch_duration.clone()))?;
+        registry.register(Box::new(signings_in_flight.clone()))?;
+        Ok(Metrics {
+            registry,
+            sxg_generation,
+            sxg_generation_duration,
+            unsigned_fallback,
+            ocsp_cache,
+            ocsp_fetch,
+            backend_fetch_duration,
+            signings_in_flight,
+        })
+    }
+    pub fn observe_sxg_generation(&self, outcome: &str, duration: Duration) {
+        self.sxg_generation.with_label_values(&[outcome]).inc();
+        self.sxg_generation_duration
+            .with_label_values(&[outcome])
+            .observe(duration.as_secs_f64());
+    }
+    pub fn inc_unsigned_fallback(&self) {
+        self.unsigned_fallback.inc();
+    }
+    pub fn observe_ocsp_cache(&self, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.ocsp_cache.with_label_values(&[result]).inc();
+    }
+    pub fn inc_ocsp_fetch(&self, outcome: &str) {
+        self.ocsp_fetch.with_label_values(&[outcome]).inc();
+    }
+    pub fn observe_backend_fetch(&self, outcome: &str, duration: Duration) {
+        self.backend_fetch_duration
+            .with_label_values(&[outcome])
+            .observe(duration.as_secs_f64());
+    }
+    /// Returns the gauge to be kept up to date by the signing limiter.
+    pub fn signings_in_flight(&self) -> IntGauge {
+        self.signings_in_flight.clone()
+    }
+    /// Returns the metrics in the Prometheus text format.
+    pub fn render(&self) -> Result<Vec<u8>> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+async fn handle(metrics: &Metrics, req: Request<Body>) -> Result<Response<Body>, http::Error> {
+    if req.uri().path() != "/metrics" {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty());
+    }
+    match metrics.render() {
+        Ok(body) => Response::builder()
+            .header(http::header::CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(body)),
+        Err(e) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(format!("{e}"))),
+    }
+}
+
+/// Binds the metrics endpoint to `addr`. Returns the bound address, and the
+/// server future to be spawned.
+pub fn bind(
+    metrics: &'static Metrics,
+    addr: &SocketAddr,
+) -> Result<(SocketAddr, impl Future<Output = hyper::Result<()>>)> {
+    let make_svc = make_service_fn(move |_conn: &AddrStream| async move {
+        Ok::<_, http::Error>(service_fn(move |req| handle(metrics, req)))
+    });
+    let server = Server::try_bind(addr)?.serve(make_svc);
+    Ok((server.local_addr(), server))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::client::HttpConnector;
+    async fn scrape(client: &hyper::Client<HttpConnector>, addr: SocketAddr) -> String {
+        let resp = client
+            .get(format!("http://{addr}/metrics").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+    #[tokio::test]
+    async fn scrapes_metrics() {
+        let metrics: &'static Metrics = Box::leak(Box::new(Metrics::new().unwrap()));
+        let (addr, server) = bind(metrics, &([127, 0, 0, 1], 0).into()).unwrap();
+        tokio::spawn(server);
+        let client = hyper::Client::new();
+
+        let before = scrape(&client, addr).await;
+        assert!(!before.contains(r#"sxg_generation_total{outcome="success"}"#));
+        assert!(before.contains("sxg_unsigned_fallback_total 0"));
+
+        let ms = Duration::from_millis(1);
+        metrics.observe_sxg_generation(outcome::<(), ()>(&Ok(())), ms);
+        metrics.observe_sxg_generation(outcome::<(), ()>(&Ok(())), ms);
+        metrics.observe_sxg_generation(outcome::<(), ()>(&Err(())), ms);
+        metrics.inc_unsigned_fallback();
+        metrics.observe_ocsp_cache(true);
+        metrics.observe_ocsp_cache(false);
+        metrics.inc_ocsp_fetch("error");
+        metrics.observe_backend_fetch("success", ms);
+        metrics.signings_in_flight().inc();
+
+        let after = scrape(&client, addr).await;
+        for line in [
+            r#"sxg_generation_total{outcome="success"} 2"#,
+            r#"sxg_generation_total{outcome="error"} 1"#,
+            r#"sxg_generation_duration_seconds_count{outcome="success"} 2"#,
+            "sxg_unsigned_fallback_total 1",
+            r#"ocsp_cache_total{result="hit"} 1"#,
+            r#"ocsp_cache_total{result="miss"} 1"#,
+            r#"ocsp_fetch_total{outcome="error"} 1"#,
+            r#"backend_fetch_duration_seconds_count{outcome="success"} 1"#,
+            "sxg_signings_in_flight 1",
+        ] {
+            assert!(
+                after.lines().any(|l| l == line),
+                "missing {line} in {after}"
+            );
+        }
+
+        let resp = client
+            .get(format!("http://{addr}/other").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
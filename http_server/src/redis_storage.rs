@@ -0,0 +1,262 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stores OCSP responses and ACME state in Redis, so that the replicas of
+//! http_server share them, and a CA is asked for each OCSP response once
+//! rather than once per replica.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use redis::aio::MultiplexedConnection;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sxg_rs::storage::Storage;
+use tokio::sync::Mutex;
+
+// The longest wait for connecting to Redis, or for the reply to a command.
+const TIMEOUT: Duration = Duration::from_secs(1);
+// After a failure, Redis is skipped for this long before connecting again, so
+// that requests don't each wait on an unreachable server.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Stores each value as a Redis string under its key. If Redis can't be
+/// reached, the error is logged, and the storage behaves as if it were empty
+/// and drops writes, so that OCSP responses are fetched from the CA instead of
+/// failing the request.
+#[derive(Clone)]
+pub struct RedisStorage {
+    client: redis::Client,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    connection: Option<MultiplexedConnection>,
+    // While set and in the future, Redis is skipped.
+    retry_at: Option<Instant>,
+}
+
+impl RedisStorage {
+    /// `url` is like `redis://host:port/db`. The connection is made on first
+    /// use, so that http_server starts while Redis is down.
+    pub fn new(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).map_err(|e| anyhow!("invalid Redis URL: {e}"))?;
+        Ok(RedisStorage {
+            client,
+            state: Arc::new(Mutex::new(State::default())),
+        })
+    }
+    // Returns the connection, connecting if needed, or None if Redis is
+    // skipped after a failure.
+    async fn connection(&self) -> Option<MultiplexedConnection> {
+        let mut state = self.state.lock().await;
+        if state.connection.is_none() {
+            if matches!(state.retry_at, Some(retry_at) if Instant::now() < retry_at) {
+                return None;
+            }
+            match with_timeout(self.client.get_multiplexed_tokio_connection()).await {
+                Ok(connection) => state.connection = Some(connection),
+                Err(e) => {
+                    println!("Unable to connect to Redis; retrying in {RETRY_DELAY:?}: {e:#}");
+                    state.retry_at = Some(Instant::now() + RETRY_DELAY);
+                    return None;
+                }
+            }
+        }
+        state.connection.clone()
+    }
+    // Sends `cmd`, and returns its reply, or None if it fails.
+    async fn query<T: redis::FromRedisValue + Send>(&self, cmd: redis::Cmd) -> Option<T> {
+        let mut connection = self.connection().await?;
+        match with_timeout(cmd.query_async(&mut connection)).await {
+            Ok(reply) => Some(reply),
+            Err(e) => {
+                println!("Redis command failed; retrying in {RETRY_DELAY:?}: {e:#}");
+                let mut state = self.state.lock().await;
+                state.connection = None;
+                state.retry_at = Some(Instant::now() + RETRY_DELAY);
+                None
+            }
+        }
+    }
+}
+
+async fn with_timeout<T>(future: impl Future<Output = redis::RedisResult<T>>) -> Result<T> {
+    let reply = tokio::time::timeout(TIMEOUT, future)
+        .await
+        .map_err(|_| anyhow!("Redis didn't respond within {TIMEOUT:?}."))?;
+    Ok(reply?)
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn read(&self, k: &str) -> Result<Option<String>> {
+        let mut cmd = redis::cmd("GET");
+        cmd.arg(k);
+        Ok(self.query::<Option<String>>(cmd).await.flatten())
+    }
+    async fn write(&self, k: &str, v: &str) -> Result<()> {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(k).arg(v);
+        self.query::<()>(cmd).await;
+        Ok(())
+    }
+    async fn write_with_ttl(&self, k: &str, v: &str, ttl: Duration) -> Result<()> {
+        // Redis rejects a TTL of 0.
+        let ttl_ms = std::cmp::max(ttl.as_millis(), 1) as u64;
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(k).arg(v).arg("PX").arg(ttl_ms);
+        self.query::<()>(cmd).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // The values of the mock Redis, with their expiration times.
+    type Values = std::sync::Mutex<HashMap<Vec<u8>, (Vec<u8>, Option<Instant>)>>;
+
+    // Serves GET, and SET with an optional PX, from memory, like Redis.
+    // Returns its URL.
+    async fn serve_mock_redis() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        let values = Arc::new(Values::default());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let values = values.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut reader = BufReader::new(reader);
+                    while let Some(command) = read_command(&mut reader).await {
+                        let reply = reply(&values, &command);
+                        if writer.write_all(&reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    // Reads a command, which a client sends as an array of bulk strings.
+    // Returns None at the end of the stream.
+    async fn read_command(reader: &mut (impl AsyncBufRead + Unpin)) -> Option<Vec<Vec<u8>>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok()?;
+        let len: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+        let mut command = vec![];
+        for _ in 0..len {
+            line.clear();
+            reader.read_line(&mut line).await.ok()?;
+            let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).await.ok()?;
+            arg.truncate(len);
+            command.push(arg);
+        }
+        Some(command)
+    }
+
+    fn reply(values: &Values, command: &[Vec<u8>]) -> Vec<u8> {
+        let mut values = values.lock().unwrap();
+        match command {
+            [name, key] if name.eq_ignore_ascii_case(b"GET") => match values.get(key) {
+                Some((value, expiration)) if expiration.map_or(true, |e| Instant::now() < e) => [
+                    format!("${}\r\n", value.len()).as_bytes(),
+                    value.as_slice(),
+                    &b"\r\n"[..],
+                ]
+                .concat(),
+                _ => b"$-1\r\n".to_vec(),
+            },
+            [name, key, value, options @ ..] if name.eq_ignore_ascii_case(b"SET") => {
+                let expiration = match options {
+                    [] => None,
+                    [px, ms] if px.eq_ignore_ascii_case(b"PX") => {
+                        match String::from_utf8_lossy(ms).parse::<u64>() {
+                            Ok(ms) if ms > 0 => Some(Instant::now() + Duration::from_millis(ms)),
+                            _ => return b"-ERR invalid expire time in 'set' command\r\n".to_vec(),
+                        }
+                    }
+                    _ => return b"-ERR syntax error\r\n".to_vec(),
+                };
+                values.insert(key.clone(), (value.clone(), expiration));
+                b"+OK\r\n".to_vec()
+            }
+            _ => b"-ERR unknown command\r\n".to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_and_writes_with_ttl() {
+        let url = serve_mock_redis().await;
+        let storage = RedisStorage::new(&url).unwrap();
+        assert_eq!(storage.read("OCSP").await.unwrap(), None);
+        storage.write("OCSP", "first").await.unwrap();
+        storage.write("OCSP", "second").await.unwrap();
+        storage
+            .write_with_ttl("ACME", "state", Duration::from_millis(100))
+            .await
+            .unwrap();
+        // Another replica sees the same values.
+        let replica = RedisStorage::new(&url).unwrap();
+        assert_eq!(
+            replica.read("OCSP").await.unwrap().as_deref(),
+            Some("second")
+        );
+        assert_eq!(
+            replica.read("ACME").await.unwrap().as_deref(),
+            Some("state")
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(replica.read("ACME").await.unwrap(), None);
+        assert_eq!(
+            replica.read("OCSP").await.unwrap().as_deref(),
+            Some("second")
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_redis_behaves_as_empty() {
+        // A port with nothing listening.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let storage = RedisStorage::new(&url).unwrap();
+        assert_eq!(storage.read("OCSP").await.unwrap(), None);
+        assert!(storage.state.lock().await.retry_at.is_some());
+        // Until the retry, Redis is skipped without connecting.
+        let start = Instant::now();
+        storage.write("OCSP", "value").await.unwrap();
+        storage
+            .write_with_ttl("OCSP", "value", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(storage.read("OCSP").await.unwrap(), None);
+        assert!(start.elapsed() < TIMEOUT);
+    }
+
+    #[test]
+    fn rejects_invalid_url() {
+        assert!(RedisStorage::new("not a url").is_err());
+    }
+}
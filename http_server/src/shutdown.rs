@@ -0,0 +1,177 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Graceful shutdown: on SIGTERM or SIGINT, stop accepting connections and
+//! let in-flight requests finish, up to a timeout.
+//!
+//! Background tasks, such as ACME renewal and OCSP refresh, are not drained;
+//! they are dropped when `main` returns. This is safe because the file
+//! storage writes each file to a temporary path and renames it into place,
+//! so an interrupted write leaves the previous contents intact.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Resolves when the process receives SIGTERM or SIGINT.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminations) => {
+                tokio::select! {
+                    _ = terminations.recv() => {},
+                    _ = tokio::signal::ctrl_c() => {},
+                }
+            }
+            Err(e) => {
+                println!("Unable to listen for SIGTERM: {e}");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Wraps `shutdown`, returning a future to pass to
+/// `Server::with_graceful_shutdown` and a receiver that resolves once
+/// shutdown has begun, for use with `drain`.
+pub fn notify_on(
+    shutdown: impl Future<Output = ()>,
+) -> (impl Future<Output = ()>, oneshot::Receiver<()>) {
+    let (tx, rx) = oneshot::channel();
+    let shutdown = async move {
+        shutdown.await;
+        let _ = tx.send(());
+    };
+    (shutdown, rx)
+}
+
+/// Runs `server` until it exits. Once `started` resolves, waits at most
+/// `timeout` for in-flight requests to complete, then returns regardless.
+pub async fn drain<E>(
+    server: impl Future<Output = Result<(), E>>,
+    started: oneshot::Receiver<()>,
+    timeout: Duration,
+) -> Result<(), E> {
+    tokio::pin!(server);
+    tokio::select! {
+        result = &mut server => return result,
+        Ok(()) = started => {},
+    }
+    println!("Shutting down; waiting up to {timeout:?} for in-flight requests.");
+    match tokio::time::timeout(timeout, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            println!("Timed out waiting for in-flight requests.");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response, Server,
+    };
+    use std::net::SocketAddr;
+    use tokio::sync::mpsc;
+
+    // Starts a server whose every response takes `delay`, and returns its
+    // address, a receiver notified when each request arrives, and the future
+    // that drains it after `shutdown` resolves.
+    fn serve_slowly(
+        delay: Duration,
+        shutdown: oneshot::Receiver<()>,
+        timeout: Duration,
+    ) -> (
+        SocketAddr,
+        mpsc::UnboundedReceiver<()>,
+        impl Future<Output = hyper::Result<()>>,
+    ) {
+        let (arrived_tx, arrived_rx) = mpsc::unbounded_channel();
+        let make_svc = make_service_fn(move |_| {
+            let arrived_tx = arrived_tx.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |_req| {
+                    let _ = arrived_tx.send(());
+                    async move {
+                        tokio::time::sleep(delay).await;
+                        Ok::<_, hyper::Error>(Response::new(Body::from("done")))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        let (shutdown, started) = notify_on(async {
+            let _ = shutdown.await;
+        });
+        let server = server.with_graceful_shutdown(shutdown);
+        (addr, arrived_rx, drain(server, started, timeout))
+    }
+    #[tokio::test]
+    async fn in_flight_request_completes() {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let (addr, mut arrived, server) = serve_slowly(
+            Duration::from_millis(200),
+            shutdown_rx,
+            Duration::from_secs(10),
+        );
+        let server = tokio::spawn(server);
+        let request = tokio::spawn(async move {
+            let resp = hyper::Client::new()
+                .get(format!("http://{addr}/").parse().unwrap())
+                .await?;
+            hyper::body::to_bytes(resp.into_body()).await
+        });
+        arrived.recv().await.unwrap();
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        // Had the connection been dropped at shutdown, the request would fail.
+        assert_eq!(request.await.unwrap().unwrap(), "done");
+    }
+    #[tokio::test]
+    async fn gives_up_after_timeout() {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let (addr, mut arrived, server) = serve_slowly(
+            Duration::from_secs(3600),
+            shutdown_rx,
+            Duration::from_millis(100),
+        );
+        let server = tokio::spawn(server);
+        let request = tokio::spawn(async move {
+            hyper::Client::new()
+                .get(format!("http://{addr}/").parse().unwrap())
+                .await
+        });
+        arrived.recv().await.unwrap();
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!request.is_finished());
+    }
+    #[tokio::test]
+    async fn exits_without_shutdown_if_server_fails() {
+        let (_shutdown_tx, started) = oneshot::channel::<()>();
+        let server = async { Err::<(), _>("bind failed") };
+        assert_eq!(
+            drain(server, started, Duration::from_secs(1)).await,
+            Err("bind failed")
+        );
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Measures the end-to-end latency of `create_signed_exchange`, including
+//! MICE and CBOR encoding and ECDSA signing. Run with:
+//!
+//!   cargo bench -p sxg_rs --features rust_signer
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::executor::block_on;
+use std::time::{Duration, UNIX_EPOCH};
+use sxg_rs::{
+    crypto::CertificateChain, http_cache::NullCache, runtime::Runtime, CreateSignedExchangeParams,
+    SxgWorker,
+};
+
+// The same certificate and key as `SIGNING_CERT_PEM` and
+// `SIGNING_PRIVATE_KEY_BASE64` in the unit tests.
+const CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIBsTCCAVegAwIBAgIURjAk4mIwIrynNtqMB0D31hlnvXAwCgYIKoZIzj0EAwIw
+MjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYT
+AlVTMB4XDTI2MTAxNDA1MjA1NVoXDTI3MDExMjA1MjA1NVowMjEUMBIGA1UEAwwL
+ZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYTAlVTMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEWpxFASoBrkOKVcdKTW60fF5l91wI2eodenO15EPf
+2EFIpof5jftxHxVn+LrB+pkC48BvjY4HZ6sml5Htdhr3JaNLMEkwEAYKKwYBBAHW
+eQIBFgQCBQAwFgYDVR0RBA8wDYILZXhhbXBsZS5vcmcwHQYDVR0OBBYEFBvUyZ+a
+zqqbRGOCuh+Y1wp8osr8MAoGCCqGSM49BAMCA0gAMEUCIG3/Z3UMSubVvWgJII3n
+dJGHumYz1sL0dBzX683gSnjiAiEAkOG6V6xs3CUNyTmelFSTeCnUtB587KcVzYKn
+tM4oz6g=
+-----END CERTIFICATE-----
+";
+const PRIVATE_KEY_BASE64: &str = "Q714JzYPYxqPwajTtDPs5ho+SUk3TlknvXzaqihejIo=";
+
+fn new_worker() -> SxgWorker {
+    let yaml = format!(
+        r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: example.org
+private_key_base64: "{}"
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity/"
+        "#,
+        PRIVATE_KEY_BASE64
+    );
+    let mut worker = SxgWorker::new(&yaml).unwrap();
+    worker
+        .add_certificate(CertificateChain::from_pem_files(&[CERT_PEM, CERT_PEM]).unwrap())
+        .unwrap();
+    worker
+}
+
+// An HTML page of roughly `size` bytes.
+fn html_payload(size: usize) -> Vec<u8> {
+    const PARAGRAPH: &str =
+        "<p>Lorem ipsum dolor sit amet, <a href=\"/next.html\">consectetur</a> adipiscing elit.</p>\n";
+    let mut html = "<!DOCTYPE html><html><head><title>Benchmark</title></head><body>\n".to_string();
+    while html.len() < size {
+        html.push_str(PARAGRAPH);
+    }
+    html.push_str("</body></html>\n");
+    html.into_bytes()
+}
+
+fn create_signed_exchange(c: &mut Criterion) {
+    let worker = new_worker();
+    // ECDSA signing by RustSigner is deterministic per RFC 6979, and the time
+    // is fixed, so every iteration does the same work.
+    let runtime = Runtime {
+        now: UNIX_EPOCH + Duration::from_secs(1_800_000_000),
+        sxg_signer: Box::new(worker.create_rust_signer().unwrap()),
+        ..Default::default()
+    };
+    let mut group = c.benchmark_group("create_signed_exchange");
+    for (name, size) in [("10KB", 10_000), ("200KB", 200_000), ("2MB", 2_000_000)] {
+        let payload = html_payload(size);
+        group.throughput(Throughput::Bytes(payload.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &payload, |b, payload| {
+            b.iter(|| {
+                block_on(
+                    worker.create_signed_exchange(
+                        &runtime,
+                        CreateSignedExchangeParams {
+                            fallback_url: "https://example.org/index.html",
+                            cert_origin: "https://example.org",
+                            payload_body: payload,
+                            payload_headers: worker
+                                .transform_payload_headers(vec![(
+                                    "content-type".into(),
+                                    "text/html".into(),
+                                )])
+                                .unwrap(),
+                            skip_process_link: false,
+                            status_code: 200,
+                            header_integrity_cache: NullCache {},
+                            mice_record_size: None,
+                        },
+                    ),
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, create_signed_exchange);
+criterion_main!(benches);
@@ -96,12 +96,13 @@ async fn write_state(runtime: &Runtime, state: &AcmeStorageData) -> Result<()> {
 const MIN_SLEEP: Duration = Duration::from_secs(59);
 const MAX_SLEEP: Duration = Duration::from_secs(600);
 
+/// A new certificate is ordered once the latest one is within this period of
+/// its expiration.
+pub const RENEWAL_WINDOW: Duration = Duration::from_secs(3600 * 24 * 7);
+
 // Parses the certificate chain PEM, and returns the expiration time of the first certificate.
 fn get_certificate_expiration_time(certificate_pem: &str) -> Result<SystemTime> {
-    let certificate_chain = CertificateChain::from_pem_files(&[certificate_pem])?;
-    let x509_cert = x509_parser::parse_x509_certificate(&certificate_chain.end_entity.der)?;
-    let timestamp = x509_cert.1.tbs_certificate.validity.not_after.timestamp();
-    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp as u64))
+    CertificateChain::from_pem_files(&[certificate_pem])?.not_after()
 }
 
 async fn update_state_impl(
@@ -113,8 +114,7 @@ async fn update_state_impl(
 ) -> Result<()> {
     if let Some(certificate_pem) = state.certificates.last() {
         let expiration = get_certificate_expiration_time(certificate_pem)?;
-        const TEN_DAYS: Duration = Duration::from_secs(3600 * 24 * 10);
-        if now + TEN_DAYS < expiration {
+        if now + RENEWAL_WINDOW < expiration {
             // There is already a certificate, and it is far from expiration,
             // so we do nothing.
             return Ok(());
@@ -742,4 +742,73 @@ mod tests {
         };
         tokio::join!(client_thread, server_thread);
     }
+    // Expiration time of `SIGNING_CERT_PEM`.
+    const SIGNING_CERT_NOT_AFTER: Duration = Duration::from_secs(1799731255);
+    // When the existing certificate is far from expiration, the state machine
+    // does nothing.
+    #[tokio::test]
+    async fn keep_certificate_outside_renewal_window() {
+        let (fetcher, _server) = crate::fetcher::mock_fetcher::create();
+        let storage = Box::new(InMemoryStorage::new());
+        let runtime = Runtime {
+            storage,
+            now: UNIX_EPOCH + SIGNING_CERT_NOT_AFTER - RENEWAL_WINDOW - Duration::from_secs(3600),
+            fetcher: Box::new(fetcher),
+            ..Default::default()
+        };
+        let old_state = create_from_certificate(crate::utils::tests::SIGNING_CERT_PEM);
+        write_state(&runtime, &old_state).await.unwrap();
+        let account: Account = serde_json::from_str(ACCOUNT).unwrap();
+        update_state(&runtime, &account).await.unwrap();
+        assert_eq!(read_current_state(&runtime).await.unwrap(), old_state);
+    }
+    // When the existing certificate is within the renewal window, the state
+    // machine places a new order, and keeps the existing certificate until the
+    // new one is issued.
+    #[tokio::test]
+    async fn renew_certificate_inside_renewal_window() {
+        let (fetcher, mut server) = crate::fetcher::mock_fetcher::create();
+        let server_thread = async {
+            handle_server_directory(&mut server, "1").await;
+            server
+                .handle_next_request(
+                    example_new_order_request("1").await,
+                    example_new_order_response("2"),
+                )
+                .await
+                .unwrap();
+            server
+                .handle_next_request(
+                    example_authorization_request("2").await,
+                    example_pending_authorization_response("3"),
+                )
+                .await
+                .unwrap();
+        };
+        let now = UNIX_EPOCH + SIGNING_CERT_NOT_AFTER - RENEWAL_WINDOW + Duration::from_secs(3600);
+        let client_thread = async {
+            let storage = Box::new(InMemoryStorage::new());
+            let runtime = Runtime {
+                storage,
+                now,
+                fetcher: Box::new(fetcher),
+                ..Default::default()
+            };
+            let old_state = create_from_certificate(crate::utils::tests::SIGNING_CERT_PEM);
+            write_state(&runtime, &old_state).await.unwrap();
+            let account: Account = serde_json::from_str(ACCOUNT).unwrap();
+            update_state(&runtime, &account).await.unwrap();
+            let new_state = read_current_state(&runtime).await.unwrap();
+            assert_eq!(new_state.certificates, old_state.certificates);
+            assert_eq!(
+                new_state.task.unwrap().schedule,
+                Schedule {
+                    updated_at: now,
+                    wait_time: Duration::from_secs(59),
+                    next_step: TaskStep::RequestChallengeValidation,
+                }
+            );
+        };
+        tokio::join!(client_thread, server_thread);
+    }
 }
@@ -0,0 +1,243 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Constructs an [`SxgWorker`] from typed settings, for embedding sxg-rs in
+//! other Rust services without writing its config as YAML.
+
+use crate::config::{Config, PathPatterns, TrailingSlash};
+use crate::crypto::CertificateChain;
+use crate::SxgWorker;
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+/// Starts from the defaults of `input.example.yaml`, e.g. serving the
+/// cert-chain at `/.well-known/sxg-certs/`, forwarding no request headers,
+/// and falling back to unsigned responses. The settings are normalized and
+/// checked in [`build`](Self::build), like those of [`SxgWorker::new`].
+pub struct SxgWorkerBuilder {
+    config: Config,
+    certificates: Vec<CertificateChain>,
+}
+
+impl SxgWorkerBuilder {
+    /// `html_host` is the host of the signed pages, such as `example.com`.
+    pub fn new(html_host: &str) -> Self {
+        Self::from_config(Config {
+            brotli_level: None,
+            cert_url_base: None,
+            cert_url_dirname: ".well-known/sxg-certs".into(),
+            cors_allow_origin: None,
+            expose_cert_sha256: false,
+            fallback_host: None,
+            fallback_to_unsigned: true,
+            forward_request_headers: BTreeSet::new(),
+            header_integrity_revalidation: false,
+            hosts: BTreeMap::new(),
+            html_host: html_host.into(),
+            index_document: None,
+            inject_canonical: false,
+            max_concurrent_signings: None,
+            max_subresources: None,
+            mice_record_size: None,
+            ocsp_stale_grace_seconds: None,
+            preview_cert: None,
+            preview_mode: false,
+            private_key_base64: None,
+            process_links: true,
+            randomized_signatures: false,
+            reserved_path: ".sxg".into(),
+            rewrite_base: false,
+            sign_client_error_responses: true,
+            sign_error_responses: false,
+            signable_content_types: None,
+            signed_paths: PathPatterns::default(),
+            unsigned_paths: PathPatterns::default(),
+            signature_duration_seconds: None,
+            skip_no_cache: true,
+            sniff_content_type: false,
+            strip_request_headers: BTreeSet::new(),
+            strip_response_headers: BTreeSet::new(),
+            subresource_fetch_concurrency: None,
+            sxg_opt_out_header: None,
+            trailing_slash: TrailingSlash::default(),
+            trust_signed_host_header: false,
+            validity_update_size: None,
+            validity_url_dirname: ".well-known/sxg-validity".into(),
+        })
+    }
+    /// Starts from `config`, e.g. as parsed from YAML.
+    pub fn from_config(config: Config) -> Self {
+        SxgWorkerBuilder {
+            config,
+            certificates: vec![],
+        }
+    }
+    pub fn html_host(mut self, html_host: &str) -> Self {
+        self.config.html_host = html_host.into();
+        self
+    }
+    /// The https origin of the cert-url, such as `https://example.com`.
+    pub fn cert_url_base(mut self, origin: &str) -> Self {
+        self.config.cert_url_base = Some(origin.into());
+        self
+    }
+    pub fn cert_url_dirname(mut self, dirname: &str) -> Self {
+        self.config.cert_url_dirname = dirname.into();
+        self
+    }
+    /// The https origin of the fallback URLs, such as `https://example.com`.
+    pub fn fallback_host(mut self, origin: &str) -> Self {
+        self.config.fallback_host = Some(origin.into());
+        self
+    }
+    pub fn forward_request_headers<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        self.config.forward_request_headers = names.into_iter().map(String::from).collect();
+        self
+    }
+    pub fn reserved_path(mut self, path: &str) -> Self {
+        self.config.reserved_path = path.into();
+        self
+    }
+    /// The maximum lifetime of signatures, in whole seconds. Must not exceed
+    /// 7 days.
+    pub fn signature_duration(mut self, duration: Duration) -> Self {
+        self.config.signature_duration_seconds = Some(duration.as_secs());
+        self
+    }
+    pub fn strip_request_headers<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        self.config.strip_request_headers = names.into_iter().map(String::from).collect();
+        self
+    }
+    pub fn strip_response_headers<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        self.config.strip_response_headers = names.into_iter().map(String::from).collect();
+        self
+    }
+    pub fn validity_url_dirname(mut self, dirname: &str) -> Self {
+        self.config.validity_url_dirname = dirname.into();
+        self
+    }
+    /// The Brotli quality of payloads, from 0 to 11.
+    pub fn brotli_level(mut self, level: u32) -> Self {
+        self.config.brotli_level = Some(level);
+        self
+    }
+    /// Adds a certificate to sign with, as by
+    /// [`SxgWorker::add_certificate`]. Certificates are added in order.
+    pub fn certificate(mut self, certificate: CertificateChain) -> Self {
+        self.certificates.push(certificate);
+        self
+    }
+    /// Returns an error if any setting is invalid, or any certificate isn't
+    /// SXG-compatible.
+    pub fn build(self) -> Result<SxgWorker> {
+        let mut worker = SxgWorker::from_parsed(self.config.validate()?);
+        for certificate in self.certificates {
+            worker.add_certificate(certificate)?;
+        }
+        Ok(worker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tests::SELF_SIGNED_CERT_PEM;
+    fn config_json(worker: &SxgWorker) -> serde_json::Value {
+        serde_json::to_value(worker.config()).unwrap()
+    }
+    #[test]
+    fn matches_example_defaults() {
+        let yaml = r#"
+cert_url_dirname: ".well-known/sxg-certs"
+forward_request_headers: []
+html_host: example.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+        "#;
+        let built = SxgWorkerBuilder::new("example.com").build().unwrap();
+        assert_eq!(
+            config_json(&built),
+            config_json(&SxgWorker::new(yaml).unwrap())
+        );
+    }
+    #[cfg(feature = "content_coding")]
+    #[test]
+    fn matches_yaml() {
+        let yaml = r#"
+brotli_level: 5
+cert_url_base: "https://CDN.example.com/"
+cert_url_dirname: "certs"
+fallback_host: "https://www.example.com"
+forward_request_headers: ["User-Agent"]
+html_host: example.com
+reserved_path: "/sxg/"
+signature_duration_seconds: 3600
+strip_request_headers: ["Forwarded"]
+strip_response_headers: ["Set-Cookie"]
+validity_url_dirname: "validity"
+        "#;
+        let built = SxgWorkerBuilder::new("example.org")
+            .html_host("example.com")
+            .brotli_level(5)
+            .cert_url_base("https://CDN.example.com/")
+            .cert_url_dirname("certs")
+            .fallback_host("https://www.example.com")
+            .forward_request_headers(vec!["User-Agent"])
+            .reserved_path("/sxg/")
+            .signature_duration(Duration::from_secs(3600))
+            .strip_request_headers(vec!["Forwarded"])
+            .strip_response_headers(vec!["Set-Cookie"])
+            .validity_url_dirname("validity")
+            .build()
+            .unwrap();
+        assert_eq!(
+            config_json(&built),
+            config_json(&SxgWorker::new(yaml).unwrap())
+        );
+        assert_eq!(
+            built.config().cert_url_base.as_deref(),
+            Some("https://cdn.example.com")
+        );
+    }
+    #[test]
+    fn validates_settings() {
+        let builder = || SxgWorkerBuilder::new("example.com");
+        assert!(builder().brotli_level(12).build().is_err());
+        assert!(builder()
+            .fallback_host("http://example.com")
+            .build()
+            .is_err());
+        assert!(builder()
+            .signature_duration(Duration::from_secs(604801))
+            .build()
+            .is_err());
+    }
+    #[test]
+    fn adds_certificates() {
+        let certificate =
+            CertificateChain::from_pem_files(&[SELF_SIGNED_CERT_PEM, SELF_SIGNED_CERT_PEM])
+                .unwrap();
+        let worker = SxgWorkerBuilder::new("example.com")
+            .certificate(certificate.clone())
+            .build()
+            .unwrap();
+        assert_eq!(
+            worker.latest_certificate_basename(),
+            Some(certificate.basename.as_str())
+        );
+    }
+}
@@ -14,10 +14,12 @@
 
 // https://tools.ietf.org/html/rfc7049
 
+use anyhow::{anyhow, Result};
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
 
+#[derive(Debug, PartialEq, Eq)]
 pub enum DataItem<'a> {
-    #[allow(dead_code)]
     UnsignedInteger(u64),
     ByteString(&'a [u8]),
     TextString(&'a str),
@@ -29,8 +31,132 @@ impl<'a> DataItem<'a> {
     pub fn serialize(&self) -> Vec<u8> {
         let mut result = Vec::new();
         self.append_binary_to(&mut result);
+        debug_assert!(
+            check_canonical_order(&result).is_ok(),
+            "Serialized CBOR maps are not in canonical order"
+        );
         result
     }
+    /// Parses one data item from the start of `input`, and returns it along
+    /// with the remaining bytes. Only the major types above are supported,
+    /// and only with definite lengths.
+    pub fn parse(input: &'a [u8]) -> Result<(DataItem<'a>, &'a [u8])> {
+        use DataItem::*;
+        let (initial, input) = input
+            .split_first()
+            .ok_or_else(|| anyhow!("Unexpected end of CBOR input"))?;
+        let major_type = initial >> 5;
+        let (data, mut input) = parse_integer(initial & 0x1f, input)?;
+        match major_type {
+            0 => Ok((UnsignedInteger(data), input)),
+            2 => {
+                let (bytes, input) = take(input, data)?;
+                Ok((ByteString(bytes), input))
+            }
+            3 => {
+                let (bytes, input) = take(input, data)?;
+                Ok((TextString(std::str::from_utf8(bytes)?), input))
+            }
+            4 => {
+                let mut items = vec![];
+                for _ in 0..data {
+                    let (item, rest) = DataItem::parse(input)?;
+                    items.push(item);
+                    input = rest;
+                }
+                Ok((Array(items), input))
+            }
+            5 => {
+                let mut fields = vec![];
+                for _ in 0..data {
+                    let (key, rest) = DataItem::parse(input)?;
+                    let (value, rest) = DataItem::parse(rest)?;
+                    fields.push((key, value));
+                    input = rest;
+                }
+                Ok((Map(fields), input))
+            }
+            _ => Err(anyhow!("Unsupported CBOR major type {}", major_type)),
+        }
+    }
+    /// Appends an outline of the data item to `lines`, one scalar per line,
+    /// indented by two spaces for each level of `depth`. Map fields keep
+    /// their order, so that the outlines of equal items are equal.
+    pub fn outline(&self, depth: usize, lines: &mut Vec<String>) {
+        use DataItem::*;
+        let indent = "  ".repeat(depth);
+        match self {
+            Array(items) => {
+                for item in items {
+                    match item.scalar() {
+                        Some(value) => lines.push(format!("{}- {}", indent, value)),
+                        None => {
+                            lines.push(format!("{}-", indent));
+                            item.outline(depth + 1, lines);
+                        }
+                    }
+                }
+            }
+            Map(fields) => {
+                for (key, value) in fields {
+                    let key = key.inline();
+                    match value.scalar() {
+                        Some(value) => lines.push(format!("{}{}: {}", indent, key, value)),
+                        None => {
+                            lines.push(format!("{}{}:", indent, key));
+                            value.outline(depth + 1, lines);
+                        }
+                    }
+                }
+            }
+            _ => lines.extend(self.scalar().map(|value| format!("{}{}", indent, value))),
+        }
+    }
+    // Formats an integer or string as in the diagnostic notation of
+    // https://www.rfc-editor.org/rfc/rfc8610#appendix-G: byte strings of
+    // printable ASCII as 'text', other byte strings as h'hex', and text
+    // strings quoted.
+    fn scalar(&self) -> Option<String> {
+        use DataItem::*;
+        match self {
+            UnsignedInteger(x) => Some(x.to_string()),
+            ByteString(bytes) if bytes.iter().all(|b| matches!(b, b' '..=b'~')) => {
+                let text = std::str::from_utf8(bytes).unwrap();
+                Some(format!(
+                    "'{}'",
+                    text.replace('\\', "\\\\").replace('\'', "\\'")
+                ))
+            }
+            ByteString(bytes) => Some(format!(
+                "h'{}'",
+                bytes
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>()
+            )),
+            TextString(text) => Some(format!("{:?}", text)),
+            Array(_) | Map(_) => None,
+        }
+    }
+    // Formats the data item on one line, with arrays as [a, b] and maps as
+    // {k: v}.
+    fn inline(&self) -> String {
+        use DataItem::*;
+        match self {
+            Array(items) => {
+                let items: Vec<String> = items.iter().map(DataItem::inline).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Map(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key.inline(), value.inline()))
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            _ => self.scalar().unwrap_or_default(),
+        }
+    }
     fn append_binary_to(&self, output: &mut Vec<u8>) {
         use DataItem::*;
         match self {
@@ -50,12 +176,14 @@ impl<'a> DataItem<'a> {
                 }
             }
             Map(fields) => {
-                let mut map = BTreeMap::<Vec<u8>, Vec<u8>>::new();
+                // Keyed by length first, for the canonical order.
+                let mut map = BTreeMap::<(usize, Vec<u8>), Vec<u8>>::new();
                 for (key, value) in fields {
-                    map.insert(key.serialize(), value.serialize());
+                    let key = key.serialize();
+                    map.insert((key.len(), key), value.serialize());
                 }
                 append_integer(output, 5, map.len() as u64);
-                for (mut key, mut value) in map.into_iter() {
+                for ((_, mut key), mut value) in map.into_iter() {
                     output.append(&mut key);
                     output.append(&mut value);
                 }
@@ -64,6 +192,58 @@ impl<'a> DataItem<'a> {
     }
 }
 
+/// Checks that `input` is one data item, in which the keys of every map are
+/// in the canonical order of
+/// https://www.rfc-editor.org/rfc/rfc7049#section-3.9: shorter keys first,
+/// then bytewise lower keys first, and without duplicates. Browsers reject
+/// SXGs whose signed headers aren't canonical.
+pub fn check_canonical_order(input: &[u8]) -> Result<()> {
+    match check_item(input)? {
+        [] => Ok(()),
+        rest => Err(anyhow!("{} bytes after the CBOR data item", rest.len())),
+    }
+}
+
+// Checks the key order of the maps in the data item at the start of `input`,
+// and returns the remaining bytes.
+fn check_item(input: &[u8]) -> Result<&[u8]> {
+    let (initial, input) = input
+        .split_first()
+        .ok_or_else(|| anyhow!("Unexpected end of CBOR input"))?;
+    let major_type = initial >> 5;
+    let (data, mut input) = parse_integer(initial & 0x1f, input)?;
+    match major_type {
+        0 => Ok(input),
+        2 | 3 => Ok(take(input, data)?.1),
+        4 => {
+            for _ in 0..data {
+                input = check_item(input)?;
+            }
+            Ok(input)
+        }
+        5 => {
+            let mut previous_key: Option<&[u8]> = None;
+            for _ in 0..data {
+                let rest = check_item(input)?;
+                let key = &input[..input.len() - rest.len()];
+                if let Some(previous_key) = previous_key {
+                    if (previous_key.len(), previous_key) >= (key.len(), key) {
+                        return Err(anyhow!(
+                            "CBOR map key {:02x?} is not after {:02x?} in canonical order",
+                            key,
+                            previous_key
+                        ));
+                    }
+                }
+                previous_key = Some(key);
+                input = check_item(rest)?;
+            }
+            Ok(input)
+        }
+        _ => Err(anyhow!("Unsupported CBOR major type {}", major_type)),
+    }
+}
+
 fn append_integer(output: &mut Vec<u8>, major_type: u8, data: u64) {
     let major_type = major_type << 5;
     match data {
@@ -89,6 +269,34 @@ fn append_integer(output: &mut Vec<u8>, major_type: u8, data: u64) {
     };
 }
 
+fn parse_integer(additional_info: u8, input: &[u8]) -> Result<(u64, &[u8])> {
+    let size = match additional_info {
+        0..=23 => return Ok((additional_info as u64, input)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => {
+            return Err(anyhow!(
+                "Unsupported CBOR additional information {}",
+                additional_info
+            ))
+        }
+    };
+    let (bytes, input) = take(input, size)?;
+    let data = bytes
+        .iter()
+        .fold(0, |data, &byte| (data << 8) | byte as u64);
+    Ok((data, input))
+}
+
+fn take(input: &[u8], size: u64) -> Result<(&[u8], &[u8])> {
+    match usize::try_from(size) {
+        Ok(size) if size <= input.len() => Ok(input.split_at(size)),
+        _ => Err(anyhow!("Unexpected end of CBOR input")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -161,4 +369,92 @@ mod tests {
             from_hex("a261420562414106"),
         );
     }
+    #[test]
+    fn parse_round_trips() {
+        use DataItem::*;
+        let item = Map(vec![
+            (ByteString(b":status"), ByteString(b"200")),
+            (
+                TextString("list"),
+                Array(vec![UnsignedInteger(1000000000000), UnsignedInteger(7)]),
+            ),
+        ]);
+        let bytes = [item.serialize(), vec![0xff]].concat();
+        let (parsed, rest) = DataItem::parse(&bytes).unwrap();
+        assert_eq!(parsed.serialize(), item.serialize());
+        assert_eq!(rest, &[0xff]);
+    }
+    #[test]
+    fn parse_errors() {
+        // Truncated byte string.
+        assert!(DataItem::parse(&from_hex("440102")).is_err());
+        // Negative integer.
+        assert!(DataItem::parse(&from_hex("20")).is_err());
+        // Indefinite-length array.
+        assert!(DataItem::parse(&from_hex("9fff")).is_err());
+        assert!(DataItem::parse(&[]).is_err());
+    }
+    #[test]
+    fn canonical_order() {
+        use DataItem::*;
+        let mixed = Map(vec![
+            (UnsignedInteger(1000), UnsignedInteger(1)),
+            (ByteString(b"a"), UnsignedInteger(2)),
+            (
+                TextString("nested"),
+                Array(vec![Map(vec![
+                    (TextString("AA"), UnsignedInteger(3)),
+                    (TextString("B"), UnsignedInteger(4)),
+                ])]),
+            ),
+        ]);
+        // The 2-byte key b"a" is before the 3-byte key 1000.
+        let bytes = mixed.serialize();
+        assert!(bytes.starts_with(&from_hex("a3416102")));
+        assert!(check_canonical_order(&bytes).is_ok());
+        assert!(check_canonical_order(&from_hex("a0")).is_ok());
+    }
+    #[test]
+    fn outline() {
+        use DataItem::*;
+        let item = Map(vec![
+            (ByteString(b":status"), ByteString(b"200")),
+            (ByteString(b"it's"), ByteString(&[0, 0xff])),
+            (
+                TextString("nested"),
+                Array(vec![
+                    UnsignedInteger(1),
+                    Map(vec![(Array(vec![UnsignedInteger(2)]), TextString("x"))]),
+                ]),
+            ),
+        ]);
+        let mut lines = vec![];
+        item.outline(1, &mut lines);
+        assert_eq!(
+            lines,
+            vec![
+                "  ':status': '200'",
+                "  'it\\'s': h'00ff'",
+                "  \"nested\":",
+                "    - 1",
+                "    -",
+                "      [2]: \"x\"",
+            ]
+        );
+    }
+    #[test]
+    fn misordered_keys() {
+        // {"AA": 6, "B": 5}, where the longer key comes first.
+        assert!(check_canonical_order(&from_hex("a262414106614205")).is_err());
+        // {2: 5, 1: 6}, where the bytewise higher key comes first.
+        assert!(check_canonical_order(&from_hex("a202050106")).is_err());
+        // {1: 6, 1: 5}, with a duplicate key.
+        assert!(check_canonical_order(&from_hex("a201060105")).is_err());
+        // [{2: 5, 1: 6}], with a misordered map inside an array.
+        assert!(check_canonical_order(&from_hex("81a202050106")).is_err());
+        // {0: {2: 5, 1: 6}}, with a misordered map as a value.
+        assert!(check_canonical_order(&from_hex("a100a202050106")).is_err());
+        // Trailing bytes.
+        assert!(check_canonical_order(&from_hex("a0ff")).is_err());
+    }
 }
@@ -12,44 +12,508 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use crate::http_parser::parse_content_type_header;
+use crate::signature::SEVEN_DAYS;
+use anyhow::{anyhow, Error, Result};
+#[cfg(feature = "path_patterns")]
+use regex::RegexSet;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet};
+use url::Url;
 
 // This struct is source-of-truth of the sxg config. The user need to create
 // a file (like `config.yaml`) to provide this config input.
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
+    // If set, the SXG payload is Brotli-compressed with this quality, from 0
+    // (fastest) to 11 (smallest), unless its media type is already compressed
+    // (e.g. images and video). A payload that the origin encoded with gzip,
+    // deflate or br is always decoded first, and signed unencoded if this is
+    // unset.
+    pub brotli_level: Option<u32>,
+    // The https origin of the cert-url of signatures, such as
+    // 'https://example.com', if it should differ from the origin that the
+    // worker was reached on, e.g. to serve the cert-chain from the signed
+    // origin. The origin must serve `cert_url_dirname` from this worker.
+    pub cert_url_base: Option<String>,
+    // The directory of the cert-url of signatures, where the cert-chain of
+    // each certificate is served under its basename. Must not overlap
+    // `reserved_path` or `validity_url_dirname`.
     pub cert_url_dirname: String,
+    // If set, the cert-chain and validity responses include an
+    // `Access-Control-Allow-Origin` header with this value, such as '*' or
+    // 'https://www.example.com', for setups where they are fetched from
+    // another origin. Signed responses never include it.
+    pub cors_allow_origin: Option<String>,
+    // If true, SXG responses include an `sxg-rs-cert-sha256` header with the
+    // base64url SHA-256 of the public key of the signing certificate, as
+    // printed by `gen-dev-cert`, e.g. to monitor certificate rotation.
+    // Defaults to false.
+    #[serde(default)]
+    pub expose_cert_sha256: bool,
+    // The origin of the fallback URL of SXGs, such as 'https://example.com',
+    // if it differs from `https://{html_host}`, e.g. when the backend is an
+    // internal hostname. Must be an absolute https origin.
+    pub fallback_host: Option<String>,
+    // If SXG generation fails, serve the upstream response unsigned instead of
+    // an error. Defaults to true.
+    #[serde(default = "default_true")]
+    pub fallback_to_unsigned: bool,
+    // Request headers, such as 'x-geo', forwarded to the backend unchanged. If
+    // repeated, their values are joined in order. All other request headers
+    // are dropped, as are hop-by-hop headers, Cookie, Proxy-Authorization,
+    // Via, and X-SXG-Signed-Host, even if listed here.
     pub forward_request_headers: BTreeSet<String>,
+    // If true, a cached header-integrity of a subresource is used only while
+    // the subresource's ETag (or else Last-Modified) is unchanged, per a HEAD
+    // request on each lookup. Defaults to false, which trusts the cache until
+    // the entry expires.
+    #[serde(default)]
+    pub header_integrity_revalidation: bool,
+    // Additional hosts signed by this worker, keyed by the `Host` of incoming
+    // requests, such as 'blog.example.com'. Requests to any other host are
+    // signed with `html_host`, `fallback_host`, and the certificates added by
+    // `SxgWorker::add_certificate`. All certificates must share one private
+    // key.
+    #[serde(default)]
+    pub hosts: BTreeMap<String, HostConfig>,
     pub html_host: String,
+    // If set, such as 'index.html', a path ending in a slash is fetched from
+    // the backend as this document within that directory, while it is signed
+    // with the directory as the fallback URL. Must be a single path segment.
+    pub index_document: Option<String>,
+    // If true, a `rel=canonical` link to the fallback URL is added to the
+    // signed Link header, after any links from the origin, so that search
+    // engines attribute the SXG to the unsigned page.
+    #[serde(default)]
+    pub inject_canonical: bool,
+    // The maximum number of SXGs being signed at once by http_server. Beyond
+    // this, responses are served unsigned. If unset, there is no limit.
+    pub max_concurrent_signings: Option<usize>,
+    // If set, at most this many preloads in the Link header of a page are
+    // processed, and the others are dropped without being fetched, so that a
+    // page with many preloads doesn't cause as many subresource fetches. If
+    // unset, 20.
+    pub max_subresources: Option<usize>,
+    // If set, the payload of each SXG is MICE-encoded in records of this many
+    // bytes, which must be a power of two up to 16384, so that a browser can
+    // verify a page sooner as it streams in, at the cost of a 32-byte proof
+    // per record. If unset, 16384.
+    pub mice_record_size: Option<usize>,
+    // If set, an OCSP response in storage keeps being stapled to the
+    // cert-chain for this many seconds past its nextUpdate while the OCSP
+    // responder can't be fetched, instead of failing the cert-chain response.
+    // Must not exceed 7 days. Not applied to OCSP Must-Staple certificates.
+    pub ocsp_stale_grace_seconds: Option<u64>,
+    // The certificate to sign with in preview mode, by the basename of its
+    // cert-url, which is the base64url SHA-256 of its end-entity certificate.
+    // Required in preview mode, so that the certificate previewed isn't left
+    // to the order in which certificates were added.
+    pub preview_cert: Option<String>,
+    // If true, SXGs are signed for previewing a new certificate before it is
+    // put into rotation: `preview_cert` is always used, the signatures expire
+    // within an hour, and the SXG responses carry an `sxg-rs-preview: 1`
+    // header. Defaults to false.
+    #[serde(default)]
+    pub preview_mode: bool,
     // This field is only needed by Fastly, because Cloudflare uses secret
     // env variables to store private key.
     // TODO: check if Fastly edge dictionary is ok to store private key.
     pub private_key_base64: Option<String>,
+    // If false, the `link` header of signed pages is signed as is, as if
+    // every caller set `skip_process_link`, so no subresource is fetched for
+    // its header-integrity. Defaults to true.
+    #[serde(default = "default_true")]
+    pub process_links: bool,
+    // If true, the signer of `SxgWorker::create_rust_signer` mixes random
+    // bytes into the nonce of each signature. Defaults to false, in which the
+    // nonce is derived from the key and the message per RFC 6979, so that the
+    // same payload signed at the same time yields identical SXG bytes.
+    #[serde(default)]
+    pub randomized_signatures: bool,
     pub reserved_path: String,
+    // If true, a `<base href>` of the fallback URL is inserted at the start of
+    // the `<head>` of signed HTML, so that its relative URLs resolve against
+    // the fallback URL, after any content-encoding is decoded. HTML that
+    // already has a `<base href>` is signed as is. Defaults to false.
+    #[serde(default)]
+    pub rewrite_base: bool,
+    // If false, responses with a 4xx status, such as a not-found page, are
+    // proxied unsigned with their status. Defaults to true.
+    #[serde(default = "default_true")]
+    pub sign_client_error_responses: bool,
+    // If true, responses with a 5xx status are signed too. Defaults to false,
+    // in which they are proxied unsigned with their status, so that a backend
+    // error page isn't signed and cached for the lifetime of the signature.
+    #[serde(default)]
+    pub sign_error_responses: bool,
+    // If set, only payloads with one of these content types, such as
+    // 'text/html', are signed, regardless of parameters such as charset.
+    // Others are proxied unsigned. If unset, any content type that the SXG
+    // spec permits is signed, which is all but nested SXGs.
+    pub signable_content_types: Option<BTreeSet<String>>,
+    // Regular expressions matched against the URL path, such as '\.html$'.
+    // If `signed_paths` is non-empty, only paths matching one of them are
+    // signed. Paths matching any of `unsigned_paths` are never signed.
+    // Responses that aren't signed are proxied as-is.
+    #[serde(default)]
+    pub signed_paths: PathPatterns,
+    #[serde(default)]
+    pub unsigned_paths: PathPatterns,
+    // The maximum lifetime of signatures, which is further limited by the
+    // cache-control headers of the payload. Must not exceed 7 days.
+    pub signature_duration_seconds: Option<u64>,
+    // If true, payloads whose cache-control has `no-cache` or `max-age=0` are
+    // not signed, since they are not meant to be reused. Defaults to true.
+    // Payloads with `private` or `no-store` are never signed, regardless.
+    #[serde(default = "default_true")]
+    pub skip_no_cache: bool,
+    // If true, payloads whose body grossly mismatches their content-type, such
+    // as binary data declared as text/html, are not signed, since browsers may
+    // reject them. Only the start of text and common image bodies is checked.
+    // Defaults to false.
+    #[serde(default)]
+    pub sniff_content_type: bool,
     pub strip_request_headers: BTreeSet<String>,
     pub strip_response_headers: BTreeSet<String>,
+    // If set, at most this many preloaded subresources of a page are fetched
+    // at once to compute their header-integrity. If unset, all of them are
+    // fetched at once. Must be at least 1.
+    pub subresource_fetch_concurrency: Option<usize>,
+    // If set, requests with this header, such as 'x-no-sxg', are treated as
+    // not accepting SXG, regardless of their Accept header, and hence are
+    // proxied unsigned.
+    pub sxg_opt_out_header: Option<String>,
+    // How the trailing slash of request paths is normalized, so that `/dir` and
+    // `/dir/` are signed as the same page. A request to sign for a path that
+    // isn't normalized is redirected to the normalized path, unsigned, since
+    // an SXG is only valid for its request URL. Defaults to 'preserve'.
+    #[serde(default)]
+    pub trailing_slash: TrailingSlash,
+    // If true, http_server signs a request for the host named by its
+    // `x-sxg-signed-host` header, which must be `html_host` or one of `hosts`,
+    // rather than for the host it was sent to. The header is only read from
+    // the routing layer in front of http_server; see its
+    // `--signed-host-source`. Defaults to false.
+    #[serde(default)]
+    pub trust_signed_host_header: bool,
+    // If set, the validity data served at the validity URL includes an
+    // update entry with this size, telling clients that an updated response
+    // of about this many bytes is available at the request URL. If unset,
+    // the validity data is an empty map.
+    pub validity_update_size: Option<u64>,
+    // The directory of the validity-url of signatures, which is served as
+    // `validity` in it.
     pub validity_url_dirname: String,
 }
 
+/// The signing context of one of the `hosts` of a [`Config`].
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HostConfig {
+    // The PEM files of the certificate and its issuer. They are only read by
+    // http_server; other platforms call `SxgWorker::add_certificate_for_host`.
+    pub cert_file: Option<String>,
+    pub fallback_host: Option<String>,
+    // Defaults to the key of this host in `hosts`.
+    pub html_host: Option<String>,
+    pub issuer_file: Option<String>,
+}
+
+/// The normalization of the trailing slash of request paths. The root path
+/// `/` is never changed.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailingSlash {
+    /// Paths are kept as requested.
+    Preserve,
+    /// A slash is appended to paths whose last segment has no extension,
+    /// such as `/dir`, but not `/page.html`.
+    Add,
+    /// Trailing slashes are removed, such as from `/dir/`.
+    Remove,
+}
+
+impl Default for TrailingSlash {
+    fn default() -> Self {
+        TrailingSlash::Preserve
+    }
+}
+
+/// A set of regular expressions, written in config as a list of strings.
+/// Invalid expressions fail deserialization, as does any expression without
+/// the path_patterns feature.
+#[cfg(feature = "path_patterns")]
+#[derive(Clone, Debug)]
+pub struct PathPatterns(RegexSet);
+
+#[cfg(not(feature = "path_patterns"))]
+#[derive(Clone, Debug, Default)]
+pub struct PathPatterns;
+
+#[cfg(feature = "path_patterns")]
+impl PathPatterns {
+    fn new(patterns: &[String]) -> Result<Self, regex::Error> {
+        RegexSet::new(patterns).map(PathPatterns)
+    }
+    fn patterns(&self) -> &[String] {
+        self.0.patterns()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn is_match(&self, path: &str) -> bool {
+        self.0.is_match(path)
+    }
+}
+
+#[cfg(not(feature = "path_patterns"))]
+impl PathPatterns {
+    fn new(patterns: &[String]) -> Result<Self> {
+        match patterns.first() {
+            Some(pattern) => Err(anyhow!(
+                "The path pattern {} needs the path_patterns feature.",
+                pattern
+            )),
+            None => Ok(PathPatterns),
+        }
+    }
+    fn patterns(&self) -> &[String] {
+        &[]
+    }
+    pub fn is_empty(&self) -> bool {
+        true
+    }
+    pub fn is_match(&self, _path: &str) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "path_patterns")]
+impl Default for PathPatterns {
+    fn default() -> Self {
+        PathPatterns(RegexSet::empty())
+    }
+}
+
+impl<'de> Deserialize<'de> for PathPatterns {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let patterns = Vec::<String>::deserialize(deserializer)?;
+        PathPatterns::new(&patterns).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for PathPatterns {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.patterns().serialize(serializer)
+    }
+}
+
 impl Config {
     pub fn normalize(&mut self) {
         self.cert_url_dirname = to_url_prefix(&self.cert_url_dirname);
         lowercase_all(&mut self.forward_request_headers);
         self.reserved_path = to_url_prefix(&self.reserved_path);
+        if let Some(types) = &mut self.signable_content_types {
+            lowercase_all(types);
+        }
         lowercase_all(&mut self.strip_request_headers);
         lowercase_all(&mut self.strip_response_headers);
+        if let Some(header) = &mut self.sxg_opt_out_header {
+            header.make_ascii_lowercase();
+        }
         self.validity_url_dirname = to_url_prefix(&self.validity_url_dirname);
     }
     /// Creates config from text
     pub fn new(input_yaml: &str) -> Result<Self> {
-        let mut input: Self = serde_yaml::from_str(input_yaml)?;
+        let input: Self = serde_yaml::from_str(input_yaml)?;
+        input.validate()
+    }
+    // Normalizes the config, and returns an error if any value is out of
+    // range. This is what `new` does after parsing, and what
+    // `SxgWorkerBuilder` does to the config it built.
+    pub(crate) fn validate(self) -> Result<Self> {
+        self.validate_all().map_err(|mut errors| errors.remove(0))
+    }
+    /// Normalizes the config like [`Config::new`], but returns every value
+    /// that is out of range rather than only the first, e.g. to report all
+    /// the problems of a config file at once.
+    pub fn validate_all(self) -> Result<Self, Vec<Error>> {
+        let mut input = self;
         input.normalize();
-        Ok(input)
+        let mut errors = vec![];
+        // Returns the value of `result`, or else records its error.
+        fn check<T>(errors: &mut Vec<Error>, result: Result<T>) -> Option<T> {
+            result.map_err(|e| errors.push(e)).ok()
+        }
+        if let Some(level) = input.brotli_level {
+            if cfg!(not(feature = "content_coding")) {
+                errors.push(anyhow!("brotli_level needs the content_coding feature."));
+            } else if level > 11 {
+                errors.push(anyhow!(
+                    "brotli_level is {}, which exceeds the limit 11.",
+                    level
+                ));
+            }
+        }
+        if !input.html_host.is_empty() {
+            if let Some(host) = check(&mut errors, parse_html_host(&input.html_host)) {
+                input.html_host = host;
+            }
+        }
+        if let Some(base) = &input.cert_url_base {
+            input.cert_url_base = check(&mut errors, parse_https_origin("cert_url_base", base));
+        }
+        if let Some(host) = &input.fallback_host {
+            input.fallback_host = check(&mut errors, parse_https_origin("fallback_host", host));
+        }
+        // The preset content is routed by these prefixes, so one inside
+        // another would be shadowed.
+        let dirnames = [
+            ("cert_url_dirname", &input.cert_url_dirname),
+            ("reserved_path", &input.reserved_path),
+            ("validity_url_dirname", &input.validity_url_dirname),
+        ];
+        for (i, (name, dirname)) in dirnames.iter().enumerate() {
+            for (other_name, other) in &dirnames[i + 1..] {
+                if dirname.starts_with(other.as_str()) || other.starts_with(dirname.as_str()) {
+                    errors.push(anyhow!(
+                        "{} {} and {} {} overlap; each must be outside the other.",
+                        name,
+                        dirname,
+                        other_name,
+                        other
+                    ));
+                }
+            }
+        }
+        input.hosts = std::mem::take(&mut input.hosts)
+            .into_iter()
+            .filter_map(|(host, mut host_config)| {
+                let host = check(&mut errors, parse_html_host(&host))?;
+                host_config.html_host = check(
+                    &mut errors,
+                    parse_html_host(host_config.html_host.as_deref().unwrap_or(&host)),
+                );
+                if let Some(fallback_host) = &host_config.fallback_host {
+                    host_config.fallback_host = check(
+                        &mut errors,
+                        parse_https_origin("fallback_host", fallback_host),
+                    );
+                }
+                Some((host, host_config))
+            })
+            .collect();
+        if let Some(size) = input.mice_record_size {
+            if let Err(e) = crate::mice::check_record_size(size) {
+                errors.push(anyhow!("mice_record_size is invalid: {}", e));
+            }
+        }
+        if let Some(seconds) = input.ocsp_stale_grace_seconds {
+            if seconds > SEVEN_DAYS.as_secs() {
+                errors.push(anyhow!(
+                    "ocsp_stale_grace_seconds is {}, which exceeds the limit {}.",
+                    seconds,
+                    SEVEN_DAYS.as_secs()
+                ));
+            }
+        }
+        if input.preview_mode && input.preview_cert.is_none() {
+            errors.push(anyhow!(
+                "preview_mode is set without preview_cert, the certificate to preview."
+            ));
+        }
+        for content_type in input.signable_content_types.iter().flatten() {
+            match parse_content_type_header(content_type) {
+                Ok(media_type) if media_type.parameters.is_empty() => (),
+                _ => errors.push(anyhow!(
+                    "signable_content_types has {:?}, which is not a type/subtype such as text/html.",
+                    content_type
+                )),
+            }
+        }
+        if let Some(seconds) = input.signature_duration_seconds {
+            if seconds > SEVEN_DAYS.as_secs() {
+                errors.push(anyhow!(
+                    "signature_duration_seconds is {}, which exceeds the limit {}.",
+                    seconds,
+                    SEVEN_DAYS.as_secs()
+                ));
+            }
+        }
+        if let Some(index_document) = &input.index_document {
+            if index_document.is_empty() || index_document.contains(&['/', '?', '#'][..]) {
+                errors.push(anyhow!(
+                    "index_document is \"{}\", which is not a single path segment.",
+                    index_document
+                ));
+            }
+        }
+        if input.subresource_fetch_concurrency == Some(0) {
+            errors.push(anyhow!(
+                "subresource_fetch_concurrency is 0, which must be at least 1."
+            ));
+        }
+        if errors.is_empty() {
+            Ok(input)
+        } else {
+            Err(errors)
+        }
     }
 }
 
+// Returns `input`, which must be a host with an optional port, such as
+// 'example.com:8443', with an internationalized domain name converted to its
+// ASCII form (A-labels), as used in URLs.
+pub(crate) fn parse_html_host(input: &str) -> Result<String> {
+    let url = Url::parse(&format!("https://{}/", input))
+        .map_err(|e| anyhow!("html_host {:?} is not a host: {}", input, e))?;
+    let host = match url.host_str() {
+        Some(host)
+            if url.username().is_empty()
+                && url.password().is_none()
+                && url.path() == "/"
+                && url.query().is_none()
+                && url.fragment().is_none() =>
+        {
+            host
+        }
+        _ => {
+            return Err(anyhow!(
+                "html_host {:?} is not a host, such as example.com.",
+                input
+            ))
+        }
+    };
+    Ok(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+// Returns the ASCII serialization of `input`, which must be an https origin
+// without a path, query, or credentials.
+fn parse_https_origin(name: &str, input: &str) -> Result<String> {
+    let url = Url::parse(input).map_err(|e| anyhow!("{} {:?} is not a URL: {}", name, input, e))?;
+    if url.scheme() != "https"
+        || !url.username().is_empty()
+        || url.password().is_some()
+        || url.path() != "/"
+        || url.query().is_some()
+        || url.fragment().is_some()
+    {
+        return Err(anyhow!(
+            "{} {:?} is not an https origin, such as https://example.com.",
+            name,
+            input
+        ));
+    }
+    Ok(url.origin().ascii_serialization())
+}
+
+fn default_true() -> bool {
+    true
+}
+
 fn lowercase_all(names: &mut BTreeSet<String>) {
     let old_names = std::mem::take(names);
     *names = old_names
@@ -80,7 +544,10 @@ strip_response_headers: ["Set-Cookie", "STRICT-TRANSPORT-SECURITY"]
 validity_url_dirname: "//.well-known/sxg-validity"
         "#;
         let config = Config::new(yaml).unwrap();
+        assert_eq!(config.brotli_level, None);
         assert_eq!(config.cert_url_dirname, "/.well-known/sxg-certs/");
+        assert!(config.fallback_to_unsigned);
+        assert_eq!(config.fallback_host, None);
         assert_eq!(
             config.forward_request_headers,
             ["cf-ipcountry", "user-agent"]
@@ -89,6 +556,14 @@ validity_url_dirname: "//.well-known/sxg-validity"
                 .collect()
         );
         assert_eq!(config.html_host, "my_domain.com".to_string());
+        assert_eq!(config.max_concurrent_signings, None);
+        assert_eq!(config.max_subresources, None);
+        assert_eq!(config.mice_record_size, None);
+        assert!(!config.sniff_content_type);
+        assert!(!config.trust_signed_host_header);
+        assert_eq!(config.ocsp_stale_grace_seconds, None);
+        assert_eq!(config.preview_cert, None);
+        assert!(config.process_links);
         assert_eq!(
             config.strip_request_headers,
             ["forwarded"].iter().map(|s| s.to_string()).collect()
@@ -101,6 +576,387 @@ validity_url_dirname: "//.well-known/sxg-validity"
                 .collect()
         );
         assert_eq!(config.reserved_path, "/.sxg/");
+        assert!(config.signed_paths.is_empty());
+        assert!(config.skip_no_cache);
+        assert!(config.unsigned_paths.is_empty());
+        assert_eq!(config.signature_duration_seconds, None);
+        assert_eq!(config.sxg_opt_out_header, None);
         assert_eq!(config.validity_url_dirname, "/.well-known/sxg-validity/");
     }
+    #[test]
+    fn signature_duration_limit() {
+        let yaml = |seconds: u64| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+signature_duration_seconds: {seconds}
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        assert_eq!(
+            Config::new(&yaml(604800))
+                .unwrap()
+                .signature_duration_seconds,
+            Some(604800)
+        );
+        assert!(Config::new(&yaml(604801)).is_err());
+    }
+    #[test]
+    fn fallback_host() {
+        let yaml = |fallback_host: &str| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+fallback_host: "{fallback_host}"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        let fallback_host = |input| Config::new(&yaml(input)).map(|c| c.fallback_host.unwrap());
+        assert_eq!(
+            fallback_host("https://WWW.Example.com/").unwrap(),
+            "https://www.example.com"
+        );
+        assert_eq!(
+            fallback_host("https://example.com:8443").unwrap(),
+            "https://example.com:8443"
+        );
+        assert!(fallback_host("example.com").is_err());
+        assert!(fallback_host("http://example.com").is_err());
+        assert!(fallback_host("https://example.com/path").is_err());
+        assert!(fallback_host("https://user@example.com").is_err());
+        assert!(fallback_host("https://example.com/?q").is_err());
+    }
+    #[test]
+    fn html_host() {
+        let yaml = |html_host: &str| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: "{html_host}"
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        let html_host = |input| Config::new(&yaml(input)).map(|c| c.html_host);
+        assert_eq!(html_host("例え.jp").unwrap(), "xn--r8jz45g.jp");
+        assert_eq!(
+            html_host("Bücher.example:8443").unwrap(),
+            "xn--bcher-kva.example:8443"
+        );
+        assert_eq!(html_host("Example.com").unwrap(), "example.com");
+        assert_eq!(html_host("").unwrap(), "");
+        assert!(html_host("example.com/path").is_err());
+        assert!(html_host("user@example.com").is_err());
+        assert!(html_host("exa mple.com").is_err());
+    }
+    #[test]
+    fn hosts() {
+        let yaml = r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+hosts:
+  Blog.example.com:
+    cert_file: blog.pem
+    fallback_host: "https://Www.blog.example.com/"
+  例え.jp:
+    html_host: www.例え.jp
+html_host: example.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+        "#;
+        let config = Config::new(yaml).unwrap();
+        let blog = &config.hosts["blog.example.com"];
+        assert_eq!(blog.cert_file.as_deref(), Some("blog.pem"));
+        assert_eq!(
+            blog.fallback_host.as_deref(),
+            Some("https://www.blog.example.com")
+        );
+        assert_eq!(blog.html_host.as_deref(), Some("blog.example.com"));
+        let jp = &config.hosts["xn--r8jz45g.jp"];
+        assert_eq!(jp.html_host.as_deref(), Some("www.xn--r8jz45g.jp"));
+        assert_eq!(jp.fallback_host, None);
+        assert!(Config::new(&yaml.replace("Blog.example.com:", "blog/:")).is_err());
+    }
+    #[test]
+    fn cert_url_base() {
+        let yaml = |cert_url_base: &str| {
+            format!(
+                r#"
+cert_url_base: "{cert_url_base}"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        assert_eq!(
+            Config::new(&yaml("https://Example.com/"))
+                .unwrap()
+                .cert_url_base
+                .unwrap(),
+            "https://example.com"
+        );
+        assert_eq!(
+            Config::new(&yaml("http://example.com"))
+                .unwrap_err()
+                .to_string(),
+            r#"cert_url_base "http://example.com" is not an https origin, such as https://example.com."#
+        );
+    }
+    #[cfg(feature = "content_coding")]
+    #[test]
+    fn brotli_level_limit() {
+        let yaml = |level: u32| {
+            format!(
+                r#"
+brotli_level: {level}
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        assert_eq!(Config::new(&yaml(11)).unwrap().brotli_level, Some(11));
+        assert!(Config::new(&yaml(12)).is_err());
+    }
+    #[test]
+    fn preview_mode_needs_preview_cert() {
+        let yaml = |preview: &str| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+{preview}
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        assert!(Config::new(&yaml("preview_mode: true")).is_err());
+        let config = Config::new(&yaml("preview_cert: abc\npreview_mode: true")).unwrap();
+        assert_eq!(config.preview_cert.as_deref(), Some("abc"));
+    }
+    #[test]
+    fn mice_record_size_limit() {
+        let yaml = |size: usize| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+mice_record_size: {size}
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        assert_eq!(
+            Config::new(&yaml(4096)).unwrap().mice_record_size,
+            Some(4096)
+        );
+        assert!(Config::new(&yaml(3)).is_err());
+        assert!(Config::new(&yaml(32768)).is_err());
+    }
+    #[test]
+    fn subresource_fetch_concurrency_minimum() {
+        let yaml = |concurrency: usize| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+subresource_fetch_concurrency: {concurrency}
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        assert_eq!(
+            Config::new(&yaml(1)).unwrap().subresource_fetch_concurrency,
+            Some(1)
+        );
+        assert!(Config::new(&yaml(0)).is_err());
+    }
+    #[test]
+    fn directory_urls() {
+        let yaml = |options: &str| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+{options}
+                "#
+            )
+        };
+        let config = Config::new(&yaml("")).unwrap();
+        assert_eq!(config.trailing_slash, TrailingSlash::Preserve);
+        assert_eq!(config.index_document, None);
+        let config = Config::new(&yaml("trailing_slash: add\nindex_document: index.html")).unwrap();
+        assert_eq!(config.trailing_slash, TrailingSlash::Add);
+        assert_eq!(config.index_document.as_deref(), Some("index.html"));
+        assert_eq!(
+            Config::new(&yaml("trailing_slash: remove"))
+                .unwrap()
+                .trailing_slash,
+            TrailingSlash::Remove
+        );
+        assert!(Config::new(&yaml("trailing_slash: always")).is_err());
+        for index_document in ["''", "a/index.html", "index.html?q=1"] {
+            assert!(Config::new(&yaml(&format!("index_document: {}", index_document))).is_err());
+        }
+    }
+    #[test]
+    fn path_patterns() {
+        let yaml = |patterns: &str| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+signed_paths: {patterns}
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        let config = Config::new(&yaml(r#"['\.html$', "^/$"]"#)).unwrap();
+        assert!(config.signed_paths.is_match("/index.html"));
+        assert!(config.signed_paths.is_match("/"));
+        assert!(!config.signed_paths.is_match("/api/data"));
+        assert!(Config::new(&yaml(r#"["/api/("]"#)).is_err());
+    }
+    #[test]
+    fn signable_content_types() {
+        let yaml = |types: &str| {
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+signable_content_types: {types}
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            )
+        };
+        let config = Config::new(&yaml(r#"["Text/HTML", "application/json"]"#)).unwrap();
+        assert_eq!(
+            config.signable_content_types,
+            Some(
+                ["application/json".to_string(), "text/html".to_string()]
+                    .iter()
+                    .cloned()
+                    .collect()
+            )
+        );
+        assert_eq!(
+            Config::new(&yaml("null")).unwrap().signable_content_types,
+            None
+        );
+        assert!(Config::new(&yaml(r#"["text/html; charset=utf-8"]"#)).is_err());
+        assert!(Config::new(&yaml(r#"["html"]"#)).is_err());
+    }
+    #[test]
+    fn validate_all_reports_every_error() {
+        let yaml = r#"
+brotli_level: 12
+cert_url_dirname: ".well-known/sxg-certs/"
+fallback_host: "http://example.com"
+forward_request_headers: []
+hosts:
+  "blog.example.com/path": {}
+html_host: my_domain.com
+reserved_path: ".sxg"
+signature_duration_seconds: 604801
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+        "#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let errors: Vec<String> = config
+            .clone()
+            .validate_all()
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[0].starts_with("brotli_level"));
+        assert!(errors[1].starts_with("fallback_host"));
+        assert!(errors[2].contains("blog.example.com/path"));
+        assert!(errors[3].starts_with("signature_duration_seconds"));
+        // `new` reports the first.
+        assert_eq!(Config::new(yaml).unwrap_err().to_string(), errors[0]);
+    }
+    #[test]
+    fn rejects_overlapping_dirnames() {
+        let yaml = |cert_url_dirname: &str, validity_url_dirname: &str| {
+            format!(
+                r#"
+cert_url_dirname: "{}"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: "{}"
+                "#,
+                cert_url_dirname, validity_url_dirname
+            )
+        };
+        let config =
+            Config::new(&yaml(".well-known/sxg/certs", ".well-known/sxg/validity")).unwrap();
+        assert_eq!(config.cert_url_dirname, "/.well-known/sxg/certs/");
+        assert_eq!(config.validity_url_dirname, "/.well-known/sxg/validity/");
+        assert_eq!(
+            Config::new(&yaml(".well-known/sxg", ".well-known/sxg/validity"))
+                .unwrap_err()
+                .to_string(),
+            "cert_url_dirname /.well-known/sxg/ and validity_url_dirname /.well-known/sxg/validity/ overlap; each must be outside the other."
+        );
+        assert!(Config::new(&yaml(".well-known/sxg", ".well-known/sxg")).is_err());
+        assert!(Config::new(&yaml(".sxg/certs", ".well-known/sxg-validity")).is_err());
+    }
 }
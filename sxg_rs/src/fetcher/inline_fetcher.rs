@@ -0,0 +1,106 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`Fetcher`] serving responses that are already in hand, such as the
+//! subresources output by a build step, so that their header-integrity is
+//! computed without fetching them again.
+
+use super::Fetcher;
+use crate::http::{HttpRequest, HttpResponse};
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+/// Put into the `fetcher` of the [`Runtime`](crate::runtime::Runtime) of a
+/// signing call. URLs are compared after parsing, so e.g. the host is
+/// case-insensitive. Requests for other URLs fail, unless a fallback is set,
+/// e.g. to fetch OCSP. Clones share the fallback.
+#[derive(Clone, Default)]
+pub struct InlineFetcher {
+    responses: HashMap<Url, HttpResponse>,
+    fallback: Option<Arc<dyn Fetcher>>,
+}
+
+impl InlineFetcher {
+    pub fn new(responses: HashMap<Url, HttpResponse>) -> Self {
+        InlineFetcher {
+            responses,
+            fallback: None,
+        }
+    }
+    /// Adds the response for requests to `url`.
+    pub fn with_response(mut self, url: &str, response: HttpResponse) -> Result<Self> {
+        let url =
+            Url::parse(url).map_err(|e| Error::new(e).context(format!("Invalid URL {:?}", url)))?;
+        self.responses.insert(url, response);
+        Ok(self)
+    }
+    /// Sends the requests for URLs without a response to `fallback`.
+    pub fn with_fallback(mut self, fallback: Box<dyn Fetcher>) -> Self {
+        self.fallback = Some(fallback.into());
+        self
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl Fetcher for InlineFetcher {
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let response = Url::parse(&request.url)
+            .ok()
+            .and_then(|url| self.responses.get(&url));
+        match (response, &self.fallback) {
+            (Some(response), _) => Ok(response.clone()),
+            (None, Some(fallback)) => fallback.fetch(request).await,
+            (None, None) => Err(anyhow!("No inline response for URL \"{}\"", request.url)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{get, RouteFetcher};
+    use super::*;
+    fn response(body: &[u8]) -> HttpResponse {
+        HttpResponse {
+            body: body.to_vec(),
+            headers: vec![],
+            status: 200,
+        }
+    }
+    #[tokio::test]
+    async fn serves_inline_responses() {
+        let fetcher = InlineFetcher::default()
+            .with_response("https://FOO.com/1", response(&[1, 2, 3]))
+            .unwrap();
+        assert_eq!(get(&fetcher, "https://foo.com/1").await.unwrap(), [1, 2, 3]);
+        assert!(get(&fetcher, "https://foo.com/2").await.is_err());
+        assert!(InlineFetcher::default()
+            .with_response("not a url", response(b""))
+            .is_err());
+    }
+    #[tokio::test]
+    async fn falls_back_for_other_urls() {
+        let fallback = RouteFetcher::default().with_route("https://foo.com/2", response(&[4]));
+        let fetcher = InlineFetcher::default()
+            .with_response("https://foo.com/1", response(&[1]))
+            .unwrap()
+            .with_fallback(Box::new(fallback.clone()));
+        assert_eq!(get(&fetcher, "https://foo.com/1").await.unwrap(), [1]);
+        assert_eq!(get(&fetcher, "https://foo.com/2").await.unwrap(), [4]);
+        fallback.assert_fetched(&["https://foo.com/2"]);
+    }
+}
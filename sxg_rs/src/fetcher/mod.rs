@@ -12,9 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod inline_fetcher;
 #[cfg(feature = "wasm")]
 pub mod js_fetcher;
 pub mod mock_fetcher;
+pub mod retry_fetcher;
+#[cfg(any(test, feature = "test_util"))]
+pub mod route_fetcher;
+
+pub use inline_fetcher::InlineFetcher;
+#[cfg(any(test, feature = "test_util"))]
+pub use route_fetcher::RouteFetcher;
 
 use crate::http::{HttpRequest, HttpResponse};
 use crate::utils::{MaybeSend, MaybeSync};
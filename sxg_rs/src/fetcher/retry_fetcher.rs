@@ -0,0 +1,171 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`Fetcher`] decorator that adds a per-request timeout and retries with
+//! exponential backoff. It uses tokio timers, so it needs a tokio runtime.
+
+use super::Fetcher;
+use crate::http::{HttpRequest, HttpResponse};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+
+pub struct RetryFetcher<F: Fetcher> {
+    inner: F,
+    timeout: Duration,
+    max_attempts: u32,
+    base_delay: Duration,
+}
+
+impl<F: Fetcher> RetryFetcher<F> {
+    /// Wraps `inner`, so that each attempt is aborted after `timeout`, and
+    /// failed attempts are retried up to a total of `max_attempts`. The n-th
+    /// retry waits `base_delay * 2^(n-1)`.
+    pub fn new(inner: F, timeout: Duration, max_attempts: u32, base_delay: Duration) -> Self {
+        RetryFetcher {
+            inner,
+            timeout,
+            max_attempts: max_attempts.max(1),
+            base_delay,
+        }
+    }
+    fn backoff(&self, retry: u32) -> Duration {
+        self.base_delay
+            .checked_mul(1 << retry.min(16))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl<F: Fetcher> Fetcher for RetryFetcher<F> {
+    /// Returns the first successful response, or the error from the last
+    /// attempt.
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let mut attempt = 0;
+        loop {
+            let error = match timeout(self.timeout, self.inner.fetch(request.clone())).await {
+                Ok(Ok(response)) => return Ok(response),
+                Ok(Err(e)) => e,
+                Err(_) => anyhow!(
+                    "Fetching {} timed out after {:?}",
+                    request.url,
+                    self.timeout
+                ),
+            };
+            attempt += 1;
+            if attempt >= self.max_attempts {
+                return Err(error);
+            }
+            sleep(self.backoff(attempt - 1)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // Fails the first `failures` fetches, and optionally sleeps on each fetch.
+    struct FlakyFetcher {
+        failures: u32,
+        delay: Duration,
+        calls: AtomicU32,
+    }
+
+    #[cfg_attr(feature = "wasm", async_trait(?Send))]
+    #[cfg_attr(not(feature = "wasm"), async_trait)]
+    impl Fetcher for FlakyFetcher {
+        async fn fetch(&self, _request: HttpRequest) -> Result<HttpResponse> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            sleep(self.delay).await;
+            if call < self.failures {
+                Err(anyhow!("failure #{}", call))
+            } else {
+                Ok(HttpResponse {
+                    body: vec![1, 2, 3],
+                    headers: vec![],
+                    status: 200,
+                })
+            }
+        }
+    }
+
+    fn flaky(failures: u32, delay: Duration) -> FlakyFetcher {
+        FlakyFetcher {
+            failures,
+            delay,
+            calls: AtomicU32::new(0),
+        }
+    }
+
+    fn request() -> HttpRequest {
+        HttpRequest {
+            body: vec![],
+            headers: vec![],
+            method: crate::http::Method::Get,
+            url: "https://foo.com/".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        let fetcher = RetryFetcher::new(
+            flaky(2, Duration::ZERO),
+            Duration::from_secs(1),
+            3,
+            Duration::from_millis(1),
+        );
+        assert_eq!(fetcher.fetch(request()).await.unwrap().body, vec![1, 2, 3]);
+        assert_eq!(fetcher.inner.calls.load(Ordering::SeqCst), 3);
+    }
+    #[tokio::test]
+    async fn returns_last_error() {
+        let fetcher = RetryFetcher::new(
+            flaky(5, Duration::ZERO),
+            Duration::from_secs(1),
+            3,
+            Duration::from_millis(1),
+        );
+        let error = fetcher.fetch(request()).await.unwrap_err();
+        assert_eq!(error.to_string(), "failure #2");
+        assert_eq!(fetcher.inner.calls.load(Ordering::SeqCst), 3);
+    }
+    #[tokio::test]
+    async fn times_out() {
+        let fetcher = RetryFetcher::new(
+            flaky(0, Duration::from_secs(10)),
+            Duration::from_millis(10),
+            2,
+            Duration::from_millis(1),
+        );
+        let error = fetcher.fetch(request()).await.unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+        assert_eq!(fetcher.inner.calls.load(Ordering::SeqCst), 2);
+    }
+    #[test]
+    fn backoff_is_exponential() {
+        let fetcher = RetryFetcher::new(
+            flaky(0, Duration::ZERO),
+            Duration::from_secs(1),
+            5,
+            Duration::from_millis(100),
+        );
+        assert_eq!(fetcher.backoff(0), Duration::from_millis(100));
+        assert_eq!(fetcher.backoff(1), Duration::from_millis(200));
+        assert_eq!(fetcher.backoff(3), Duration::from_millis(800));
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`Fetcher`] returning canned responses by URL, to test the signing
+//! pipeline without an HTTP server. Unlike [`super::mock_fetcher`], requests
+//! may arrive in any order and any number of times.
+
+use super::{Fetcher, InlineFetcher};
+use crate::http::{HttpRequest, HttpResponse};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+/// An [`InlineFetcher`] that also records the requests it receives. Clones
+/// share the recorded requests, so that a clone can be moved into a
+/// `Runtime` while the original is used to check what was fetched.
+#[derive(Clone, Default)]
+pub struct RouteFetcher {
+    routes: InlineFetcher,
+    requests: Arc<Mutex<Vec<HttpRequest>>>,
+}
+
+impl RouteFetcher {
+    pub fn new(routes: HashMap<Url, HttpResponse>) -> Self {
+        RouteFetcher {
+            routes: InlineFetcher::new(routes),
+            requests: Arc::new(Mutex::new(vec![])),
+        }
+    }
+    /// Adds a route, so that requests for `url` get `response`. Panics if
+    /// `url` is invalid.
+    pub fn with_route(self, url: &str, response: HttpResponse) -> Self {
+        let routes = self
+            .routes
+            .with_response(url, response)
+            .unwrap_or_else(|e| panic!("Invalid route: {:#}", e));
+        RouteFetcher { routes, ..self }
+    }
+    /// Returns the requests received so far, in order, including those
+    /// without a route.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+    /// Returns the URLs of the requests received so far, in order.
+    pub fn fetched_urls(&self) -> Vec<String> {
+        let requests = self.requests.lock().unwrap();
+        requests.iter().map(|request| request.url.clone()).collect()
+    }
+    /// Panics unless the URLs of the requests received so far are `urls`, in
+    /// order.
+    pub fn assert_fetched(&self, urls: &[&str]) {
+        assert_eq!(self.fetched_urls(), urls);
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl Fetcher for RouteFetcher {
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
+        self.requests.lock().unwrap().push(request.clone());
+        self.routes.fetch(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::get;
+    use super::*;
+    use crate::http::Method;
+    fn response(body: &[u8]) -> HttpResponse {
+        HttpResponse {
+            body: body.to_vec(),
+            headers: vec![],
+            status: 200,
+        }
+    }
+    #[tokio::test]
+    async fn returns_routed_responses() {
+        let fetcher = RouteFetcher::default()
+            .with_route("https://foo.com/1", response(&[1, 2, 3]))
+            .with_route("https://foo.com/2", response(&[7, 8, 9]));
+        assert_eq!(get(&fetcher, "https://foo.com/2").await.unwrap(), [7, 8, 9]);
+        assert_eq!(get(&fetcher, "https://foo.com/1").await.unwrap(), [1, 2, 3]);
+        assert_eq!(get(&fetcher, "https://foo.com/2").await.unwrap(), [7, 8, 9]);
+        fetcher.assert_fetched(&[
+            "https://foo.com/2",
+            "https://foo.com/1",
+            "https://foo.com/2",
+        ]);
+    }
+    #[tokio::test]
+    async fn errors_without_route() {
+        let fetcher = RouteFetcher::default().with_route("https://foo.com/", response(b""));
+        assert!(get(&fetcher, "https://bar.com/").await.is_err());
+        assert!(get(&fetcher, "not a url").await.is_err());
+        fetcher.assert_fetched(&["https://bar.com/", "not a url"]);
+    }
+    #[tokio::test]
+    async fn matches_normalized_url() {
+        let mut routes = HashMap::new();
+        routes.insert(Url::parse("https://FOO.com").unwrap(), response(b"foo"));
+        let fetcher = RouteFetcher::new(routes);
+        assert_eq!(get(&fetcher, "https://foo.com/").await.unwrap(), b"foo");
+    }
+    #[tokio::test]
+    async fn records_requests() {
+        let fetcher = RouteFetcher::default().with_route("https://foo.com/", response(b""));
+        let request = HttpRequest {
+            body: b"body".to_vec(),
+            headers: vec![("accept".into(), "*/*".into())],
+            method: Method::Post,
+            url: "https://foo.com/".into(),
+        };
+        fetcher.clone().fetch(request.clone()).await.unwrap();
+        assert_eq!(fetcher.requests(), vec![request]);
+    }
+}
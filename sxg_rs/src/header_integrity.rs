@@ -16,29 +16,50 @@ use crate::crypto::HashAlgorithm;
 use crate::fetcher::{Fetcher, NULL_FETCHER};
 use crate::headers::Headers;
 use crate::http::{HttpRequest, HttpResponse, Method};
-use crate::http_cache::{HttpCache, NullCache};
+use crate::http_cache::{self, HttpCache, NullCache};
 use crate::utils::{signed_headers_and_payload, MaybeSend, MaybeSync};
 use anyhow::{anyhow, Error, Result};
 use async_trait::async_trait;
 use once_cell::sync::Lazy;
 use std::collections::BTreeSet;
+use std::sync::Mutex;
 use url::Url;
 
+/// The default of [`HeaderIntegrityFetcher::max_subresources`].
+pub const DEFAULT_MAX_SUBRESOURCES: usize = 20;
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 pub trait HeaderIntegrityFetcher: MaybeSend + MaybeSync {
     async fn fetch(&self, url: &str) -> Result<String>;
+    /// The maximum number of `fetch`es in flight while processing the Link
+    /// header of a page, or None for no limit.
+    fn concurrency(&self) -> Option<usize> {
+        None
+    }
+    /// The maximum number of preloads in the Link header of a page that are
+    /// processed. The others are dropped without being fetched.
+    fn max_subresources(&self) -> usize {
+        DEFAULT_MAX_SUBRESOURCES
+    }
 }
 
+/// `brotli_level` must match the one used to sign the subresources, since the
+/// header-integrity covers the Brotli-compressed payload.
 pub fn new_fetcher<'a, C: HttpCache>(
     subresource_fetcher: &'a dyn Fetcher,
     header_integrity_cache: C,
     strip_response_headers: &'a BTreeSet<String>,
+    brotli_level: Option<u32>,
 ) -> HeaderIntegrityFetcherImpl<'a, C> {
     HeaderIntegrityFetcherImpl {
         subresource_fetcher,
         header_integrity_cache,
         strip_response_headers,
+        brotli_level,
+        revalidate: false,
+        concurrency: None,
+        max_subresources: DEFAULT_MAX_SUBRESOURCES,
     }
 }
 
@@ -46,6 +67,34 @@ pub struct HeaderIntegrityFetcherImpl<'a, C: HttpCache> {
     subresource_fetcher: &'a dyn Fetcher,
     header_integrity_cache: C,
     strip_response_headers: &'a BTreeSet<String>,
+    brotli_level: Option<u32>,
+    revalidate: bool,
+    concurrency: Option<usize>,
+    max_subresources: usize,
+}
+
+impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
+    /// If `revalidate` is true, each lookup first sends a HEAD request for
+    /// the subresource, and uses the cached header-integrity only if it was
+    /// computed from a response with the same ETag, or else Last-Modified.
+    /// This costs a request per lookup, but notices a subresource that
+    /// changed without changing its URL.
+    pub fn with_revalidation(mut self, revalidate: bool) -> Self {
+        self.revalidate = revalidate;
+        self
+    }
+    /// Limits the number of subresources of a page that are fetched at once,
+    /// or with None, fetches all of them at once.
+    pub fn with_concurrency(mut self, concurrency: Option<usize>) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+    /// Limits the number of preloads of a page that are processed, or with
+    /// None, to [`DEFAULT_MAX_SUBRESOURCES`].
+    pub fn with_max_subresources(mut self, max_subresources: Option<usize>) -> Self {
+        self.max_subresources = max_subresources.unwrap_or(DEFAULT_MAX_SUBRESOURCES);
+        self
+    }
 }
 
 // A synthesized error response that can be cached, to prevent overloading the
@@ -60,7 +109,15 @@ static ERROR_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl<'a, C: HttpCache> HeaderIntegrityFetcher for HeaderIntegrityFetcherImpl<'a, C> {
     async fn fetch(&self, url: &str) -> Result<String> {
-        let integrity_response = match self.cache_get(url).await {
+        let cached = match self.current_validator(url).await {
+            Some(validator) => {
+                self.header_integrity_cache
+                    .get_validated(url, &validator)
+                    .await
+            }
+            None => self.cache_get(url).await,
+        };
+        let integrity_response = match cached {
             // Use cached header-integrity.
             Ok(response @ HttpResponse { status: 200, .. }) => response,
             // Respect the cached error status; don't fetch from origin.
@@ -78,10 +135,19 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcher for HeaderIntegrityFetcherImpl<'a,
                                     ..response
                                 }
                             }
-                            Err(err) => Self::error_response(&format!(
-                                "Error computing header-integrity for {}: {:#}",
-                                url, err
-                            )),
+                            Err(err) => HttpResponse {
+                                // Keep the validator, so that the error is
+                                // cached until the subresource changes.
+                                headers: [
+                                    ERROR_RESPONSE.headers.clone(),
+                                    validator_headers(&response),
+                                ]
+                                .concat(),
+                                ..Self::error_response(&format!(
+                                    "Error computing header-integrity for {}: {:#}",
+                                    url, err
+                                ))
+                            },
                         }
                     }
                     Err(err) => Self::error_response(&format!(
@@ -95,6 +161,12 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcher for HeaderIntegrityFetcherImpl<'a,
         };
         Self::extract_integrity(integrity_response)
     }
+    fn concurrency(&self) -> Option<usize> {
+        self.concurrency
+    }
+    fn max_subresources(&self) -> usize {
+        self.max_subresources
+    }
 }
 
 impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
@@ -104,8 +176,24 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
     async fn cache_put(&self, url: &str, response: &HttpResponse) -> Result<()> {
         self.header_integrity_cache.put(url, response).await
     }
+    // If revalidation is enabled, returns the validator of the current
+    // version of the subresource, per a HEAD request. Returns None if the
+    // request fails or the response has no validator.
+    async fn current_validator(&self, url: &str) -> Option<String> {
+        if !self.revalidate {
+            return None;
+        }
+        let response = self.request_subresource(Method::Head, url).await.ok()?;
+        if response.status != 200 {
+            return None;
+        }
+        http_cache::validator(&response).map(str::to_string)
+    }
     /// Fetches unsigned subresource from URL.
     async fn fetch_subresource(&self, url: &str) -> Result<HttpResponse> {
+        self.request_subresource(Method::Get, url).await
+    }
+    async fn request_subresource(&self, method: Method, url: &str) -> Result<HttpResponse> {
         // A generic Accept header, for use in populating the
         // subresource integrity cache. This will be cached and reused for
         // other clients, so there is no need to proxy the client's accept
@@ -115,7 +203,7 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
         let request = HttpRequest {
             body: vec![],
             headers: vec![("Accept".into(), ACCEPT.into())],
-            method: Method::Get,
+            method,
             url: url.into(),
         };
         self.subresource_fetcher.fetch(request).await
@@ -138,7 +226,7 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
         // TODO: Figure out how to reduce the amount of data cloned.
         let payload_headers = Headers::new(response.headers.clone(), self.strip_response_headers);
         let mut header_integrity_fetcher =
-            new_fetcher(&NULL_FETCHER, NullCache, self.strip_response_headers);
+            new_fetcher(&NULL_FETCHER, NullCache, self.strip_response_headers, None);
         let (signed_headers, _) = signed_headers_and_payload(
             &fallback_base,
             response.status,
@@ -146,6 +234,9 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
             &response.body,
             &mut header_integrity_fetcher,
             skip_process_link,
+            self.brotli_level,
+            // Subresources are assumed to be signed with the default record size.
+            crate::mice::MAX_RECORD_SIZE,
         )
         .await?;
         Ok([
@@ -170,34 +261,89 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
     }
 }
 
+// Returns the ETag and Last-Modified headers of the response.
+fn validator_headers(response: &HttpResponse) -> Vec<(String, String)> {
+    response
+        .headers
+        .iter()
+        .filter(|(k, _)| k.eq_ignore_ascii_case("etag") || k.eq_ignore_ascii_case("last-modified"))
+        .cloned()
+        .collect()
+}
+
+// Records the header-integrity of each subresource that the wrapped fetcher
+// computes successfully, so that it can be reported alongside the SXG.
+pub(crate) struct RecordingFetcher<F: HeaderIntegrityFetcher> {
+    inner: F,
+    records: Mutex<Vec<(String, String)>>,
+}
+
+impl<F: HeaderIntegrityFetcher> RecordingFetcher<F> {
+    pub(crate) fn new(inner: F) -> Self {
+        RecordingFetcher {
+            inner,
+            records: Mutex::new(vec![]),
+        }
+    }
+    // Returns the (url, header-integrity) pairs, sorted by URL, since the
+    // fetches are made concurrently.
+    pub(crate) fn into_records(self) -> Vec<(String, String)> {
+        let mut records = self.records.into_inner().unwrap_or_default();
+        records.sort();
+        records
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl<F: HeaderIntegrityFetcher> HeaderIntegrityFetcher for RecordingFetcher<F> {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        let integrity = self.inner.fetch(url).await?;
+        if let Ok(mut records) = self.records.lock() {
+            records.push((url.to_string(), integrity.clone()));
+        }
+        Ok(integrity)
+    }
+    fn concurrency(&self) -> Option<usize> {
+        self.inner.concurrency()
+    }
+    fn max_subresources(&self) -> usize {
+        self.inner.max_subresources()
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::fetcher::NULL_FETCHER;
+    use crate::fetcher::{RouteFetcher, NULL_FETCHER};
     use crate::http_cache::NullCache;
     use anyhow::{anyhow, Result};
     use std::collections::HashMap;
-    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     static EMPTY_SET: Lazy<BTreeSet<String>> = Lazy::new(BTreeSet::new);
 
     // For use in other modules' tests.
     pub fn null_integrity_fetcher() -> HeaderIntegrityFetcherImpl<'static, NullCache> {
-        new_fetcher(&NULL_FETCHER, NullCache {}, &EMPTY_SET)
+        new_fetcher(&NULL_FETCHER, NullCache {}, &EMPTY_SET, None)
     }
 
-    const TEST_URL: &str = "https://signed-exchange-testing.dev/sxgs/image.jpg";
-    static TEST_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
+    pub const TEST_URL: &str = "https://signed-exchange-testing.dev/sxgs/image.jpg";
+    pub static TEST_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
         // The result of `curl $TEST_URL`.
         body: include_bytes!("static/image.jpg").to_vec(),
         // The headers from `dump-signedexchange -payload=false -uri $TEST_URL`.
         headers: vec![("content-type".into(), "image/jpeg".into())],
         status: 200,
     });
-    // The result of `dump-signedexchange -headerIntegrity -uri $TEST_URL`.
-    const EXPECTED_HEADER_INTEGRITY: &str = "sha256-ypu/jZuGukVK2EEGlEkiN92qQDg3Zw6Fb0kCtees1bo=";
+    // The result of `dump-signedexchange -headerIntegrity -uri $TEST_URL`
+    // was sha256-ypu/jZuGukVK2EEGlEkiN92qQDg3Zw6Fb0kCtees1bo=. This differs
+    // because sxg-rs also signs the content-length of the MICE-encoded
+    // payload, 103691.
+    pub const EXPECTED_HEADER_INTEGRITY: &str =
+        "sha256-PIcyGxn/rTNYcQIGq3zLqHK+2AkT1lmKO2OcDDJ+YEw=";
 
-    struct FakeFetcher<'a>(&'a HttpResponse);
+    pub struct FakeFetcher<'a>(pub &'a HttpResponse);
 
     #[cfg_attr(feature = "wasm", async_trait(?Send))]
     #[cfg_attr(not(feature = "wasm"), async_trait)]
@@ -219,11 +365,52 @@ pub mod tests {
             &*FAKE_FETCHER_WITH_TEST_RESPONSE,
             NullCache {},
             &strip_response_headers,
+            None,
+        );
+        assert_eq!(
+            fetcher.fetch(TEST_URL).await.unwrap(),
+            EXPECTED_HEADER_INTEGRITY
         );
+    }
+
+    #[tokio::test]
+    async fn computes_integrity_of_routed_url() {
+        let route_fetcher = RouteFetcher::default().with_route(TEST_URL, TEST_RESPONSE.clone());
+        let strip_response_headers = BTreeSet::new();
+        let fetcher = new_fetcher(&route_fetcher, NullCache {}, &strip_response_headers, None);
         assert_eq!(
             fetcher.fetch(TEST_URL).await.unwrap(),
             EXPECTED_HEADER_INTEGRITY
         );
+        assert!(fetcher
+            .fetch("https://signed-exchange-testing.dev/sxgs/missing.jpg")
+            .await
+            .is_err());
+        route_fetcher.assert_fetched(&[
+            TEST_URL,
+            "https://signed-exchange-testing.dev/sxgs/missing.jpg",
+        ]);
+    }
+    #[tokio::test]
+    async fn records_computed_integrity() {
+        let strip_response_headers = BTreeSet::new();
+        let fetcher = RecordingFetcher::new(new_fetcher(
+            &*FAKE_FETCHER_WITH_TEST_RESPONSE,
+            NullCache {},
+            &strip_response_headers,
+            None,
+        ));
+        fetcher.fetch(TEST_URL).await.unwrap();
+        assert_eq!(
+            fetcher.into_records(),
+            vec![(TEST_URL.to_string(), EXPECTED_HEADER_INTEGRITY.to_string())]
+        );
+    }
+    #[tokio::test]
+    async fn does_not_record_errors() {
+        let fetcher = RecordingFetcher::new(null_integrity_fetcher());
+        assert!(fetcher.fetch(TEST_URL).await.is_err());
+        assert_eq!(fetcher.into_records(), vec![]);
     }
 
     struct InMemoryCache<'a>(&'a Mutex<HashMap<String, HttpResponse>>);
@@ -264,6 +451,7 @@ pub mod tests {
             &*FAKE_FETCHER_WITH_TEST_RESPONSE,
             cache,
             &strip_response_headers,
+            None,
         );
 
         assert_eq!(fetcher.fetch(TEST_URL).await.unwrap(), "sha256-blah",);
@@ -284,6 +472,7 @@ pub mod tests {
             &*FAKE_FETCHER_WITH_TEST_RESPONSE,
             cache,
             &strip_response_headers,
+            None,
         );
 
         assert_eq!(
@@ -300,6 +489,7 @@ pub mod tests {
             &*FAKE_FETCHER_WITH_TEST_RESPONSE,
             InMemoryCache(&store),
             &strip_response_headers,
+            None,
         );
 
         let _ = fetcher.fetch(TEST_URL).await;
@@ -308,6 +498,80 @@ pub mod tests {
             EXPECTED_HEADER_INTEGRITY.as_bytes(),
         );
     }
+    // Serves TEST_RESPONSE with the given ETag, counting the GET requests.
+    struct VersionedFetcher {
+        etag: Mutex<&'static str>,
+        gets: AtomicUsize,
+    }
+
+    impl VersionedFetcher {
+        fn new(etag: &'static str) -> Self {
+            VersionedFetcher {
+                etag: Mutex::new(etag),
+                gets: AtomicUsize::new(0),
+            }
+        }
+        fn gets(&self) -> usize {
+            self.gets.load(Ordering::SeqCst)
+        }
+    }
+
+    #[cfg_attr(feature = "wasm", async_trait(?Send))]
+    #[cfg_attr(not(feature = "wasm"), async_trait)]
+    impl Fetcher for VersionedFetcher {
+        async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
+            if request.method == Method::Get {
+                self.gets.fetch_add(1, Ordering::SeqCst);
+            }
+            let mut response = TEST_RESPONSE.clone();
+            let etag = self.etag.lock().unwrap().to_string();
+            response.headers.push(("etag".into(), etag));
+            Ok(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn revalidates_by_etag() {
+        let store = Mutex::new(HashMap::new());
+        let strip_response_headers = BTreeSet::new();
+        let subresource = VersionedFetcher::new("\"v1\"");
+        let fetcher = new_fetcher(
+            &subresource,
+            InMemoryCache(&store),
+            &strip_response_headers,
+            None,
+        )
+        .with_revalidation(true);
+        let v1 = fetcher.fetch(TEST_URL).await.unwrap();
+        assert_eq!(subresource.gets(), 1);
+        // Unchanged, so the cached integrity is used after only a HEAD.
+        assert_eq!(fetcher.fetch(TEST_URL).await.unwrap(), v1);
+        assert_eq!(subresource.gets(), 1);
+        // Changed, so the integrity is recomputed. The ETag is among the
+        // signed headers, so the integrity differs.
+        *subresource.etag.lock().unwrap() = "\"v2\"";
+        let v2 = fetcher.fetch(TEST_URL).await.unwrap();
+        assert_eq!(subresource.gets(), 2);
+        assert_ne!(v1, v2);
+        assert_eq!(fetcher.fetch(TEST_URL).await.unwrap(), v2);
+        assert_eq!(subresource.gets(), 2);
+    }
+    #[tokio::test]
+    async fn ignores_etag_without_revalidation() {
+        let store = Mutex::new(HashMap::new());
+        let strip_response_headers = BTreeSet::new();
+        let subresource = VersionedFetcher::new("\"v1\"");
+        let fetcher = new_fetcher(
+            &subresource,
+            InMemoryCache(&store),
+            &strip_response_headers,
+            None,
+        );
+        let v1 = fetcher.fetch(TEST_URL).await.unwrap();
+        *subresource.etag.lock().unwrap() = "\"v2\"";
+        assert_eq!(fetcher.fetch(TEST_URL).await.unwrap(), v1);
+        assert_eq!(subresource.gets(), 1);
+    }
     #[tokio::test]
     async fn out_of_order() {
         use crate::utils::tests::{out_of_order, OutOfOrderState};
@@ -346,6 +610,7 @@ pub mod tests {
             &*FAKE_FETCHER_WITH_TEST_RESPONSE,
             cache,
             &strip_response_headers,
+            None,
         );
 
         stream::iter(1..=2)
@@ -12,13 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::crypto::HashAlgorithm;
 use crate::header_integrity::HeaderIntegrityFetcher;
 use crate::http::HeaderFields;
 use crate::http_parser::{
-    media_type::MediaType, parse_accept_header, parse_cache_control_header,
-    parse_content_type_header, parse_vary_header,
+    media_type::MediaType, parse_accept_header, parse_cache_control_directives,
+    parse_cache_control_header, parse_content_type_header, parse_vary_header,
 };
 use crate::link::process_link_header;
+use crate::structured_header::{ShItem, ShParamList};
+use crate::utils::console_log;
 use crate::MAX_PAYLOAD_SIZE;
 use anyhow::{anyhow, ensure, Result};
 use once_cell::sync::Lazy;
@@ -30,7 +33,7 @@ use url::Url;
 pub struct Headers(HashMap<String, String>);
 
 /// The preference level of how requestors accepts SXG content.
-#[derive(Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
 pub enum AcceptLevel {
     /// The Accept header does not explicitly mention SXG, even when they accept `*/*`.
     RejectsSxg,
@@ -51,6 +54,15 @@ const USER_AGENT: &str = "Mozilla/5.0 (Linux; Android 6.0.1; Nexus 5X Build/MMB2
 /// containing this value.
 pub const VIA_SXGRS: &str = "sxgrs";
 
+// The AMP caches, per the `amp-cache-transform` request header, for which
+// SXGs may be served.
+const AMP_CACHES: &[&str] = &["any", "google"];
+// The version of AMP transforms that the signed content is served with.
+const AMP_CACHE_TRANSFORM_VERSION: u64 = 1;
+// The names, per the `accept-signature` request header, of the only algorithm
+// that SXGs are signed with: its label, and its RFC 8446 signature scheme.
+const SIGNATURE_ALGORITHMS: &[&str] = &["ecdsa_p256_sha256", "ecdsa_secp256r1_sha256"];
+
 impl Headers {
     pub fn new(data: HeaderFields, strip_headers: &BTreeSet<String>) -> Self {
         let mut headers = Headers(HashMap::new());
@@ -77,17 +89,12 @@ impl Headers {
     pub fn into_inner(self) -> HashMap<String, String> {
         self.0
     }
-    pub fn forward_to_origin_server(
-        self,
-        required_accept_level: AcceptLevel,
-        forwarded_header_names: &BTreeSet<String>,
-    ) -> Result<HeaderFields> {
-        if self.0.contains_key("authorization") {
-            // We should not sign personalized content, but we cannot anonymize this request per
-            // https://datatracker.ietf.org/doc/html/rfc7235#section-4.2:
-            // "A proxy forwarding a request MUST NOT modify any Authorization fields in that request."
-            return Err(anyhow!("The request contains an Authorization header."));
-        }
+    /// Returns how much the requestor accepts SXG, per its Accept header. If
+    /// `opt_out_header` is present in the request, regardless of its value,
+    /// the requestor is treated as rejecting SXG. So is a requestor whose
+    /// `accept-signature` header doesn't list `ecdsa_p256_sha256`; without
+    /// that header, as sent by browsers, any signature is accepted.
+    pub fn accept_level(&self, opt_out_header: Option<&str>) -> Result<AcceptLevel> {
         let accept = self
             .0
             .get("accept")
@@ -106,60 +113,302 @@ impl Headers {
                 }
             }
         }
-        ensure!(actual_accept_level >= required_accept_level);
+        if let Some(opt_out_header) = opt_out_header {
+            if self.0.contains_key(opt_out_header) {
+                actual_accept_level = AcceptLevel::RejectsSxg;
+            }
+        }
+        if let Some(accept_signature) = self.0.get("accept-signature") {
+            if !accepts_signature_algorithm(accept_signature) {
+                actual_accept_level = AcceptLevel::RejectsSxg;
+            }
+        }
+        Ok(actual_accept_level)
+    }
+    /// Negotiates the transform for AMP caches per the `amp-cache-transform`
+    /// request header, as specified by
+    /// https://github.com/ampproject/amphtml/blob/main/docs/spec/amp-cache-transform.md.
+    /// Returns the value of the `amp-cache-transform` response header to serve
+    /// the SXG with, or None if the request has no such header. Returns an
+    /// error if none of the offered caches and versions is supported, in which
+    /// case the response should be proxied unsigned.
+    pub fn amp_cache_transform(&self) -> Result<Option<String>> {
+        let value = match self.0.get("amp-cache-transform") {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let list = ShParamList::parse(value)
+            .map_err(|e| e.context("Failed to parse the amp-cache-transform header"))?;
+        for item in list.iter() {
+            if !AMP_CACHES.contains(&item.primary_id.as_ref()) {
+                continue;
+            }
+            // Without a `v` parameter, version 1 is assumed.
+            let accepted = match item.iter().find(|(name, _)| name == "v") {
+                None => accepts_amp_version("1", AMP_CACHE_TRANSFORM_VERSION),
+                Some((_, Some(ShItem::String(versions)))) => {
+                    accepts_amp_version(versions, AMP_CACHE_TRANSFORM_VERSION)
+                }
+                Some(_) => false,
+            };
+            if accepted {
+                return Ok(Some(format!(
+                    r#"{};v="{}""#,
+                    item.primary_id, AMP_CACHE_TRANSFORM_VERSION
+                )));
+            }
+        }
+        Err(anyhow!(
+            r#"The amp-cache-transform header "{}" has no supported version."#,
+            value
+        ))
+    }
+    pub fn forward_to_origin_server(
+        self,
+        required_accept_level: AcceptLevel,
+        forwarded_header_names: &BTreeSet<String>,
+        opt_out_header: Option<&str>,
+    ) -> Result<HeaderFields> {
+        if self.0.contains_key("authorization") {
+            // We should not sign personalized content, but we cannot anonymize this request per
+            // https://datatracker.ietf.org/doc/html/rfc7235#section-4.2:
+            // "A proxy forwarding a request MUST NOT modify any Authorization fields in that request."
+            return Err(anyhow!("The request contains an Authorization header."));
+        }
+        let actual_accept_level = self.accept_level(opt_out_header)?;
+        ensure!(
+            actual_accept_level >= required_accept_level,
+            "The requestor {:?}, but {:?} is required.",
+            actual_accept_level,
+            required_accept_level
+        );
         // Set Via per https://tools.ietf.org/html/rfc7230#section-5.7.1
         let mut via = VIA_SXGRS.to_string();
         if let Some(upstream_via) = self.0.get("via") {
             via = format!("{}, {}", upstream_via, via);
         }
+        // Even if listed in `forwarded_header_names`, hop-by-hop headers
+        // describe the connection to this worker, and credentials would
+        // personalize the signed content.
+        let connection = self.connection_headers();
         // new_headers is ordered to make testing easier.
         let mut new_headers: HashMap<String, String> = self
             .0
             .into_iter()
-            .filter_map(|(k, v)| {
-                let v = if forwarded_header_names.contains(&k) {
-                    v
-                } else {
-                    return None;
-                };
-                Some((k, v))
+            .filter(|(k, _)| {
+                forwarded_header_names.contains(k)
+                    && !HOP_BY_HOP_HEADERS.contains(k.as_str())
+                    && !UNFORWARDED_REQUEST_HEADERS.contains(k.as_str())
+                    && !connection.contains(k)
             })
             .collect();
-        let default_values = vec![("user-agent", USER_AGENT), ("via", &via)];
-        for (k, v) in default_values {
-            if !new_headers.contains_key(k) {
-                new_headers.insert(k.to_string(), v.to_string());
-            }
+        if !new_headers.contains_key("user-agent") {
+            new_headers.insert("user-agent".to_string(), USER_AGENT.to_string());
         }
+        // Always replaced, so that network loops are detected.
+        new_headers.insert("via".to_string(), via);
         Ok(new_headers.into_iter().collect())
     }
-    pub fn validate_as_sxg_payload(&self) -> Result<()> {
+    // Removes the hop-by-hop headers, which describe the connection to the
+    // origin rather than the response, and hence are invalid inside an SXG per
+    // https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-uncached-header-fields.
+    pub fn remove_hop_by_hop_headers(&mut self) {
+        let connection = self.connection_headers();
+        self.0
+            .retain(|k, _| !HOP_BY_HOP_HEADERS.contains(k.as_str()) && !connection.contains(k));
+    }
+    // Sets the content-type to `text/html` if the origin didn't send one,
+    // since the payload of an SXG must have a content-type.
+    pub fn default_content_type(&mut self) {
+        self.0
+            .entry("content-type".to_string())
+            .or_insert_with(|| "text/html".to_string());
+    }
+    /// Rewrites the Link header to the preloads allowed by the SXG cache, each
+    /// followed by `allowed-alt-sxg` links with the header-integrity of the
+    /// subresources it refers to, so that browsers can prefetch their SXGs.
+    /// Malformed or disallowed entries are dropped, keeping the rest; if none
+    /// remain, the header is removed. `fallback_url` is the base for relative
+    /// URLs.
+    pub async fn process_link_header(
+        &mut self,
+        fallback_url: &Url,
+        header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
+    ) {
+        if let Some(value) = self.0.remove("link") {
+            let link = process_link_header(&value, fallback_url, header_integrity_fetcher).await;
+            if !link.is_empty() {
+                self.0.insert("link".to_string(), link);
+            }
+        }
+    }
+    /// Appends a `rel=canonical` link to `url` to the Link header, keeping
+    /// any existing links.
+    pub fn add_canonical_link(&mut self, url: &Url) {
+        let link = self.0.entry("link".to_string()).or_default();
+        if !link.is_empty() {
+            link.push(',');
+        }
+        link.push_str(&format!("<{}>;rel=canonical", url));
+    }
+    /// Whether the content-type is `text/html`. Link processing and
+    /// [`process_html`](crate::process_html) only apply to HTML payloads.
+    pub fn is_html(&self) -> bool {
+        self.0.get("content-type").map_or(false, |t| {
+            matches!(parse_content_type_header(t),
+                     Ok(MediaType {primary_type, sub_type, ..})
+                         if primary_type.eq_ignore_ascii_case("text") && sub_type.eq_ignore_ascii_case("html"))
+        })
+    }
+    /// Returns an error unless the content-type, without its parameters, is
+    /// one of `signable_types`, which are lowercase, such as `text/html`.
+    pub fn check_signable_content_type(&self, signable_types: &BTreeSet<String>) -> Result<()> {
+        let content_type = self
+            .0
+            .get("content-type")
+            .ok_or_else(|| anyhow!("The content-type header is missing."))?;
+        let essence = match parse_content_type_header(content_type) {
+            Ok(MediaType {
+                primary_type,
+                sub_type,
+                ..
+            }) => format!("{}/{}", primary_type, sub_type).to_ascii_lowercase(),
+            Err(_) => {
+                return Err(anyhow!(
+                    r#"The content-type header "{}" is invalid."#,
+                    content_type
+                ))
+            }
+        };
+        if signable_types.contains(&essence) {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "The content-type {} is not one of signable_content_types.",
+                essence
+            ))
+        }
+    }
+    // Whether the payload should be Brotli-compressed: only if its media type
+    // isn't already compressed. Any content coding applied by the origin is
+    // undone before signing, so it doesn't matter here.
+    pub fn is_compressible(&self) -> bool {
+        match self
+            .0
+            .get("content-type")
+            .map(|t| parse_content_type_header(t))
+        {
+            Some(Ok(MediaType {
+                primary_type,
+                sub_type,
+                ..
+            })) => {
+                let primary_type = primary_type.to_ascii_lowercase();
+                let sub_type = sub_type.to_ascii_lowercase();
+                match primary_type.as_str() {
+                    "image" => sub_type == "svg+xml",
+                    "audio" | "video" => false,
+                    "font" => !sub_type.starts_with("woff"),
+                    "application" => !COMPRESSED_APPLICATION_TYPES.contains(sub_type.as_str()),
+                    _ => true,
+                }
+            }
+            _ => false,
+        }
+    }
+    /// Returns an error if `body` grossly mismatches the content-type: if it
+    /// is declared as text but has binary bytes, as HTML but doesn't start
+    /// with markup, or as a PNG, JPEG, GIF or WebP image but lacks its
+    /// signature. Other types aren't checked. `body` must already be decoded
+    /// of any content-encoding.
+    pub fn check_body_matches_content_type(&self, body: &[u8]) -> Result<()> {
+        let (primary_type, sub_type) = match self
+            .0
+            .get("content-type")
+            .map(|t| parse_content_type_header(t))
+        {
+            Some(Ok(MediaType {
+                primary_type,
+                sub_type,
+                ..
+            })) => (
+                primary_type.to_ascii_lowercase(),
+                sub_type.to_ascii_lowercase(),
+            ),
+            _ => return Ok(()),
+        };
+        let start = &body[..std::cmp::min(body.len(), SNIFF_LEN)];
+        let is_html = matches!(
+            (primary_type.as_str(), sub_type.as_str()),
+            ("text", "html") | ("application", "xhtml+xml")
+        );
+        let is_text = primary_type == "text"
+            || is_html
+            || (primary_type == "application"
+                && (TEXT_APPLICATION_TYPES.contains(sub_type.as_str())
+                    || sub_type.ends_with("+json")
+                    || sub_type.ends_with("+xml")));
+        if is_text {
+            // UTF-16 text has bytes that look binary in isolation.
+            let is_utf16 = start.starts_with(b"\xfe\xff") || start.starts_with(b"\xff\xfe");
+            if !is_utf16 && start.iter().any(|&b| is_binary_byte(b)) {
+                return Err(anyhow!(
+                    "The body is binary, but the content-type is {}/{}.",
+                    primary_type,
+                    sub_type
+                ));
+            }
+            if is_html && !is_utf16 {
+                let markup = start
+                    .strip_prefix(b"\xef\xbb\xbf")
+                    .unwrap_or(start)
+                    .iter()
+                    .find(|b| !b.is_ascii_whitespace());
+                if !matches!(markup, None | Some(b'<')) {
+                    return Err(anyhow!(
+                        "The body doesn't start with markup, but the content-type is {}/{}.",
+                        primary_type,
+                        sub_type
+                    ));
+                }
+            }
+        } else if primary_type == "image" {
+            let signatures: &[&[u8]] = match sub_type.as_str() {
+                "png" => &[b"\x89PNG\r\n\x1a\n"],
+                "jpeg" => &[b"\xff\xd8\xff"],
+                "gif" => &[b"GIF87a", b"GIF89a"],
+                "webp" => &[b"RIFF"],
+                _ => &[],
+            };
+            if !signatures.is_empty() && !signatures.iter().any(|s| start.starts_with(s)) {
+                return Err(anyhow!(
+                    "The body lacks the signature of its content-type image/{}.",
+                    sub_type
+                ));
+            }
+        }
+        Ok(())
+    }
+    // Returns an error if the response must not be signed. If `skip_no_cache`,
+    // that includes responses with cache-control `no-cache` or `max-age=0`.
+    pub fn validate_as_sxg_payload(&self, skip_no_cache: bool) -> Result<()> {
         for (k, v) in self.0.iter() {
             if DONT_SIGN_RESPONSE_HEADERS.contains(k.as_str()) {
                 return Err(anyhow!(r#"A stateful header "{}" is found."#, k));
             }
-            if CACHE_CONTROL_HEADERS_SET.contains(k.as_str()) {
-                // `private` and `no-store` are disallowed by
-                // https://github.com/google/webpackager/blob/master/docs/cache_requirements.md#user-content-google-sxg-cache,
-                // while the other two are signals that the document is not usually cached and reused.
-                if v.contains("private")
-                    || v.contains("no-store")
-                    || v.contains("no-cache")
-                    || v.contains("max-age=0")
-                {
-                    return Err(anyhow!(r#"The {} header is "{}"."#, k, v));
-                }
+            if CACHE_CONTROL_HEADERS_SET.contains(k.as_str()) && !is_reusable(v, skip_no_cache) {
+                return Err(anyhow!(r#"The {} header is "{}"."#, k, v));
             }
-            // TODO: Remove this section once https://crbug.com/1250532 is fixed in most clients.
-            if let Some(vary) = self.0.get("vary") {
-                if let Ok(directives) = parse_vary_header(vary) {
-                    if directives.contains(&"*") {
-                        return Err(anyhow!(
-                            "The response may vary by anything,\
-                            because its \"vary\" header is \"{}\".",
-                            vary
-                        ));
-                    }
+        }
+        // Chrome ignores SXGs that vary by anything (https://crbug.com/1250532),
+        // but the header is kept as is, since it is what the origin intended.
+        if let Some(vary) = self.0.get("vary") {
+            if let Ok(directives) = parse_vary_header(vary) {
+                if directives.contains(&"*") {
+                    console_log(&format!(
+                        "The response may vary by anything, because its \"vary\" header is \"{}\".",
+                        vary
+                    ));
                 }
             }
         }
@@ -177,20 +426,42 @@ impl Headers {
         }
         // The payload of SXG must have a content-type. See step 8 of
         // https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-signature-validity
-        if !self.0.contains_key("content-type") {
-            return Err(anyhow!("The content-type header is missing."));
+        let content_type = self
+            .0
+            .get("content-type")
+            .ok_or_else(|| anyhow!("The content-type header is missing."))?;
+        // Browsers reject an SXG whose inner content-type doesn't parse, or
+        // which nests another SXG.
+        match parse_content_type_header(content_type) {
+            Ok(MediaType {
+                primary_type,
+                sub_type,
+                ..
+            }) if primary_type.eq_ignore_ascii_case("application")
+                && sub_type.eq_ignore_ascii_case("signed-exchange") =>
+            {
+                Err(anyhow!("Nested signed exchanges are not allowed."))
+            }
+            Ok(_) => Ok(()),
+            Err(_) => Err(anyhow!(
+                r#"The content-type header "{}" is invalid."#,
+                content_type
+            )),
         }
-        Ok(())
     }
     // Returns the signed headers via the serializer callback instead of return
     // value, because it contains a mix of &str and String. This makes it easy
     // to test the intermediate Vec<(&str, &str)> without sacrificing
     // performance by copying it into a Vec<(String, String)>.
+    // `payload_length` is the size of the MICE-encoded payload.
+    #[allow(clippy::too_many_arguments)]
     async fn get_signed_headers<O, S>(
         &self,
         fallback_url: &Url,
         status_code: u16,
         mice_digest: &[u8],
+        payload_length: usize,
+        brotli: bool,
         header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
         serializer: S,
         skip_process_link: bool,
@@ -200,10 +471,10 @@ impl Headers {
     {
         let connection = self.connection_headers();
         let mut fields: Vec<(&str, &str)> = vec![];
-        let html = self.0.get("content-type").map_or(false, |t|
-            matches!(parse_content_type_header(t),
-                     Ok(MediaType {primary_type, sub_type, ..})
-                         if primary_type.eq_ignore_ascii_case("text") && sub_type.eq_ignore_ascii_case("html")));
+        let html = self.is_html();
+        // Preloads only apply to HTML documents, so the links of other
+        // payloads, such as JSON, are signed as is.
+        let skip_process_link = skip_process_link || !html;
         let link;
         match (skip_process_link, self.0.get("link")) {
             (false, Some(value)) => {
@@ -236,23 +507,38 @@ impl Headers {
         }
         let status_code = status_code.to_string();
         let digest = format!("mi-sha256-03={}", ::base64::encode(mice_digest));
+        let content_length = payload_length.to_string();
         fields.push((":status", &status_code));
-        fields.push(("content-encoding", "mi-sha256-03"));
+        fields.push(("content-length", &content_length));
+        // Content codings are listed in the order they were applied, per
+        // https://datatracker.ietf.org/doc/html/rfc7231#section-3.1.2.2.
+        let content_encoding = if brotli {
+            "br, mi-sha256-03"
+        } else {
+            "mi-sha256-03"
+        };
+        fields.push(("content-encoding", content_encoding));
         fields.push(("digest", &digest));
         serializer(fields)
     }
+    // `brotli` is whether the payload is Brotli-compressed before MICE encoding.
+    #[allow(clippy::too_many_arguments)]
     pub async fn get_signed_headers_bytes(
         &self,
         fallback_url: &Url,
         status_code: u16,
         mice_digest: &[u8],
+        payload_length: usize,
         header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
         skip_process_link: bool,
+        brotli: bool,
     ) -> Vec<u8> {
         self.get_signed_headers(
             fallback_url,
             status_code,
             mice_digest,
+            payload_length,
+            brotli,
             header_integrity_fetcher,
             |fields| {
                 use crate::cbor::DataItem;
@@ -311,35 +597,164 @@ impl Headers {
     }
 }
 
-// These headers are always stripped before signing, but preserved when serving unsigned (e.g.
-// direct or same-origin navigations, or non-prefetched subresources).
-static STRIP_RESPONSE_HEADERS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+// Whether a cache-control header value allows the response to be signed and
+// reused by third parties. `private` and `no-store` are disallowed by
+// https://github.com/google/webpackager/blob/master/docs/cache_requirements.md#user-content-google-sxg-cache,
+// while `no-cache` and `max-age=0` are signals that the document is not usually
+// cached and reused. Directive names are case-insensitive. A value that
+// doesn't parse is checked for the same names as substrings.
+fn is_reusable(cache_control: &str, skip_no_cache: bool) -> bool {
+    use crate::http_parser::cache_control::Directive;
+    let directives = match parse_cache_control_directives(cache_control) {
+        Ok(directives) => directives,
+        Err(_) => {
+            let value = cache_control.to_ascii_lowercase();
+            return !(value.contains("private")
+                || value.contains("no-store")
+                || (skip_no_cache && (value.contains("no-cache") || value.contains("max-age=0"))));
+        }
+    };
+    directives.iter().all(|directive| match directive {
+        Directive::Other(name, _) => {
+            let name = name.to_ascii_lowercase();
+            name != "private" && name != "no-store" && !(skip_no_cache && name == "no-cache")
+        }
+        Directive::MaxAge(duration) => !(skip_no_cache && duration.is_zero()),
+        Directive::SMaxAge(_) => true,
+    })
+}
+
+/// Whether the header is an uncached header field, which is not allowed inside
+/// an SXG: either hop-by-hop or stateful, per
+/// https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-uncached-header-fields.
+pub(crate) fn is_uncached_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    HOP_BY_HOP_HEADERS.contains(name.as_str()) || DONT_SIGN_RESPONSE_HEADERS.contains(name.as_str())
+}
+
+// Request headers that are never forwarded to the origin, even if listed in
+// `forward_request_headers`. Repeated values of the others are joined in
+// order by `Headers::new`.
+static UNFORWARDED_REQUEST_HEADERS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    vec![
+        "cookie",
+        "proxy-authorization",
+        "via",
+        crate::SIGNED_HOST_HEADER,
+    ]
+    .into_iter()
+    .collect()
+});
+
+// https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-uncached-header-fields
+static HOP_BY_HOP_HEADERS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
     vec![
-        // https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-uncached-header-fields
         "connection",
         "keep-alive",
         "proxy-connection",
         "trailer",
         "transfer-encoding",
         "upgrade",
-        // Include the HSTS header from
-        // https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#stateful-headers
-        // because it is an origin-wide (not URL-specific) header and origins shouldn't have to
-        // choose between HSTS and SXG. (We shouldn't create an artificial reason to disable HSTS.)
-        "strict-transport-security",
-        // These headers are reserved for SXG
-        ":status",
-        "content-encoding",
-        "digest",
-        // These headers are prohibited by Google SXG cache
-        // https://github.com/google/webpackager/blob/master/docs/cache_requirements.md
-        "variant-key-04",
-        "variants-04",
     ]
     .into_iter()
     .collect()
 });
 
+/// Returns a strong ETag over `body`, i.e. its quoted SHA-256.
+pub fn strong_etag(body: &[u8]) -> String {
+    format!(
+        "\"{}\"",
+        base64::encode_config(HashAlgorithm::Sha256.digest(body), base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// Returns whether the value of an If-None-Match request header lists `etag`,
+/// using the weak comparison of
+/// https://www.rfc-editor.org/rfc/rfc9110#section-13.1.2. ETags containing
+/// commas are not supported.
+pub fn if_none_match(header: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| {
+        let tag = tag.trim();
+        tag.strip_prefix("W/").unwrap_or(tag).to_string()
+    };
+    let etag = opaque(etag);
+    header
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+/// The cache-control header of the outer SXG response, given the duration from
+/// now until the signature expires. The outer max-age is set smaller, hence the
+/// downstream CDNs (e.g. the AMP cache) are able to refetch the SXG before the
+/// signature expires.
+pub fn outer_cache_control(signature_validity: Duration) -> String {
+    format!("public, max-age={}", signature_validity.as_secs() / 4)
+}
+
+// Subtypes of `application/*` whose contents don't benefit from compression.
+static COMPRESSED_APPLICATION_TYPES: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    vec![
+        "font-woff",
+        "gzip",
+        "octet-stream",
+        "pdf",
+        "vnd.rar",
+        "wasm",
+        "x-7z-compressed",
+        "x-bzip2",
+        "x-gzip",
+        "zip",
+        "zstd",
+    ]
+    .into_iter()
+    .collect()
+});
+
+// Subtypes of `application/*` that are text, besides those with a `+json` or
+// `+xml` suffix.
+static TEXT_APPLICATION_TYPES: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    vec!["ecmascript", "javascript", "json", "x-javascript", "xml"]
+        .into_iter()
+        .collect()
+});
+
+// The number of bytes at the start of a body that are checked against its
+// content-type, as in https://mimesniff.spec.whatwg.org/#reading-the-resource-header.
+const SNIFF_LEN: usize = 1445;
+
+// Whether `b` is a binary data byte, per
+// https://mimesniff.spec.whatwg.org/#binary-data-byte.
+fn is_binary_byte(b: u8) -> bool {
+    matches!(b, 0x00..=0x08 | 0x0b | 0x0e..=0x1a | 0x1c..=0x1f)
+}
+
+// These headers are always stripped before signing, but preserved when serving unsigned (e.g.
+// direct or same-origin navigations, or non-prefetched subresources).
+static STRIP_RESPONSE_HEADERS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    HOP_BY_HOP_HEADERS
+        .iter()
+        .copied()
+        .chain(vec![
+            // Include the HSTS header from
+            // https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#stateful-headers
+            // because it is an origin-wide (not URL-specific) header and origins shouldn't have to
+            // choose between HSTS and SXG. (We shouldn't create an artificial reason to disable HSTS.)
+            "strict-transport-security",
+            // These headers are reserved for SXG
+            ":status",
+            "content-encoding",
+            "digest",
+            // The upstream's content-length describes the body before MICE
+            // encoding, so it is replaced with that of the signed payload.
+            "content-length",
+            // These headers are prohibited by Google SXG cache
+            // https://github.com/google/webpackager/blob/master/docs/cache_requirements.md
+            "variant-key-04",
+            "variants-04",
+        ])
+        .collect()
+});
+
 // These headers don't affect the semantics of the response inside an
 // SXG, but they vary frequently. This prevents the SXG from being used
 // as a subresource due to the header-integrity requirement:
@@ -407,6 +822,31 @@ static CACHE_CONTROL_HEADERS: Lazy<Vec<&'static str>> = Lazy::new(|| {
 static CACHE_CONTROL_HEADERS_SET: Lazy<HashSet<&'static str>> =
     Lazy::new(|| CACHE_CONTROL_HEADERS.clone().into_iter().collect());
 
+// Whether `versions`, the `v` parameter of an `amp-cache-transform` entry such
+// as "1..3,5", includes `version`. Malformed ranges are ignored.
+fn accepts_amp_version(versions: &str, version: u64) -> bool {
+    versions.split(',').any(|range| {
+        let range = range.trim();
+        let (start, end) = range.split_once("..").unwrap_or((range, range));
+        matches!(
+            (start.parse::<u64>(), end.parse::<u64>()),
+            (Ok(start), Ok(end)) if (start..=end).contains(&version)
+        )
+    })
+}
+
+// Whether `accept_signature`, the value of an `accept-signature` header such
+// as "rsa-pss-sha512, ecdsa_p256_sha256", lists one of SIGNATURE_ALGORITHMS.
+// Parameters of the entries are ignored.
+fn accepts_signature_algorithm(accept_signature: &str) -> bool {
+    accept_signature.split(',').any(|entry| {
+        let name = entry.split(';').next().unwrap_or("").trim();
+        SIGNATURE_ALGORITHMS
+            .iter()
+            .any(|algorithm| name.eq_ignore_ascii_case(algorithm))
+    })
+}
+
 // Checks whether to serve SXG based on the Accept header of the HTTP request.
 // Returns Ok iff the input string has a `application/signed-exchange;v=b3`,
 // and either accept_filter != PrefersSxg or its `q` value is 1.
@@ -516,7 +956,7 @@ mod tests {
     fn basic_request_headers() {
         assert_eq!(
             headers(vec![("accept", "application/signed-exchange;v=b3")])
-                .forward_to_origin_server(AcceptLevel::PrefersSxg, &BTreeSet::new())
+                .forward_to_origin_server(AcceptLevel::PrefersSxg, &BTreeSet::new(), None)
                 .unwrap()
                 .into_iter()
                 .collect::<HashMap<String, String>>(),
@@ -530,7 +970,7 @@ mod tests {
                 ("accept", "application/signed-exchange;v=b3"),
                 ("via", "nginx")
             ])
-            .forward_to_origin_server(AcceptLevel::PrefersSxg, &BTreeSet::new())
+            .forward_to_origin_server(AcceptLevel::PrefersSxg, &BTreeSet::new(), None)
             .unwrap()
             .into_iter()
             .collect::<HashMap<String, String>>(),
@@ -538,25 +978,206 @@ mod tests {
         );
     }
     #[test]
+    fn forwarded_request_headers() {
+        let forwarded = vec![
+            "x-geo",
+            "x-experiment",
+            "connection",
+            "cookie",
+            "via",
+            "x-sxg-signed-host",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(
+            headers(vec![
+                ("accept", "application/signed-exchange;v=b3"),
+                ("x-geo", "US"),
+                ("X-Experiment", "a"),
+                ("x-other", "1"),
+                ("x-experiment", "b"),
+                ("connection", "close"),
+                ("cookie", "session=1"),
+                ("via", "nginx"),
+                ("x-sxg-signed-host", "blog.example.com"),
+            ])
+            .forward_to_origin_server(AcceptLevel::PrefersSxg, &forwarded, None)
+            .unwrap()
+            .into_iter()
+            .collect::<HashMap<String, String>>(),
+            header_fields(vec![
+                ("user-agent", USER_AGENT),
+                ("via", "nginx, sxgrs"),
+                ("x-experiment", "a,b"),
+                ("x-geo", "US"),
+            ])
+        );
+    }
+    #[test]
+    fn connection_listed_request_headers() {
+        let forwarded = vec!["x-geo".to_string()].into_iter().collect();
+        assert_eq!(
+            headers(vec![
+                ("accept", "application/signed-exchange;v=b3"),
+                ("connection", "x-geo"),
+                ("x-geo", "US"),
+            ])
+            .forward_to_origin_server(AcceptLevel::PrefersSxg, &forwarded, None)
+            .unwrap()
+            .into_iter()
+            .collect::<HashMap<String, String>>(),
+            header_fields(vec![("user-agent", USER_AGENT), ("via", "sxgrs")])
+        );
+    }
+    #[test]
     fn authenticated_request_headers() {
         assert_eq!(
             headers(vec![
                 ("accept", "application/signed-exchange;v=b3"),
                 ("authorization", "x")
             ])
-            .forward_to_origin_server(AcceptLevel::PrefersSxg, &BTreeSet::new())
+            .forward_to_origin_server(AcceptLevel::PrefersSxg, &BTreeSet::new(), None)
             .unwrap_err()
             .to_string(),
             "The request contains an Authorization header."
         );
     }
 
+    #[test]
+    fn required_accept_level() {
+        let requests = [
+            ("text/html,*/*", AcceptLevel::RejectsSxg),
+            (
+                "text/html;q=1,application/signed-exchange;v=b3;q=0.9",
+                AcceptLevel::AcceptsSxg,
+            ),
+            ("application/signed-exchange;v=b3", AcceptLevel::PrefersSxg),
+        ];
+        let required_levels = [
+            AcceptLevel::RejectsSxg,
+            AcceptLevel::AcceptsSxg,
+            AcceptLevel::PrefersSxg,
+        ];
+        for (accept, actual) in requests {
+            for required in required_levels {
+                let forwarded = headers(vec![("accept", accept)]).forward_to_origin_server(
+                    required,
+                    &BTreeSet::new(),
+                    None,
+                );
+                assert_eq!(
+                    forwarded.is_ok(),
+                    actual >= required,
+                    "accept: {}, required: {:?}",
+                    accept,
+                    required
+                );
+            }
+        }
+    }
+    #[test]
+    fn opt_out_header() {
+        let request = || {
+            headers(vec![
+                ("accept", "application/signed-exchange;v=b3"),
+                ("X-No-SXG", "1"),
+            ])
+        };
+        assert_eq!(
+            request().accept_level(Some("x-no-sxg")).unwrap(),
+            AcceptLevel::RejectsSxg
+        );
+        assert_eq!(
+            request().accept_level(Some("x-other")).unwrap(),
+            AcceptLevel::PrefersSxg
+        );
+        assert_eq!(
+            request().accept_level(None).unwrap(),
+            AcceptLevel::PrefersSxg
+        );
+        assert_eq!(
+            request()
+                .forward_to_origin_server(
+                    AcceptLevel::AcceptsSxg,
+                    &BTreeSet::new(),
+                    Some("x-no-sxg")
+                )
+                .unwrap_err()
+                .to_string(),
+            "The requestor RejectsSxg, but AcceptsSxg is required."
+        );
+    }
+    #[test]
+    fn accept_signature_header() {
+        let request = |accept_signature: &str| {
+            headers(vec![
+                ("accept", "application/signed-exchange;v=b3"),
+                ("accept-signature", accept_signature),
+            ])
+            .accept_level(None)
+            .unwrap()
+        };
+        assert_eq!(request("ecdsa_p256_sha256"), AcceptLevel::PrefersSxg);
+        assert_eq!(
+            request("rsa-pss-sha512, ECDSA_P256_SHA256;q=0.5"),
+            AcceptLevel::PrefersSxg
+        );
+        assert_eq!(request("ecdsa_secp256r1_sha256"), AcceptLevel::PrefersSxg);
+        assert_eq!(request("ed25519"), AcceptLevel::RejectsSxg);
+        assert_eq!(request("ecdsa_p384_sha384"), AcceptLevel::RejectsSxg);
+        assert_eq!(request(""), AcceptLevel::RejectsSxg);
+    }
     #[test]
     fn accept_level_ord() {
         assert!(AcceptLevel::RejectsSxg < AcceptLevel::AcceptsSxg);
         assert!(AcceptLevel::AcceptsSxg < AcceptLevel::PrefersSxg);
     }
 
+    // === amp_cache_transform ===
+    #[test]
+    fn amp_cache_transform_matching() {
+        let transform = |value| {
+            headers(vec![("amp-cache-transform", value)])
+                .amp_cache_transform()
+                .unwrap()
+        };
+        assert_eq!(transform("google"), Some(r#"google;v="1""#.into()));
+        assert_eq!(
+            transform(r#"google;v="1..100""#),
+            Some(r#"google;v="1""#.into())
+        );
+        assert_eq!(transform(r#"any;v="0,1""#), Some(r#"any;v="1""#.into()));
+        // Unknown caches are skipped.
+        assert_eq!(
+            transform(r#"foo, google;v="1""#),
+            Some(r#"google;v="1""#.into())
+        );
+    }
+    #[test]
+    fn amp_cache_transform_unsupported() {
+        for value in [
+            r#"google;v="2..5""#,
+            r#"google;v="x""#,
+            "google;v=1",
+            "bing",
+            "Google",
+        ] {
+            assert!(headers(vec![("amp-cache-transform", value)])
+                .amp_cache_transform()
+                .is_err());
+        }
+    }
+    #[test]
+    fn amp_cache_transform_absent() {
+        assert_eq!(
+            headers(vec![("accept", "application/signed-exchange;v=b3")])
+                .amp_cache_transform()
+                .unwrap(),
+            None
+        );
+    }
+
     // === parse_accept_level ===
     #[test]
     fn prefers_sxg() {
@@ -632,53 +1253,144 @@ mod tests {
         assert_eq!(parse_chrome_major_version("Internet Explorer"), None);
     }
 
+    // === check_signable_content_type ===
+    #[test]
+    fn check_signable_content_type() {
+        let signable: BTreeSet<String> = ["text/html".to_string(), "application/json".to_string()]
+            .iter()
+            .cloned()
+            .collect();
+        let check = |content_type| {
+            headers(vec![("content-type", content_type)]).check_signable_content_type(&signable)
+        };
+        assert!(check("text/html").is_ok());
+        assert!(check("Text/HTML; charset=utf-8").is_ok());
+        assert!(check("application/json").is_ok());
+        assert_eq!(
+            check("application/octet-stream").unwrap_err().to_string(),
+            "The content-type application/octet-stream is not one of signable_content_types."
+        );
+        assert!(check("text/html/x").is_err());
+        assert!(headers(vec![])
+            .check_signable_content_type(&signable)
+            .is_err());
+    }
+
+    #[test]
+    fn check_body_matches_content_type() {
+        let check = |content_type, body: &[u8]| {
+            headers(vec![("content-type", content_type)]).check_body_matches_content_type(body)
+        };
+        assert!(check("text/html", b"<!DOCTYPE html><html></html>").is_ok());
+        assert!(check("text/html;charset=utf-8", b"\xef\xbb\xbf\n  <html>").is_ok());
+        assert!(check("text/html", b"\xef\xbb\xbf \r\n\t<!-- c --><html>").is_ok());
+        assert!(check("text/html", b"").is_ok());
+        assert!(check("application/json", br#"{"a": 1}"#).is_ok());
+        assert!(check("image/png", b"\x89PNG\r\n\x1a\n\x00\x00").is_ok());
+        assert!(check("image/gif", b"GIF89a\x01\x00").is_ok());
+        assert!(check("application/octet-stream", b"\x00\x01").is_ok());
+        assert_eq!(
+            check("text/html", b"\x89PNG\r\n\x1a\n\x00\x00")
+                .unwrap_err()
+                .to_string(),
+            "The body is binary, but the content-type is text/html."
+        );
+        assert_eq!(
+            check("Text/HTML", b"%PDF-1.7").unwrap_err().to_string(),
+            "The body doesn't start with markup, but the content-type is text/html."
+        );
+        assert!(check("application/javascript", b"\x00asm").is_err());
+        assert_eq!(
+            check("image/jpeg", b"<html></html>")
+                .unwrap_err()
+                .to_string(),
+            "The body lacks the signature of its content-type image/jpeg."
+        );
+    }
+
     // === validate_as_sxg_payload ===
     #[test]
     fn response_headers_minimum_valid() {
         assert!(headers(vec![("content-type", "text/html")])
-            .validate_as_sxg_payload()
+            .validate_as_sxg_payload(true)
             .is_ok());
     }
     #[test]
+    fn response_headers_content_type() {
+        for content_type in ["application/json", "application/json; charset=utf-8"] {
+            assert!(headers(vec![("content-type", content_type)])
+                .validate_as_sxg_payload(true)
+                .is_ok());
+        }
+        for content_type in ["json", "application/signed-exchange;v=b3"] {
+            assert!(headers(vec![("content-type", content_type)])
+                .validate_as_sxg_payload(true)
+                .is_err());
+        }
+    }
+    #[test]
     fn response_headers_caching() {
         assert!(headers(vec![
             ("content-type", "text/html"),
             ("cache-control", "max-age=1")
         ])
-        .validate_as_sxg_payload()
+        .validate_as_sxg_payload(true)
         .is_ok());
         assert!(headers(vec![
             ("content-type", "text/html"),
             ("cache-control", "private")
         ])
-        .validate_as_sxg_payload()
+        .validate_as_sxg_payload(true)
         .is_err());
         assert!(headers(vec![
             ("content-type", "text/html"),
             ("cdn-cache-control", "no-store")
         ])
-        .validate_as_sxg_payload()
+        .validate_as_sxg_payload(true)
         .is_err());
         assert!(headers(vec![
             ("content-type", "text/html"),
             ("cloudflare-cdn-cache-control", "no-cache")
         ])
-        .validate_as_sxg_payload()
+        .validate_as_sxg_payload(true)
         .is_err());
         assert!(headers(vec![
             ("content-type", "text/html"),
             ("surrogate-control", "max-age=0")
         ])
-        .validate_as_sxg_payload()
+        .validate_as_sxg_payload(true)
         .is_err());
     }
     #[test]
+    fn response_headers_caching_directives() {
+        let validate = |cache_control: &str, skip_no_cache: bool| {
+            headers(vec![
+                ("content-type", "text/html"),
+                ("cache-control", cache_control),
+            ])
+            .validate_as_sxg_payload(skip_no_cache)
+        };
+        for skip_no_cache in [true, false] {
+            assert!(validate("no-store", skip_no_cache).is_err());
+            assert!(validate("public, Private", skip_no_cache).is_err());
+            assert!(validate("max-age=3600, NO-STORE", skip_no_cache).is_err());
+            assert!(validate("private=\"set-cookie\"", skip_no_cache).is_err());
+            assert!(validate("max-age=3600, x-no-store-ext", skip_no_cache).is_ok());
+        }
+        assert!(validate("max-age=0", true).is_err());
+        assert!(validate("max-age=0", false).is_ok());
+        assert!(validate("max-age=0, s-maxage=3600", false).is_ok());
+        assert!(validate("no-cache", true).is_err());
+        assert!(validate("no-cache", false).is_ok());
+        assert!(validate("max-age=600", true).is_ok());
+    }
+    #[test]
     fn response_headers_stateful() {
         assert!(headers(vec![
             ("content-type", "text/html"),
             ("clear-site-data", r#""*""#)
         ])
-        .validate_as_sxg_payload()
+        .validate_as_sxg_payload(true)
         .is_err());
     }
     #[test]
@@ -687,16 +1399,182 @@ mod tests {
             ("content-type", "text/html"),
             ("content-length", "8000000")
         ])
-        .validate_as_sxg_payload()
+        .validate_as_sxg_payload(true)
         .is_ok());
         assert!(headers(vec![
             ("content-type", "text/html"),
             ("content-length", "8000001")
         ])
-        .validate_as_sxg_payload()
+        .validate_as_sxg_payload(true)
         .is_err());
     }
 
+    #[test]
+    fn response_headers_vary_star() {
+        assert!(headers(vec![("content-type", "text/html"), ("vary", "*")])
+            .validate_as_sxg_payload(true)
+            .is_ok());
+    }
+
+    // === remove_hop_by_hop_headers ===
+    #[test]
+    fn removes_hop_by_hop_headers() {
+        let mut headers = headers(vec![
+            ("connection", "keep-alive, x-conn"),
+            ("content-type", "text/html"),
+            ("keep-alive", "timeout=5"),
+            ("transfer-encoding", "chunked"),
+            ("vary", "*"),
+            ("x-conn", "1"),
+        ]);
+        headers.remove_hop_by_hop_headers();
+        assert_eq!(
+            headers.0,
+            header_fields(vec![("content-type", "text/html"), ("vary", "*")])
+        );
+    }
+
+    // === default_content_type ===
+    #[test]
+    fn default_content_type_when_missing() {
+        let mut headers = headers(vec![]);
+        headers.default_content_type();
+        assert_eq!(
+            headers.0,
+            header_fields(vec![("content-type", "text/html")])
+        );
+    }
+    #[test]
+    fn default_content_type_when_present() {
+        let mut headers = headers(vec![("content-type", "image/png")]);
+        headers.default_content_type();
+        assert_eq!(
+            headers.0,
+            header_fields(vec![("content-type", "image/png")])
+        );
+    }
+
+    // === process_link_header ===
+    #[tokio::test]
+    async fn process_link_header_keeps_valid_entries() {
+        let mut headers = headers(vec![
+            ("content-type", "text/html"),
+            (
+                "link",
+                "</a.js>;rel=preload;as=script,b.js>;rel=preload,</a.js>;rel=allowed-alt-sxg;header-integrity=blah",
+            ),
+        ]);
+        headers
+            .process_link_header(
+                &Url::parse("https://foo.com/").unwrap(),
+                &mut null_integrity_fetcher(),
+            )
+            .await;
+        assert_eq!(
+            headers.0,
+            header_fields(vec![
+                ("content-type", "text/html"),
+                ("link", "<https://foo.com/a.js>;rel=preload;as=script,<https://foo.com/a.js>;rel=allowed-alt-sxg;header-integrity=blah"),
+            ])
+        );
+    }
+    #[tokio::test]
+    async fn process_link_header_removes_invalid_header() {
+        let mut headers = headers(vec![("link", "<a.js;rel=preload"), ("vary", "accept")]);
+        headers
+            .process_link_header(
+                &Url::parse("https://foo.com/").unwrap(),
+                &mut null_integrity_fetcher(),
+            )
+            .await;
+        assert_eq!(headers.0, header_fields(vec![("vary", "accept")]));
+    }
+
+    // === add_canonical_link ===
+    #[test]
+    fn add_canonical_link_without_link() {
+        let mut headers = headers(vec![("content-type", "text/html")]);
+        headers.add_canonical_link(&Url::parse("https://foo.com/a?b").unwrap());
+        assert_eq!(
+            headers.0,
+            header_fields(vec![
+                ("content-type", "text/html"),
+                ("link", "<https://foo.com/a?b>;rel=canonical"),
+            ])
+        );
+    }
+    #[test]
+    fn add_canonical_link_merges_with_link() {
+        let mut headers = headers(vec![
+            ("link", "<https://foo.com/a.js>;rel=preload;as=script"),
+            ("link", "<https://foo.com/b.js>;rel=preload;as=script"),
+        ]);
+        headers.add_canonical_link(&Url::parse("https://foo.com/").unwrap());
+        assert_eq!(
+            headers.0,
+            header_fields(vec![(
+                "link",
+                "<https://foo.com/a.js>;rel=preload;as=script,<https://foo.com/b.js>;rel=preload;as=script,<https://foo.com/>;rel=canonical"
+            )])
+        );
+    }
+
+    // === ETags ===
+    #[test]
+    fn strong_etag_of_body() {
+        let etag = strong_etag(b"body");
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert_eq!(etag.len(), 45);
+        assert_eq!(etag, strong_etag(b"body"));
+        assert_ne!(etag, strong_etag(b"other body"));
+    }
+    #[test]
+    fn if_none_match_lists() {
+        assert!(if_none_match(r#""abc""#, r#""abc""#));
+        assert!(if_none_match(r#""x", W/"abc" ,"y""#, r#""abc""#));
+        assert!(if_none_match("*", r#""abc""#));
+        assert!(!if_none_match(r#""abcd""#, r#""abc""#));
+        assert!(!if_none_match("", r#""abc""#));
+    }
+
+    // === outer_cache_control ===
+    #[test]
+    fn outer_cache_control_is_quarter_of_validity() {
+        assert_eq!(
+            outer_cache_control(Duration::from_secs(3600)),
+            "public, max-age=900"
+        );
+    }
+
+    // === is_html ===
+    #[test]
+    fn is_html() {
+        for content_type in ["text/html", "TEXT/HTML;charset=utf-8"] {
+            assert!(headers(vec![("content-type", content_type)]).is_html());
+        }
+        for content_type in ["application/json", "text/plain", "application/xhtml+xml"] {
+            assert!(!headers(vec![("content-type", content_type)]).is_html());
+        }
+        assert!(!headers(vec![]).is_html());
+    }
+
+    // === is_compressible ===
+    #[test]
+    fn compressible_types() {
+        for content_type in ["text/html", "application/javascript", "image/svg+xml"] {
+            assert!(headers(vec![("content-type", content_type)]).is_compressible());
+        }
+        for content_type in ["image/png", "video/mp4", "font/woff2", "application/zip"] {
+            assert!(!headers(vec![("content-type", content_type)]).is_compressible());
+        }
+        assert!(headers(vec![
+            ("content-type", "text/html"),
+            ("content-encoding", "gzip")
+        ])
+        .is_compressible());
+        assert!(!headers(vec![]).is_compressible());
+    }
+
     // === connection_headers ===
     #[test]
     fn no_connection_headers() {
@@ -809,6 +1687,8 @@ mod tests {
                 &url,
                 200,
                 &[],
+                0,
+                false,
                 &mut null_integrity_fetcher(),
                 header_fields,
                 false,
@@ -818,6 +1698,7 @@ mod tests {
                 ("content-type", "image/jpeg"),
                 // x-request-id is missing
                 (":status", "200"),
+                ("content-length", "0"),
                 ("content-encoding", "mi-sha256-03"),
                 ("digest", "mi-sha256-03=")
             ])
@@ -831,6 +1712,8 @@ mod tests {
                 &url,
                 200,
                 &[],
+                0,
+                false,
                 &mut null_integrity_fetcher(),
                 header_fields,
                 false,
@@ -840,6 +1723,7 @@ mod tests {
                 ("content-type", "text/html;charset=utf-8"),
                 ("x-request-id", "abcdef123"),
                 (":status", "200"),
+                ("content-length", "0"),
                 ("content-encoding", "mi-sha256-03"),
                 ("digest", "mi-sha256-03=")
             ])
@@ -857,6 +1741,8 @@ mod tests {
                 &url,
                 200,
                 &[],
+                0,
+                false,
                 &mut null_integrity_fetcher(),
                 header_fields,
                 false,
@@ -866,6 +1752,7 @@ mod tests {
                 ("content-type", "text/html"),
                 ("link", "<https://foo.com/foo>;rel=preload,<https://foo.com/foo>;rel=allowed-alt-sxg;header-integrity=blah"),
                 (":status", "200"),
+                ("content-length", "0"),
                 ("content-encoding", "mi-sha256-03"),
                 ("digest", "mi-sha256-03=")
             ])
@@ -879,6 +1766,8 @@ mod tests {
                 &url,
                 200,
                 &[],
+                0,
+                false,
                 &mut null_integrity_fetcher(),
                 header_fields,
                 false
@@ -887,6 +1776,36 @@ mod tests {
             header_fields::<HashMap<String, String>>(vec![
                 ("content-type", "text/html"),
                 (":status", "200"),
+                ("content-length", "0"),
+                ("content-encoding", "mi-sha256-03"),
+                ("digest", "mi-sha256-03=")
+            ])
+        );
+    }
+    #[tokio::test]
+    async fn includes_link_of_non_html_as_is() {
+        let url = Url::parse("https://foo.com").unwrap();
+        assert_eq!(
+            headers(vec![
+                ("content-type", "application/json"),
+                ("link", "</page/2>;rel=next")
+            ])
+            .get_signed_headers::<HashMap<String, String>, _>(
+                &url,
+                200,
+                &[],
+                0,
+                false,
+                &mut null_integrity_fetcher(),
+                header_fields,
+                false,
+            )
+            .await,
+            header_fields::<HashMap<String, String>>(vec![
+                ("content-type", "application/json"),
+                ("link", "</page/2>;rel=next"),
+                (":status", "200"),
+                ("content-length", "0"),
                 ("content-encoding", "mi-sha256-03"),
                 ("digest", "mi-sha256-03=")
             ])
@@ -897,7 +1816,7 @@ mod tests {
     #[tokio::test]
     async fn get_signed_headers_bytes() {
         let url = Url::parse("https://foo.com").unwrap();
-        assert_eq!(headers(vec![("content-type", "image/jpeg")]).get_signed_headers_bytes(&url, 200, &[], &mut null_integrity_fetcher(), false).await,
-                   b"\xA4FdigestMmi-sha256-03=G:statusC200Lcontent-typeJimage/jpegPcontent-encodingLmi-sha256-03");
+        assert_eq!(headers(vec![("content-type", "image/jpeg")]).get_signed_headers_bytes(&url, 200, &[], 0, &mut null_integrity_fetcher(), false, false).await,
+                   b"\xA5FdigestMmi-sha256-03=G:statusC200Lcontent-typeJimage/jpegNcontent-lengthA0Pcontent-encodingLmi-sha256-03");
     }
 }
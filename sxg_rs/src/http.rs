@@ -60,6 +60,81 @@ pub struct HttpResponse {
     pub status: u16,
 }
 
+impl HttpResponse {
+    /// Returns a builder for a response to be signed, which rejects the
+    /// headers that are not allowed inside an SXG, such as `Set-Cookie`.
+    pub fn builder_for_sxg() -> SxgResponseBuilder {
+        SxgResponseBuilder {
+            headers: vec![],
+            status: 200,
+            error: None,
+        }
+    }
+    /// Returns 304 Not Modified, with only the `etag` header, if this is a 200
+    /// response whose ETag is listed by `if_none_match`, the request header.
+    /// Otherwise returns the response unchanged.
+    pub fn revalidate(self, if_none_match: Option<&str>) -> Self {
+        let etag = self
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("etag"))
+            .map(|(_, value)| value.clone());
+        match (if_none_match, etag) {
+            (Some(if_none_match), Some(etag))
+                if self.status == 200 && crate::headers::if_none_match(if_none_match, &etag) =>
+            {
+                HttpResponse {
+                    body: vec![],
+                    headers: vec![("etag".to_string(), etag)],
+                    status: 304,
+                }
+            }
+            _ => self,
+        }
+    }
+}
+
+/// Builds an [`HttpResponse`] that can be signed. Like
+/// [`::http::response::Builder`], the first error is kept and returned by
+/// [`SxgResponseBuilder::body`].
+pub struct SxgResponseBuilder {
+    headers: HeaderFields,
+    status: u16,
+    error: Option<Error>,
+}
+
+impl SxgResponseBuilder {
+    /// Sets the status code. The default is 200.
+    pub fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+    /// Appends a header, or records an error if the header is hop-by-hop or
+    /// stateful.
+    pub fn header(mut self, name: impl ToString, value: impl ToString) -> Self {
+        let name = name.to_string();
+        if self.error.is_none() && crate::headers::is_uncached_header(&name) {
+            self.error = Some(anyhow!(
+                r#"The header "{}" is not allowed in a signed exchange."#,
+                name
+            ));
+        }
+        self.headers.push((name, value.to_string()));
+        self
+    }
+    /// Returns the response, or the first error from building it.
+    pub fn body(self, body: Vec<u8>) -> Result<HttpResponse> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        Ok(HttpResponse {
+            body,
+            headers: self.headers,
+            status: self.status,
+        })
+    }
+}
+
 impl TryFrom<::http::response::Response<Vec<u8>>> for HttpResponse {
     type Error = Error;
     fn try_from(input: ::http::response::Response<Vec<u8>>) -> Result<Self> {
@@ -90,7 +165,7 @@ pub type HeaderFields = Vec<(String, String)>;
 // ```
 // But compiler will throw error E0117 if do that.
 // Because `HeaderFields` is a type alias, and we are not the author of `Vec<(String, String)>`.
-fn try_from_header_map(input: ::http::header::HeaderMap) -> Result<HeaderFields> {
+pub(crate) fn try_from_header_map(input: ::http::header::HeaderMap) -> Result<HeaderFields> {
     let mut output = vec![];
     for (name, value) in input.iter() {
         let value = value.to_str().map_err(|e| {
@@ -107,7 +182,7 @@ fn try_from_header_map(input: ::http::header::HeaderMap) -> Result<HeaderFields>
 // ```
 // But compiler will throw error E0117 if do that.
 // Because `HeaderFields` is a type alias, and we are not the author of `Vec<(String, String)>`.
-fn try_into_header_map(input: HeaderFields) -> Result<::http::header::HeaderMap> {
+pub(crate) fn try_into_header_map(input: HeaderFields) -> Result<::http::header::HeaderMap> {
     input
         .iter()
         .map(|(name, value)| -> Result<_> {
@@ -121,16 +196,24 @@ fn try_into_header_map(input: HeaderFields) -> Result<::http::header::HeaderMap>
 
 #[derive(Debug, Eq, PartialEq, Serialize, Clone)]
 pub enum Method {
+    Delete,
     Get,
+    Head,
+    Options,
     Post,
+    Put,
 }
 
 impl TryFrom<::http::Method> for Method {
     type Error = Error;
     fn try_from(method: ::http::Method) -> Result<Self> {
         match method {
+            ::http::Method::DELETE => Ok(Method::Delete),
             ::http::Method::GET => Ok(Method::Get),
+            ::http::Method::HEAD => Ok(Method::Head),
+            ::http::Method::OPTIONS => Ok(Method::Options),
             ::http::Method::POST => Ok(Method::Post),
+            ::http::Method::PUT => Ok(Method::Put),
             x => Err(anyhow!("Method {} is not supported", x)),
         }
     }
@@ -140,8 +223,12 @@ impl TryInto<::http::Method> for Method {
     type Error = Infallible;
     fn try_into(self) -> Result<::http::Method, Self::Error> {
         match self {
+            Method::Delete => Ok(::http::Method::DELETE),
             Method::Get => Ok(::http::Method::GET),
+            Method::Head => Ok(::http::Method::HEAD),
+            Method::Options => Ok(::http::Method::OPTIONS),
             Method::Post => Ok(::http::Method::POST),
+            Method::Put => Ok(::http::Method::PUT),
         }
     }
 }
@@ -155,3 +242,97 @@ impl std::fmt::Debug for HttpResponse {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn method_round_trip() {
+        for method in [
+            Method::Delete,
+            Method::Get,
+            Method::Head,
+            Method::Options,
+            Method::Post,
+            Method::Put,
+        ] {
+            let request = HttpRequest {
+                body: vec![],
+                headers: vec![],
+                method: method.clone(),
+                url: "https://example.com/".to_string(),
+            };
+            let request: ::http::request::Request<Vec<u8>> = request.try_into().unwrap();
+            let request: HttpRequest = request.try_into().unwrap();
+            assert_eq!(request.method, method);
+        }
+    }
+    #[test]
+    fn unsupported_method() {
+        assert!(Method::try_from(::http::Method::TRACE).is_err());
+    }
+    #[test]
+    fn revalidate() {
+        let response = || HttpResponse {
+            body: b"cbor".to_vec(),
+            headers: vec![
+                (
+                    "content-type".to_string(),
+                    "application/cert-chain+cbor".to_string(),
+                ),
+                ("ETag".to_string(), r#""abc""#.to_string()),
+            ],
+            status: 200,
+        };
+        assert_eq!(
+            response().revalidate(Some(r#""abc""#)),
+            HttpResponse {
+                body: vec![],
+                headers: vec![("etag".to_string(), r#""abc""#.to_string())],
+                status: 304,
+            }
+        );
+        assert_eq!(response().revalidate(Some(r#""old""#)), response());
+        assert_eq!(response().revalidate(None), response());
+        let no_etag = HttpResponse {
+            headers: vec![],
+            ..response()
+        };
+        assert_eq!(no_etag.clone().revalidate(Some("*")), no_etag);
+    }
+    #[test]
+    fn builder_for_sxg_allows_headers() {
+        let response = HttpResponse::builder_for_sxg()
+            .header("content-type", "text/html")
+            .header("Cache-Control", "max-age=3600")
+            .body(b"<html></html>".to_vec())
+            .unwrap();
+        assert_eq!(
+            response,
+            HttpResponse {
+                body: b"<html></html>".to_vec(),
+                headers: vec![
+                    ("content-type".to_string(), "text/html".to_string()),
+                    ("Cache-Control".to_string(), "max-age=3600".to_string()),
+                ],
+                status: 200,
+            }
+        );
+    }
+    #[test]
+    fn builder_for_sxg_rejects_uncached_headers() {
+        for name in [
+            "Set-Cookie",
+            "authentication-info",
+            "WWW-Authenticate",
+            "connection",
+            "transfer-encoding",
+        ] {
+            let response = HttpResponse::builder_for_sxg()
+                .header(name, "x")
+                .header("content-type", "text/html")
+                .body(vec![]);
+            assert!(response.unwrap_err().to_string().contains(name));
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{check_validator, HttpCache};
+use crate::http::HttpResponse;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+
+/// An [`HttpCache`] that stores responses in memory, keyed by URL. When full,
+/// the least recently used entry is evicted. Entries expire after a fixed TTL,
+/// which is measured by the system clock, so this is not suitable for
+/// `wasm32-unknown-unknown`.
+pub struct InMemoryCache {
+    ttl: Duration,
+    /// The responses with their expiration times, or None if the cache holds
+    /// no entries.
+    entries: Option<Mutex<LruCache<String, (SystemTime, HttpResponse)>>>,
+}
+
+impl InMemoryCache {
+    pub fn new(max_entries: usize, ttl: Duration) -> Self {
+        InMemoryCache {
+            ttl,
+            entries: NonZeroUsize::new(max_entries).map(|cap| Mutex::new(LruCache::new(cap))),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HttpCache for InMemoryCache {
+    async fn get(&self, url: &str) -> Result<HttpResponse> {
+        let mut entries = match &self.entries {
+            Some(entries) => entries.lock().await,
+            None => return Err(anyhow!("No cache entry found for {}", url)),
+        };
+        match entries.get(url) {
+            Some((expires, response)) if *expires > SystemTime::now() => Ok(response.clone()),
+            Some(_) => {
+                entries.pop(url);
+                Err(anyhow!("Cache entry for {} has expired", url))
+            }
+            None => Err(anyhow!("No cache entry found for {}", url)),
+        }
+    }
+    // Evicts a mismatched entry, since it is for an outdated version.
+    async fn get_validated(&self, url: &str, validator: &str) -> Result<HttpResponse> {
+        let response = self.get(url).await?;
+        if let Err(e) = check_validator(url, &response, validator) {
+            if let Some(entries) = &self.entries {
+                entries.lock().await.pop(url);
+            }
+            return Err(e);
+        }
+        Ok(response)
+    }
+    async fn put(&self, url: &str, response: &HttpResponse) -> Result<()> {
+        if let Some(entries) = &self.entries {
+            let expires = SystemTime::now() + self.ttl;
+            entries
+                .lock()
+                .await
+                .put(url.to_string(), (expires, response.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn response(body: &[u8]) -> HttpResponse {
+        HttpResponse {
+            body: body.to_vec(),
+            headers: vec![],
+            status: 200,
+        }
+    }
+    fn response_with_header(body: &[u8], name: &str, value: &str) -> HttpResponse {
+        HttpResponse {
+            headers: vec![(name.into(), value.into())],
+            ..response(body)
+        }
+    }
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let cache = InMemoryCache::new(2, Duration::from_secs(60));
+        cache.put("a", &response(b"a")).await.unwrap();
+        cache.put("b", &response(b"b")).await.unwrap();
+        assert_eq!(cache.get("a").await.unwrap().body, b"a");
+        cache.put("c", &response(b"c")).await.unwrap();
+        assert_eq!(cache.get("a").await.unwrap().body, b"a");
+        assert!(cache.get("b").await.is_err());
+        assert_eq!(cache.get("c").await.unwrap().body, b"c");
+    }
+    #[tokio::test]
+    async fn get_validated_by_etag() {
+        let cache = InMemoryCache::new(2, Duration::from_secs(60));
+        cache
+            .put("a", &response_with_header(b"v1", "etag", "\"v1\""))
+            .await
+            .unwrap();
+        let cached = cache.get_validated("a", "\"v1\"").await.unwrap();
+        assert_eq!(cached.body, b"v1");
+        // A different ETag misses, and evicts the outdated entry.
+        assert!(cache.get_validated("a", "\"v2\"").await.is_err());
+        assert!(cache.get("a").await.is_err());
+        cache
+            .put("a", &response_with_header(b"v2", "etag", "\"v2\""))
+            .await
+            .unwrap();
+        let cached = cache.get_validated("a", "\"v2\"").await.unwrap();
+        assert_eq!(cached.body, b"v2");
+    }
+    #[tokio::test]
+    async fn get_validated_without_validator() {
+        let cache = InMemoryCache::new(2, Duration::from_secs(60));
+        cache.put("a", &response(b"a")).await.unwrap();
+        assert!(cache.get_validated("a", "\"v1\"").await.is_err());
+    }
+    #[tokio::test]
+    async fn expires_after_ttl() {
+        let cache = InMemoryCache::new(2, Duration::ZERO);
+        cache.put("a", &response(b"a")).await.unwrap();
+        assert!(cache.get("a").await.is_err());
+    }
+}
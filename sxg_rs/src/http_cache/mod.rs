@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "in_memory_cache")]
+mod in_memory_cache;
 #[cfg(feature = "wasm")]
 pub mod js_http_cache;
 
@@ -19,6 +21,8 @@ use crate::http::HttpResponse;
 use crate::utils::{MaybeSend, MaybeSync};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+#[cfg(feature = "in_memory_cache")]
+pub use in_memory_cache::InMemoryCache;
 
 /// An interface for storing HTTP responses in a cache.
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -26,6 +30,38 @@ use async_trait::async_trait;
 pub trait HttpCache: MaybeSend + MaybeSync {
     async fn get(&self, url: &str) -> Result<HttpResponse>;
     async fn put(&self, url: &str, response: &HttpResponse) -> Result<()>;
+    /// Like `get`, but misses unless the cached response has the given
+    /// [`validator`], e.g. because the resource has since changed.
+    async fn get_validated(&self, url: &str, validator: &str) -> Result<HttpResponse> {
+        let response = self.get(url).await?;
+        check_validator(url, &response, validator)?;
+        Ok(response)
+    }
+}
+
+/// Returns the token identifying the version of the response: its ETag, or
+/// else its Last-Modified date.
+pub fn validator(response: &HttpResponse) -> Option<&str> {
+    let header = |name: &str| {
+        response
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    };
+    header("etag").or_else(|| header("last-modified"))
+}
+
+fn check_validator(url: &str, response: &HttpResponse, expected: &str) -> Result<()> {
+    if validator(response) == Some(expected) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Cache entry for {} does not match validator {}",
+            url,
+            expected
+        ))
+    }
 }
 
 pub struct NullCache;
@@ -40,3 +76,48 @@ impl HttpCache for NullCache {
         Ok(())
     }
 }
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl<T: HttpCache + ?Sized> HttpCache for &T {
+    async fn get(&self, url: &str) -> Result<HttpResponse> {
+        (**self).get(url).await
+    }
+    async fn put(&self, url: &str, response: &HttpResponse) -> Result<()> {
+        (**self).put(url, response).await
+    }
+    async fn get_validated(&self, url: &str, validator: &str) -> Result<HttpResponse> {
+        (**self).get_validated(url, validator).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn response(body: &[u8]) -> HttpResponse {
+        HttpResponse {
+            body: body.to_vec(),
+            headers: vec![],
+            status: 200,
+        }
+    }
+    fn response_with_header(body: &[u8], name: &str, value: &str) -> HttpResponse {
+        HttpResponse {
+            headers: vec![(name.into(), value.into())],
+            ..response(body)
+        }
+    }
+    #[test]
+    fn validators() {
+        assert_eq!(validator(&response(b"a")), None);
+        assert_eq!(
+            validator(&response_with_header(b"a", "ETag", "\"v1\"")),
+            Some("\"v1\"")
+        );
+        let modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert_eq!(
+            validator(&response_with_header(b"a", "last-modified", modified)),
+            Some(modified)
+        );
+    }
+}
@@ -151,6 +151,42 @@ pub fn link(input: &str) -> IResult<&str, Link> {
     )(input)
 }
 
+// Splits a Link header into its comma-separated entries, ignoring commas
+// within a URI reference or quoted string, so that a malformed entry can be
+// skipped without losing the rest.
+pub fn split_entries(input: &str) -> Vec<&str> {
+    let mut entries = vec![];
+    let mut start = 0;
+    let mut in_uri = false;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in input.char_indices() {
+        if in_quotes {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_quotes = false;
+            }
+        } else if in_uri {
+            in_uri = c != '>';
+        } else {
+            match c {
+                '<' => in_uri = true,
+                '"' => in_quotes = true,
+                ',' => {
+                    entries.push(&input[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+    }
+    entries.push(&input[start..]);
+    entries
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -266,4 +302,12 @@ mod tests {
             "</foo>"
         );
     }
+    #[test]
+    fn split() {
+        assert_eq!(split_entries(""), vec![""]);
+        assert_eq!(
+            split_entries(r#"</a,b>;rel=preload, </c>;title="d,\",e",<f"#),
+            vec!["</a,b>;rel=preload", r#" </c>;title="d,\",e""#, "<f"]
+        );
+    }
 }
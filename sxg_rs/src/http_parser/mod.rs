@@ -27,7 +27,7 @@ use nom::{
     character::complete::char as char1,
     combinator::eof,
     multi::separated_list0,
-    sequence::{separated_pair, terminated},
+    sequence::{delimited, pair, separated_pair, terminated},
     IResult,
 };
 use std::time::Duration;
@@ -73,6 +73,18 @@ pub fn parse_link_header(input: &str) -> Result<Vec<link::Link>> {
     parse_vec(input, link::link)
 }
 
+// Like `parse_link_header`, but skips malformed entries instead of failing.
+pub fn parse_link_header_lenient(input: &str) -> Vec<link::Link> {
+    link::split_entries(input)
+        .into_iter()
+        .filter_map(|entry| {
+            delimited(ows, link::link, pair(ows, eof))(entry)
+                .ok()
+                .map(|(_, link)| link)
+        })
+        .collect()
+}
+
 pub fn parse_token_list(input: &str) -> Result<Vec<&str>> {
     parse_vec(input, base::token)
 }
@@ -100,6 +112,25 @@ mod tests {
         assert!(parse_vary_header("tokens only; no spaces or semicolons allowed").is_err());
     }
     #[test]
+    fn link_lenient() {
+        let links: Vec<String> = parse_link_header_lenient(
+            r#"</a>;rel=preload, b>, </c>;title="unterminated,</d> ; rel=preload,,</e>;x=="#,
+        )
+        .iter()
+        .map(|link| link.serialize())
+        .collect();
+        assert_eq!(links, vec!["</a>;rel=preload"]);
+        let links: Vec<String> =
+            parse_link_header_lenient(r#"bad, </a>;rel=preload , </b>;x="y,z";rel=preload"#)
+                .iter()
+                .map(|link| link.serialize())
+                .collect();
+        assert_eq!(
+            links,
+            vec!["</a>;rel=preload", r#"</b>;x="y,z";rel=preload"#]
+        );
+    }
+    #[test]
     fn incomplete_is_err() {
         assert!(parse_accept_header("application/signed-exchange;v=").is_err());
         assert!(parse_cache_control_header("max-age=\"3600").is_err());
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 pub mod acme;
+pub mod builder;
 mod cbor;
 pub mod config;
 pub mod crypto;
@@ -29,34 +30,44 @@ pub mod ocsp;
 pub mod process_html;
 pub mod runtime;
 pub mod serde_helpers;
+#[cfg(feature = "tower")]
+pub mod service;
 pub mod signature;
 pub mod storage;
 pub mod structured_header;
 pub mod sxg;
 pub mod utils;
+#[cfg(feature = "rust_signer")]
+pub mod validation;
 #[cfg(feature = "wasm")]
 mod wasm_worker;
 
 use crate::http::{HeaderFields, HttpResponse};
 use crate::utils::console_log;
 use anyhow::{anyhow, Error, Result};
-use config::Config;
+pub use builder::SxgWorkerBuilder;
+use config::{Config, TrailingSlash};
 use crypto::CertificateChain;
 use headers::{AcceptLevel, Headers};
 use http_cache::HttpCache;
 use runtime::Runtime;
 use serde::Serialize;
-use std::collections::VecDeque;
+#[cfg(feature = "tower")]
+pub use service::SxgService;
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use url::{Origin, Url};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SxgWorker {
     config: Config,
     /// Each new certificate is pushed to the back of the deque.
     /// The back certificate the the latest one.
     certificates: VecDeque<CertificateChain>,
+    /// The signing contexts of `config.hosts`, keyed by their normalized
+    /// host. Each has its own config and certificates, and no hosts.
+    hosts: BTreeMap<String, SxgWorker>,
 }
 
 #[derive(Serialize, Debug, Eq, PartialEq)]
@@ -74,29 +85,190 @@ pub enum PresetContent {
 // expiration because it goes against the origin's cache-control header. (e.g. For max-age
 // <1h, an SXG would be instantly invalid; this would be confusing.)
 const BACKDATING: Duration = Duration::from_secs(60 * 60);
+// The maximum lifetime of signatures in preview mode.
+const PREVIEW_SIGNATURE_DURATION: Duration = Duration::from_secs(60 * 60);
 
 pub const MAX_PAYLOAD_SIZE: usize = 8_000_000;
 
+/// The request header that names the host to sign for, if the config sets
+/// `trust_signed_host_header`; see [`SxgWorker::request_host`].
+pub const SIGNED_HOST_HEADER: &str = "x-sxg-signed-host";
+
+/// How to serve a request, as decided by [`SxgWorker::route_request`] before
+/// anything is fetched from the backend.
+#[derive(Debug, Eq, PartialEq)]
+pub enum RequestAction {
+    /// Serve this response, such as preset content, as is.
+    Respond(HttpResponse),
+    /// Sign the preset `payload` for `fallback_url`, or serve `fallback` if
+    /// that fails.
+    Sign {
+        fallback_url: String,
+        payload: HttpResponse,
+        fallback: HttpResponse,
+    },
+    /// GET `backend_path` from the backend with `backend_headers`, and sign
+    /// the response for `fallback_url`. A HEAD request is fetched as GET, so
+    /// that the signed headers and MICE integrity are computed over the same
+    /// payload a GET would produce.
+    Fetch {
+        fallback_url: String,
+        backend_path: String,
+        backend_headers: HeaderFields,
+    },
+    /// Send the request to the backend unchanged, and serve the response
+    /// unsigned, because it isn't eligible for signing: per its method, or
+    /// per `signed_paths` and `unsigned_paths`.
+    ProxyUnsigned,
+}
+
 impl SxgWorker {
+    /// Creates a worker from YAML config; see `input.example.yaml`. To
+    /// configure a worker in code, use [`SxgWorkerBuilder`] instead.
     pub fn new(config_yaml: &str) -> Result<Self> {
-        let config = Config::new(config_yaml)?;
-        Ok(Self::from_parsed(config))
+        let config: Config = serde_yaml::from_str(config_yaml)?;
+        SxgWorkerBuilder::from_config(config).build()
     }
+    /// Creates a worker from `config` as is, without normalizing it as
+    /// [`Config::new`] and [`SxgWorkerBuilder::build`] do.
     pub fn from_parsed(config: Config) -> Self {
+        let hosts = config
+            .hosts
+            .iter()
+            .map(|(host, host_config)| {
+                let mut config = config.clone();
+                config.hosts.clear();
+                config.html_host = host_config
+                    .html_host
+                    .clone()
+                    .unwrap_or_else(|| host.clone());
+                config.fallback_host = host_config.fallback_host.clone();
+                (host.clone(), SxgWorker::from_parsed(config))
+            })
+            .collect();
         SxgWorker {
             config,
             certificates: VecDeque::new(),
+            hosts,
+        }
+    }
+    /// Returns the signing context for requests whose `Host` is `host`, such
+    /// as `blog.example.com:8443`: that of the matching entry of
+    /// `config.hosts`, with or without the port, or else `self`.
+    pub fn for_host(&self, host: &str) -> &SxgWorker {
+        let host = host.to_ascii_lowercase();
+        let without_port = match host.rsplit_once(':') {
+            Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+            _ => &host,
+        };
+        self.hosts
+            .get(&host)
+            .or_else(|| self.hosts.get(without_port))
+            .unwrap_or(self)
+    }
+    /// Returns `signed_host`, such as `Blog.example.com`, normalized as in
+    /// the config, if it is `html_host` or one of `config.hosts`. Unlike
+    /// [`for_host`](Self::for_host), any other host is an error, since a
+    /// caller that names the host to sign for must name one it is allowed to.
+    pub fn signed_host(&self, signed_host: &str) -> Result<String> {
+        let host = config::parse_html_host(signed_host)?;
+        if (!host.is_empty() && host == self.config.html_host) || self.hosts.contains_key(&host) {
+            Ok(host)
+        } else {
+            Err(anyhow!(
+                "{:?} is not html_host or one of the configured hosts.",
+                signed_host
+            ))
+        }
+    }
+    /// Returns the host to sign a request with header `fields` for `url`:
+    /// that named by its [`SIGNED_HOST_HEADER`] if the config trusts it,
+    /// which must be one of the configured hosts, or else the host it was
+    /// sent to, per its `host` header, or else `url` if absolute, as in
+    /// HTTP/2 and subresource requests. Returns an empty string if neither is
+    /// known. The caller must remove the header from requests that come from
+    /// untrusted clients.
+    pub fn request_host(&self, fields: &HeaderFields, url: &str) -> Result<String> {
+        let header = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value)
+        };
+        match header(SIGNED_HOST_HEADER) {
+            Some(value) if self.config.trust_signed_host_header => self
+                .signed_host(value)
+                .map_err(|e| e.context(format!("invalid {SIGNED_HOST_HEADER}"))),
+            _ => Ok(header("host")
+                .cloned()
+                .or_else(|| {
+                    let url = Url::parse(url).ok()?;
+                    let host = url.host_str()?;
+                    Some(match url.port() {
+                        Some(port) => format!("{host}:{port}"),
+                        None => host.to_string(),
+                    })
+                })
+                .unwrap_or_default()),
+        }
+    }
+    /// Adds a certificate to sign requests to `host` with, which must be a
+    /// key of `config.hosts`, after checking it as
+    /// [`add_certificate`](Self::add_certificate) does, including that its
+    /// key is that of the certificates of this worker.
+    pub fn add_certificate_for_host(
+        &mut self,
+        host: &str,
+        certificate: CertificateChain,
+    ) -> Result<()> {
+        self.check_signing_key(&certificate)?;
+        self.hosts
+            .get_mut(host)
+            .ok_or_else(|| anyhow!("{:?} is not one of the configured hosts.", host))?
+            .add_certificate(certificate)
+    }
+    // Checks that `certificate` has the public key of the certificates
+    // already added to this worker and its hosts, since all of them are
+    // signed for by the one `runtime.sxg_signer`. The first certificate added
+    // thus determines the signing key.
+    fn check_signing_key(&self, certificate: &CertificateChain) -> Result<()> {
+        if let Some(existing) = self.all_certificates().next() {
+            if certificate.public_key_sha256()? != existing.public_key_sha256()? {
+                return Err(anyhow!(
+                    "The key of certificate {} is not the signing key, which is that of certificate {}.",
+                    certificate.basename,
+                    existing.basename
+                ));
+            }
         }
+        Ok(())
     }
-    pub fn add_certificate(&mut self, certificate: CertificateChain) {
+    // The certificates of this worker and of all its hosts.
+    fn all_certificates(&self) -> impl Iterator<Item = &CertificateChain> {
+        self.certificates.iter().chain(
+            self.hosts
+                .values()
+                .flat_map(|host| host.certificates.iter()),
+        )
+    }
+    /// Adds a certificate to sign with, after checking it with
+    /// [`CertificateChain::validate`], and that it has the key of any
+    /// certificate already added to this worker or its hosts.
+    pub fn add_certificate(&mut self, certificate: CertificateChain) -> Result<()> {
+        certificate.validate().map_err(|errors| {
+            let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            anyhow!("Invalid certificate chain: {}", errors.join("; "))
+        })?;
+        self.check_signing_key(&certificate)?;
         self.certificates.push_back(certificate);
+        Ok(())
     }
     /// Reads ACME storage, and adds all ACME certificates to worker.
     pub async fn add_acme_certificates_from_storage(&mut self, runtime: &Runtime) -> Result<()> {
         let acme_state = acme::state_machine::read_current_state(runtime).await?;
         for certificate_pem in acme_state.certificates {
             let certificate = CertificateChain::from_pem_files(&[&certificate_pem])?;
-            self.add_certificate(certificate);
+            self.add_certificate(certificate)?;
         }
         Ok(())
     }
@@ -111,6 +283,26 @@ impl SxgWorker {
     pub fn latest_certificate_basename(&self) -> Option<&str> {
         Some(&self.certificates.back()?.basename)
     }
+    /// Describes the certificates of this worker, in the order they were
+    /// added, e.g. for a debug page. The certificates of `config.hosts` are
+    /// described by those of [`for_host`](Self::for_host). This is async
+    /// because OCSP freshness is read from `runtime.storage`.
+    pub async fn certificates(&self, runtime: &Runtime) -> Result<Vec<CertificateInfo>> {
+        let mut infos = vec![];
+        for certificate in &self.certificates {
+            infos.push(CertificateInfo {
+                basename: certificate.basename.clone(),
+                subject: certificate.subject()?,
+                not_before: certificate.not_before()?,
+                not_after: certificate.not_after()?,
+                public_key_sha256: certificate.public_key_sha256()?,
+                ocsp_fresh: ocsp::read_unexpired_ocsp_from_storage(certificate, runtime)
+                    .await
+                    .is_some(),
+            });
+        }
+        Ok(infos)
+    }
     pub fn create_cert_cbor(&self, cert_basename: &str, ocsp_der: &[u8]) -> Vec<u8> {
         if let Some(certificate) = self.find_certificate_by_basename(cert_basename) {
             certificate.create_cert_cbor(ocsp_der)
@@ -118,6 +310,136 @@ impl SxgWorker {
             cbor::DataItem::Array(vec![]).serialize()
         }
     }
+    /// Creates the `application/cert-chain+cbor` response for the latest
+    /// certificate, with the unexpired OCSP response stapled. This is the
+    /// resource that browsers fetch from the `cert-url` of the signature.
+    /// The optional `sct` item is included if SCTs were supplied by
+    /// [`CertificateChain::with_sct_list`]; otherwise, SCTs embedded in the
+    /// certificate are relied on.
+    pub async fn create_cert_chain_cbor(&self, runtime: &Runtime) -> Result<HttpResponse> {
+        let certificate = self
+            .certificates
+            .back()
+            .ok_or_else(|| anyhow!("No certificate has been added"))?;
+        self.cert_chain_response(runtime, certificate).await
+    }
+    async fn cert_chain_response(
+        &self,
+        runtime: &Runtime,
+        certificate: &CertificateChain,
+    ) -> Result<HttpResponse> {
+        let ocsp_der = self.get_unexpired_ocsp(runtime, certificate).await?;
+        let body = certificate.create_cert_cbor(&ocsp_der);
+        // The ETag covers the stapled OCSP, so it changes when OCSP is updated.
+        let etag = headers::strong_etag(&body);
+        let mut headers = vec![
+            (
+                String::from("content-type"),
+                String::from("application/cert-chain+cbor"),
+            ),
+            (String::from("etag"), etag),
+        ];
+        headers.extend(self.cors_headers());
+        Ok(HttpResponse {
+            body,
+            headers,
+            status: 200,
+        })
+    }
+    // The CORS headers of the cert-chain and validity responses.
+    fn cors_headers(&self) -> HeaderFields {
+        match &self.config.cors_allow_origin {
+            Some(origin) => vec![(String::from("access-control-allow-origin"), origin.clone())],
+            None => vec![],
+        }
+    }
+    /// Returns whether the URL path is eligible for signing, according to
+    /// `signed_paths` and `unsigned_paths` in the config.
+    pub fn should_sign(&self, url: &Url) -> bool {
+        let path = url.path();
+        let signed = &self.config.signed_paths;
+        (signed.is_empty() || signed.is_match(path)) && !self.config.unsigned_paths.is_match(path)
+    }
+    /// Returns `url` with the trailing slash of its path normalized according
+    /// to `trailing_slash` in the config, as the URL to sign the request for.
+    pub fn normalize_url(&self, url: &Url) -> Url {
+        let path = url.path();
+        let normalized = match self.config.trailing_slash {
+            TrailingSlash::Preserve => None,
+            TrailingSlash::Add => {
+                let last_segment = path.rsplit('/').next().unwrap_or_default();
+                if path.ends_with('/') || last_segment.contains('.') {
+                    None
+                } else {
+                    Some(format!("{}/", path))
+                }
+            }
+            TrailingSlash::Remove => match path.trim_end_matches('/') {
+                trimmed if trimmed.len() == path.len() => None,
+                "" => Some("/".to_string()),
+                trimmed => Some(trimmed.to_string()),
+            },
+        };
+        let mut url = url.clone();
+        if let Some(normalized) = normalized {
+            url.set_path(&normalized);
+        }
+        url
+    }
+    /// Returns the path and query to fetch from the backend for `url`, as
+    /// returned by [`normalize_url`](Self::normalize_url). If `url` is a
+    /// directory, this is its `index_document` in the config, if any.
+    pub fn backend_path(&self, url: &Url) -> String {
+        let mut path = url.path().to_string();
+        if let Some(index_document) = &self.config.index_document {
+            if path.ends_with('/') {
+                path.push_str(index_document);
+            }
+        }
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        path
+    }
+    /// Returns whether a response with `status` is eligible for signing,
+    /// according to `sign_error_responses` and `sign_client_error_responses`
+    /// in the config. Others should be proxied unsigned, with their status.
+    pub fn should_sign_status(&self, status: u16) -> bool {
+        match status {
+            400..=499 => self.config.sign_client_error_responses,
+            500..=599 => self.config.sign_error_responses,
+            _ => true,
+        }
+    }
+    /// Returns whether the backend response `payload` is eligible for
+    /// signing: per [`should_sign_status`](Self::should_sign_status), and per
+    /// `signable_content_types` and `sniff_content_type` in the config.
+    /// Others should be proxied unsigned as they are, rather than served as
+    /// an unsigned fallback.
+    pub fn should_sign_payload(&self, payload: &HttpResponse) -> bool {
+        if !self.should_sign_status(payload.status) {
+            return false;
+        }
+        let mut headers =
+            Headers::new(payload.headers.clone(), &self.config.strip_response_headers);
+        headers.default_content_type();
+        if let Some(signable_types) = &self.config.signable_content_types {
+            if headers.check_signable_content_type(signable_types).is_err() {
+                return false;
+            }
+        }
+        if !self.config.sniff_content_type {
+            return true;
+        }
+        // A body that can't be decoded can't be signed either.
+        match utils::decode_content(headers.inner().get("content-encoding"), &payload.body) {
+            Ok(decoded) => headers
+                .check_body_matches_content_type(decoded.as_deref().unwrap_or(&payload.body))
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
     pub fn process_html(
         &self,
         input: Arc<HttpResponse>,
@@ -130,6 +452,61 @@ impl SxgWorker {
         runtime: &Runtime,
         params: CreateSignedExchangeParams<'_, C>,
     ) -> Result<HttpResponse> {
+        Ok(self
+            .create_signed_exchange_with_integrity(runtime, params)
+            .await?
+            .response)
+    }
+    /// Like [`create_signed_exchange`](Self::create_signed_exchange), but
+    /// blocks the current thread instead of being async, e.g. for a build
+    /// tool without an async runtime.
+    ///
+    /// Signing must not need a fetch, so `runtime.fetcher` is never used:
+    /// the payload must have no `link` header unless `skip_process_link` is
+    /// set (or `process_links` is false in the config), and the cert-url
+    /// origin must be a valid URL, since inlining the cert-chain needs OCSP.
+    /// Otherwise an error is returned without signing. `runtime.storage` and
+    /// `runtime.sxg_signer` must not depend on an async runtime such as
+    /// tokio, as the defaults of [`Runtime`] don't.
+    ///
+    /// This must not be called from within an async context, such as a task
+    /// or a `block_on` of tokio or `futures`, since it blocks the thread with
+    /// [`futures::executor::block_on`], which may deadlock or panic there.
+    /// Async callers should use `create_signed_exchange` instead.
+    #[cfg(not(feature = "wasm"))]
+    pub fn create_signed_exchange_blocking<C: HttpCache>(
+        &self,
+        runtime: &Runtime,
+        params: CreateSignedExchangeParams<'_, C>,
+    ) -> Result<HttpResponse> {
+        let processes_links = !params.skip_process_link && self.config.process_links;
+        if processes_links && params.payload_headers.inner().contains_key("link") {
+            return Err(anyhow!(
+                "Can't sign without fetching the preloads of the link header; set skip_process_link."
+            ));
+        }
+        let cert_origin = self
+            .config
+            .cert_url_base
+            .as_deref()
+            .unwrap_or(params.cert_origin);
+        if Url::parse(cert_origin).is_err() {
+            return Err(anyhow!(
+                "Can't sign without fetching OCSP, because cert origin {:?} isn't a valid URL.",
+                cert_origin
+            ));
+        }
+        futures::executor::block_on(self.create_signed_exchange(runtime, params))
+    }
+    /// Like [`create_signed_exchange`](Self::create_signed_exchange), but
+    /// also reports the header-integrity computed for each preloaded
+    /// subresource, e.g. to check that the `allowed-alt-sxg` links of the
+    /// page match the subresource SXGs.
+    pub async fn create_signed_exchange_with_integrity<C: HttpCache>(
+        &self,
+        runtime: &Runtime,
+        params: CreateSignedExchangeParams<'_, C>,
+    ) -> Result<SignedExchange> {
         let CreateSignedExchangeParams {
             fallback_url,
             cert_origin,
@@ -138,6 +515,7 @@ impl SxgWorker {
             skip_process_link,
             status_code,
             header_integrity_cache,
+            mice_record_size,
         } = params;
         if payload_body.len() > MAX_PAYLOAD_SIZE {
             return Err(anyhow!(
@@ -146,26 +524,76 @@ impl SxgWorker {
                 MAX_PAYLOAD_SIZE
             ));
         }
+        if !self.should_sign_status(status_code) {
+            return Err(anyhow!(
+                "The response status {} is not signed, per the config.",
+                status_code
+            ));
+        }
+        // The body is sniffed, and later rewritten and signed, as decoded.
+        let decoded = utils::decode_content(
+            payload_headers.inner().get("content-encoding"),
+            payload_body,
+        )?;
+        let payload_body = decoded.as_deref().unwrap_or(payload_body);
+        if self.config.sniff_content_type {
+            payload_headers.check_body_matches_content_type(payload_body)?;
+        }
+        let mice_record_size = mice_record_size.unwrap_or(mice::MAX_RECORD_SIZE);
+        mice::check_record_size(mice_record_size)?;
+        let skip_process_link = skip_process_link || !self.config.process_links;
 
-        let latest_certificate = self
-            .certificates
-            .back()
-            .ok_or_else(|| Error::msg("Can't create signed exchange without certificate chain."))?;
+        let cert_origin = self.config.cert_url_base.as_deref().unwrap_or(cert_origin);
+        let certificate = self.select_certificate(runtime).await?;
 
         let fallback_base = Url::parse(fallback_url)
             .map_err(|e| Error::new(e).context("Failed to parse fallback URL"))?;
-        let mut header_integrity_fetcher = header_integrity::new_fetcher(
-            runtime.fetcher.as_ref(),
-            header_integrity_cache,
-            &self.config.strip_response_headers,
+        let mut header_integrity_fetcher = header_integrity::RecordingFetcher::new(
+            header_integrity::new_fetcher(
+                runtime.fetcher.as_ref(),
+                header_integrity_cache,
+                &self.config.strip_response_headers,
+                self.config.brotli_level,
+            )
+            .with_revalidation(self.config.header_integrity_revalidation)
+            .with_concurrency(self.config.subresource_fetch_concurrency)
+            .with_max_subresources(self.config.max_subresources),
         );
-        let (signed_headers, payload_body) = utils::signed_headers_and_payload(
+        // The canonical link isn't a preload, so it is added after the
+        // origin's links are processed, which would otherwise drop it.
+        let mut payload_headers = payload_headers;
+        let skip_process_link = if self.config.inject_canonical {
+            if !skip_process_link && payload_headers.is_html() {
+                payload_headers
+                    .process_link_header(&fallback_base, &mut header_integrity_fetcher)
+                    .await;
+            }
+            payload_headers.add_canonical_link(&fallback_base);
+            true
+        } else {
+            skip_process_link
+        };
+        let rewritten_body;
+        let payload_body = if self.config.rewrite_base && payload_headers.is_html() {
+            match process_html::inject_base(payload_body, &fallback_base) {
+                Some(body) => {
+                    rewritten_body = body;
+                    &rewritten_body[..]
+                }
+                None => payload_body,
+            }
+        } else {
+            payload_body
+        };
+        let (signed_headers, payload_body) = utils::signed_headers_and_decoded_payload(
             &fallback_base,
             status_code,
             &payload_headers,
             payload_body,
             &mut header_integrity_fetcher,
             skip_process_link,
+            self.config.brotli_level,
+            mice_record_size,
         )
         .await?;
         let cert_url = match Url::parse(cert_origin) {
@@ -173,15 +601,15 @@ impl SxgWorker {
                 let cert_url = cert_base
                     .join(&format!(
                         "{}{}",
-                        &self.config.cert_url_dirname, &latest_certificate.basename
+                        &self.config.cert_url_dirname, &certificate.basename
                     ))
                     .map_err(|e| Error::new(e).context("Failed to parse cert_url_dirname"))?;
                 cert_url.into()
             }
             // In the case that `cert_origin` is invalid, it fallbacks to use data-url.
             Err(_) => {
-                let ocsp_der = self.get_unexpired_ocsp(runtime, latest_certificate).await?;
-                let cert_body = latest_certificate.create_cert_cbor(&ocsp_der);
+                let ocsp_der = self.get_unexpired_ocsp(runtime, certificate).await?;
+                let cert_body = certificate.create_cert_cbor(&ocsp_der);
                 format!(
                     "data:application/cert-chain+cbor;base64,{}",
                     base64::encode(&cert_body)
@@ -198,11 +626,19 @@ impl SxgWorker {
             .now
             .checked_sub(BACKDATING)
             .ok_or_else(|| anyhow!("Failed to construct date"))?;
-        let max_age = payload_headers.signature_duration()?;
+        let mut max_age = payload_headers.signature_duration()?;
+        if let Some(seconds) = self.config.signature_duration_seconds {
+            max_age = std::cmp::min(max_age, Duration::from_secs(seconds));
+        }
+        if self.config.preview_mode {
+            max_age = std::cmp::min(max_age, PREVIEW_SIGNATURE_DURATION);
+        }
+        // The signature expires at most 7 days after the backdated date.
+        max_age = std::cmp::min(max_age, signature::SEVEN_DAYS - BACKDATING);
         let expires = runtime.now.checked_add(max_age);
         let signature = signature::Signature::new(signature::SignatureParams {
             cert_url: cert_url.as_str(),
-            cert_sha256: &latest_certificate.end_entity_sha256,
+            cert_sha256: &certificate.end_entity_sha256,
             date,
             expires,
             headers: &signed_headers,
@@ -221,51 +657,193 @@ impl SxgWorker {
             &payload_body,
         )
         .map_err(|e| e.context("Failed to create SXG."))?;
-        Ok(HttpResponse {
+        let subresource_integrity = header_integrity_fetcher
+            .into_records()
+            .into_iter()
+            .map(|(url, header_integrity)| SubresourceIntegrity {
+                url,
+                header_integrity,
+            })
+            .collect();
+        let mut headers = vec![
+            (
+                "content-type".into(),
+                "application/signed-exchange;v=b3".into(),
+            ),
+            ("x-content-type-options".into(), "nosniff".into()),
+            (
+                "cache-control".into(),
+                headers::outer_cache_control(max_age),
+            ),
+        ];
+        if self.config.expose_cert_sha256 {
+            let sha256 = certificate.public_key_sha256()?;
+            headers.push((
+                "sxg-rs-cert-sha256".into(),
+                base64::encode_config(&sha256, base64::URL_SAFE_NO_PAD),
+            ));
+        }
+        if self.config.preview_mode {
+            headers.push(("sxg-rs-preview".into(), "1".into()));
+        }
+        let response = HttpResponse {
             body: sxg_body,
-            headers: vec![
-                (
-                    "content-type".into(),
-                    "application/signed-exchange;v=b3".into(),
-                ),
-                ("x-content-type-options".into(), "nosniff".into()),
-                (
-                    "cache-control".into(),
-                    format!(
-                        "public, max-age={}",
-                        // The outer max-age is set smaller, hence the downstream CDNs are able to
-                        // refetch SXG before the SXG signature expires.
-                        max_age.as_secs() / 4,
-                    ),
-                ),
-            ],
+            headers,
             status: 200,
+        };
+        Ok(SignedExchange {
+            response,
+            subresource_integrity,
         })
     }
-    fn create_validity(&self) -> Vec<u8> {
-        let validity = cbor::DataItem::Map(vec![]);
-        validity.serialize()
+    // Chooses the certificate to sign with. Among the unexpired certificates,
+    // prefers one whose OCSP in storage is unexpired, and then the one that
+    // expires last. A certificate whose OCSP fetch failed has no OCSP in
+    // storage, so the next one is used instead. If all certificates have
+    // expired, the latest added one is used. In preview mode, `preview_cert`
+    // is always used, and nothing is signed without it.
+    async fn select_certificate(&self, runtime: &Runtime) -> Result<&CertificateChain> {
+        if self.config.preview_mode {
+            let basename = self.config.preview_cert.as_deref().ok_or_else(|| {
+                Error::msg("Can't create signed exchange in preview mode without preview_cert.")
+            })?;
+            return self
+                .certificates
+                .iter()
+                .find(|certificate| certificate.basename == basename)
+                .ok_or_else(|| {
+                    anyhow!("preview_cert {} is not one of the certificates.", basename)
+                });
+        }
+        let mut unexpired: Vec<(SystemTime, &CertificateChain)> = self
+            .certificates
+            .iter()
+            .filter_map(|certificate| Some((certificate.not_after().ok()?, certificate)))
+            .filter(|(not_after, _)| *not_after > runtime.now)
+            .collect();
+        unexpired.sort_by(|a, b| b.0.cmp(&a.0));
+        for (_, certificate) in &unexpired {
+            if ocsp::read_unexpired_ocsp_from_storage(certificate, runtime)
+                .await
+                .is_some()
+            {
+                return Ok(*certificate);
+            }
+        }
+        unexpired
+            .first()
+            .map(|(_, certificate)| *certificate)
+            .or_else(|| self.certificates.back())
+            .ok_or_else(|| Error::msg("Can't create signed exchange without certificate chain."))
+    }
+    /// Returns the validity data served at the validity URL of every SXG,
+    /// in canonical CBOR. The spec's `signatures` entry is never included, so
+    /// this is an empty map unless `validity_update_size` is configured.
+    /// https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#name-validity-url
+    pub fn create_validity_cbor(&self) -> Vec<u8> {
+        use cbor::DataItem;
+        let mut validity = vec![];
+        if let Some(size) = self.config.validity_update_size {
+            validity.push((
+                DataItem::TextString("update"),
+                DataItem::Map(vec![(
+                    DataItem::TextString("size"),
+                    DataItem::UnsignedInteger(size),
+                )]),
+            ));
+        }
+        DataItem::Map(validity).serialize()
     }
+    /// Returns the OCSP to staple to the cert-chain of `certificate`, fetching
+    /// it if the one in storage has expired. If the responder can't be
+    /// fetched, an expired OCSP is still returned for
+    /// `ocsp_stale_grace_seconds`, unless `certificate` is OCSP Must-Staple,
+    /// which requires a valid, unexpired OCSP.
     pub async fn get_unexpired_ocsp(
         &self,
         runtime: &Runtime,
         certificate: &CertificateChain,
     ) -> Result<Vec<u8>> {
-        ocsp::read_and_update_ocsp_in_storage(
-            certificate,
-            runtime,
-            ocsp::OcspUpdateStrategy::LazyIfUnexpired,
-        )
-        .await
+        let must_staple = certificate.is_must_staple()?;
+        let strategy = match self.config.ocsp_stale_grace_seconds {
+            Some(seconds) if !must_staple => ocsp::OcspUpdateStrategy::LazyOrStale {
+                grace: Duration::from_secs(seconds),
+            },
+            _ => ocsp::OcspUpdateStrategy::LazyIfUnexpired,
+        };
+        let ocsp_der =
+            ocsp::read_and_update_ocsp_in_storage(certificate, runtime, strategy).await?;
+        if must_staple {
+            ocsp::check_unexpired(&ocsp_der, runtime.now).map_err(|e| {
+                e.context(format!(
+                    "Certificate {} is OCSP Must-Staple",
+                    certificate.basename
+                ))
+            })?;
+        }
+        Ok(ocsp_der)
     }
+    /// Updates the OCSP of every certificate, including those of the hosts.
+    /// If any update fails, the rest are still attempted, and the first error
+    /// is returned.
     pub async fn update_oscp_in_storage(&self, runtime: &Runtime) -> Result<()> {
-        if let Some(certificate) = &self.certificates.back() {
-            ocsp::read_and_update_ocsp_in_storage(
+        let mut result = Ok(());
+        for certificate in self.all_certificates() {
+            let update = ocsp::read_and_update_ocsp_in_storage(
                 certificate,
                 runtime,
                 ocsp::OcspUpdateStrategy::EarlyAsRecommended,
             )
-            .await?;
+            .await;
+            if let Err(e) = update {
+                if result.is_ok() {
+                    result = Err(e.context(format!(
+                        "Failed to update OCSP for certificate {}",
+                        certificate.basename
+                    )));
+                }
+            }
+        }
+        result
+    }
+    /// Returns the earliest time at which `update_oscp_in_storage` will fetch
+    /// a new OCSP, or `None` if any certificate has no OCSP in storage yet.
+    pub async fn next_ocsp_update_time(&self, runtime: &Runtime) -> Option<SystemTime> {
+        let mut next: Option<SystemTime> = None;
+        for certificate in self.all_certificates() {
+            let time = ocsp::read_recommended_update_time(certificate, runtime).await?;
+            next = Some(next.map_or(time, |next| next.min(time)));
+        }
+        next
+    }
+    /// Returns an error saying why signing would currently fail, e.g. for a
+    /// health check: there is no unexpired certificate, or the OCSP in
+    /// storage of the certificate to sign with is missing or expired, i.e.
+    /// past its nextUpdate. Each of the hosts is checked too. Unlike
+    /// signing, this doesn't fetch OCSP.
+    pub async fn check_health(&self, runtime: &Runtime) -> Result<()> {
+        self.check_certificate_health(runtime).await?;
+        for (host, worker) in &self.hosts {
+            worker
+                .check_certificate_health(runtime)
+                .await
+                .map_err(|e| e.context(format!("Host {} is unhealthy", host)))?;
+        }
+        Ok(())
+    }
+    async fn check_certificate_health(&self, runtime: &Runtime) -> Result<()> {
+        let certificate = self.select_certificate(runtime).await?;
+        if certificate.not_after()? <= runtime.now {
+            return Err(anyhow!("Certificate {} has expired.", certificate.basename));
+        }
+        if ocsp::read_unexpired_ocsp_from_storage(certificate, runtime)
+            .await
+            .is_none()
+        {
+            return Err(anyhow!(
+                "The OCSP of certificate {} is missing or expired.",
+                certificate.basename
+            ));
         }
         Ok(())
     }
@@ -323,15 +901,24 @@ impl SxgWorker {
             }
         } else if let Some(cert_name) = path.strip_prefix(&self.config.cert_url_dirname) {
             if let Some(certificate) = self.find_certificate_by_basename(cert_name) {
-                let ocsp_der = self.get_unexpired_ocsp(runtime, certificate).await.ok()?;
-                Some(PresetContent::Direct(HttpResponse {
-                    body: certificate.create_cert_cbor(&ocsp_der),
-                    headers: vec![(
-                        String::from("content-type"),
-                        String::from("application/cert-chain+cbor"),
-                    )],
-                    status: 200,
-                }))
+                match self.cert_chain_response(runtime, certificate).await {
+                    Ok(response) => Some(PresetContent::Direct(response)),
+                    // Browsers reject a Must-Staple cert-chain without OCSP,
+                    // so it fails here rather than being served another way.
+                    Err(e) if certificate.is_must_staple().unwrap_or(true) => {
+                        console_log(&format!("{:#}", e));
+                        Some(PresetContent::Direct(HttpResponse {
+                            headers: vec![
+                                (String::from("content-type"), String::from("text/plain")),
+                                (String::from("cache-control"), String::from("no-store")),
+                            ],
+                            status: 503,
+                            body: format!("No valid OCSP for certificate {}", cert_name)
+                                .into_bytes(),
+                        }))
+                    }
+                    Err(_) => None,
+                }
             } else {
                 Some(PresetContent::Direct(HttpResponse {
                     headers: vec![(String::from("content-type"), String::from("text/plain"))],
@@ -341,12 +928,14 @@ impl SxgWorker {
             }
         } else if let Some(validity_name) = path.strip_prefix(&self.config.validity_url_dirname) {
             if validity_name == "validity" {
+                let mut headers = vec![(
+                    String::from("content-type"),
+                    String::from("application/cbor"),
+                )];
+                headers.extend(self.cors_headers());
                 Some(PresetContent::Direct(HttpResponse {
-                    body: self.create_validity(),
-                    headers: vec![(
-                        String::from("content-type"),
-                        String::from("application/cbor"),
-                    )],
+                    body: self.create_validity_cbor(),
+                    headers,
                     status: 200,
                 }))
             } else {
@@ -388,22 +977,154 @@ impl SxgWorker {
             None
         }
     }
+    /// Decides how to serve a request with `method`, `url`, and header
+    /// `fields`, where `self` is the signing context of its host; see
+    /// [`request_host`](Self::request_host) and [`for_host`](Self::for_host).
+    /// A relative `url`, as in HTTP/1.1, refers to `html_host`. Preset content
+    /// is served with `runtime`, and revalidated against the `if-none-match`
+    /// of the request. A request to sign whose path isn't normalized per
+    /// `trailing_slash` is redirected, unsigned, to the normalized path.
+    /// Returns an error if the request is eligible for signing but can't be
+    /// signed, such as if it doesn't prefer SXG; the
+    /// caller should proxy it unsigned, like [`RequestAction::ProxyUnsigned`].
+    pub async fn route_request(
+        &self,
+        runtime: &Runtime,
+        method: &http::Method,
+        url: &str,
+        fields: &HeaderFields,
+    ) -> Result<RequestAction> {
+        // SXGs are only for responses to safe requests, so POST and other
+        // methods are proxied unsigned, even if they accept SXG.
+        if !matches!(method, http::Method::Get | http::Method::Head) {
+            return Ok(RequestAction::ProxyUnsigned);
+        }
+        let req_url = Url::parse(&format!("https://{}/", self.config.html_host))?.join(url)?;
+        match self.serve_preset_content(runtime, req_url.as_str()).await {
+            Some(PresetContent::Direct(response)) => {
+                let if_none_match = fields
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("if-none-match"))
+                    .map(|(_, value)| value.as_str());
+                Ok(RequestAction::Respond(response.revalidate(if_none_match)))
+            }
+            Some(PresetContent::ToBeSigned {
+                url,
+                payload,
+                fallback,
+            }) => {
+                self.transform_request_headers(fields.clone(), AcceptLevel::AcceptsSxg)?;
+                Ok(RequestAction::Sign {
+                    fallback_url: url,
+                    payload,
+                    fallback,
+                })
+            }
+            None => {
+                let normalized = self.normalize_url(&req_url);
+                if !self.should_sign(&normalized) {
+                    return Ok(RequestAction::ProxyUnsigned);
+                }
+                // An SXG is only valid for the URL it was requested by, so
+                // other spellings of a page are redirected to the one that
+                // is signed.
+                if normalized != req_url {
+                    return Ok(RequestAction::Respond(HttpResponse {
+                        body: vec![],
+                        headers: vec![(
+                            String::from("location"),
+                            normalized[url::Position::BeforePath..].to_string(),
+                        )],
+                        status: 301,
+                    }));
+                }
+                let (fallback_url, _) = self.get_fallback_url_and_cert_origin(&req_url)?;
+                let backend_headers =
+                    self.transform_request_headers(fields.clone(), AcceptLevel::PrefersSxg)?;
+                Ok(RequestAction::Fetch {
+                    fallback_url: fallback_url.to_string(),
+                    backend_path: self.backend_path(&req_url),
+                    backend_headers,
+                })
+            }
+        }
+    }
+    /// Signs `payload` for `fallback_url`, after processing its HTML as for
+    /// an SXG, as the response to a request with header `fields`. The SXG
+    /// includes the `amp-cache-transform` header that the request asks for.
+    pub async fn sign_payload<C: HttpCache>(
+        &self,
+        runtime: &Runtime,
+        fallback_url: &str,
+        payload: Arc<HttpResponse>,
+        fields: &HeaderFields,
+        header_integrity_cache: C,
+    ) -> Result<HttpResponse> {
+        let payload = self.process_html(payload, process_html::ProcessHtmlOption { is_sxg: true });
+        let cert_origin = Url::parse(fallback_url)?.origin().ascii_serialization();
+        let mut sxg = self
+            .create_signed_exchange(
+                runtime,
+                CreateSignedExchangeParams {
+                    fallback_url,
+                    cert_origin: &cert_origin,
+                    payload_body: &payload.body,
+                    payload_headers: self.transform_payload_headers(payload.headers.clone())?,
+                    skip_process_link: false,
+                    status_code: payload.status,
+                    header_integrity_cache,
+                    mice_record_size: self.config.mice_record_size,
+                },
+            )
+            .await?;
+        // An unsupported amp-cache-transform already failed in
+        // route_request, so that the response was proxied unsigned.
+        if let Ok(Some(transform)) = self.amp_cache_transform(fields) {
+            sxg.headers.push(("amp-cache-transform".into(), transform));
+        }
+        Ok(sxg)
+    }
+    /// Returns `payload` to serve unsigned, with its HTML processed as for an
+    /// unsigned page.
+    pub fn unsigned_response(&self, payload: Arc<HttpResponse>) -> HttpResponse {
+        let payload = self.process_html(payload, process_html::ProcessHtmlOption { is_sxg: false });
+        Arc::try_unwrap(payload).unwrap_or_else(|p| (*p).clone())
+    }
     /// Checks `fields` as request headers from browser,
     /// and returns the request headers to be sent to backend server.
+    /// Returns an error if an `amp-cache-transform` header offers no
+    /// supported version; see [`amp_cache_transform`](Self::amp_cache_transform).
     pub fn transform_request_headers(
         &self,
         fields: HeaderFields,
         required_accept_level: AcceptLevel,
     ) -> Result<HeaderFields> {
         let headers = Headers::new(fields, &self.config.strip_request_headers);
-        headers
-            .forward_to_origin_server(required_accept_level, &self.config.forward_request_headers)
+        headers.amp_cache_transform()?;
+        headers.forward_to_origin_server(
+            required_accept_level,
+            &self.config.forward_request_headers,
+            self.config.sxg_opt_out_header.as_deref(),
+        )
+    }
+    /// Returns the `amp-cache-transform` header that the SXG response to the
+    /// request with header `fields` must include, if any. AMP caches request
+    /// it with an `amp-cache-transform` request header.
+    pub fn amp_cache_transform(&self, fields: &HeaderFields) -> Result<Option<String>> {
+        Headers::new(fields.clone(), &self.config.strip_request_headers).amp_cache_transform()
     }
     /// Checks `fields` as response headers from backend server,
     /// and returns the reqsponse headers to be sent to browser.
+    /// Hop-by-hop headers are removed, and a missing content-type defaults to
+    /// `text/html`.
     pub fn transform_payload_headers(&self, fields: HeaderFields) -> Result<Headers> {
-        let headers = Headers::new(fields, &self.config.strip_response_headers);
-        headers.validate_as_sxg_payload()?;
+        let mut headers = Headers::new(fields, &self.config.strip_response_headers);
+        headers.remove_hop_by_hop_headers();
+        headers.default_content_type();
+        headers.validate_as_sxg_payload(self.config.skip_no_cache)?;
+        if let Some(signable_types) = &self.config.signable_content_types {
+            headers.check_signable_content_type(signable_types)?;
+        }
         Ok(headers)
     }
     #[cfg(feature = "rust_signer")]
@@ -414,22 +1135,90 @@ impl SxgWorker {
                 .as_ref()
                 .ok_or_else(|| Error::msg("Config private_key_base64 is not set"))?,
         )?;
-        signature::rust_signer::RustSigner::new(&private_key_der)
-            .map_err(|e| e.context("Failed to call RustSigner::new()."))
+        // The private key is the raw scalar, whose size determines the curve
+        // and hence the digest.
+        let curve = crypto::Curve::from_key_size(private_key_der.len())?;
+        let signer = signature::rust_signer::RustSigner::with_curve(curve, &private_key_der)
+            .map_err(|e| e.context("Failed to call RustSigner::with_curve()."))?;
+        Ok(if self.config.randomized_signatures {
+            signer.randomized()
+        } else {
+            signer
+        })
+    }
+    /// Checks that `sxg` is well-formed, currently valid, and signed by one of
+    /// the certificates of this worker.
+    #[cfg(feature = "rust_signer")]
+    pub fn validate_sxg(&self, sxg: &[u8]) -> Result<validation::SxgValidation> {
+        let certificates: Vec<_> = self.certificates.iter().collect();
+        validation::validate(sxg, &certificates, std::time::SystemTime::now())
+    }
+    /// Returns the response signed in `sxg`, as the inverse of
+    /// [`create_signed_exchange`](Self::create_signed_exchange): its status,
+    /// its signed headers, and its body with the MICE encoding and any
+    /// `brotli_level` compression undone. The `:status`, `content-encoding`,
+    /// `content-length` and `digest` headers are dropped, as they describe
+    /// the encoded body.
+    /// Returns an error if the body doesn't match its MICE integrity; the
+    /// signature isn't checked, as by [`validate_sxg`](Self::validate_sxg).
+    pub fn unwrap_sxg(sxg: &[u8]) -> Result<HttpResponse> {
+        let parts = sxg::parse(sxg)?;
+        let signed_headers = sxg::parse_signed_headers(parts.signed_headers)?;
+        let body = mice::decode(&sxg::mice_digest(&signed_headers)?, parts.payload_body)?;
+        let mut status = None;
+        let mut content_encoding = None;
+        let mut headers = vec![];
+        for (name, value) in signed_headers {
+            let name = std::str::from_utf8(name)?;
+            let value = std::str::from_utf8(value)?;
+            match name {
+                ":status" => status = Some(value.parse::<u16>()?),
+                "content-encoding" => content_encoding = Some(value),
+                "content-length" | "digest" => (),
+                _ => headers.push((name.to_string(), value.to_string())),
+            }
+        }
+        // The signed headers are a CBOR map, whose order is not meaningful.
+        headers.sort();
+        // MICE is the last coding applied, and is already undone.
+        let codings = content_encoding
+            .and_then(|value| value.trim().strip_suffix("mi-sha256-03"))
+            .ok_or_else(|| anyhow!("The content-encoding doesn't end with mi-sha256-03"))?
+            .trim_end()
+            .trim_end_matches(',')
+            .to_string();
+        let body = utils::decode_content(Some(&codings), &body)?.unwrap_or(body);
+        Ok(HttpResponse {
+            headers,
+            status: status.ok_or_else(|| anyhow!("The :status header is missing"))?,
+            body,
+        })
     }
     /// Given an original SXG URL (SXG outer URL),
     /// returns the fallback URL (SXG inner URL) and certificate origin.
     /// The certificate origin is the worker origin, which is taken from outer URL.
-    /// The inner URL is created by
-    /// replacing the host name to be the `html_host` in the config.
+    /// The inner URL is created by replacing the origin with `fallback_host`
+    /// in the config, or else `https://{html_host}`, regardless of the origin
+    /// of the outer URL (e.g. an internal backend).
     pub fn get_fallback_url_and_cert_origin(&self, original_url: &Url) -> Result<(Url, Origin)> {
         let mut fallback = original_url.clone();
         let html_host = &self.config.html_host;
-        if !html_host.is_empty() {
+        let fallback_origin = match &self.config.fallback_host {
+            Some(fallback_host) => Some(Url::parse(fallback_host)?),
+            None if !html_host.is_empty() => Some(Url::parse(&format!("https://{}", html_host))?),
+            None => None,
+        };
+        if let Some(fallback_origin) = fallback_origin {
             fallback
                 .set_scheme("https")
                 .map_err(|_| anyhow!("invalid scheme https"))?;
-            fallback.set_host(Some(html_host)).map_err(Error::new)?;
+            fallback
+                .set_host(fallback_origin.host_str())
+                .map_err(Error::new)?;
+            // Drops the port of the backend, if any.
+            fallback
+                .set_port(fallback_origin.port())
+                .map_err(|_| anyhow!("invalid port"))?;
         }
         let cert_origin = original_url.origin();
         Ok((fallback, cert_origin))
@@ -438,12 +1227,67 @@ impl SxgWorker {
 
 pub struct CreateSignedExchangeParams<'a, C: HttpCache> {
     pub fallback_url: &'a str,
+    /// The origin of the cert-url, unless overridden by `cert_url_base` in
+    /// the config. If it isn't a valid URL, the cert-chain is inlined as a
+    /// data URL instead.
     pub cert_origin: &'a str,
     pub payload_body: &'a [u8],
     pub payload_headers: headers::Headers,
+    /// If true, the `link` header is signed as is, without fetching its
+    /// preloads for their header-integrity. Also implied by `process_links:
+    /// false` in the config.
     pub skip_process_link: bool,
+    /// The status code of the payload, signed as the inner response's
+    /// `:status`. Redirects and client errors such as 404 may be signed, but
+    /// 1xx, 206, and 304 are rejected, as are 4xx and 5xx unless the config
+    /// signs them (see [`SxgWorker::should_sign_status`]).
     pub status_code: u16,
     pub header_integrity_cache: C,
+    /// The record size of the MICE encoding of the payload; a power of two
+    /// no larger than 16384, which is also the default. Smaller records let
+    /// the browser verify a streamed payload sooner, at the cost of a 32-byte
+    /// proof per record. Header integrity of subresources is computed with
+    /// the default, so it only matches subresources signed with the default.
+    pub mice_record_size: Option<usize>,
+}
+
+/// The result of [`SxgWorker::create_signed_exchange_with_integrity`].
+#[derive(Debug)]
+pub struct SignedExchange {
+    pub response: HttpResponse,
+    /// The subresources whose header-integrity was computed for the
+    /// `allowed-alt-sxg` links in the signed `link` header, sorted by URL.
+    /// Empty if `skip_process_link` is set. Doesn't include subresources
+    /// whose `allowed-alt-sxg` link was already provided by the backend.
+    pub subresource_integrity: Vec<SubresourceIntegrity>,
+}
+
+/// A certificate of an [`SxgWorker`], as returned by
+/// [`SxgWorker::certificates`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertificateInfo {
+    /// The basename of the cert-url, which is the base64url SHA-256 of the
+    /// end-entity certificate.
+    pub basename: String,
+    /// The subject of the end-entity certificate, such as `CN=example.com`.
+    pub subject: String,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+    /// The SHA-256 of the SubjectPublicKeyInfo of the end-entity certificate,
+    /// as printed by `gen-dev-cert`.
+    pub public_key_sha256: Vec<u8>,
+    /// Whether `runtime.storage` has an unexpired OCSP response for the
+    /// certificate, which is stapled to its cert-chain.
+    pub ocsp_fresh: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SubresourceIntegrity {
+    /// The absolute URL of the subresource.
+    pub url: String,
+    /// The SHA-256 of the signed headers of the subresource SXG, which include
+    /// the MICE digest of its payload, in the format of an SRI hash.
+    pub header_integrity: String,
 }
 
 #[cfg(test)]
@@ -465,14 +1309,61 @@ validity_url_dirname: "//.well-known/sxg-validity"
         "#;
 
         let mut worker = SxgWorker::new(yaml).unwrap();
-        worker.add_certificate(
-            CertificateChain::from_pem_files(&[
-                util::SELF_SIGNED_CERT_PEM,
-                util::SELF_SIGNED_CERT_PEM,
-            ])
-            .unwrap(),
-        );
         worker
+            .add_certificate(
+                CertificateChain::from_pem_files(&[
+                    util::SELF_SIGNED_CERT_PEM,
+                    util::SELF_SIGNED_CERT_PEM,
+                ])
+                .unwrap(),
+            )
+            .unwrap();
+        worker
+    }
+    const HTML: &[(&str, &str)] = &[("content-type", "text/html")];
+    // A runtime at a time within the validity of the test certificates.
+    fn signing_runtime() -> Runtime {
+        Runtime {
+            now: std::time::UNIX_EPOCH + Duration::from_secs(86400),
+            ..Default::default()
+        }
+    }
+    // The params to sign a payload with `status_code`, `payload_body`, and
+    // `headers` at https://my_domain.com/.
+    fn params<'a>(
+        worker: &SxgWorker,
+        status_code: u16,
+        payload_body: &'a [u8],
+        headers: &[(&str, &str)],
+    ) -> CreateSignedExchangeParams<'a, http_cache::NullCache> {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        CreateSignedExchangeParams {
+            fallback_url: "https://my_domain.com/",
+            cert_origin: "https://my_domain.com",
+            payload_body,
+            payload_headers: worker.transform_payload_headers(headers).unwrap(),
+            skip_process_link: false,
+            status_code,
+            header_integrity_cache: http_cache::NullCache {},
+            mice_record_size: None,
+        }
+    }
+    // Signs the payload of `params` with `signing_runtime`.
+    async fn sign(
+        worker: &SxgWorker,
+        status_code: u16,
+        payload_body: &[u8],
+        headers: &[(&str, &str)],
+    ) -> Result<HttpResponse> {
+        worker
+            .create_signed_exchange(
+                &signing_runtime(),
+                params(worker, status_code, payload_body, headers),
+            )
+            .await
     }
     #[test]
     fn cert_basename() {
@@ -543,21 +1434,1789 @@ validity_url_dirname: "//.well-known/sxg-validity"
             Some(PresetContent::Direct(HttpResponse { status: 404, .. }))
         ));
     }
-    #[cfg(not(feature = "wasm"))]
-    #[test]
-    fn require_send() {
-        use std::collections::BTreeSet;
-        // Require async fns to implement Send, so they can be shared across
-        // threads. This is required by hyper, as used in http_server. See
-        // https://blog.rust-lang.org/inside-rust/2019/10/11/AsyncAwait-Not-Send-Error-Improvements.html.
-        // Adding the requirement directly in this test makes it easier to
-        // diagnose; compiler errors are more specific than when the
-        // requirement is indirect via hyper. Values don't matter in this test;
-        // we're only verifying types.
+    #[tokio::test]
+    async fn validity_cbor() {
+        let mut worker = new_worker();
+        let runtime = Runtime::default();
+        let url = "https://my_domain.com/.well-known/sxg-validity/validity";
+        let response = match worker.serve_preset_content(&runtime, url).await {
+            Some(PresetContent::Direct(response)) => response,
+            _ => panic!("Expected validity data"),
+        };
+        assert_eq!(
+            response.headers,
+            vec![(
+                String::from("content-type"),
+                String::from("application/cbor")
+            )]
+        );
+        // The empty map.
+        assert_eq!(response.body, [0xa0]);
+
+        worker.config.validity_update_size = Some(1000);
+        let body = worker.create_validity_cbor();
+        let (validity, rest) = cbor::DataItem::parse(&body).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            validity,
+            cbor::DataItem::Map(vec![(
+                cbor::DataItem::TextString("update"),
+                cbor::DataItem::Map(vec![(
+                    cbor::DataItem::TextString("size"),
+                    cbor::DataItem::UnsignedInteger(1000),
+                )]),
+            )])
+        );
+        // Canonical: definite lengths, and shortest integer encodings.
+        assert_eq!(
+            body,
+            [
+                &[0xa1, 0x66][..],
+                b"update",
+                &[0xa1, 0x64],
+                b"size",
+                &[0x19, 0x03, 0xe8]
+            ]
+            .concat()
+        );
+    }
+    #[tokio::test]
+    async fn cors_allow_origin() {
+        let mut worker = new_worker();
+        let runtime = signing_runtime();
+        let cert_url = format!(
+            "https://my_domain.com/.well-known/sxg-certs/{}",
+            util::SELF_SIGNED_CERT_SHA256
+        );
+        let validity_url = "https://my_domain.com/.well-known/sxg-validity/validity";
+        let cors_header = |preset_content: Option<PresetContent>| match preset_content {
+            Some(PresetContent::Direct(response)) => response
+                .headers
+                .into_iter()
+                .find(|(name, _)| name == "access-control-allow-origin")
+                .map(|(_, value)| value),
+            _ => panic!("Expected a direct response"),
+        };
+        assert_eq!(
+            cors_header(worker.serve_preset_content(&runtime, &cert_url).await),
+            None
+        );
+
+        worker.config.cors_allow_origin = Some("https://www.example.com".into());
+        let expected = Some(String::from("https://www.example.com"));
+        assert_eq!(
+            cors_header(worker.serve_preset_content(&runtime, &cert_url).await),
+            expected
+        );
+        assert_eq!(
+            cors_header(worker.serve_preset_content(&runtime, validity_url).await),
+            expected
+        );
+        assert_eq!(
+            cors_header(
+                worker
+                    .serve_preset_content(&runtime, "https://my_domain.com/.sxg/test.html")
+                    .await
+            ),
+            None
+        );
+        let sxg = sign(&worker, 200, b"<h1>Hello</h1>", HTML).await.unwrap();
+        assert!(!sxg
+            .headers
+            .iter()
+            .any(|(name, _)| name == "access-control-allow-origin"));
+        assert!(!String::from_utf8_lossy(&sxg.body).contains("access-control-allow-origin"));
+    }
+    #[tokio::test]
+    async fn cert_url_revalidation() {
         let worker = new_worker();
         let runtime = Runtime::default();
-        fn is_send<T: Send>(_: T) {}
-        is_send(worker.serve_preset_content(&runtime, "https://my_domain.com/unknown"));
+        let cert_url = format!(
+            "https://my_domain.com/.well-known/sxg-certs/{}",
+            util::SELF_SIGNED_CERT_SHA256
+        );
+        let response = match worker.serve_preset_content(&runtime, &cert_url).await {
+            Some(PresetContent::Direct(response)) => response,
+            _ => panic!("cert-url is not served"),
+        };
+        let (_, etag) = response
+            .headers
+            .iter()
+            .find(|(name, _)| name == "etag")
+            .unwrap();
+        assert_eq!(*etag, headers::strong_etag(&response.body));
+        assert_eq!(response.clone().revalidate(Some(etag)).status, 304);
+        assert_eq!(response.clone().revalidate(Some(r#""stale""#)), response);
+    }
+    #[tokio::test]
+    async fn cert_chain_cbor() {
+        use cbor::DataItem;
+        let worker = new_worker();
+        let response = worker
+            .create_cert_chain_cbor(&Runtime::default())
+            .await
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.headers,
+            vec![
+                (
+                    "content-type".to_string(),
+                    "application/cert-chain+cbor".to_string()
+                ),
+                ("etag".to_string(), headers::strong_etag(&response.body)),
+            ]
+        );
+        let (cbor, rest) = DataItem::parse(&response.body).unwrap();
+        assert!(rest.is_empty());
+        let certificate = &worker.certificates[0];
+        assert_eq!(
+            cbor,
+            DataItem::Array(vec![
+                DataItem::TextString("📜⛓"),
+                DataItem::Map(vec![
+                    (
+                        DataItem::TextString("cert"),
+                        DataItem::ByteString(&certificate.end_entity.der),
+                    ),
+                    // The stub OCSP response for self-signed certificates.
+                    (DataItem::TextString("ocsp"), DataItem::ByteString(b"ocsp")),
+                ]),
+                DataItem::Map(vec![(
+                    DataItem::TextString("cert"),
+                    DataItem::ByteString(&certificate.issuers[0].der),
+                )]),
+            ])
+        );
+    }
+    // A worker with two certificates, where OCSP_CERT_PEM expires last, but
+    // its OCSP can't be fetched.
+    fn new_rotating_worker() -> (SxgWorker, String, String) {
+        let mut worker = new_worker();
+        worker.certificates.clear();
+        let signing_cert =
+            CertificateChain::from_pem_files(&[util::SIGNING_CERT_PEM, util::SIGNING_CERT_PEM])
+                .unwrap();
+        let ocsp_cert =
+            CertificateChain::from_pem_files(&[util::OCSP_CERT_PEM, util::OCSP_CERT_PEM]).unwrap();
+        let (signing_basename, ocsp_basename) =
+            (signing_cert.basename.clone(), ocsp_cert.basename.clone());
+        worker.add_certificate(signing_cert).unwrap();
+        worker.add_certificate(ocsp_cert).unwrap();
+        (worker, signing_basename, ocsp_basename)
+    }
+    fn rotating_runtime() -> Runtime {
+        Runtime {
+            // 2026-11-01, before both certificates expire.
+            now: std::time::UNIX_EPOCH + Duration::from_secs(1793491200),
+            ..Default::default()
+        }
+    }
+    #[tokio::test]
+    async fn must_staple_cert_chain_requires_ocsp() {
+        let mut worker = new_worker();
+        worker.certificates.clear();
+        let certificate = CertificateChain::from_pem_files(&[
+            util::MUST_STAPLE_CERT_PEM,
+            util::MUST_STAPLE_CERT_PEM,
+        ])
+        .unwrap();
+        let cert_url = format!(
+            "https://my_domain.com/.well-known/sxg-certs/{}",
+            certificate.basename
+        );
+        worker.add_certificate(certificate).unwrap();
+        worker.config.ocsp_stale_grace_seconds = Some(86400 * 7);
+        let status = |content| match content {
+            Some(PresetContent::Direct(response)) => Some(response.status),
+            _ => None,
+        };
+
+        // The NullFetcher fails to fetch OCSP.
+        let mut runtime = rotating_runtime();
+        let response = worker.serve_preset_content(&runtime, &cert_url).await;
+        assert_eq!(status(response), Some(503));
+
+        runtime.fetcher = Box::new(fetcher::RouteFetcher::default().with_route(
+            "http://ocsp.example.org",
+            HttpResponse {
+                body: ocsp::tests::ocsp_response(Some("20261102000000Z")),
+                headers: vec![],
+                status: 200,
+            },
+        ));
+        let response = worker.serve_preset_content(&runtime, &cert_url).await;
+        assert_eq!(status(response), Some(200));
+
+        // Once the OCSP expires, it isn't stapled within the grace period.
+        runtime.fetcher = Box::new(fetcher::NULL_FETCHER);
+        runtime.now += Duration::from_secs(86400 * 2);
+        let response = worker.serve_preset_content(&runtime, &cert_url).await;
+        assert_eq!(status(response), Some(503));
+        assert!(worker.create_cert_chain_cbor(&runtime).await.is_err());
+    }
+    #[tokio::test]
+    async fn select_certificate_expiring_last() {
+        let (worker, _, ocsp_basename) = new_rotating_worker();
+        let runtime = rotating_runtime();
+        let certificate = worker.select_certificate(&runtime).await.unwrap();
+        assert_eq!(certificate.basename, ocsp_basename);
+    }
+    #[tokio::test]
+    async fn select_certificate_for_host() {
+        let yaml = r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+hosts:
+  blog.my_domain.com:
+    fallback_host: "https://www.blog.my_domain.com"
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+        "#;
+        let mut worker = SxgWorker::new(yaml).unwrap();
+        let signing_cert =
+            CertificateChain::from_pem_files(&[util::SIGNING_CERT_PEM, util::SIGNING_CERT_PEM])
+                .unwrap();
+        let ocsp_cert =
+            CertificateChain::from_pem_files(&[util::OCSP_CERT_PEM, util::OCSP_CERT_PEM]).unwrap();
+        let (signing_basename, ocsp_basename) =
+            (signing_cert.basename.clone(), ocsp_cert.basename.clone());
+        worker.add_certificate(signing_cert).unwrap();
+        worker
+            .add_certificate_for_host("blog.my_domain.com", ocsp_cert.clone())
+            .unwrap();
+        assert!(worker
+            .add_certificate_for_host("other.my_domain.com", ocsp_cert)
+            .is_err());
+        // A certificate with another key would produce SXGs that fail
+        // verification.
+        let other_key_cert = || {
+            CertificateChain::from_pem_files(&[
+                util::SELF_SIGNED_CERT_PEM,
+                util::SELF_SIGNED_CERT_PEM,
+            ])
+            .unwrap()
+        };
+        assert!(worker
+            .add_certificate_for_host("blog.my_domain.com", other_key_cert())
+            .is_err());
+        assert!(worker.add_certificate(other_key_cert()).is_err());
+
+        let runtime = rotating_runtime();
+        let blog = worker.for_host("Blog.my_domain.com:443");
+        assert_eq!(blog.config().html_host, "blog.my_domain.com");
+        assert_eq!(
+            blog.config().fallback_host.as_deref(),
+            Some("https://www.blog.my_domain.com")
+        );
+        assert_eq!(
+            blog.select_certificate(&runtime).await.unwrap().basename,
+            ocsp_basename
+        );
+        let root = worker.for_host("my_domain.com");
+        assert_eq!(root.config().html_host, "my_domain.com");
+        assert_eq!(
+            root.select_certificate(&runtime).await.unwrap().basename,
+            signing_basename
+        );
+        assert_eq!(
+            worker.for_host("unknown.com").latest_certificate_basename(),
+            Some(signing_basename.as_str())
+        );
+    }
+    #[test]
+    fn signed_host_is_configured() {
+        let yaml = r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+hosts:
+  blog.my_domain.com: {}
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+        "#;
+        let worker = SxgWorker::new(yaml).unwrap();
+        assert_eq!(
+            worker.signed_host("Blog.my_domain.com").unwrap(),
+            "blog.my_domain.com"
+        );
+        assert_eq!(
+            worker.signed_host("my_domain.com").unwrap(),
+            "my_domain.com"
+        );
+        for host in [
+            "other.my_domain.com",
+            "my_domain.com:8443",
+            "",
+            "my_domain.com/",
+        ] {
+            assert!(worker.signed_host(host).is_err(), "{}", host);
+        }
+    }
+    fn fields(fields: &[(&str, &str)]) -> HeaderFields {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+    #[test]
+    fn request_host_from_header_or_url() {
+        let worker = new_worker();
+        assert_eq!(
+            worker
+                .request_host(&fields(&[("Host", "blog.example.com")]), "/a.html")
+                .unwrap(),
+            "blog.example.com"
+        );
+        assert_eq!(
+            worker
+                .request_host(&vec![], "https://blog.example.com:8443/a.css")
+                .unwrap(),
+            "blog.example.com:8443"
+        );
+        assert_eq!(worker.request_host(&vec![], "/a.html").unwrap(), "");
+    }
+    #[test]
+    fn request_host_from_trusted_header() {
+        let worker = |trust: bool| {
+            SxgWorker::new(&format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+hosts:
+  blog.example.com: {{}}
+html_host: example.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+trust_signed_host_header: {trust}
+validity_url_dirname: ".well-known/sxg-validity"
+                "#
+            ))
+            .unwrap()
+        };
+        let request_host = |worker: &SxgWorker, signed_host: &str| {
+            let headers = [
+                ("host", "edge.example.net"),
+                ("X-SXG-Signed-Host", signed_host),
+            ];
+            worker.request_host(&fields(&headers), "/a.html")
+        };
+        let trusting = worker(true);
+        assert_eq!(
+            request_host(&trusting, "Blog.example.com").unwrap(),
+            "blog.example.com"
+        );
+        assert_eq!(
+            request_host(&trusting, "example.com").unwrap(),
+            "example.com"
+        );
+        assert!(request_host(&trusting, "evil.example.org").is_err());
+        assert!(request_host(&trusting, "blog.example.com/a").is_err());
+        // Without the header, the request is signed for the Host it was sent to.
+        assert_eq!(
+            trusting
+                .request_host(&fields(&[("host", "edge.example.net")]), "/a.html")
+                .unwrap(),
+            "edge.example.net"
+        );
+        // Unless the config trusts it, the header is ignored.
+        assert_eq!(
+            request_host(&worker(false), "blog.example.com").unwrap(),
+            "edge.example.net"
+        );
+    }
+    const ACCEPT_SXG: &[(&str, &str)] = &[("accept", "application/signed-exchange;v=b3")];
+    async fn route(worker: &SxgWorker, method: http::Method, url: &str) -> Result<RequestAction> {
+        worker
+            .route_request(&signing_runtime(), &method, url, &fields(ACCEPT_SXG))
+            .await
+    }
+    #[tokio::test]
+    async fn routes_only_get_and_head_to_backend() {
+        let worker = new_worker();
+        for method in [http::Method::Get, http::Method::Head] {
+            match route(&worker, method, "/a.html?q=1").await.unwrap() {
+                RequestAction::Fetch {
+                    fallback_url,
+                    backend_path,
+                    backend_headers,
+                } => {
+                    assert_eq!(fallback_url, "https://my_domain.com/a.html?q=1");
+                    assert_eq!(backend_path, "/a.html?q=1");
+                    assert!(backend_headers.iter().any(|(name, _)| name == "via"));
+                }
+                action => panic!("/a.html is not fetched: {:?}", action),
+            }
+        }
+        for method in [
+            http::Method::Post,
+            http::Method::Put,
+            http::Method::Delete,
+            http::Method::Options,
+        ] {
+            assert_eq!(
+                route(&worker, method, "/a.html").await.unwrap(),
+                RequestAction::ProxyUnsigned
+            );
+        }
+    }
+    #[cfg(feature = "path_patterns")]
+    #[tokio::test]
+    async fn routes_by_preference_and_path() {
+        let worker = new_worker();
+        // A request that doesn't prefer SXG can't be signed.
+        let runtime = signing_runtime();
+        let html = fields(&[("accept", "text/html")]);
+        assert!(worker
+            .route_request(&runtime, &http::Method::Get, "/a.html", &html)
+            .await
+            .is_err());
+        let mut worker = worker;
+        worker.config.unsigned_paths = serde_yaml::from_str("['^/private/']").unwrap();
+        assert_eq!(
+            route(&worker, http::Method::Get, "/private/a.html")
+                .await
+                .unwrap(),
+            RequestAction::ProxyUnsigned
+        );
+        // The preset test.sxg is signed, without a fetch of the backend.
+        match route(&worker, http::Method::Get, "/.sxg/test.sxg")
+            .await
+            .unwrap()
+        {
+            RequestAction::Sign { fallback_url, .. } => {
+                assert_eq!(fallback_url, "https://my_domain.com/.sxg/fallback.html")
+            }
+            action => panic!("test.sxg is not signed: {:?}", action),
+        }
+    }
+    #[tokio::test]
+    async fn routes_cert_url_with_revalidation() {
+        let worker = new_worker();
+        let cert_url = format!("/.well-known/sxg-certs/{}", util::SELF_SIGNED_CERT_SHA256);
+        let route = |if_none_match: &str| {
+            let headers = fields(&[("If-None-Match", if_none_match)]);
+            let worker = &worker;
+            let cert_url = &cert_url;
+            async move {
+                worker
+                    .route_request(&signing_runtime(), &http::Method::Get, cert_url, &headers)
+                    .await
+                    .unwrap()
+            }
+        };
+        let response = match route("").await {
+            RequestAction::Respond(response) => response,
+            action => panic!("cert-url is not served: {:?}", action),
+        };
+        assert_eq!(response.status, 200);
+        let (_, etag) = response
+            .headers
+            .iter()
+            .find(|(name, _)| name == "etag")
+            .unwrap();
+        assert!(matches!(
+            route(etag).await,
+            RequestAction::Respond(HttpResponse { status: 304, .. })
+        ));
+    }
+    #[tokio::test]
+    async fn routes_to_internationalized_html_host() {
+        let worker = SxgWorkerBuilder::new("bücher.example").build().unwrap();
+        match route(&worker, http::Method::Get, "/a.html?q=ü")
+            .await
+            .unwrap()
+        {
+            RequestAction::Fetch {
+                fallback_url,
+                backend_path,
+                ..
+            } => {
+                assert_eq!(
+                    fallback_url,
+                    "https://xn--bcher-kva.example/a.html?q=%C3%BC"
+                );
+                assert_eq!(backend_path, "/a.html?q=%C3%BC");
+            }
+            action => panic!("/a.html is not fetched: {:?}", action),
+        }
+    }
+    #[tokio::test]
+    async fn sign_payload_with_amp_cache_transform() {
+        // Signs an HTML payload in response to a request with `headers`.
+        async fn sign(worker: &SxgWorker, headers: &[(&str, &str)]) -> HttpResponse {
+            let payload = HttpResponse {
+                body: b"<h1>Hi</h1>".to_vec(),
+                headers: fields(HTML),
+                status: 200,
+            };
+            worker
+                .sign_payload(
+                    &signing_runtime(),
+                    "https://my_domain.com/",
+                    Arc::new(payload),
+                    &fields(headers),
+                    http_cache::NullCache {},
+                )
+                .await
+                .unwrap()
+        }
+        let transform = |sxg: &HttpResponse| {
+            sxg.headers
+                .iter()
+                .find(|(name, _)| name == "amp-cache-transform")
+                .map(|(_, value)| value.clone())
+        };
+        let worker = new_worker();
+        let sxg = sign(&worker, &[]).await;
+        assert_eq!(SxgWorker::unwrap_sxg(&sxg.body).unwrap().status, 200);
+        assert_eq!(transform(&sxg), None);
+        let sxg = sign(&worker, &[("amp-cache-transform", r#"google;v="1..3""#)]).await;
+        assert_eq!(transform(&sxg).as_deref(), Some(r#"google;v="1""#));
+    }
+    #[tokio::test]
+    async fn select_certificate_with_ocsp() {
+        let (worker, signing_basename, ocsp_basename) = new_rotating_worker();
+        let runtime = rotating_runtime();
+        // The NullFetcher fails to fetch OCSP for OCSP_CERT_PEM, while
+        // SIGNING_CERT_PEM gets a stub OCSP because it has no AIA extension.
+        let result = worker.update_oscp_in_storage(&runtime).await;
+        assert!(format!("{:#}", result.unwrap_err()).contains(&ocsp_basename));
+        let certificate = worker.select_certificate(&runtime).await.unwrap();
+        assert_eq!(certificate.basename, signing_basename);
+
+        // The cert-url responder serves the selected certificate.
+        let cert_url = format!("https://my_domain.com/.well-known/sxg-certs/{signing_basename}");
+        assert!(matches!(
+            worker.serve_preset_content(&runtime, &cert_url).await,
+            Some(PresetContent::Direct(HttpResponse { status: 200, .. }))
+        ));
+    }
+    #[tokio::test]
+    async fn select_preview_cert_in_preview_mode() {
+        let (mut worker, signing_basename, ocsp_basename) = new_rotating_worker();
+        let runtime = rotating_runtime();
+        worker.update_oscp_in_storage(&runtime).await.unwrap_err();
+        let certificate = worker.select_certificate(&runtime).await.unwrap();
+        assert_eq!(certificate.basename, signing_basename);
+        // Nothing is signed without preview_cert.
+        worker.config.preview_mode = true;
+        assert!(worker.select_certificate(&runtime).await.is_err());
+        // OCSP_CERT_PEM is used despite lacking OCSP.
+        worker.config.preview_cert = Some(ocsp_basename.clone());
+        let certificate = worker.select_certificate(&runtime).await.unwrap();
+        assert_eq!(certificate.basename, ocsp_basename);
+        worker.config.preview_cert = Some("unknown".into());
+        assert_eq!(
+            worker
+                .select_certificate(&runtime)
+                .await
+                .unwrap_err()
+                .to_string(),
+            "preview_cert unknown is not one of the certificates."
+        );
+    }
+    #[tokio::test]
+    async fn select_certificate_after_expiration() {
+        let (worker, signing_basename, ocsp_basename) = new_rotating_worker();
+        let mut runtime = rotating_runtime();
+        worker.update_oscp_in_storage(&runtime).await.unwrap_err();
+        // SIGNING_CERT_PEM expired, but OCSP_CERT_PEM hasn't.
+        runtime.now = std::time::UNIX_EPOCH + Duration::from_secs(1799731255);
+        let certificate = worker.select_certificate(&runtime).await.unwrap();
+        assert_eq!(certificate.basename, ocsp_basename);
+        // Both expired.
+        runtime.now += Duration::from_secs(3600);
+        let certificate = worker.select_certificate(&runtime).await.unwrap();
+        assert_eq!(certificate.basename, ocsp_basename);
+        assert_ne!(signing_basename, ocsp_basename);
+    }
+    #[tokio::test]
+    async fn next_ocsp_update_time_of_every_certificate() {
+        let (mut worker, signing_basename, _) = new_rotating_worker();
+        let runtime = rotating_runtime();
+        assert_eq!(worker.next_ocsp_update_time(&runtime).await, None);
+        // Only SIGNING_CERT_PEM gets a stub OCSP, as in
+        // select_certificate_with_ocsp.
+        worker.update_oscp_in_storage(&runtime).await.unwrap_err();
+        assert_eq!(worker.next_ocsp_update_time(&runtime).await, None);
+        worker
+            .certificates
+            .retain(|certificate| certificate.basename == signing_basename);
+        assert_eq!(
+            worker.next_ocsp_update_time(&runtime).await,
+            Some(runtime.now + Duration::from_secs(3600 * 24))
+        );
+    }
+    #[tokio::test]
+    async fn certificates_of_fixture() {
+        let worker = new_worker();
+        let infos = worker.certificates(&Runtime::default()).await.unwrap();
+        assert_eq!(
+            infos,
+            vec![CertificateInfo {
+                basename: util::SELF_SIGNED_CERT_SHA256.into(),
+                subject: "CN=example.org, O=Test, C=US".into(),
+                // 2021-08-20T00:17:50Z and 2021-11-18T00:17:50Z.
+                not_before: std::time::UNIX_EPOCH + Duration::from_secs(1629418670),
+                not_after: std::time::UNIX_EPOCH + Duration::from_secs(1637194670),
+                public_key_sha256: base64::decode_config(
+                    util::SELF_SIGNED_PUBLIC_KEY_SHA256,
+                    base64::URL_SAFE_NO_PAD
+                )
+                .unwrap(),
+                ocsp_fresh: false,
+            }]
+        );
+    }
+    #[tokio::test]
+    async fn certificates_ocsp_freshness() {
+        let (mut worker, signing_basename, _) = new_rotating_worker();
+        worker
+            .certificates
+            .retain(|certificate| certificate.basename == signing_basename);
+        let mut runtime = rotating_runtime();
+        let ocsp_fresh = |infos: Vec<CertificateInfo>| {
+            infos.iter().map(|info| info.ocsp_fresh).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ocsp_fresh(worker.certificates(&runtime).await.unwrap()),
+            vec![false]
+        );
+        // SIGNING_CERT_PEM gets a stub OCSP, which expires in 6 days.
+        worker.update_oscp_in_storage(&runtime).await.unwrap();
+        assert_eq!(
+            ocsp_fresh(worker.certificates(&runtime).await.unwrap()),
+            vec![true]
+        );
+        runtime.now += Duration::from_secs(3600 * 24 * 7);
+        assert_eq!(
+            ocsp_fresh(worker.certificates(&runtime).await.unwrap()),
+            vec![false]
+        );
+    }
+    #[tokio::test]
+    async fn check_health_of_certificate_and_ocsp() {
+        let (mut worker, signing_basename, _) = new_rotating_worker();
+        worker
+            .certificates
+            .retain(|certificate| certificate.basename == signing_basename);
+        let mut runtime = rotating_runtime();
+        let err = worker.check_health(&runtime).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "The OCSP of certificate {} is missing or expired.",
+                signing_basename
+            )
+        );
+        // SIGNING_CERT_PEM gets a stub OCSP, which expires in 6 days.
+        worker.update_oscp_in_storage(&runtime).await.unwrap();
+        worker.check_health(&runtime).await.unwrap();
+        runtime.now += Duration::from_secs(3600 * 24 * 7);
+        assert!(worker.check_health(&runtime).await.is_err());
+        // The certificate expires on 2027-01-12.
+        runtime.now = std::time::UNIX_EPOCH + Duration::from_secs(1799731255);
+        let err = worker.check_health(&runtime).await.unwrap_err();
+        assert!(err.to_string().ends_with("has expired."));
+        worker.certificates.clear();
+        assert!(worker.check_health(&runtime).await.is_err());
+    }
+    #[cfg(feature = "path_patterns")]
+    #[test]
+    fn should_sign() {
+        let worker = |patterns: &str| {
+            let yaml = format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+{patterns}
+                "#
+            );
+            SxgWorker::new(&yaml).unwrap()
+        };
+        let path = |path: &str| {
+            Url::parse("https://my_domain.com")
+                .unwrap()
+                .join(path)
+                .unwrap()
+        };
+
+        let all = worker("");
+        assert!(all.should_sign(&path("/")));
+        assert!(all.should_sign(&path("/api/data")));
+
+        let html = worker(r#"signed_paths: ['\.html$', '^/$']"#);
+        assert!(html.should_sign(&path("/")));
+        assert!(html.should_sign(&path("/a/b.html?q=1")));
+        assert!(!html.should_sign(&path("/style.css")));
+
+        let no_api = worker("unsigned_paths: ['^/api/']");
+        assert!(no_api.should_sign(&path("/index.html")));
+        assert!(!no_api.should_sign(&path("/api/data")));
+
+        let both = worker(
+            r#"
+signed_paths: ['\.html$']
+unsigned_paths: ['^/api/']
+            "#,
+        );
+        assert!(both.should_sign(&path("/index.html")));
+        assert!(!both.should_sign(&path("/api/index.html")));
+    }
+    #[test]
+    fn fallback_url_uses_configured_origin() {
+        let backend_url = Url::parse("http://backend.internal:8080/a/b.html?q=1").unwrap();
+        let mut worker = new_worker();
+        let (fallback_url, cert_origin) = worker
+            .get_fallback_url_and_cert_origin(&backend_url)
+            .unwrap();
+        assert_eq!(fallback_url.as_str(), "https://my_domain.com/a/b.html?q=1");
+        assert_eq!(cert_origin, backend_url.origin());
+
+        worker.config.fallback_host = Some("https://www.example.com:8443".into());
+        let (fallback_url, _) = worker
+            .get_fallback_url_and_cert_origin(&backend_url)
+            .unwrap();
+        assert_eq!(
+            fallback_url.as_str(),
+            "https://www.example.com:8443/a/b.html?q=1"
+        );
+        let (fallback_url, _) = worker
+            .get_fallback_url_and_cert_origin(&Url::parse("https://other.com/").unwrap())
+            .unwrap();
+        assert_eq!(fallback_url.as_str(), "https://www.example.com:8443/");
+    }
+    #[test]
+    fn normalizes_directory_urls() {
+        // Returns the backend path and fallback URL of a request for `path`.
+        let urls = |worker: &SxgWorker, path: &str| {
+            let url = Url::parse("https://my_domain.com")
+                .unwrap()
+                .join(path)
+                .unwrap();
+            let url = worker.normalize_url(&url);
+            let fallback_url = worker.get_fallback_url_and_cert_origin(&url).unwrap().0;
+            (worker.backend_path(&url), fallback_url.to_string())
+        };
+        let mut worker = new_worker();
+        let expected = |backend_path: &str, fallback_path: &str| {
+            (
+                backend_path.to_string(),
+                format!("https://my_domain.com{}", fallback_path),
+            )
+        };
+        assert_eq!(urls(&worker, "/"), expected("/", "/"));
+        assert_eq!(urls(&worker, "/dir"), expected("/dir", "/dir"));
+        assert_eq!(urls(&worker, "/dir/"), expected("/dir/", "/dir/"));
+
+        worker.config.trailing_slash = TrailingSlash::Add;
+        worker.config.index_document = Some("index.html".into());
+        assert_eq!(urls(&worker, "/"), expected("/index.html", "/"));
+        assert_eq!(urls(&worker, "/dir"), expected("/dir/index.html", "/dir/"));
+        assert_eq!(
+            urls(&worker, "/dir/?q=1"),
+            expected("/dir/index.html?q=1", "/dir/?q=1")
+        );
+        assert_eq!(
+            urls(&worker, "/dir/page.html"),
+            expected("/dir/page.html", "/dir/page.html")
+        );
+
+        worker.config.trailing_slash = TrailingSlash::Remove;
+        assert_eq!(urls(&worker, "/"), expected("/index.html", "/"));
+        assert_eq!(urls(&worker, "/dir"), expected("/dir", "/dir"));
+        assert_eq!(urls(&worker, "/dir/"), expected("/dir", "/dir"));
+        assert_eq!(
+            urls(&worker, "/dir//?q=1"),
+            expected("/dir?q=1", "/dir?q=1")
+        );
+    }
+    #[cfg(feature = "path_patterns")]
+    #[tokio::test]
+    async fn redirects_to_normalized_url() {
+        let mut worker = new_worker();
+        worker.config.trailing_slash = TrailingSlash::Add;
+        worker.config.index_document = Some("index.html".into());
+        let redirect = |location: &str| {
+            RequestAction::Respond(HttpResponse {
+                body: vec![],
+                headers: vec![("location".into(), location.into())],
+                status: 301,
+            })
+        };
+        assert_eq!(
+            route(&worker, http::Method::Get, "/dir?q=1").await.unwrap(),
+            redirect("/dir/?q=1")
+        );
+        worker.config.trailing_slash = TrailingSlash::Remove;
+        assert_eq!(
+            route(&worker, http::Method::Get, "/dir//").await.unwrap(),
+            redirect("/dir")
+        );
+        // Paths that aren't signed are proxied as requested.
+        worker.config.unsigned_paths = serde_yaml::from_str("['^/private/']").unwrap();
+        assert_eq!(
+            route(&worker, http::Method::Get, "/private/dir/")
+                .await
+                .unwrap(),
+            RequestAction::ProxyUnsigned
+        );
+    }
+    #[tokio::test]
+    async fn signs_only_for_request_url() {
+        let mut worker = new_worker();
+        worker.config.index_document = Some("index.html".into());
+        let paths = [
+            "/",
+            "/dir",
+            "/dir/",
+            "/dir//?q=1",
+            "/dir/page.html",
+            "/a?b=/",
+        ];
+        for trailing_slash in [
+            TrailingSlash::Preserve,
+            TrailingSlash::Add,
+            TrailingSlash::Remove,
+        ] {
+            worker.config.trailing_slash = trailing_slash;
+            for path in paths {
+                let request_url = format!("https://my_domain.com{}", path);
+                match route(&worker, http::Method::Get, path).await.unwrap() {
+                    RequestAction::Fetch { fallback_url, .. } => {
+                        assert_eq!(fallback_url, request_url, "{:?}", trailing_slash)
+                    }
+                    RequestAction::Respond(HttpResponse { status: 301, .. }) => (),
+                    action => panic!("{} is neither signed nor redirected: {:?}", path, action),
+                }
+            }
+        }
+    }
+    #[test]
+    fn fallback_url_of_internationalized_html_host() {
+        let worker = SxgWorkerBuilder::new("例え.jp").build().unwrap();
+        assert_eq!(worker.config().html_host, "xn--r8jz45g.jp");
+        let (fallback_url, _) = worker
+            .get_fallback_url_and_cert_origin(
+                &Url::parse("http://backend.internal/ページ").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            fallback_url.as_str(),
+            "https://xn--r8jz45g.jp/%E3%83%9A%E3%83%BC%E3%82%B8"
+        );
+    }
+    #[test]
+    fn transform_payload_headers_by_cache_control() {
+        let mut worker = new_worker();
+        let payload = |cache_control: &str| {
+            vec![
+                ("content-type".into(), "text/html".into()),
+                ("cache-control".into(), cache_control.into()),
+            ]
+        };
+        // An error means that the payload is proxied unsigned.
+        let signs = |worker: &SxgWorker, cache_control| {
+            worker
+                .transform_payload_headers(payload(cache_control))
+                .is_ok()
+        };
+        assert!(signs(&worker, "public, max-age=600"));
+        assert!(!signs(&worker, "no-store"));
+        assert!(!signs(&worker, "private, max-age=600"));
+        assert!(!signs(&worker, "max-age=0"));
+        assert!(!signs(&worker, "no-cache"));
+
+        worker.config.skip_no_cache = false;
+        assert!(signs(&worker, "max-age=0, s-maxage=600"));
+        assert!(signs(&worker, "no-cache"));
+        assert!(!signs(&worker, "no-store"));
+        assert!(!signs(&worker, "private, max-age=600"));
+    }
+    #[test]
+    fn transform_request_headers_with_opt_out_header() {
+        let mut worker = new_worker();
+        let request = || {
+            vec![
+                ("accept".into(), "application/signed-exchange;v=b3".into()),
+                ("x-no-sxg".into(), "1".into()),
+            ]
+        };
+        assert!(worker
+            .transform_request_headers(request(), AcceptLevel::PrefersSxg)
+            .is_ok());
+        worker.config.sxg_opt_out_header = Some("x-no-sxg".into());
+        assert!(worker
+            .transform_request_headers(request(), AcceptLevel::AcceptsSxg)
+            .is_err());
+        assert!(worker
+            .transform_request_headers(request(), AcceptLevel::RejectsSxg)
+            .is_ok());
+    }
+    #[test]
+    fn transform_request_headers_with_accept_signature() {
+        let worker = new_worker();
+        let request = |accept_signature: &str| {
+            vec![
+                ("accept".into(), "application/signed-exchange;v=b3".into()),
+                ("accept-signature".into(), accept_signature.into()),
+            ]
+        };
+        assert!(worker
+            .transform_request_headers(request("ecdsa_p256_sha256"), AcceptLevel::PrefersSxg)
+            .is_ok());
+        // The response is then proxied unsigned.
+        assert!(worker
+            .transform_request_headers(request("rsa-pss-sha512"), AcceptLevel::AcceptsSxg)
+            .is_err());
+        assert!(worker
+            .transform_request_headers(request("rsa-pss-sha512"), AcceptLevel::RejectsSxg)
+            .is_ok());
+    }
+    #[test]
+    fn transform_request_headers_with_amp_cache_transform() {
+        let worker = new_worker();
+        let request = |transform: &str| {
+            vec![
+                ("accept".into(), "application/signed-exchange;v=b3".into()),
+                ("amp-cache-transform".into(), transform.into()),
+            ]
+        };
+        let supported = request(r#"google;v="1..3""#);
+        assert_eq!(
+            worker.amp_cache_transform(&supported).unwrap().as_deref(),
+            Some(r#"google;v="1""#)
+        );
+        assert!(worker
+            .transform_request_headers(supported, AcceptLevel::PrefersSxg)
+            .is_ok());
+        let unsupported = request(r#"google;v="2""#);
+        assert!(worker.amp_cache_transform(&unsupported).is_err());
+        assert!(worker
+            .transform_request_headers(unsupported, AcceptLevel::PrefersSxg)
+            .is_err());
+    }
+    #[tokio::test]
+    async fn signature_duration_from_config() {
+        let mut worker = new_worker();
+        worker.config.signature_duration_seconds = Some(3600);
+        let sxg = sign(&worker, 200, b"<h1>Hello</h1>", HTML).await.unwrap();
+        let sxg = String::from_utf8_lossy(&sxg.body);
+        assert!(sxg.contains(";date=82800;"));
+        assert!(sxg.contains(";expires=90000"));
+    }
+    #[tokio::test]
+    async fn preview_mode_marks_and_shortens_signature() {
+        let is_preview = |sxg: &HttpResponse| {
+            sxg.headers
+                .iter()
+                .any(|(name, value)| name == "sxg-rs-preview" && value == "1")
+        };
+        let mut worker = new_worker();
+        let sxg = sign(&worker, 200, b"<h1>Hello</h1>", HTML).await.unwrap();
+        assert!(!is_preview(&sxg));
+        assert!(String::from_utf8_lossy(&sxg.body).contains(";expires=687600"));
+
+        worker.config.preview_mode = true;
+        worker.config.preview_cert = Some(util::SELF_SIGNED_CERT_SHA256.into());
+        let sxg = sign(&worker, 200, b"<h1>Hello</h1>", HTML).await.unwrap();
+        assert!(is_preview(&sxg));
+        let body = String::from_utf8_lossy(&sxg.body);
+        assert!(body.contains(";date=82800;"));
+        assert!(body.contains(";expires=90000"));
+    }
+    #[tokio::test]
+    async fn exposes_cert_sha256() {
+        async fn cert_sha256(worker: &SxgWorker) -> Option<String> {
+            let sxg = sign(worker, 200, b"<h1>Hello</h1>", HTML).await.unwrap();
+            sxg.headers
+                .into_iter()
+                .find(|(name, _)| name == "sxg-rs-cert-sha256")
+                .map(|(_, value)| value)
+        }
+        let mut worker = new_worker();
+        assert_eq!(cert_sha256(&worker).await, None);
+        worker.config.expose_cert_sha256 = true;
+        assert_eq!(
+            cert_sha256(&worker).await.as_deref(),
+            Some(util::SELF_SIGNED_PUBLIC_KEY_SHA256)
+        );
+    }
+    #[cfg(feature = "content_coding")]
+    #[tokio::test]
+    async fn unwrap_sxg_inverts_create_signed_exchange() {
+        let body = b"<h1>Hello</h1><p>spanning several MICE records</p>";
+        let headers = [("content-type", "text/html"), ("x-custom", "1")];
+        let mut worker = new_worker();
+        for brotli_level in vec![None, Some(5)] {
+            worker.config.brotli_level = brotli_level;
+            let params = CreateSignedExchangeParams {
+                mice_record_size: Some(16),
+                ..params(&worker, 200, body, &headers)
+            };
+            let sxg = worker
+                .create_signed_exchange(&signing_runtime(), params)
+                .await
+                .unwrap()
+                .body;
+            let inner = SxgWorker::unwrap_sxg(&sxg).unwrap();
+            assert_eq!(inner.status, 200);
+            assert_eq!(inner.body, body.to_vec());
+            assert!(inner
+                .headers
+                .contains(&("content-type".into(), "text/html".into())));
+            assert!(inner.headers.contains(&("x-custom".into(), "1".into())));
+            assert!(!inner.headers.iter().any(|(name, _)| {
+                name == ":status"
+                    || name == "content-encoding"
+                    || name == "content-length"
+                    || name == "digest"
+            }));
+
+            let mut tampered = sxg.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            assert!(SxgWorker::unwrap_sxg(&tampered).is_err());
+        }
+    }
+    #[tokio::test]
+    async fn cert_url_from_config() {
+        async fn sign(worker: &SxgWorker) -> HttpResponse {
+            let params = CreateSignedExchangeParams {
+                cert_origin: "https://worker.example",
+                ..params(worker, 200, b"<h1>Hello</h1>", HTML)
+            };
+            worker
+                .create_signed_exchange(&signing_runtime(), params)
+                .await
+                .unwrap()
+        }
+        let mut worker = new_worker();
+        let cert_url = |origin: &str| {
+            format!(
+                r#";cert-url="{origin}/.well-known/sxg-certs/{}";"#,
+                util::SELF_SIGNED_CERT_SHA256
+            )
+        };
+        let sxg = sign(&worker).await;
+        assert!(String::from_utf8_lossy(&sxg.body).contains(&cert_url("https://worker.example")));
+
+        worker.config.cert_url_base = Some("https://my_domain.com".into());
+        let sxg = sign(&worker).await;
+        assert!(String::from_utf8_lossy(&sxg.body).contains(&cert_url("https://my_domain.com")));
+    }
+    #[tokio::test]
+    async fn preset_paths_from_config() {
+        let yaml = r#"
+cert_url_dirname: "/.well-known/sxg/certs"
+forward_request_headers: []
+html_host: my_domain.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg/validity/"
+        "#;
+        let mut worker = SxgWorker::new(yaml).unwrap();
+        worker
+            .add_certificate(
+                CertificateChain::from_pem_files(&[
+                    util::SELF_SIGNED_CERT_PEM,
+                    util::SELF_SIGNED_CERT_PEM,
+                ])
+                .unwrap(),
+            )
+            .unwrap();
+        let runtime = signing_runtime();
+        let sxg = sign(&worker, 200, b"<h1>Hello</h1>", HTML).await.unwrap();
+        let cert_url = format!(
+            "https://my_domain.com/.well-known/sxg/certs/{}",
+            util::SELF_SIGNED_CERT_SHA256
+        );
+        let validity_url = "https://my_domain.com/.well-known/sxg/validity/validity";
+        let body = String::from_utf8_lossy(&sxg.body);
+        assert!(body.contains(&format!(r#";cert-url="{}";"#, cert_url)));
+        assert!(body.contains(&format!(r#";validity-url="{}";"#, validity_url)));
+
+        // The signature's URLs are served, and the default paths aren't.
+        let content_type = |content| match content {
+            Some(PresetContent::Direct(HttpResponse {
+                status: 200,
+                headers,
+                ..
+            })) => headers
+                .into_iter()
+                .find(|(name, _)| name == "content-type")
+                .map(|(_, value)| value),
+            _ => None,
+        };
+        assert_eq!(
+            content_type(worker.serve_preset_content(&runtime, &cert_url).await).as_deref(),
+            Some("application/cert-chain+cbor")
+        );
+        assert_eq!(
+            content_type(worker.serve_preset_content(&runtime, validity_url).await).as_deref(),
+            Some("application/cbor")
+        );
+        for url in [
+            "https://my_domain.com/.well-known/sxg-certs/".to_string()
+                + util::SELF_SIGNED_CERT_SHA256,
+            "https://my_domain.com/.well-known/sxg-validity/validity".to_string(),
+        ] {
+            assert!(worker.serve_preset_content(&runtime, &url).await.is_none());
+        }
+    }
+    #[cfg(feature = "rust_signer")]
+    #[tokio::test]
+    async fn reproducible_unless_randomized_signatures() {
+        async fn sign(worker: &SxgWorker) -> Vec<u8> {
+            let runtime = Runtime {
+                sxg_signer: Box::new(worker.create_rust_signer().unwrap()),
+                ..signing_runtime()
+            };
+            worker
+                .create_signed_exchange(&runtime, params(worker, 200, b"<h1>Hello</h1>", HTML))
+                .await
+                .unwrap()
+                .body
+        }
+        let mut worker = new_worker();
+        worker.config.private_key_base64 = Some(base64::encode([1u8; 32]));
+        assert_eq!(sign(&worker).await, sign(&worker).await);
+        worker.config.randomized_signatures = true;
+        assert_ne!(sign(&worker).await, sign(&worker).await);
+    }
+    #[tokio::test]
+    async fn rewrites_base_of_relative_links() {
+        async fn signed_body(worker: &SxgWorker, body: &[u8]) -> Vec<u8> {
+            let params = CreateSignedExchangeParams {
+                fallback_url: "https://my_domain.com/blog/2022/post.html",
+                ..params(worker, 200, body, HTML)
+            };
+            let sxg = worker
+                .create_signed_exchange(&signing_runtime(), params)
+                .await
+                .unwrap();
+            SxgWorker::unwrap_sxg(&sxg.body).unwrap().body
+        }
+        let body = br#"<html><head><link rel=stylesheet href="style.css"></head><body><a href="../about">About</a></body></html>"#;
+        let mut worker = new_worker();
+        assert_eq!(signed_body(&worker, body).await, body);
+
+        worker.config.rewrite_base = true;
+        let html = String::from_utf8(signed_body(&worker, body).await).unwrap();
+        let base = html
+            .split(r#"<base href=""#)
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap();
+        let base = Url::parse(base).unwrap();
+        assert_eq!(
+            base.join("style.css").unwrap().as_str(),
+            "https://my_domain.com/blog/2022/style.css"
+        );
+        assert_eq!(
+            base.join("../about").unwrap().as_str(),
+            "https://my_domain.com/blog/about"
+        );
+    }
+    #[cfg(feature = "content_coding")]
+    #[tokio::test]
+    async fn rewrites_base_of_encoded_payload() {
+        use std::io::Write;
+        let mut deflate = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        deflate
+            .write_all(b"<head></head><a href=\"a.html\">A</a>")
+            .unwrap();
+        let body = deflate.finish().unwrap();
+        let headers = &[
+            ("content-type", "text/html"),
+            ("content-encoding", "deflate"),
+        ];
+        let mut worker = new_worker();
+        worker.config.rewrite_base = true;
+        let params = CreateSignedExchangeParams {
+            fallback_url: "https://my_domain.com/blog/post.html",
+            ..params(&worker, 200, &body, headers)
+        };
+        let sxg = worker
+            .create_signed_exchange(&signing_runtime(), params)
+            .await
+            .unwrap();
+        assert_eq!(
+            SxgWorker::unwrap_sxg(&sxg.body).unwrap().body,
+            b"<head><base href=\"https://my_domain.com/blog/post.html\"></head><a href=\"a.html\">A</a>"
+        );
+    }
+    #[tokio::test]
+    async fn signs_error_statuses_per_config() {
+        async fn signs(worker: &SxgWorker, status_code: u16) -> bool {
+            sign(worker, status_code, b"<h1>Error</h1>", HTML)
+                .await
+                .is_ok()
+        }
+        let mut worker = new_worker();
+        assert!(signs(&worker, 200).await);
+        assert!(signs(&worker, 404).await);
+        assert!(!signs(&worker, 500).await);
+        assert!(!worker.should_sign_status(503));
+        worker.config.sign_error_responses = true;
+        assert!(signs(&worker, 500).await);
+        worker.config.sign_client_error_responses = false;
+        assert!(!signs(&worker, 404).await);
+        assert!(worker.should_sign_status(200));
+    }
+    #[tokio::test]
+    async fn sniffs_content_type_per_config() {
+        let blob = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        let mut worker = new_worker();
+        assert!(sign(&worker, 200, blob, HTML).await.is_ok());
+        worker.config.sniff_content_type = true;
+        assert!(sign(&worker, 200, blob, HTML).await.is_err());
+        assert!(sign(&worker, 200, b"<h1>Hello</h1>", HTML).await.is_ok());
+    }
+    #[cfg(feature = "content_coding")]
+    #[tokio::test]
+    async fn sniffs_gzip_payload_as_decoded() {
+        use std::io::Write;
+        let gzip = |body: &[u8]| {
+            let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+            gzip.write_all(body).unwrap();
+            gzip.finish().unwrap()
+        };
+        let headers = &[("content-type", "text/html"), ("content-encoding", "gzip")];
+        let mut worker = new_worker();
+        worker.config.sniff_content_type = true;
+        let sxg = sign(&worker, 200, &gzip(b"<h1>Hello</h1>"), headers)
+            .await
+            .unwrap();
+        assert_eq!(
+            SxgWorker::unwrap_sxg(&sxg.body).unwrap().body,
+            b"<h1>Hello</h1>"
+        );
+        let blob = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        assert!(sign(&worker, 200, &gzip(blob), headers).await.is_err());
+    }
+    #[tokio::test]
+    async fn injects_canonical_link() {
+        async fn signed_link(worker: &SxgWorker, link: Option<&str>) -> String {
+            let mut headers = HTML.to_vec();
+            headers.extend(link.map(|link| ("link", link)));
+            let sxg = sign(worker, 200, b"<h1>Hello</h1>", &headers)
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&sxg.body).into_owned()
+        }
+        let mut worker = new_worker();
+        assert!(!signed_link(&worker, None).await.contains("rel=canonical"));
+
+        worker.config.inject_canonical = true;
+        assert!(signed_link(&worker, None)
+            .await
+            .contains("<https://my_domain.com/>;rel=canonical"));
+        // The origin's links are processed as usual, and the canonical link
+        // is appended.
+        let link = "</foo>;rel=preload,</foo>;rel=allowed-alt-sxg;header-integrity=blah";
+        assert!(signed_link(&worker, Some(link)).await.contains(
+            "<https://my_domain.com/foo>;rel=preload,<https://my_domain.com/foo>;rel=allowed-alt-sxg;header-integrity=blah,<https://my_domain.com/>;rel=canonical"
+        ));
+    }
+    #[tokio::test]
+    async fn signs_redirect() {
+        let worker = new_worker();
+        let headers = [
+            ("content-type", "text/html"),
+            ("location", "https://my_domain.com/a"),
+        ];
+        let sxg = sign(&worker, 301, b"<h1>Moved</h1>", &headers)
+            .await
+            .unwrap();
+        // The outer response is always 200; the signed headers, CBOR-encoded
+        // in the body, carry the inner status and location.
+        assert_eq!(sxg.status, 200);
+        let body = String::from_utf8_lossy(&sxg.body);
+        assert!(body.contains("G:statusC301"));
+        assert!(body.contains("HlocationWhttps://my_domain.com/a"));
+        assert!(body.contains("Lcontent-typeItext/html"));
+        assert_eq!(
+            sign(&worker, 206, b"<h1>Moved</h1>", &headers)
+                .await
+                .unwrap_err()
+                .to_string(),
+            "The resource status code is 206, which cannot be signed."
+        );
+    }
+    #[tokio::test]
+    async fn mice_record_size_limit() {
+        let worker = new_worker();
+        let runtime = signing_runtime();
+        let sign = |mice_record_size| {
+            let params = CreateSignedExchangeParams {
+                mice_record_size,
+                ..params(&worker, 200, b"<h1>Hello</h1>", HTML)
+            };
+            worker.create_signed_exchange(&runtime, params)
+        };
+        assert!(sign(Some(4096)).await.is_ok());
+        assert!(sign(Some(4000)).await.is_err());
+        assert!(sign(Some(32768)).await.is_err());
+    }
+    #[tokio::test]
+    async fn outer_cache_control_from_signature_validity() {
+        let worker = new_worker();
+        let sxg = sign(&worker, 200, b"<h1>Hello</h1>", &[]).await.unwrap();
+        // Without a cache-control from the origin, the signature lasts 7 days
+        // from the backdated date.
+        let validity = 7 * 86400 - 3600;
+        assert!(sxg.headers.contains(&(
+            "cache-control".into(),
+            format!("public, max-age={}", validity / 4)
+        )));
+        let sxg = String::from_utf8_lossy(&sxg.body);
+        assert!(sxg.contains(&format!(";expires={}", 86400 + validity)));
+    }
+    #[test]
+    fn transform_payload_headers_for_sxg() {
+        let worker = new_worker();
+        let headers = worker
+            .transform_payload_headers(vec![
+                ("Connection".into(), "close".into()),
+                ("Keep-Alive".into(), "timeout=5".into()),
+                ("Transfer-Encoding".into(), "chunked".into()),
+                ("Vary".into(), "*".into()),
+            ])
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            headers,
+            vec![
+                ("content-type".to_string(), "text/html".to_string()),
+                ("vary".to_string(), "*".to_string()),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+    #[test]
+    fn transform_payload_headers_strips_configured_headers() {
+        let mut worker = new_worker();
+        worker
+            .config
+            .strip_response_headers
+            .insert("x-debug".into());
+        let headers = worker
+            .transform_payload_headers(vec![
+                ("Content-Type".into(), "text/html".into()),
+                ("SET-COOKIE".into(), "id=1".into()),
+                ("Strict-Transport-Security".into(), "max-age=60".into()),
+                ("X-Debug".into(), "backend-3".into()),
+                ("X-Request-Id".into(), "abc".into()),
+            ])
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            headers,
+            vec![
+                ("content-type".to_string(), "text/html".to_string()),
+                ("x-request-id".to_string(), "abc".to_string()),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+    #[test]
+    fn transform_payload_headers_by_content_type() {
+        let mut worker = new_worker();
+        let payload = |content_type: &str| vec![("content-type".into(), content_type.into())];
+        assert!(worker
+            .transform_payload_headers(payload("application/octet-stream"))
+            .is_ok());
+        worker.config.signable_content_types = Some(
+            ["text/html".to_string(), "application/json".to_string()]
+                .iter()
+                .cloned()
+                .collect(),
+        );
+        assert!(worker
+            .transform_payload_headers(payload("text/html; charset=utf-8"))
+            .is_ok());
+        assert!(worker
+            .transform_payload_headers(payload("application/json"))
+            .is_ok());
+        assert!(worker
+            .transform_payload_headers(payload("application/octet-stream"))
+            .is_err());
+    }
+    #[test]
+    fn should_sign_payload_by_status_and_content_type() {
+        let mut worker = new_worker();
+        let payload = |status: u16, headers: &[(&str, &str)]| HttpResponse {
+            body: vec![],
+            headers: fields(headers),
+            status,
+        };
+        let octet_stream = &[("content-type", "application/octet-stream")];
+        assert!(worker.should_sign_payload(&payload(200, octet_stream)));
+        assert!(!worker.should_sign_payload(&payload(500, HTML)));
+        worker.config.signable_content_types = Some(["text/html".to_string()].into());
+        assert!(worker.should_sign_payload(&payload(200, HTML)));
+        // A missing content-type defaults to text/html.
+        assert!(worker.should_sign_payload(&payload(200, &[])));
+        assert!(!worker.should_sign_payload(&payload(200, octet_stream)));
+    }
+    #[test]
+    fn should_sign_payload_by_sniffed_body() {
+        let mut worker = new_worker();
+        let payload = |body: &[u8]| HttpResponse {
+            body: body.to_vec(),
+            headers: fields(HTML),
+            status: 200,
+        };
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        assert!(worker.should_sign_payload(&payload(png)));
+        worker.config.sniff_content_type = true;
+        assert!(!worker.should_sign_payload(&payload(png)));
+        assert!(worker.should_sign_payload(&payload(b"\xef\xbb\xbf\n<!-- c --><html>")));
+    }
+    #[cfg(feature = "content_coding")]
+    #[test]
+    fn should_sign_payload_by_sniffed_decoded_body() {
+        let mut worker = new_worker();
+        worker.config.sniff_content_type = true;
+        let payload = |body: &[u8]| {
+            let mut br = vec![];
+            brotli::BrotliCompress(&mut &*body, &mut br, &Default::default()).unwrap();
+            HttpResponse {
+                body: br,
+                headers: fields(&[("content-type", "text/html"), ("content-encoding", "br")]),
+                status: 200,
+            }
+        };
+        assert!(worker.should_sign_payload(&payload(b"<h1>Hello</h1>")));
+        assert!(!worker.should_sign_payload(&payload(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR")));
+        // The br body doesn't decode as gzip.
+        let mut misencoded = payload(b"<h1>Hello</h1>");
+        misencoded.headers[1].1 = "gzip".into();
+        assert!(!worker.should_sign_payload(&misencoded));
+    }
+    #[cfg(feature = "in_memory_cache")]
+    #[tokio::test]
+    async fn header_integrity_cache_is_shared_across_signings() {
+        use async_trait::async_trait;
+        use fetcher::Fetcher;
+        use http::HttpRequest;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        struct CountingFetcher(Arc<AtomicUsize>);
+        #[cfg_attr(feature = "wasm", async_trait(?Send))]
+        #[cfg_attr(not(feature = "wasm"), async_trait)]
+        impl Fetcher for CountingFetcher {
+            async fn fetch(&self, _request: HttpRequest) -> Result<HttpResponse> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(HttpResponse {
+                    body: b"body { color: red; }".to_vec(),
+                    headers: vec![("content-type".into(), "text/css".into())],
+                    status: 200,
+                })
+            }
+        }
+        let worker = new_worker();
+        let fetch_count = Arc::new(AtomicUsize::new(0));
+        let runtime = Runtime {
+            fetcher: Box::new(CountingFetcher(fetch_count.clone())),
+            ..signing_runtime()
+        };
+        let cache = http_cache::InMemoryCache::new(10, Duration::from_secs(60));
+        for page in ["page1.html", "page2.html"] {
+            // Unlike `params`, this shares an in-memory cache.
+            let sxg = worker
+                .create_signed_exchange(
+                    &runtime,
+                    CreateSignedExchangeParams {
+                        fallback_url: &format!("https://my_domain.com/{}", page),
+                        cert_origin: "https://my_domain.com",
+                        payload_body: b"<h1>Hello</h1>",
+                        payload_headers: worker
+                            .transform_payload_headers(vec![
+                                ("content-type".into(), "text/html".into()),
+                                ("link".into(), "</style.css>;rel=preload;as=style".into()),
+                            ])
+                            .unwrap(),
+                        skip_process_link: false,
+                        status_code: 200,
+                        header_integrity_cache: &cache,
+                        mice_record_size: None,
+                    },
+                )
+                .await
+                .unwrap();
+            assert!(String::from_utf8_lossy(&sxg.body).contains("allowed-alt-sxg"));
+            assert_eq!(fetch_count.load(Ordering::SeqCst), 1);
+        }
+    }
+    #[tokio::test]
+    async fn reports_subresource_integrity() {
+        use header_integrity::tests::{
+            FakeFetcher, EXPECTED_HEADER_INTEGRITY, TEST_RESPONSE, TEST_URL,
+        };
+        let worker = new_worker();
+        let runtime = Runtime {
+            fetcher: Box::new(FakeFetcher(&TEST_RESPONSE)),
+            ..signing_runtime()
+        };
+        let headers = [
+            ("content-type", "text/html"),
+            ("link", "<image.jpg>;rel=preload;as=image"),
+        ];
+        let params = CreateSignedExchangeParams {
+            fallback_url: "https://signed-exchange-testing.dev/sxgs/",
+            cert_origin: "https://signed-exchange-testing.dev",
+            ..params(&worker, 200, b"<img src=image.jpg>", &headers)
+        };
+        let sxg = worker
+            .create_signed_exchange_with_integrity(&runtime, params)
+            .await
+            .unwrap();
+        // The expected value is from dump-signedexchange, which computes the
+        // MICE digest independently.
+        assert_eq!(
+            sxg.subresource_integrity,
+            vec![SubresourceIntegrity {
+                url: TEST_URL.into(),
+                header_integrity: EXPECTED_HEADER_INTEGRITY.into(),
+            }]
+        );
+        assert!(
+            String::from_utf8_lossy(&sxg.response.body).contains(&format!(
+                "header-integrity=\"{}\"",
+                EXPECTED_HEADER_INTEGRITY
+            ))
+        );
+    }
+    #[tokio::test]
+    async fn signs_json_without_processing_link() {
+        let worker = new_worker();
+        let headers = [
+            ("content-type", "application/json"),
+            ("link", "</style.css>;rel=preload;as=style"),
+        ];
+        let params = CreateSignedExchangeParams {
+            fallback_url: "https://my_domain.com/api/data",
+            ..params(&worker, 200, br#"{"hello":"world"}"#, &headers)
+        };
+        let sxg = worker
+            .create_signed_exchange_with_integrity(&signing_runtime(), params)
+            .await
+            .unwrap();
+        assert_eq!(sxg.subresource_integrity, vec![]);
+        let body = String::from_utf8_lossy(&sxg.response.body);
+        assert!(body.contains("application/json"));
+        // The link is neither resolved nor given an allowed-alt-sxg.
+        assert!(body.contains("</style.css>;rel=preload;as=style"));
+        assert!(!body.contains("allowed-alt-sxg"));
+    }
+    #[tokio::test]
+    async fn computes_integrity_of_inline_subresources() {
+        async fn subresource_integrity(
+            fetcher: Box<dyn fetcher::Fetcher>,
+        ) -> Vec<SubresourceIntegrity> {
+            let worker = new_worker();
+            let runtime = Runtime {
+                fetcher,
+                ..signing_runtime()
+            };
+            let headers = [
+                ("content-type", "text/html"),
+                (
+                    "link",
+                    "</a.css>;rel=preload;as=style,</b.js>;rel=preload;as=script",
+                ),
+            ];
+            worker
+                .create_signed_exchange_with_integrity(
+                    &runtime,
+                    params(&worker, 200, b"<h1>Hello</h1>", &headers),
+                )
+                .await
+                .unwrap()
+                .subresource_integrity
+        }
+        let response = |content_type: &str, body: &[u8]| HttpResponse {
+            body: body.to_vec(),
+            headers: vec![("content-type".into(), content_type.into())],
+            status: 200,
+        };
+        let css = response("text/css", b"body { color: red; }");
+        let js = response("text/javascript", b"console.log(1);");
+        let inline = fetcher::InlineFetcher::default()
+            .with_response("https://my_domain.com/a.css", css.clone())
+            .unwrap()
+            .with_response("https://my_domain.com/b.js", js.clone())
+            .unwrap();
+        let fetched = fetcher::RouteFetcher::default()
+            .with_route("https://my_domain.com/a.css", css)
+            .with_route("https://my_domain.com/b.js", js);
+        let inline = subresource_integrity(Box::new(inline)).await;
+        assert_eq!(inline.len(), 2);
+        assert_eq!(
+            inline,
+            subresource_integrity(Box::new(fetched.clone())).await
+        );
+        assert_eq!(fetched.fetched_urls().len(), 2);
+    }
+    #[tokio::test]
+    async fn skips_link_processing_by_config() {
+        let mut worker = new_worker();
+        worker.config.process_links = false;
+        let fetcher = fetcher::RouteFetcher::default();
+        let runtime = Runtime {
+            fetcher: Box::new(fetcher.clone()),
+            ..signing_runtime()
+        };
+        let sxg = worker
+            .create_signed_exchange_with_integrity(
+                &runtime,
+                params(
+                    &worker,
+                    200,
+                    b"<h1>Hello</h1>",
+                    &[
+                        ("content-type", "text/html"),
+                        ("link", "</style.css>;rel=preload;as=style"),
+                    ],
+                ),
+            )
+            .await
+            .unwrap();
+        assert_eq!(sxg.subresource_integrity, vec![]);
+        assert_eq!(fetcher.fetched_urls(), Vec::<String>::new());
+        let body = String::from_utf8_lossy(&sxg.response.body);
+        assert!(body.contains("</style.css>;rel=preload;as=style"));
+        assert!(!body.contains("allowed-alt-sxg"));
+    }
+    #[tokio::test]
+    async fn skips_subresource_integrity() {
+        let worker = new_worker();
+        let params = CreateSignedExchangeParams {
+            skip_process_link: true,
+            ..params(
+                &worker,
+                200,
+                b"<h1>Hello</h1>",
+                &[
+                    ("content-type", "text/html"),
+                    ("link", "</style.css>;rel=preload;as=style"),
+                ],
+            )
+        };
+        let sxg = worker
+            .create_signed_exchange_with_integrity(&signing_runtime(), params)
+            .await
+            .unwrap();
+        assert_eq!(sxg.subresource_integrity, vec![]);
+    }
+    // The params of an HTML payload with the given link header, if any.
+    #[cfg(not(feature = "wasm"))]
+    fn html_params<'a>(
+        worker: &SxgWorker,
+        link: Option<&str>,
+        cert_origin: &'a str,
+    ) -> CreateSignedExchangeParams<'a, http_cache::NullCache> {
+        let mut headers = HTML.to_vec();
+        headers.extend(link.map(|link| ("link", link)));
+        CreateSignedExchangeParams {
+            cert_origin,
+            ..params(worker, 200, b"<h1>Hello</h1>", &headers)
+        }
+    }
+    #[cfg(not(feature = "wasm"))]
+    #[test]
+    fn signs_blocking_without_fetch() {
+        let worker = new_worker();
+        let fetcher = fetcher::RouteFetcher::default();
+        let runtime = Runtime {
+            fetcher: Box::new(fetcher.clone()),
+            ..signing_runtime()
+        };
+        let params = |link, cert_origin| html_params(&worker, link, cert_origin);
+        let sxg = worker
+            .create_signed_exchange_blocking(&runtime, params(None, "https://my_domain.com"))
+            .unwrap();
+        assert_eq!(sxg.status, 200);
+        assert!(String::from_utf8_lossy(&sxg.body).contains("<h1>Hello</h1>"));
+
+        // Links are signed as is if skip_process_link is set.
+        let link = "</style.css>;rel=preload;as=style";
+        let sxg = worker
+            .create_signed_exchange_blocking(
+                &runtime,
+                CreateSignedExchangeParams {
+                    skip_process_link: true,
+                    ..params(Some(link), "https://my_domain.com")
+                },
+            )
+            .unwrap();
+        assert!(String::from_utf8_lossy(&sxg.body).contains(link));
+        assert_eq!(fetcher.fetched_urls(), Vec::<String>::new());
+    }
+    #[cfg(not(feature = "wasm"))]
+    #[test]
+    fn signs_blocking_errors_if_fetch_needed() {
+        let worker = new_worker();
+        let fetcher = fetcher::RouteFetcher::default();
+        let runtime = Runtime {
+            fetcher: Box::new(fetcher.clone()),
+            ..signing_runtime()
+        };
+        let params = |link, cert_origin| html_params(&worker, link, cert_origin);
+        let link = "</style.css>;rel=preload;as=style";
+        let err = worker
+            .create_signed_exchange_blocking(&runtime, params(Some(link), "https://my_domain.com"))
+            .unwrap_err();
+        assert!(err.to_string().contains("skip_process_link"));
+        // An invalid cert origin inlines the cert-chain, which needs OCSP.
+        let err = worker
+            .create_signed_exchange_blocking(&runtime, params(None, "not a url"))
+            .unwrap_err();
+        assert!(err.to_string().contains("OCSP"));
+        assert_eq!(fetcher.fetched_urls(), Vec::<String>::new());
+    }
+    #[cfg(not(feature = "wasm"))]
+    #[test]
+    fn require_send() {
+        use std::collections::BTreeSet;
+        // Require async fns to implement Send, so they can be shared across
+        // threads. This is required by hyper, as used in http_server. See
+        // https://blog.rust-lang.org/inside-rust/2019/10/11/AsyncAwait-Not-Send-Error-Improvements.html.
+        // Adding the requirement directly in this test makes it easier to
+        // diagnose; compiler errors are more specific than when the
+        // requirement is indirect via hyper. Values don't matter in this test;
+        // we're only verifying types.
+        let worker = new_worker();
+        let runtime = Runtime::default();
+        fn is_send<T: Send>(_: T) {}
+        is_send(worker.serve_preset_content(&runtime, "https://my_domain.com/unknown"));
+        is_send(worker.route_request(&runtime, &http::Method::Get, "/", &vec![]));
+        is_send(worker.sign_payload(
+            &runtime,
+            "",
+            Arc::new(HttpResponse {
+                body: vec![],
+                headers: vec![],
+                status: 200,
+            }),
+            &vec![],
+            http_cache::NullCache {},
+        ));
         is_send(worker.create_signed_exchange(
             &runtime,
             CreateSignedExchangeParams {
@@ -568,6 +3227,7 @@ validity_url_dirname: "//.well-known/sxg-validity"
                 skip_process_link: false,
                 status_code: 200,
                 header_integrity_cache: http_cache::NullCache {},
+                mice_record_size: None,
             },
         ));
     }
@@ -1,5 +1,6 @@
 use crate::header_integrity::HeaderIntegrityFetcher;
-use crate::http_parser::{link::Link, parse_link_header, srcset};
+use crate::http_parser::{link::Link, parse_link_header_lenient, srcset};
+use crate::utils::console_log;
 use futures::{stream, stream::StreamExt};
 use once_cell::sync::Lazy;
 use std::borrow::Cow;
@@ -10,25 +11,26 @@ use url::{Origin, Url};
 
 // Filters the link header to comply with
 // https://github.com/google/webpackager/blob/main/docs/cache_requirements.md,
-// and adds allowed-alt-sxg with header-integrity if not present.
+// and adds allowed-alt-sxg with header-integrity if not present. Malformed
+// entries are dropped, keeping the well-formed ones.
 pub(crate) async fn process_link_header(
     value: &str,
     fallback_url: &Url,
     header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
 ) -> String {
-    let links = match parse_link_header(value) {
-        Ok(links) => links,
-        Err(_) => {
-            return "".into();
-        }
-    };
+    let links = parse_link_header_lenient(value);
 
-    let (preloads, allowed_alt_sxgs) = preloads_and_allowed_alt_sxgs(links, fallback_url);
+    let (preloads, allowed_alt_sxgs) = preloads_and_allowed_alt_sxgs(
+        links,
+        fallback_url,
+        header_integrity_fetcher.max_subresources(),
+    );
 
     let fallback_origin = fallback_url.origin();
     let directives = Mutex::new(vec![]);
+    let concurrency = header_integrity_fetcher.concurrency();
     stream::iter(preloads)
-        .for_each_concurrent(None, |link| async {
+        .for_each_concurrent(concurrency, |link| async {
             let link = link;
             let srcset = get_param(&link.params, "imagesrcset");
             let images = match &srcset {
@@ -116,10 +118,11 @@ pub static ALLOWED_PARAM_NAMES: Lazy<HashSet<&'static str>> = Lazy::new(|| {
 
 // Filters the given Link header to only the allowed preload and allowed-alt-sxg directives.
 // Converts URLs to absolute, given fallback_url as base href. Returns the allowed-alt-sxgs as a
-// HashMap for convenient lookup by URL.
+// HashMap for convenient lookup by URL. Only the first `max_preloads` preloads are kept.
 fn preloads_and_allowed_alt_sxgs<'a>(
     links: Vec<Link<'a>>,
     fallback_url: &Url,
+    max_preloads: usize,
 ) -> (Vec<Link<'a>>, HashMap<String, Link<'a>>) {
     static ALLOWED_REL: Lazy<HashSet<&'static str>> =
         Lazy::new(|| vec!["preload", "allowed-alt-sxg"].into_iter().collect());
@@ -149,7 +152,15 @@ fn preloads_and_allowed_alt_sxgs<'a>(
             }
         })
         .partition::<Vec<(bool, Link)>, _>(|(is_preload, _)| *is_preload);
-    preloads.truncate(20);
+    if preloads.len() > max_preloads {
+        console_log(&format!(
+            "Ignoring {} of the {} preloads, beyond max_subresources of {}.",
+            preloads.len() - max_preloads,
+            preloads.len(),
+            max_preloads
+        ));
+        preloads.truncate(max_preloads);
+    }
 
     let preloads: Vec<Link> = preloads.into_iter().map(|(_, link)| link).collect();
     let allowed_alt_sxgs: HashMap<String, Link> = allowed_alt_sxgs
@@ -276,6 +287,53 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn skips_malformed_links() {
+        let url = Url::parse("https://foo.com").unwrap();
+        assert_eq!(
+            process_link_header(
+                r#"</a.js>;rel=preload;as=script,</b js>;rel=preload,c.css>;rel=preload,</d.css>;rel=preload;as=style"#,
+                &url,
+                &mut FakeIntegrityFetcher(Ok("sha256-blah".into())),
+            )
+            .await,
+            concat!(
+                "<https://foo.com/a.js>;rel=preload;as=script,",
+                "<https://foo.com/a.js>;rel=allowed-alt-sxg;header-integrity=sha256-blah,",
+                "<https://foo.com/d.css>;rel=preload;as=style,",
+                "<https://foo.com/d.css>;rel=allowed-alt-sxg;header-integrity=sha256-blah"
+            )
+        );
+        // An unterminated quoted string consumes the rest of the header.
+        assert_eq!(
+            process_link_header(
+                r#"</a.js>;rel=preload,</b.js>;rel=preload;as="unterminated,</c.js>;rel=preload"#,
+                &url,
+                &mut FakeIntegrityFetcher(Ok("sha256-blah".into())),
+            )
+            .await,
+            "<https://foo.com/a.js>;rel=preload,<https://foo.com/a.js>;rel=allowed-alt-sxg;header-integrity=sha256-blah"
+        );
+        assert_eq!(
+            process_link_header(
+                r#"bad,</a.js>;rel=preload,</a.js>;rel=allowed-alt-sxg;header-integrity=blah;=x"#,
+                &url,
+                &mut null_integrity_fetcher(),
+            )
+            .await,
+            ""
+        );
+        assert_eq!(
+            process_link_header(
+                r#"bad,</a.js>;rel=preload,;;,</a.js>;rel=allowed-alt-sxg;header-integrity=blah"#,
+                &url,
+                &mut null_integrity_fetcher(),
+            )
+            .await,
+            "<https://foo.com/a.js>;rel=preload,<https://foo.com/a.js>;rel=allowed-alt-sxg;header-integrity=blah"
+        );
+    }
+
     #[cfg(feature = "srcset")]
     #[tokio::test]
     async fn imagesrcset() {
@@ -384,6 +442,96 @@ mod tests {
         );
     }
 
+    // With time paused, the sleeps of the fetcher advance the clock exactly,
+    // however slow the machine running the test.
+    #[tokio::test(start_paused = true)]
+    async fn fetch_header_integrity_concurrency_limit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+        use tokio::time::Instant;
+        const DELAY: Duration = Duration::from_millis(50);
+        // Records the most fetches in flight at once.
+        struct CountingFetcher {
+            limit: Option<usize>,
+            in_flight: AtomicUsize,
+            max_in_flight: AtomicUsize,
+        }
+        #[cfg_attr(feature = "wasm", async_trait(?Send))]
+        #[cfg_attr(not(feature = "wasm"), async_trait)]
+        impl HeaderIntegrityFetcher for CountingFetcher {
+            async fn fetch(&self, _url: &str) -> Result<String> {
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                tokio::time::sleep(DELAY).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok("sha256-blah".into())
+            }
+            fn concurrency(&self) -> Option<usize> {
+                self.limit
+            }
+        }
+        let url = Url::parse("https://foo.com").unwrap();
+        let preloads: Vec<String> = (0..6).map(|i| format!("</{}>;rel=preload", i)).collect();
+        for limit in [Some(1), Some(3), None] {
+            let mut fetcher = CountingFetcher {
+                limit,
+                in_flight: AtomicUsize::new(0),
+                max_in_flight: AtomicUsize::new(0),
+            };
+            let start = Instant::now();
+            let link = process_link_header(&preloads.join(","), &url, &mut fetcher).await;
+            let elapsed = start.elapsed();
+            assert_eq!(link.matches("rel=allowed-alt-sxg").count(), 6);
+            let expected = limit.unwrap_or(6);
+            assert_eq!(fetcher.max_in_flight.into_inner(), expected);
+            // The fetches take 6 / `expected` rounds of DELAY.
+            let rounds = (6 / expected) as u32;
+            assert_eq!(elapsed, DELAY * rounds);
+        }
+    }
+
+    #[tokio::test]
+    async fn max_subresources() {
+        // Records the URLs fetched.
+        struct RecordingFetcher {
+            max_subresources: usize,
+            urls: std::sync::Mutex<Vec<String>>,
+        }
+        #[cfg_attr(feature = "wasm", async_trait(?Send))]
+        #[cfg_attr(not(feature = "wasm"), async_trait)]
+        impl HeaderIntegrityFetcher for RecordingFetcher {
+            async fn fetch(&self, url: &str) -> Result<String> {
+                self.urls.lock().unwrap().push(url.to_string());
+                Ok("sha256-blah".into())
+            }
+            fn max_subresources(&self) -> usize {
+                self.max_subresources
+            }
+        }
+        let url = Url::parse("https://foo.com").unwrap();
+        let preloads: Vec<String> = (0..25).map(|i| format!("</{}>;rel=preload", i)).collect();
+        for (max_subresources, expected) in [(3, 3), (0, 0), (100, 25)] {
+            let mut fetcher = RecordingFetcher {
+                max_subresources,
+                urls: std::sync::Mutex::new(vec![]),
+            };
+            let link = process_link_header(&preloads.join(","), &url, &mut fetcher).await;
+            assert_eq!(link.matches("rel=preload").count(), expected);
+            let mut urls = fetcher.urls.into_inner().unwrap();
+            urls.sort_by_key(|url| url["https://foo.com/".len()..].parse::<usize>().unwrap());
+            let first: Vec<String> = (0..expected)
+                .map(|i| format!("https://foo.com/{}", i))
+                .collect();
+            assert_eq!(urls, first);
+        }
+        let mut fetcher = FakeIntegrityFetcher(Ok("sha256-blah".into()));
+        let link = process_link_header(&preloads.join(","), &url, &mut fetcher).await;
+        assert_eq!(
+            link.matches("rel=preload").count(),
+            crate::header_integrity::DEFAULT_MAX_SUBRESOURCES
+        );
+    }
+
     #[tokio::test]
     async fn fetch_header_integrity_ok_none() {
         let mut fetcher = FakeIntegrityFetcher(Err("some error".into()));
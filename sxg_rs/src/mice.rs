@@ -16,8 +16,32 @@
 
 use crate::crypto::HashAlgorithm;
 use ::sha2::{Digest, Sha256};
+use anyhow::{anyhow, Result};
 use std::collections::VecDeque;
 
+// The maximum record size allowed by the SXG spec.
+// https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#section-3.5-7.9.1
+pub const MAX_RECORD_SIZE: usize = 16384;
+
+/// Checks that the record size is a power of two, no larger than
+/// [`MAX_RECORD_SIZE`].
+pub fn check_record_size(record_size: usize) -> Result<()> {
+    if !record_size.is_power_of_two() {
+        Err(anyhow!(
+            "The MICE record size {} is not a power of two.",
+            record_size
+        ))
+    } else if record_size > MAX_RECORD_SIZE {
+        Err(anyhow!(
+            "The MICE record size {} exceeds the limit {}.",
+            record_size,
+            MAX_RECORD_SIZE
+        ))
+    } else {
+        Ok(())
+    }
+}
+
 pub fn calculate(input: &[u8], record_size: usize) -> (Vec<u8>, Vec<u8>) {
     if input.is_empty() {
         return (HashAlgorithm::Sha256.digest(&[0]), vec![]);
@@ -52,6 +76,84 @@ pub fn calculate(input: &[u8], record_size: usize) -> (Vec<u8>, Vec<u8>) {
     (integrity, message)
 }
 
+// The size of each proof, which is a SHA-256 digest.
+const PROOF_SIZE: usize = 32;
+
+/// Returns the record size and the number of records of the non-empty
+/// `message` returned by [`calculate`], without checking its integrity.
+pub fn count_records(message: &[u8]) -> Result<(usize, usize)> {
+    let (record_size, rest) = split_record_size(message)?;
+    if rest.is_empty() {
+        return Err(anyhow!("The payload is missing its last record"));
+    }
+    // Each record except the last is followed by the proof of the next one.
+    let step = record_size.saturating_add(PROOF_SIZE);
+    let later = rest.len().saturating_sub(record_size);
+    Ok((record_size, 1 + (later + step - 1) / step))
+}
+
+// Splits the record size from the start of a non-empty `message`.
+fn split_record_size(message: &[u8]) -> Result<(usize, &[u8])> {
+    if message.len() < 8 {
+        return Err(anyhow!("The payload is too short to contain a record size"));
+    }
+    let (record_size, rest) = message.split_at(8);
+    let mut record_size_bytes = [0u8; 8];
+    record_size_bytes.copy_from_slice(record_size);
+    let record_size = u64::from_be_bytes(record_size_bytes) as usize;
+    if record_size == 0 {
+        return Err(anyhow!("The record size is 0"));
+    }
+    Ok((record_size, rest))
+}
+
+/// Checks the `message` returned by [`calculate`] against its `integrity`,
+/// and returns the original input.
+pub fn decode(integrity: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+    if message.is_empty() {
+        return if integrity == HashAlgorithm::Sha256.digest(&[0]) {
+            Ok(vec![])
+        } else {
+            Err(anyhow!("The integrity of the empty payload doesn't match"))
+        };
+    }
+    let (record_size, mut rest) = split_record_size(message)?;
+    // Each record except the last is followed by the proof of the next one.
+    let mut records = vec![];
+    let mut proofs = vec![];
+    while rest.len() > record_size {
+        if rest.len() < record_size + PROOF_SIZE {
+            return Err(anyhow!("The payload is truncated in a proof"));
+        }
+        records.push(&rest[..record_size]);
+        proofs.push(&rest[record_size..record_size + PROOF_SIZE]);
+        rest = &rest[record_size + PROOF_SIZE..];
+    }
+    if rest.is_empty() {
+        return Err(anyhow!("The payload is missing its last record"));
+    }
+    records.push(rest);
+    let mut next_proof: Option<Vec<u8>> = None;
+    for (i, record) in records.iter().enumerate().rev() {
+        let mut hasher = Sha256::new();
+        hasher.update(record);
+        if let Some(next_proof) = &next_proof {
+            if proofs[i] != next_proof.as_slice() {
+                return Err(anyhow!("The proof after record {} doesn't match", i));
+            }
+            hasher.update(next_proof);
+            hasher.update([1u8]);
+        } else {
+            hasher.update([0u8]);
+        }
+        next_proof = Some(hasher.finalize().to_vec());
+    }
+    if next_proof.as_deref() != Some(integrity) {
+        return Err(anyhow!("The integrity of the payload doesn't match"));
+    }
+    Ok(records.concat())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,4 +195,45 @@ mod tests {
             ),
         );
     }
+    #[test]
+    fn decode_inverts_calculate() {
+        let input = "When I grow up, I want to be a watermelon".as_bytes();
+        for record_size in [1, 16, 41, 1000] {
+            let (integrity, message) = calculate(input, record_size);
+            assert_eq!(decode(&integrity, &message).unwrap(), input);
+        }
+        let (integrity, message) = calculate(b"", 16384);
+        assert_eq!(decode(&integrity, &message).unwrap(), b"");
+    }
+    #[test]
+    fn counts_records() {
+        let input = "When I grow up, I want to be a watermelon".as_bytes();
+        for (record_size, records) in [(1, 41), (16, 3), (41, 1), (1000, 1)] {
+            let (_, message) = calculate(input, record_size);
+            assert_eq!(
+                count_records(&message).unwrap(),
+                (std::cmp::min(record_size, input.len()), records)
+            );
+        }
+        assert!(count_records(&0x10_u64.to_be_bytes()).is_err());
+        assert!(count_records(&[0; 9]).is_err());
+    }
+    #[test]
+    fn record_size_limits() {
+        for record_size in [1, 1024, 16384] {
+            assert!(check_record_size(record_size).is_ok());
+        }
+        for record_size in [0, 1000, 32768] {
+            assert!(check_record_size(record_size).is_err());
+        }
+    }
+    #[test]
+    fn decode_rejects_corruption() {
+        let input = "When I grow up, I want to be a watermelon".as_bytes();
+        let (integrity, mut message) = calculate(input, 16);
+        assert!(decode(&integrity[1..], &message).is_err());
+        assert!(decode(&integrity, &message[..message.len() - 1]).is_err());
+        *message.last_mut().unwrap() ^= 1;
+        assert!(decode(&integrity, &message).is_err());
+    }
 }
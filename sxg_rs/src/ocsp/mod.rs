@@ -22,13 +22,17 @@ use crate::crypto::{CertificateChain, HashAlgorithm};
 use crate::fetcher::Fetcher;
 use crate::http::{HttpRequest, Method};
 use crate::runtime::Runtime;
+use crate::signature::SEVEN_DAYS;
+use crate::utils::console_log;
 use anyhow::{anyhow, Error, Result};
 use der_parser::{
-    ber::{BerObject, BerObjectContent},
+    asn1_rs::ASN1TimeZone,
+    ber::{BerObject, BerObjectContent, Class, Tag},
     oid,
     oid::Oid,
 };
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::time::{Duration, SystemTime};
 use tokio::sync::Mutex;
 use x509_parser::{
@@ -137,28 +141,377 @@ pub async fn fetch_from_ca(
         .fetch(req)
         .await
         .map_err(|e| e.context("Failed to fetch OCSP"))?;
+    if rsp.status != 200 {
+        let error = format!("The OCSP responder returned status {}", rsp.status);
+        let retry_after = rsp.headers.iter().find_map(|(name, value)| {
+            if name.eq_ignore_ascii_case("retry-after") {
+                RetryAfter::parse(value)
+            } else {
+                None
+            }
+        });
+        return Err(match retry_after {
+            Some(retry_after) if matches!(rsp.status, 429 | 503) => {
+                Error::new(retry_after).context(error)
+            }
+            _ => Error::msg(error),
+        });
+    }
     Ok(rsp.body)
 }
 
+/// The `Retry-After` of a 429 or 503 response from the OCSP responder, which
+/// should not be fetched again until then, to be polite per
+/// https://gist.github.com/sleevi/5efe9ef98961ecfb4da8. This is the
+/// underlying error of `fetch_from_ca` in that case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryAfter {
+    Delay(Duration),
+    Date(SystemTime),
+}
+
+impl RetryAfter {
+    // The longest wait honored, so that a misconfigured responder can't
+    // prevent the OCSP from being updated before it expires.
+    const MAX_DELAY: Duration = Duration::from_secs(3600 * 24);
+    // Parses the header value, either delay-seconds or an HTTP-date, per
+    // https://datatracker.ietf.org/doc/html/rfc7231#section-7.1.3.
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Some(RetryAfter::Delay(Duration::from_secs(seconds)));
+        }
+        let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+        let seconds = u64::try_from(date.timestamp()).ok()?;
+        Some(RetryAfter::Date(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(seconds),
+        ))
+    }
+    /// Returns the time before which the responder should not be fetched.
+    pub fn time(&self, now: SystemTime) -> SystemTime {
+        let time = match self {
+            RetryAfter::Delay(delay) => now + (*delay).min(Self::MAX_DELAY),
+            RetryAfter::Date(date) => *date,
+        };
+        time.min(now + Self::MAX_DELAY)
+    }
+}
+
+impl std::fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RetryAfter::Delay(delay) => write!(f, "Retry after {} seconds", delay.as_secs()),
+            RetryAfter::Date(date) => write!(
+                f,
+                "Retry after {}",
+                chrono::DateTime::<chrono::Utc>::from(*date).to_rfc2822()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RetryAfter {}
+
+// Returns the item at `index` of the SEQUENCE `sequence`.
+fn item<'a, 'b>(sequence: &'b BerObject<'a>, index: usize) -> Result<&'b BerObject<'a>> {
+    sequence
+        .as_sequence()?
+        .get(index)
+        .ok_or_else(|| anyhow!("Expecting a SEQUENCE of at least {} items", index + 1))
+}
+
+// Parses the DER object inside `outer`, which is an OCTET STRING or an
+// EXPLICIT tag, whose contents der_parser leaves unparsed.
+fn parse_inner<'a>(outer: &BerObject<'a>) -> Result<BerObject<'a>> {
+    Ok(der_parser::der::parse_der(outer.as_slice()?)?.1)
+}
+
+// Whether `object` has the context-specific `tag` of an EXPLICIT or IMPLICIT
+// field, such as the [0] of `nextUpdate`.
+fn has_context_tag(object: &BerObject, tag: u32) -> bool {
+    object.header.class() == Class::ContextSpecific && object.header.tag() == Tag(tag)
+}
+
+fn parse_generalized_time(object: &BerObject) -> Result<SystemTime> {
+    let time = match &object.content {
+        BerObjectContent::GeneralizedTime(time) => time,
+        _ => {
+            return Err(anyhow!(
+                "Expecting a GeneralizedTime, found {:?}",
+                object.header.tag()
+            ))
+        }
+    };
+    if !matches!(time.tz, ASN1TimeZone::Z) {
+        return Err(anyhow!("Expecting GeneralizedTime to be in UTC"));
+    }
+    // Fractional seconds are dropped.
+    let time =
+        chrono::NaiveDate::from_ymd_opt(time.year as i32, time.month.into(), time.day.into())
+            .and_then(|date| {
+                date.and_hms_opt(time.hour.into(), time.minute.into(), time.second.into())
+            })
+            .ok_or_else(|| anyhow!("Invalid GeneralizedTime"))?;
+    let secs = u64::try_from(time.timestamp())?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Parses a DER-encoded OCSP response, and returns the result of `f` on its
+/// first `SingleResponse`.
+fn parse_single_response<T>(ocsp_der: &[u8], f: impl FnOnce(&BerObject) -> Result<T>) -> Result<T> {
+    // https://tools.ietf.org/html/rfc6960#section-4.2.1
+    // OCSPResponse ::= SEQUENCE {
+    //    responseStatus         OCSPResponseStatus,
+    //    responseBytes          [0] EXPLICIT ResponseBytes OPTIONAL }
+    let (_, ocsp_response) = der_parser::der::parse_der(ocsp_der)?;
+    let response_bytes = item(&ocsp_response, 1)?;
+    if !has_context_tag(response_bytes, 0) {
+        return Err(anyhow!("Expecting responseBytes"));
+    }
+    // ResponseBytes ::=       SEQUENCE {
+    //     responseType   OBJECT IDENTIFIER,
+    //     response       OCTET STRING }
+    let response_bytes = parse_inner(response_bytes)?;
+    // BasicOCSPResponse       ::= SEQUENCE {
+    //    tbsResponseData      ResponseData,
+    //    ...
+    let basic_response = parse_inner(item(&response_bytes, 1)?)?;
+    // ResponseData ::= SEQUENCE {
+    //    version              [0] EXPLICIT Version DEFAULT v1,
+    //    responderID              ResponderID,
+    //    producedAt               GeneralizedTime,
+    //    responses                SEQUENCE OF SingleResponse,
+    //    responseExtensions   [1] EXPLICIT Extensions OPTIONAL }
+    // The responderID is a CHOICE of [1] and [2], so it and the version are
+    // the leading context-specific fields.
+    let response_data = item(&basic_response, 0)?;
+    let responses = response_data
+        .as_sequence()?
+        .iter()
+        .filter(|field| field.header.class() != Class::ContextSpecific)
+        .nth(1)
+        .ok_or_else(|| anyhow!("Expecting ResponseData to contain responses"))?;
+    // SingleResponse ::= SEQUENCE {
+    //    certID                       CertID,
+    //    certStatus                   CertStatus,
+    //    thisUpdate                   GeneralizedTime,
+    //    nextUpdate         [0]       EXPLICIT GeneralizedTime OPTIONAL,
+    //    singleExtensions   [1]       EXPLICIT Extensions OPTIONAL }
+    f(item(responses, 0)?)
+}
+
+/// Parses a DER-encoded OCSP response, and returns the `thisUpdate` and
+/// `nextUpdate` of its first `SingleResponse`.
+fn parse_ocsp_validity(ocsp_der: &[u8]) -> Result<(SystemTime, Option<SystemTime>)> {
+    parse_single_response(ocsp_der, |single_response| {
+        let this_update = parse_generalized_time(item(single_response, 2)?)?;
+        let next_update = match single_response.as_sequence()?.get(3) {
+            Some(next_update) if has_context_tag(next_update, 0) => {
+                Some(parse_generalized_time(&parse_inner(next_update)?)?)
+            }
+            _ => None,
+        };
+        Ok((this_update, next_update))
+    })
+}
+
+// Whether the first `SingleResponse` of `ocsp_der` is for the serial number
+// of the end-entity certificate of `certificate_chain`. The issuer isn't
+// compared, as this only tells apart the certificates of one deployment.
+fn is_for_certificate(ocsp_der: &[u8], certificate_chain: &CertificateChain) -> bool {
+    let cert = match x509_parser::parse_x509_certificate(&certificate_chain.end_entity.der) {
+        Ok((_, cert)) => cert,
+        Err(_) => return false,
+    };
+    parse_single_response(ocsp_der, |single_response| {
+        // CertID ::= SEQUENCE {
+        //     hashAlgorithm       AlgorithmIdentifier,
+        //     issuerNameHash      OCTET STRING,
+        //     issuerKeyHash       OCTET STRING,
+        //     serialNumber        CertificateSerialNumber }
+        let serial_number = item(item(single_response, 0)?, 3)?;
+        Ok(matches!(
+            serial_number.content,
+            BerObjectContent::Integer(serial_number)
+                if serial_number == cert.tbs_certificate.raw_serial()
+        ))
+    })
+    .unwrap_or(false)
+}
+
+/// Returns an error unless `ocsp_der` is an OCSP response whose `nextUpdate`,
+/// if any, is after `now`. The stub for a certificate without an OCSP
+/// responder is rejected.
+pub fn check_unexpired(ocsp_der: &[u8], now: SystemTime) -> Result<()> {
+    let (_, next_update) =
+        parse_ocsp_validity(ocsp_der).map_err(|e| e.context("Invalid OCSP response"))?;
+    match next_update {
+        Some(next_update) if next_update <= now => Err(anyhow!(
+            "The OCSP response expired at {}",
+            chrono::DateTime::<chrono::Utc>::from(next_update).to_rfc2822()
+        )),
+        _ => Ok(()),
+    }
+}
+
 const OCSP_KEY: &str = "OCSP";
 
+// Each certificate has its own OCSP response in storage, so that one
+// certificate's OCSP is not served for another while they are rotated.
+// Before, the OCSP of the only certificate was stored at OCSP_KEY itself.
+fn storage_key(certificate_chain: &CertificateChain) -> String {
+    format!("{}-{}", OCSP_KEY, certificate_chain.basename)
+}
+
 #[derive(Serialize, Deserialize)]
 struct OcspData {
     pub expiration_time: SystemTime,
     pub recommended_update_time: SystemTime,
     #[serde(with = "crate::serde_helpers::base64")]
     pub value: Vec<u8>,
+    // The time before which the responder isn't fetched again: that of its
+    // `Retry-After`, or after a fetch that failed while this OCSP was served
+    // stale, STALE_RETRY_DELAY later.
+    #[serde(default)]
+    pub retry_after: Option<SystemTime>,
+}
+
+impl OcspData {
+    fn update_time(&self, strategy: &OcspUpdateStrategy) -> SystemTime {
+        match strategy {
+            OcspUpdateStrategy::EarlyAsRecommended => self.recommended_update_time,
+            OcspUpdateStrategy::LazyIfUnexpired | OcspUpdateStrategy::LazyOrStale { .. } => {
+                self.expiration_time
+            }
+        }
+    }
+    // Returns whether this OCSP may be served at `now` when it can't be
+    // updated.
+    fn is_servable(&self, strategy: &OcspUpdateStrategy, now: SystemTime) -> bool {
+        let grace = match strategy {
+            OcspUpdateStrategy::LazyOrStale { grace } => *grace,
+            _ => Duration::ZERO,
+        };
+        !self.value.is_empty() && self.expiration_time + grace > now
+    }
 }
 
 pub enum OcspUpdateStrategy {
     EarlyAsRecommended,
     LazyIfUnexpired,
+    /// Like `LazyIfUnexpired`, but if the responder can't be fetched, the
+    /// OCSP in storage is still served for `grace` past its expiration.
+    LazyOrStale {
+        grace: Duration,
+    },
+}
+
+// Reads the `OcspData` for the certificate from storage. Returns `None` if
+// there is none, or if it can't be read or parsed.
+async fn read_from_storage(key: &str, runtime: &Runtime) -> Option<OcspData> {
+    match runtime.storage.read(key).await {
+        Ok(Some(old_ocsp)) => serde_json::from_str::<OcspData>(&old_ocsp).ok(),
+        Ok(None) => None,
+        Err(e) => {
+            console_log(&format!("Failed to read OCSP from storage. {}", e));
+            None
+        }
+    }
+}
+
+// Reads the `OcspData` at the `storage_key` of the certificate, or else, if
+// it is for the certificate, the one at the legacy OCSP_KEY. The latter is
+// copied to the `storage_key`, so that it is read from the legacy key once.
+async fn read_for_certificate(
+    certificate_chain: &CertificateChain,
+    runtime: &Runtime,
+) -> Option<OcspData> {
+    let key = storage_key(certificate_chain);
+    if let Some(ocsp) = read_from_storage(&key, runtime).await {
+        return Some(ocsp);
+    }
+    let legacy_ocsp = read_from_storage(OCSP_KEY, runtime)
+        .await
+        .filter(|ocsp| is_for_certificate(&ocsp.value, certificate_chain))?;
+    // A failed write is logged, and the legacy OCSP is read again next time.
+    let _ = write_to_storage(&key, &legacy_ocsp, runtime).await;
+    Some(legacy_ocsp)
+}
+
+// Without a `Retry-After`, a responder that failed while the OCSP in storage
+// was served stale by `OcspUpdateStrategy::LazyOrStale` isn't fetched again
+// for this long, so that an outage doesn't cost a fetch per request.
+const STALE_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+// OCSP in storage is kept for this long past its expiration, which is the
+// longest grace of `OcspUpdateStrategy::LazyOrStale`.
+const STORAGE_TTL_PAST_EXPIRATION: Duration = SEVEN_DAYS;
+
+async fn write_to_storage(key: &str, ocsp: &OcspData, runtime: &Runtime) -> Result<()> {
+    let ttl = ocsp
+        .expiration_time
+        .duration_since(runtime.now)
+        .unwrap_or_default()
+        + STORAGE_TTL_PAST_EXPIRATION;
+    let write_result = runtime
+        .storage
+        .write_with_ttl(key, &serde_json::to_string(ocsp)?, ttl)
+        .await;
+    if let Err(e) = write_result {
+        console_log(&format!("Failed to write OCSP to storage. {}", e));
+    }
+    Ok(())
+}
+
+/// Returns the OCSP in storage if it is unexpired, without fetching from the
+/// server.
+pub async fn read_unexpired_ocsp_from_storage(
+    certificate_chain: &CertificateChain,
+    runtime: &Runtime,
+) -> Option<Vec<u8>> {
+    let old_ocsp = read_for_certificate(certificate_chain, runtime).await?;
+    if old_ocsp.expiration_time > runtime.now {
+        Some(old_ocsp.value)
+    } else {
+        None
+    }
+}
+
+/// Returns the time at which the OCSP in storage should be updated under
+/// `OcspUpdateStrategy::EarlyAsRecommended`, or `None` if there is none. This
+/// is no earlier than the responder's `Retry-After`.
+pub async fn read_recommended_update_time(
+    certificate_chain: &CertificateChain,
+    runtime: &Runtime,
+) -> Option<SystemTime> {
+    let old_ocsp = read_for_certificate(certificate_chain, runtime).await?;
+    Some(match old_ocsp.retry_after {
+        Some(retry_after) => retry_after.max(old_ocsp.recommended_update_time),
+        None => old_ocsp.recommended_update_time,
+    })
+}
+
+// Logs that `ocsp` is served although it has expired, which is only allowed by
+// `OcspUpdateStrategy::LazyOrStale`.
+fn warn_if_stale(certificate_chain: &CertificateChain, ocsp: &OcspData, now: SystemTime) {
+    if ocsp.expiration_time <= now {
+        console_log(&format!(
+            "Serving the OCSP of certificate {}, which expired at {}, because the responder can't be fetched.",
+            certificate_chain.basename,
+            chrono::DateTime::<chrono::Utc>::from(ocsp.expiration_time).to_rfc2822()
+        ));
+    }
 }
 
 /// Reads OCSP in storage, checks the expiration status, and returns latest.
 /// If OCSP in storage needs update, fetches it from the server and writes it
 /// into storage. The outging traffic to the server is throttled to be a
 /// single task.
+/// If the server responds with `Retry-After`, it isn't fetched again until
+/// then, and meanwhile the OCSP in storage is returned while unexpired, or
+/// within the grace period of `OcspUpdateStrategy::LazyOrStale`. Under the
+/// latter, a failure without `Retry-After` that is answered with the stale
+/// OCSP likewise delays the next fetch, by `STALE_RETRY_DELAY`.
 /// If there is any error when reading from and writing to storage, the error
 /// will be ignored but an error message will be printed to output/log.
 pub async fn read_and_update_ocsp_in_storage(
@@ -167,30 +520,24 @@ pub async fn read_and_update_ocsp_in_storage(
     strategy: OcspUpdateStrategy,
 ) -> Result<Vec<u8>> {
     // Checks whether we can directly return the existing OCSP in storage.
-    match runtime.storage.read(OCSP_KEY).await {
-        Ok(Some(old_ocsp)) => {
-            if let Ok(old_ocsp) = serde_json::from_str::<OcspData>(&old_ocsp) {
-                match strategy {
-                    OcspUpdateStrategy::EarlyAsRecommended => {
-                        if old_ocsp.recommended_update_time > runtime.now {
-                            return Ok(old_ocsp.value);
-                        }
-                    }
-                    OcspUpdateStrategy::LazyIfUnexpired => {
-                        if old_ocsp.expiration_time > runtime.now {
-                            return Ok(old_ocsp.value);
-                        }
-                    }
-                }
-            } else {
-                // The existing OCSP in storage can't be parsed as `OcspData`.
-            }
-        }
-        Ok(None) => {
-            // There is no OCSP in storage.
+    let key = storage_key(certificate_chain);
+    let old_ocsp = match read_for_certificate(certificate_chain, runtime).await {
+        Some(old_ocsp) if old_ocsp.update_time(&strategy) > runtime.now => {
+            return Ok(old_ocsp.value);
         }
-        Err(e) => {
-            println!("Failed to read OCSP from storage. {}", e);
+        old_ocsp => old_ocsp,
+    };
+    if let Some(old_ocsp) = &old_ocsp {
+        if let Some(retry_after) = old_ocsp.retry_after.filter(|t| *t > runtime.now) {
+            return if old_ocsp.is_servable(&strategy, runtime.now) {
+                warn_if_stale(certificate_chain, old_ocsp, runtime.now);
+                Ok(old_ocsp.value.clone())
+            } else {
+                Err(anyhow!(
+                    "The OCSP responder is not fetched again until {}",
+                    chrono::DateTime::<chrono::Utc>::from(retry_after).to_rfc2822()
+                ))
+            };
         }
     }
     if certificate_chain.issuers.is_empty() {
@@ -198,26 +545,497 @@ pub async fn read_and_update_ocsp_in_storage(
     }
     let cert_der = &certificate_chain.end_entity.der;
     let issuer_der = &certificate_chain.issuers[0].der;
-    let new_ocsp_value = {
+    let fetched = {
         static SINGLE_TASK: Mutex<()> = Mutex::const_new(());
         let guard = SINGLE_TASK.lock().await;
-        let ocsp = fetch_from_ca(cert_der, issuer_der, runtime.fetcher.as_ref()).await?;
+        let ocsp = fetch_from_ca(cert_der, issuer_der, runtime.fetcher.as_ref()).await;
         std::mem::drop(guard);
         ocsp
     };
+    let new_ocsp_value = match fetched {
+        Ok(value) => value,
+        Err(e) => {
+            let retry_after = match e.downcast_ref::<RetryAfter>() {
+                Some(retry_after) => retry_after.time(runtime.now),
+                // Without a Retry-After, the stored OCSP is only served past
+                // its update time by LazyOrStale.
+                None => match &old_ocsp {
+                    Some(old_ocsp)
+                        if matches!(strategy, OcspUpdateStrategy::LazyOrStale { .. })
+                            && old_ocsp.is_servable(&strategy, runtime.now) =>
+                    {
+                        runtime.now + STALE_RETRY_DELAY
+                    }
+                    _ => return Err(e),
+                },
+            };
+            // Keeps the last OCSP, if any, to be served until the retry.
+            let mut ocsp = old_ocsp.unwrap_or(OcspData {
+                expiration_time: SystemTime::UNIX_EPOCH,
+                recommended_update_time: SystemTime::UNIX_EPOCH,
+                value: vec![],
+                retry_after: None,
+            });
+            ocsp.retry_after = Some(retry_after);
+            write_to_storage(&key, &ocsp, runtime).await?;
+            return if ocsp.is_servable(&strategy, runtime.now) {
+                warn_if_stale(certificate_chain, &ocsp, runtime.now);
+                Ok(ocsp.value)
+            } else {
+                Err(e)
+            };
+        }
+    };
     const SIX_DAYS: Duration = Duration::from_secs(3600 * 24 * 6);
     const ONE_DAY: Duration = Duration::from_secs(3600 * 24);
+    let (expiration_time, recommended_update_time) = match parse_ocsp_validity(&new_ocsp_value) {
+        // Don't fetch again until half of the validity window has elapsed.
+        Ok((this_update, Some(next_update))) if next_update > this_update => {
+            let half = next_update.duration_since(this_update).unwrap_or_default() / 2;
+            (next_update, this_update + half)
+        }
+        // The responder didn't specify nextUpdate, or the response is a stub
+        // for a self-signed certificate.
+        _ => (runtime.now + SIX_DAYS, runtime.now + ONE_DAY),
+    };
     let new_ocsp = OcspData {
-        expiration_time: runtime.now + SIX_DAYS,
-        recommended_update_time: runtime.now + ONE_DAY,
+        expiration_time,
+        recommended_update_time,
         value: new_ocsp_value,
+        retry_after: None,
     };
-    let write_result = runtime
-        .storage
-        .write(OCSP_KEY, &serde_json::to_string(&new_ocsp)?)
+    write_to_storage(&key, &new_ocsp, runtime).await?;
+    Ok(new_ocsp.value)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::fetcher::RouteFetcher;
+    use crate::http::HttpResponse;
+    use crate::utils::tests as util;
+
+    fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let len = contents.len();
+        let len = if len < 0x80 {
+            vec![len as u8]
+        } else if len < 0x100 {
+            vec![0x81, len as u8]
+        } else {
+            vec![0x82, (len >> 8) as u8, len as u8]
+        };
+        [&[tag], len.as_slice(), contents].concat()
+    }
+    // A minimal OCSP response, unsigned, that parse_ocsp_validity accepts.
+    pub(crate) fn ocsp_response(next_update: Option<&str>) -> Vec<u8> {
+        ocsp_response_with_cert_id(tlv(0x30, &[]), next_update)
+    }
+    // Like `ocsp_response`, for the certificate of `serial_number`.
+    fn ocsp_response_for_serial(serial_number: &[u8], next_update: Option<&str>) -> Vec<u8> {
+        let cert_id = [
+            tlv(0x30, &[]),
+            tlv(0x04, &[0; 20]),
+            tlv(0x04, &[0; 20]),
+            tlv(0x02, serial_number),
+        ]
+        .concat();
+        ocsp_response_with_cert_id(tlv(0x30, &cert_id), next_update)
+    }
+    fn ocsp_response_with_cert_id(cert_id: Vec<u8>, next_update: Option<&str>) -> Vec<u8> {
+        let mut single_response = [cert_id, tlv(0x80, &[]), tlv(0x18, b"20221010000000Z")].concat();
+        if let Some(next_update) = next_update {
+            single_response.extend(tlv(0xa0, &tlv(0x18, next_update.as_bytes())));
+        }
+        let response_data = tlv(
+            0x30,
+            &[
+                tlv(0xa2, &tlv(0x04, &[0; 20])),
+                tlv(0x18, b"20221010000000Z"),
+                tlv(0x30, &tlv(0x30, &single_response)),
+            ]
+            .concat(),
+        );
+        let basic_response = tlv(
+            0x30,
+            &[response_data, tlv(0x30, &[]), tlv(0x03, &[0; 200])].concat(),
+        );
+        tlv(
+            0x30,
+            &[
+                tlv(0x0a, &[0]),
+                tlv(
+                    0xa0,
+                    &tlv(
+                        0x30,
+                        &[
+                            tlv(
+                                0x06,
+                                &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01],
+                            ),
+                            tlv(0x04, &basic_response),
+                        ]
+                        .concat(),
+                    ),
+                ),
+            ]
+            .concat(),
+        )
+    }
+    #[test]
+    fn parses_validity() {
+        assert_eq!(
+            parse_ocsp_validity(&ocsp_response(Some("20221017000000Z"))).unwrap(),
+            (
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000),
+                Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1665964800)),
+            )
+        );
+        assert_eq!(
+            parse_ocsp_validity(&ocsp_response(None)).unwrap(),
+            (
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000),
+                None
+            )
+        );
+        assert!(parse_ocsp_validity(b"ocsp").is_err());
+    }
+    #[test]
+    fn checks_unexpired() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1665964800);
+        assert!(check_unexpired(&ocsp_response(Some("20221018000000Z")), now).is_ok());
+        assert!(check_unexpired(&ocsp_response(Some("20221017000000Z")), now).is_err());
+        assert!(check_unexpired(&ocsp_response(None), now).is_ok());
+        assert!(check_unexpired(b"ocsp", now).is_err());
+    }
+    #[tokio::test]
+    async fn reuses_ocsp_in_storage() {
+        let certificate = CertificateChain::from_pem_files(&[
+            util::SELF_SIGNED_CERT_PEM,
+            util::SELF_SIGNED_CERT_PEM,
+        ])
+        .unwrap();
+        let runtime = Runtime {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000),
+            ..Default::default()
+        };
+        let stored = OcspData {
+            expiration_time: runtime.now + Duration::from_secs(3600 * 24 * 7),
+            recommended_update_time: runtime.now + Duration::from_secs(3600 * 24 * 3),
+            value: b"stored".to_vec(),
+            retry_after: None,
+        };
+        runtime
+            .storage
+            .write(
+                &storage_key(&certificate),
+                &serde_json::to_string(&stored).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            read_and_update_ocsp_in_storage(
+                &certificate,
+                &runtime,
+                OcspUpdateStrategy::EarlyAsRecommended
+            )
+            .await
+            .unwrap(),
+            b"stored"
+        );
+    }
+    #[tokio::test]
+    async fn reads_legacy_ocsp_of_certificate() {
+        let certificate = CertificateChain::from_pem_files(&[
+            util::SELF_SIGNED_CERT_PEM,
+            util::SELF_SIGNED_CERT_PEM,
+        ])
+        .unwrap();
+        let (_, cert) = x509_parser::parse_x509_certificate(&certificate.end_entity.der).unwrap();
+        let serial_number = cert.tbs_certificate.raw_serial();
+        let runtime = Runtime {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000),
+            ..Default::default()
+        };
+        let legacy = |serial_number: &[u8]| OcspData {
+            expiration_time: runtime.now + Duration::from_secs(3600 * 24 * 7),
+            recommended_update_time: runtime.now + Duration::from_secs(3600 * 24 * 3),
+            value: ocsp_response_for_serial(serial_number, Some("20221017000000Z")),
+            retry_after: None,
+        };
+        // The legacy OCSP of another certificate isn't read.
+        write_to_storage(OCSP_KEY, &legacy(&[1]), &runtime)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_unexpired_ocsp_from_storage(&certificate, &runtime).await,
+            None
+        );
+        let ocsp = legacy(serial_number);
+        write_to_storage(OCSP_KEY, &ocsp, &runtime).await.unwrap();
+        assert_eq!(
+            read_unexpired_ocsp_from_storage(&certificate, &runtime).await,
+            Some(ocsp.value.clone())
+        );
+        // It is copied to the key of the certificate.
+        assert_eq!(
+            read_from_storage(&storage_key(&certificate), &runtime)
+                .await
+                .map(|stored| stored.value),
+            Some(ocsp.value)
+        );
+    }
+    fn ocsp_certificate() -> CertificateChain {
+        CertificateChain::from_pem_files(&[util::OCSP_CERT_PEM, util::OCSP_CERT_PEM]).unwrap()
+    }
+    // The OCSP responder of OCSP_CERT_PEM, responding with `status` and the
+    // given Retry-After.
+    fn responder(status: u16, retry_after: Option<&str>) -> RouteFetcher {
+        let headers = retry_after
+            .map(|value| vec![("Retry-After".into(), value.into())])
+            .unwrap_or_default();
+        RouteFetcher::default().with_route(
+            "http://ocsp.example.org",
+            HttpResponse {
+                body: b"unavailable".to_vec(),
+                headers,
+                status,
+            },
+        )
+    }
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(
+            RetryAfter::parse(" 120 "),
+            Some(RetryAfter::Delay(Duration::from_secs(120)))
+        );
+        assert_eq!(
+            RetryAfter::parse("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(RetryAfter::Date(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1445412480)
+            ))
+        );
+        assert_eq!(RetryAfter::parse("soon"), None);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000);
+        assert_eq!(
+            RetryAfter::Delay(Duration::from_secs(120)).time(now),
+            now + Duration::from_secs(120)
+        );
+        // Capped at a day.
+        assert_eq!(
+            RetryAfter::Delay(Duration::from_secs(u64::MAX)).time(now),
+            now + Duration::from_secs(3600 * 24)
+        );
+    }
+    #[tokio::test]
+    async fn serves_stored_ocsp_until_retry_after() {
+        let certificate = ocsp_certificate();
+        let fetcher = responder(503, Some("120"));
+        let mut runtime = Runtime {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000),
+            fetcher: Box::new(fetcher.clone()),
+            ..Default::default()
+        };
+        // Due for an update, but unexpired.
+        let stored = OcspData {
+            expiration_time: runtime.now + Duration::from_secs(3600 * 24 * 3),
+            recommended_update_time: runtime.now - Duration::from_secs(3600),
+            value: b"stored".to_vec(),
+            retry_after: None,
+        };
+        write_to_storage(&storage_key(&certificate), &stored, &runtime)
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                read_and_update_ocsp_in_storage(
+                    &certificate,
+                    &runtime,
+                    OcspUpdateStrategy::EarlyAsRecommended
+                )
+                .await
+                .unwrap(),
+                b"stored"
+            );
+        }
+        // Only the first call fetched; the second waits for the retry.
+        assert_eq!(fetcher.requests().len(), 1);
+        assert_eq!(
+            read_recommended_update_time(&certificate, &runtime).await,
+            Some(runtime.now + Duration::from_secs(120))
+        );
+
+        runtime.now += Duration::from_secs(121);
+        assert_eq!(
+            read_and_update_ocsp_in_storage(
+                &certificate,
+                &runtime,
+                OcspUpdateStrategy::EarlyAsRecommended
+            )
+            .await
+            .unwrap(),
+            b"stored"
+        );
+        assert_eq!(fetcher.requests().len(), 2);
+    }
+    #[tokio::test]
+    async fn delays_retry_without_stored_ocsp() {
+        let certificate = ocsp_certificate();
+        let fetcher = responder(429, Some("120"));
+        let runtime = Runtime {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000),
+            fetcher: Box::new(fetcher.clone()),
+            ..Default::default()
+        };
+        let update = read_and_update_ocsp_in_storage(
+            &certificate,
+            &runtime,
+            OcspUpdateStrategy::LazyIfUnexpired,
+        )
         .await;
-    if let Err(e) = write_result {
-        println!("Failed to write OCSP to storage. {}", e);
+        assert_eq!(
+            update.unwrap_err().downcast_ref::<RetryAfter>(),
+            Some(&RetryAfter::Delay(Duration::from_secs(120)))
+        );
+        assert!(read_and_update_ocsp_in_storage(
+            &certificate,
+            &runtime,
+            OcspUpdateStrategy::LazyIfUnexpired,
+        )
+        .await
+        .is_err());
+        assert_eq!(fetcher.requests().len(), 1);
+        assert_eq!(
+            read_unexpired_ocsp_from_storage(&certificate, &runtime).await,
+            None
+        );
+    }
+    #[tokio::test]
+    async fn retries_error_without_retry_after() {
+        let certificate = ocsp_certificate();
+        let fetcher = responder(500, Some("120"));
+        let runtime = Runtime {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000),
+            fetcher: Box::new(fetcher.clone()),
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            let update = read_and_update_ocsp_in_storage(
+                &certificate,
+                &runtime,
+                OcspUpdateStrategy::LazyIfUnexpired,
+            )
+            .await;
+            assert_eq!(
+                format!("{:#}", update.unwrap_err()),
+                "The OCSP responder returned status 500"
+            );
+        }
+        // Neither the error body nor a retry time is stored.
+        assert_eq!(fetcher.requests().len(), 2);
+        assert!(read_from_storage(&storage_key(&certificate), &runtime)
+            .await
+            .is_none());
+    }
+    #[tokio::test]
+    async fn serves_stale_ocsp_within_grace() {
+        let certificate = ocsp_certificate();
+        let fetcher = responder(500, None);
+        let mut runtime = Runtime {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000),
+            fetcher: Box::new(fetcher.clone()),
+            ..Default::default()
+        };
+        // Just past its nextUpdate.
+        let stored = OcspData {
+            expiration_time: runtime.now - Duration::from_secs(60),
+            recommended_update_time: runtime.now - Duration::from_secs(3600 * 24 * 3),
+            value: b"stored".to_vec(),
+            retry_after: None,
+        };
+        write_to_storage(&storage_key(&certificate), &stored, &runtime)
+            .await
+            .unwrap();
+        let grace = || OcspUpdateStrategy::LazyOrStale {
+            grace: Duration::from_secs(3600),
+        };
+        for _ in 0..2 {
+            assert_eq!(
+                read_and_update_ocsp_in_storage(&certificate, &runtime, grace())
+                    .await
+                    .unwrap(),
+                b"stored"
+            );
+        }
+        assert!(read_and_update_ocsp_in_storage(
+            &certificate,
+            &runtime,
+            OcspUpdateStrategy::LazyIfUnexpired
+        )
+        .await
+        .is_err());
+        // Only the first call fetched; the others wait for the retry.
+        assert_eq!(fetcher.requests().len(), 1);
+
+        runtime.now += STALE_RETRY_DELAY;
+        assert_eq!(
+            read_and_update_ocsp_in_storage(&certificate, &runtime, grace())
+                .await
+                .unwrap(),
+            b"stored"
+        );
+        assert_eq!(fetcher.requests().len(), 2);
+
+        // Past the grace, it is no longer served.
+        runtime.now += Duration::from_secs(3600);
+        assert!(
+            read_and_update_ocsp_in_storage(&certificate, &runtime, grace())
+                .await
+                .is_err()
+        );
+        assert_eq!(fetcher.requests().len(), 3);
+    }
+    #[tokio::test]
+    async fn writes_with_ttl_past_expiration() {
+        use crate::storage::Storage;
+        use async_trait::async_trait;
+        use std::sync::Arc;
+        // Records the TTLs of writes.
+        struct TtlStorage(Arc<std::sync::Mutex<Vec<Duration>>>);
+        #[cfg_attr(feature = "wasm", async_trait(?Send))]
+        #[cfg_attr(not(feature = "wasm"), async_trait)]
+        impl Storage for TtlStorage {
+            async fn read(&self, _k: &str) -> Result<Option<String>> {
+                Ok(None)
+            }
+            async fn write(&self, _k: &str, _v: &str) -> Result<()> {
+                Err(anyhow!("OCSP should be written with a TTL"))
+            }
+            async fn write_with_ttl(&self, _k: &str, _v: &str, ttl: Duration) -> Result<()> {
+                self.0.lock().unwrap().push(ttl);
+                Ok(())
+            }
+        }
+        let ttls = Arc::new(std::sync::Mutex::new(vec![]));
+        let runtime = Runtime {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(1665360000),
+            storage: Box::new(TtlStorage(ttls.clone())),
+            ..Default::default()
+        };
+        let ocsp = |expiration_time| OcspData {
+            expiration_time,
+            recommended_update_time: SystemTime::UNIX_EPOCH,
+            value: b"stored".to_vec(),
+            retry_after: None,
+        };
+        let unexpired = ocsp(runtime.now + Duration::from_secs(3600));
+        write_to_storage("OCSP", &unexpired, &runtime)
+            .await
+            .unwrap();
+        let expired = ocsp(runtime.now - Duration::from_secs(3600));
+        write_to_storage("OCSP", &expired, &runtime).await.unwrap();
+        assert_eq!(
+            *ttls.lock().unwrap(),
+            [SEVEN_DAYS + Duration::from_secs(3600), SEVEN_DAYS]
+        );
     }
-    Ok(new_ocsp.value)
 }
@@ -22,6 +22,7 @@ use crate::link::ALLOWED_PARAM_NAMES;
 use serde::Deserialize;
 use std::borrow::Cow;
 use std::sync::Arc;
+use url::Url;
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -56,6 +57,8 @@ fn parse_content_type(content_type_header_value: &str) -> ContentType {
 /// - For `<script data-issxg-var>` elements, they are replaced with
 ///   `<script>window.isSXG=...</script>`, where `...` is true or false.
 /// - The `content-length` header is updated to the new value.
+/// Everything else is left byte-identical, including inline scripts and styles along with
+/// their `nonce` and `integrity` attributes, and headers such as `content-security-policy`.
 /// If input charset is not UTF8, the input will be returned back without any modification.
 pub fn process_html(input: Arc<HttpResponse>, option: ProcessHtmlOption) -> Arc<HttpResponse> {
     // TODO: Change types from Arc<HttpResponse> to HttpResponse, and change
@@ -194,6 +197,55 @@ pub fn process_html(input: Arc<HttpResponse>, option: ProcessHtmlOption) -> Arc<
     })
 }
 
+/// Returns `body` with a `<base href>` of `base_url` inserted at the start of its `<head>`, so that
+/// its relative URLs resolve against `base_url`. Returns None if `body` isn't UTF-8, has no
+/// `<head>` tag, or already has a `<base href>`, which would take precedence.
+pub fn inject_base(body: &[u8], base_url: &Url) -> Option<Vec<u8>> {
+    let input = std::str::from_utf8(body).ok()?;
+    let mut has_base = false;
+    lol_html::rewrite_str(
+        input,
+        lol_html::Settings {
+            element_content_handlers: vec![lol_html::element!("base[href]", |_| {
+                has_base = true;
+                Ok(())
+            })],
+            ..lol_html::Settings::default()
+        },
+    )
+    .ok()?;
+    if has_base {
+        return None;
+    }
+    let base = format!(
+        r#"<base href="{}">"#,
+        base_url
+            .as_str()
+            .replace('&', "&amp;")
+            .replace('"', "&quot;")
+    );
+    let mut injected = false;
+    let output = lol_html::rewrite_str(
+        input,
+        lol_html::Settings {
+            element_content_handlers: vec![lol_html::element!("head", |e| {
+                if !injected {
+                    e.prepend(&base, lol_html::html_content::ContentType::Html);
+                    injected = true;
+                }
+                Ok(())
+            })],
+            ..lol_html::Settings::default()
+        },
+    )
+    .ok()?;
+    if injected {
+        Some(output.into_bytes())
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -312,4 +364,52 @@ mod tests {
             },
         );
     }
+    #[test]
+    fn it_injects_base() {
+        let url = Url::parse("https://example.com/blog/post?a=1&copy=2").unwrap();
+        assert_eq!(
+            inject_base(b"<html><head><title>T</title></head></html>", &url).unwrap(),
+            br#"<html><head><base href="https://example.com/blog/post?a=1&amp;copy=2"><title>T</title></head></html>"#.to_vec(),
+        );
+        // An existing base takes precedence.
+        assert_eq!(inject_base(b"<head><base href=\"/\"></head>", &url), None);
+        assert_eq!(inject_base(b"<p>No head</p>", &url), None);
+        assert_eq!(inject_base(b"<head>\xff</head>", &url), None);
+    }
+    #[test]
+    fn it_preserves_nonced_scripts_and_csp() {
+        let csp = "script-src 'nonce-r4nd0m'; style-src 'nonce-r4nd0m'";
+        // The script's string and comparisons look like markup, but aren't.
+        let body = r#"<!doctype html><html><head>
+<link rel=preload href="/app.js" as=script nonce="r4nd0m">
+<style nonce="r4nd0m">body { color: red; }</style>
+<script nonce="r4nd0m">document.write('<link rel=preload href=/x.js as=script>');</script>
+</head><body>
+<script nonce="r4nd0m" integrity="sha384-abc">if (a < b && c > d) { run(); }</script>
+</body></html>"#;
+        let headers = vec![
+            (
+                "content-type".to_string(),
+                "text/html;charset=utf-8".to_string(),
+            ),
+            ("content-security-policy".to_string(), csp.to_string()),
+        ];
+        for is_sxg in [true, false] {
+            let output = process_html(
+                Arc::new(HttpResponse {
+                    status: 200,
+                    headers: headers.clone(),
+                    body: body.as_bytes().to_vec(),
+                }),
+                ProcessHtmlOption { is_sxg },
+            );
+            let mut expected_headers = headers.clone();
+            expected_headers.push((
+                "Link".to_string(),
+                "</app.js>;rel=preload;as=script".to_string(),
+            ));
+            assert_eq!(output.body, body.as_bytes());
+            assert_eq!(output.headers, expected_headers);
+        }
+    }
 }
@@ -0,0 +1,404 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signs the responses of a [`tower_service::Service`], such as a hyper
+//! backend, so that sxg-rs composes with other tower middleware.
+
+use crate::http::{try_from_header_map, try_into_header_map, HeaderFields, HttpResponse};
+use crate::http_cache::NullCache;
+use crate::runtime::Runtime;
+use crate::utils::MaybeSend;
+use crate::{RequestAction, SxgWorker, MAX_PAYLOAD_SIZE};
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, Method, Request, Response};
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The future of [`SxgService::call`]. It isn't `Send` with the `wasm`
+/// feature, like the async traits of [`Runtime`].
+#[cfg(feature = "wasm")]
+pub type ResponseFuture = futures::future::LocalBoxFuture<'static, Result<Response<Body>>>;
+#[cfg(not(feature = "wasm"))]
+pub type ResponseFuture = futures::future::BoxFuture<'static, Result<Response<Body>>>;
+
+/// Serves GET and HEAD requests that prefer an SXG with the signed response
+/// of `inner`, and the cert-chain and validity URLs of the worker, as
+/// routed by [`SxgWorker::route_request`] for the host of the request. Other
+/// requests, and paths that the worker doesn't sign, are passed to `inner`
+/// unchanged. If signing fails, the response of `inner` is served unsigned,
+/// unless `fallback_to_unsigned` is false in the config.
+///
+/// Unlike http_server, it doesn't limit the concurrent signings, or share a
+/// header-integrity cache across requests.
+pub struct SxgService<S> {
+    worker: Arc<SxgWorker>,
+    runtime: Arc<dyn Fn() -> Runtime + Send + Sync>,
+    inner: S,
+}
+
+impl<S: Clone> Clone for SxgService<S> {
+    fn clone(&self) -> Self {
+        SxgService {
+            worker: self.worker.clone(),
+            runtime: self.runtime.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S> SxgService<S> {
+    /// `runtime` is called for each request, so that e.g. its `now` is the
+    /// time of the request.
+    pub fn new(
+        worker: impl Into<Arc<SxgWorker>>,
+        runtime: impl Fn() -> Runtime + Send + Sync + 'static,
+        inner: S,
+    ) -> Self {
+        SxgService {
+            worker: worker.into(),
+            runtime: Arc::new(runtime),
+            inner,
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for SxgService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + MaybeSend + 'static,
+    S::Future: MaybeSend + 'static,
+    S::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = anyhow::Error;
+    type Future = ResponseFuture;
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.inner.poll_ready(cx).map_err(to_anyhow)
+    }
+    fn call(&mut self, req: Request<Body>) -> ResponseFuture {
+        // The clone may not be ready, so the ready one serves this request.
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+        let worker = self.worker.clone();
+        let runtime = self.runtime.clone();
+        Box::pin(async move { handle(&worker, &runtime(), inner, req).await })
+    }
+}
+
+fn to_anyhow(e: impl Into<BoxError>) -> anyhow::Error {
+    let e: BoxError = e.into();
+    anyhow!(e)
+}
+
+async fn handle<S>(
+    worker: &SxgWorker,
+    runtime: &Runtime,
+    mut inner: S,
+    req: Request<Body>,
+) -> Result<Response<Body>>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+{
+    let req_headers = try_from_header_map(req.headers().clone())?;
+    let (method, url) = (req.method().clone(), req.uri().to_string());
+    let (worker, action) = match route(worker, runtime, method, &url, &req_headers).await {
+        Ok(routed) => routed,
+        Err(_) => return inner.call(req).await.map_err(to_anyhow),
+    };
+    match action {
+        RequestAction::Respond(response) => to_response(response),
+        RequestAction::ProxyUnsigned => inner.call(req).await.map_err(to_anyhow),
+        RequestAction::Sign {
+            fallback_url,
+            payload,
+            fallback,
+        } => {
+            let (payload, fallback) = (Arc::new(payload), Arc::new(fallback));
+            sign(
+                worker,
+                runtime,
+                &fallback_url,
+                payload,
+                fallback,
+                &req_headers,
+            )
+            .await
+        }
+        RequestAction::Fetch {
+            fallback_url,
+            backend_path,
+            backend_headers,
+        } => {
+            let (mut parts, body) = req.into_parts();
+            parts.method = Method::GET;
+            parts.uri = backend_path.parse()?;
+            parts.headers = try_into_header_map(backend_headers)?;
+            let req = Request::from_parts(parts, body);
+            let payload = inner.call(req).await.map_err(to_anyhow)?;
+            let payload = match buffer(payload).await? {
+                Ok(payload) => Arc::new(payload),
+                Err(streamed) => return Ok(streamed),
+            };
+            if !worker.should_sign_payload(&payload) {
+                return to_response(worker.unsigned_response(payload));
+            }
+            sign(
+                worker,
+                runtime,
+                &fallback_url,
+                payload.clone(),
+                payload,
+                &req_headers,
+            )
+            .await
+        }
+    }
+}
+
+// Returns the signing context for the host of a request, and how it routes
+// the request. An error means that the request should be passed to `inner`
+// unchanged.
+async fn route<'a>(
+    worker: &'a SxgWorker,
+    runtime: &Runtime,
+    method: Method,
+    url: &str,
+    req_headers: &HeaderFields,
+) -> Result<(&'a SxgWorker, RequestAction)> {
+    let method: crate::http::Method = method.try_into()?;
+    let worker = worker.for_host(&worker.request_host(req_headers, url)?);
+    let action = worker
+        .route_request(runtime, &method, url, req_headers)
+        .await?;
+    Ok((worker, action))
+}
+
+// Reads the body of `response` into memory, or returns the response to stream
+// instead if it exceeds MAX_PAYLOAD_SIZE, with the part read so far in front.
+async fn buffer(response: Response<Body>) -> Result<Result<HttpResponse, Response<Body>>> {
+    let (parts, mut body) = response.into_parts();
+    let mut buffered = vec![];
+    while let Some(chunk) = body.data().await {
+        buffered.extend_from_slice(&chunk?);
+        if buffered.len() > MAX_PAYLOAD_SIZE {
+            let head = futures::stream::once(async move { Ok(Bytes::from(buffered)) });
+            let body = Body::wrap_stream(head.chain(body));
+            return Ok(Err(Response::from_parts(parts, body)));
+        }
+    }
+    let mut payload: HttpResponse = Response::from_parts(parts, buffered).try_into()?;
+    // The body is no longer chunked.
+    payload
+        .headers
+        .retain(|(name, _)| !name.eq_ignore_ascii_case("transfer-encoding"));
+    Ok(Ok(payload))
+}
+
+// Signs `payload`, or if that fails, serves `fallback` unsigned, unless
+// `fallback_to_unsigned` is false in the config.
+async fn sign(
+    worker: &SxgWorker,
+    runtime: &Runtime,
+    fallback_url: &str,
+    payload: Arc<HttpResponse>,
+    fallback: Arc<HttpResponse>,
+    req_headers: &HeaderFields,
+) -> Result<Response<Body>> {
+    let sxg = worker
+        .sign_payload(runtime, fallback_url, payload, req_headers, NullCache {})
+        .await;
+    match sxg {
+        Ok(sxg) => to_response(sxg),
+        Err(_) if worker.config().fallback_to_unsigned => {
+            to_response(worker.unsigned_response(fallback))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn to_response(response: HttpResponse) -> Result<Response<Body>> {
+    let response: Response<Vec<u8>> = response.try_into()?;
+    Ok(response.map(Body::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CertificateChain;
+    use crate::utils::tests::{SELF_SIGNED_CERT_PEM, SELF_SIGNED_CERT_SHA256};
+    use crate::SxgWorkerBuilder;
+    use futures::future::poll_fn;
+    use hyper::service::service_fn;
+    use std::convert::Infallible;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn new_worker() -> SxgWorker {
+        SxgWorkerBuilder::new("example.com")
+            .certificate(
+                CertificateChain::from_pem_files(&[SELF_SIGNED_CERT_PEM, SELF_SIGNED_CERT_PEM])
+                    .unwrap(),
+            )
+            .build()
+            .unwrap()
+    }
+    // Like `new_worker`, but also for the host blog.example.com, and without
+    // certificates, so that signing fails.
+    fn uncertified_worker(fallback_to_unsigned: bool) -> SxgWorker {
+        SxgWorker::new(&format!(
+            r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+fallback_to_unsigned: {fallback_to_unsigned}
+forward_request_headers: []
+hosts:
+  blog.example.com: {{}}
+html_host: example.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+            "#
+        ))
+        .unwrap()
+    }
+    fn runtime() -> Runtime {
+        Runtime {
+            now: UNIX_EPOCH + Duration::from_secs(86400),
+            ..Default::default()
+        }
+    }
+    // The inner service, which responds with an HTML page that names the
+    // method and URI of the request.
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let body = format!("<h1>Hello</h1><p>{} {}</p>", req.method(), req.uri());
+        Ok(Response::builder()
+            .header("content-type", "text/html")
+            .body(Body::from(body))
+            .unwrap())
+    }
+    // Sends `req`, and returns the response with its body.
+    async fn send<S>(service: &mut S, req: Request<Body>) -> Result<(Response<()>, Vec<u8>)>
+    where
+        S: Service<Request<Body>, Response = Response<Body>, Error = anyhow::Error>,
+    {
+        poll_fn(|cx| service.poll_ready(cx)).await?;
+        let (parts, body) = service.call(req).await?.into_parts();
+        let body = hyper::body::to_bytes(body).await?.to_vec();
+        Ok((Response::from_parts(parts, ()), body))
+    }
+    // Sends a request for /page.html?q=1 with the `accept` header, and returns
+    // the response with its body.
+    async fn get<S>(service: &mut S, accept: &str) -> (Response<()>, Vec<u8>)
+    where
+        S: Service<Request<Body>, Response = Response<Body>, Error = anyhow::Error>,
+    {
+        let req = Request::get("/page.html?q=1")
+            .header("accept", accept)
+            .body(Body::empty())
+            .unwrap();
+        send(service, req).await.unwrap()
+    }
+    #[tokio::test]
+    async fn signs_inner_response() {
+        let inner = service_fn(|req: Request<Body>| async move {
+            let body = format!("<h1>Hello</h1><p>{}</p>", req.uri());
+            Ok::<_, Infallible>(
+                Response::builder()
+                    .header("content-type", "text/html")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        });
+        let mut service = SxgService::new(new_worker(), runtime, inner);
+        let html = "<h1>Hello</h1><p>/page.html?q=1</p>";
+
+        let (resp, body) = get(&mut service, "application/signed-exchange;v=b3").await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers()["content-type"],
+            "application/signed-exchange;v=b3"
+        );
+        let payload = SxgWorker::unwrap_sxg(&body).unwrap();
+        assert_eq!(payload.status, 200);
+        assert!(String::from_utf8_lossy(&payload.body).contains(html));
+
+        // A browser that doesn't prefer SXG gets the inner response as is.
+        let (resp, body) = get(&mut service, "text/html").await;
+        assert_eq!(resp.headers()["content-type"], "text/html");
+        assert_eq!(body, html.as_bytes());
+    }
+    #[tokio::test]
+    async fn passes_unsigned_requests_through() {
+        let mut service = SxgService::new(new_worker(), runtime, service_fn(echo));
+        let req = Request::post("/page.html")
+            .header("accept", "application/signed-exchange;v=b3")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = send(&mut service, req).await.unwrap();
+        assert_eq!(resp.headers()["content-type"], "text/html");
+        assert_eq!(body, b"<h1>Hello</h1><p>POST /page.html</p>");
+    }
+    #[tokio::test]
+    async fn serves_preset_content_of_host() {
+        let mut worker = uncertified_worker(true);
+        worker
+            .add_certificate_for_host(
+                "blog.example.com",
+                CertificateChain::from_pem_files(&[SELF_SIGNED_CERT_PEM, SELF_SIGNED_CERT_PEM])
+                    .unwrap(),
+            )
+            .unwrap();
+        let mut service = SxgService::new(worker, runtime, service_fn(echo));
+        let cert_url = format!("/.well-known/sxg-certs/{}", SELF_SIGNED_CERT_SHA256);
+        let get_cert = |host: &str| {
+            Request::get(&cert_url)
+                .header("host", host)
+                .body(Body::empty())
+                .unwrap()
+        };
+        // Only blog.example.com has the certificate.
+        let (resp, _) = send(&mut service, get_cert("blog.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers()["content-type"],
+            "application/cert-chain+cbor"
+        );
+        let (resp, _) = send(&mut service, get_cert("example.com")).await.unwrap();
+        assert_eq!(resp.status(), 404);
+        assert_eq!(resp.headers()["content-type"], "text/plain");
+    }
+    #[tokio::test]
+    async fn falls_back_to_unsigned_response() {
+        let mut service = SxgService::new(uncertified_worker(true), runtime, service_fn(echo));
+        let (resp, body) = get(&mut service, "application/signed-exchange;v=b3").await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "text/html");
+        assert!(String::from_utf8_lossy(&body).contains("<p>GET /page.html?q=1</p>"));
+
+        // Unless the config disables the fallback.
+        let mut service = SxgService::new(uncertified_worker(false), runtime, service_fn(echo));
+        let req = Request::get("/page.html?q=1")
+            .header("accept", "application/signed-exchange;v=b3")
+            .body(Body::empty())
+            .unwrap();
+        assert!(send(&mut service, req).await.is_err());
+    }
+}
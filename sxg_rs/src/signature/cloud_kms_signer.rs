@@ -0,0 +1,270 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`Signer`] whose private key is held by
+//! [Cloud KMS](https://cloud.google.com/kms/docs/create-validate-signatures),
+//! or by another service with an equivalent asymmetric sign RPC, such that the
+//! key is never on disk.
+
+use super::{Format, Signer};
+use crate::crypto::Curve;
+use crate::fetcher::Fetcher;
+use crate::http::{HttpRequest, Method};
+use crate::utils::{MaybeSend, MaybeSync};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// An RPC that signs a digest with a private key held by a remote service.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait AsymmetricSignRpc: MaybeSend + MaybeSync {
+    /// Signs `digest`, which was computed by `curve.hash_algorithm()`, with
+    /// the key named `key_name`, and returns the signature in ASN.1 DER.
+    async fn asymmetric_sign(&self, key_name: &str, curve: Curve, digest: &[u8])
+        -> Result<Vec<u8>>;
+}
+
+pub struct CloudKmsSigner<R: AsymmetricSignRpc> {
+    key_name: String,
+    curve: Curve,
+    rpc: R,
+}
+
+impl<R: AsymmetricSignRpc> CloudKmsSigner<R> {
+    /// `key_name` is the resource name of a key version, like
+    /// `projects/*/locations/*/keyRings/*/cryptoKeys/*/cryptoKeyVersions/*`.
+    /// Its algorithm must be `EC_SIGN_P256_SHA256` for [`Curve::P256`], or
+    /// `EC_SIGN_P384_SHA384` for [`Curve::P384`].
+    pub fn new(key_name: impl ToString, curve: Curve, rpc: R) -> Self {
+        CloudKmsSigner {
+            key_name: key_name.to_string(),
+            curve,
+            rpc,
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl<R: AsymmetricSignRpc> Signer for CloudKmsSigner<R> {
+    async fn sign(&self, message: &[u8], format: Format) -> Result<Vec<u8>> {
+        let digest = self.curve.hash_algorithm().digest(message);
+        let sig = self
+            .rpc
+            .asymmetric_sign(&self.key_name, self.curve, &digest)
+            .await
+            .map_err(|e| e.context(format!("Failed to sign with {}", self.key_name)))?;
+        match format {
+            Format::EccAsn1 => Ok(sig),
+            Format::Raw => super::parse_asn1_sig(&sig, self.curve),
+        }
+    }
+}
+
+/// Calls the
+/// [asymmetricSign](https://cloud.google.com/kms/docs/reference/rest/v1/projects.locations.keyRings.cryptoKeys.cryptoKeyVersions/asymmetricSign)
+/// method of the Cloud KMS REST API.
+pub struct CloudKmsRestRpc<F: Fetcher> {
+    fetcher: F,
+    access_token: String,
+}
+
+impl<F: Fetcher> CloudKmsRestRpc<F> {
+    /// `access_token` is an OAuth 2.0 access token with the
+    /// `https://www.googleapis.com/auth/cloudkms` scope.
+    pub fn new(fetcher: F, access_token: impl ToString) -> Self {
+        CloudKmsRestRpc {
+            fetcher,
+            access_token: access_token.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Digest {
+    Sha256(String),
+    Sha384(String),
+}
+
+#[derive(Serialize)]
+struct AsymmetricSignRequest {
+    digest: Digest,
+}
+
+#[derive(Deserialize)]
+struct AsymmetricSignResponse {
+    signature: String,
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl<F: Fetcher> AsymmetricSignRpc for CloudKmsRestRpc<F> {
+    async fn asymmetric_sign(
+        &self,
+        key_name: &str,
+        curve: Curve,
+        digest: &[u8],
+    ) -> Result<Vec<u8>> {
+        let digest = base64::encode(digest);
+        let digest = match curve {
+            Curve::P256 => Digest::Sha256(digest),
+            Curve::P384 => Digest::Sha384(digest),
+        };
+        let request = HttpRequest {
+            body: serde_json::to_vec(&AsymmetricSignRequest { digest })?,
+            headers: vec![
+                (
+                    "authorization".to_string(),
+                    format!("Bearer {}", self.access_token),
+                ),
+                ("content-type".to_string(), "application/json".to_string()),
+            ],
+            method: Method::Post,
+            url: format!(
+                "https://cloudkms.googleapis.com/v1/{}:asymmetricSign",
+                key_name
+            ),
+        };
+        let response = self.fetcher.fetch(request).await?;
+        if response.status != 200 {
+            return Err(anyhow!(
+                "Cloud KMS responded with status {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            ));
+        }
+        let response: AsymmetricSignResponse = serde_json::from_slice(&response.body)?;
+        Ok(base64::decode(response.signature)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::HashAlgorithm;
+    use crate::fetcher::mock_fetcher;
+    use crate::http::HttpResponse;
+    use std::sync::Mutex;
+
+    // SEQUENCE { INTEGER r, INTEGER s }, where r has its high bit set (so DER
+    // prepends 0x00) and s is a single byte (so P1363 left-pads it).
+    fn der_signature() -> Vec<u8> {
+        [
+            &[0x30, 0x26, 0x02, 0x21, 0x00, 0x80][..],
+            &[0xab; 31],
+            &[0x02, 0x01, 0x01],
+        ]
+        .concat()
+    }
+
+    struct MockRpc {
+        digests: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[cfg_attr(feature = "wasm", async_trait(?Send))]
+    #[cfg_attr(not(feature = "wasm"), async_trait)]
+    impl AsymmetricSignRpc for MockRpc {
+        async fn asymmetric_sign(
+            &self,
+            key_name: &str,
+            curve: Curve,
+            digest: &[u8],
+        ) -> Result<Vec<u8>> {
+            assert_eq!(
+                key_name,
+                "projects/p/locations/l/keyRings/r/cryptoKeys/k/cryptoKeyVersions/1"
+            );
+            assert_eq!(curve, Curve::P256);
+            self.digests.lock().unwrap().push(digest.to_vec());
+            Ok(der_signature())
+        }
+    }
+
+    fn mock_signer() -> CloudKmsSigner<MockRpc> {
+        CloudKmsSigner::new(
+            "projects/p/locations/l/keyRings/r/cryptoKeys/k/cryptoKeyVersions/1",
+            Curve::P256,
+            MockRpc {
+                digests: Mutex::new(vec![]),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn signs_digest_in_p1363_format() {
+        let signer = mock_signer();
+        let raw = signer.sign(b"hello", Format::Raw).await.unwrap();
+        assert_eq!(raw, [&[0x80][..], &[0xab; 31], &[0; 31], &[0x01]].concat());
+        assert_eq!(
+            *signer.rpc.digests.lock().unwrap(),
+            vec![HashAlgorithm::Sha256.digest(b"hello")]
+        );
+    }
+    #[tokio::test]
+    async fn signs_in_asn1_format() {
+        let signer = mock_signer();
+        let der = signer.sign(b"hello", Format::EccAsn1).await.unwrap();
+        assert_eq!(der, der_signature());
+        // Converting to P1363 and back to DER preserves the numbers.
+        let raw = super::super::parse_asn1_sig(&der, Curve::P256).unwrap();
+        let der = super::super::raw_sig_to_asn1(raw.clone()).unwrap();
+        assert_eq!(
+            super::super::parse_asn1_sig(&der, Curve::P256).unwrap(),
+            raw
+        );
+    }
+    #[tokio::test]
+    async fn calls_rest_api() {
+        let (fetcher, mut server) = mock_fetcher::create();
+        let rpc = CloudKmsRestRpc::new(fetcher, "token");
+        let digest = HashAlgorithm::Sha256.digest(b"hello");
+        let server_thread = async {
+            server
+                .handle_next_request(
+                    HttpRequest {
+                        body: format!(r#"{{"digest":{{"sha256":"{}"}}}}"#, base64::encode(&digest))
+                            .into_bytes(),
+                        headers: vec![
+                            ("authorization".to_string(), "Bearer token".to_string()),
+                            ("content-type".to_string(), "application/json".to_string()),
+                        ],
+                        method: Method::Post,
+                        url: "https://cloudkms.googleapis.com/v1/keys/k:asymmetricSign".to_string(),
+                    },
+                    HttpResponse {
+                        body: format!(
+                            r#"{{"signature":"{}","name":"keys/k"}}"#,
+                            base64::encode(der_signature())
+                        )
+                        .into_bytes(),
+                        headers: vec![],
+                        status: 200,
+                    },
+                )
+                .await
+                .unwrap();
+        };
+        let client_thread = async {
+            assert_eq!(
+                rpc.asymmetric_sign("keys/k", Curve::P256, &digest)
+                    .await
+                    .unwrap(),
+                der_signature()
+            );
+        };
+        tokio::join!(server_thread, client_thread);
+    }
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use super::{Format, Signer};
+use crate::crypto::Curve;
 use crate::utils::await_js_promise;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -61,7 +62,7 @@ impl Signer for JsSigner {
             (Format::Raw, Format::Raw) => Ok(sig),
             (Format::EccAsn1, Format::EccAsn1) => Ok(sig),
             (Format::Raw, Format::EccAsn1) => super::raw_sig_to_asn1(sig),
-            (Format::EccAsn1, Format::Raw) => super::parse_asn1_sig(&sig),
+            (Format::EccAsn1, Format::Raw) => super::parse_asn1_sig(&sig, Curve::P256),
         }
     }
 }
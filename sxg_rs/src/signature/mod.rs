@@ -12,12 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod cloud_kms_signer;
 #[cfg(feature = "wasm")]
 pub mod js_signer;
 pub mod mock_signer;
 #[cfg(feature = "rust_signer")]
 pub mod rust_signer;
 
+use crate::crypto::Curve;
 use crate::structured_header::{ParamItem, ShItem, ShParamList};
 use crate::utils::{MaybeSend, MaybeSync};
 use anyhow::{anyhow, Error, Result};
@@ -27,12 +29,23 @@ use std::cmp::min;
 use std::convert::TryInto;
 use std::time::Duration;
 
-#[derive(Clone, Copy)]
+/// The encoding of an ECDSA signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Format {
+    /// The IEEE P1363 encoding, which is the big-endian `r` and `s`, each
+    /// left-padded to the key size, concatenated. This is used by JWS (e.g.
+    /// for ACME) and by Web Crypto.
     Raw,
+    /// The ASN.1 DER encoding of `SEQUENCE { r INTEGER, s INTEGER }`, per
+    /// [RFC3279](https://datatracker.ietf.org/doc/html/rfc3279#section-2.2.3).
+    /// This is used by the `sig` parameter of the SXG signature header.
     EccAsn1,
 }
 
+/// An ECDSA signer, which is the extension point for keeping the private key
+/// outside of this process, e.g. in a KMS or HSM. Implementations hash the
+/// message themselves (with SHA-256 for P-256 keys), and must support both
+/// formats; [`raw_sig_to_asn1`] and [`parse_asn1_sig`] convert between them.
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 pub trait Signer: MaybeSend + MaybeSync {
@@ -64,7 +77,7 @@ pub struct Signature<'a> {
 }
 
 // Maximum signature duration per https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#section-3.5-7.3.
-const SEVEN_DAYS: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+pub const SEVEN_DAYS: Duration = Duration::from_secs(60 * 60 * 24 * 7);
 
 fn seven_days_from(date: &std::time::SystemTime) -> Result<std::time::SystemTime> {
     date.checked_add(SEVEN_DAYS)
@@ -90,23 +103,14 @@ impl<'a> Signature<'a> {
         };
         let date = time_to_number(date);
         let expires = time_to_number(expires);
-        // https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-signature-validity
-        let message = [
-            &[32u8; 64],
-            "HTTP Exchange 1 b3".as_bytes(),
-            &[0u8],
-            &[32u8],
+        let message = signed_message(
             cert_sha256,
-            &(validity_url.len() as u64).to_be_bytes(),
-            validity_url.as_bytes(),
-            &date.to_be_bytes(),
-            &expires.to_be_bytes(),
-            &(request_url.len() as u64).to_be_bytes(),
-            request_url.as_bytes(),
-            &(headers.len() as u64).to_be_bytes(),
+            validity_url,
+            date,
+            expires,
+            request_url,
             headers,
-        ]
-        .concat();
+        );
         let sig = signer
             .sign(&message, Format::EccAsn1)
             .await
@@ -148,31 +152,81 @@ impl<'a> Signature<'a> {
     }
 }
 
+// The message that the `sig` parameter signs, per
+// https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-signature-validity.
+pub(crate) fn signed_message(
+    cert_sha256: &[u8],
+    validity_url: &str,
+    date: u64,
+    expires: u64,
+    request_url: &str,
+    headers: &[u8],
+) -> Vec<u8> {
+    [
+        &[32u8; 64],
+        "HTTP Exchange 1 b3".as_bytes(),
+        &[0u8],
+        &[32u8],
+        cert_sha256,
+        &(validity_url.len() as u64).to_be_bytes(),
+        validity_url.as_bytes(),
+        &date.to_be_bytes(),
+        &expires.to_be_bytes(),
+        &(request_url.len() as u64).to_be_bytes(),
+        request_url.as_bytes(),
+        &(headers.len() as u64).to_be_bytes(),
+        headers,
+    ]
+    .concat()
+}
+
 fn time_to_number(t: std::time::SystemTime) -> u64 {
     t.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
 }
 
-// Parses an asn1 format signature and returns the raw 64 bytes data.
-pub fn parse_asn1_sig(asn1: &[u8]) -> Result<Vec<u8>> {
+// Parses an asn1 format signature and returns the raw data, which is 64 bytes
+// on P-256.
+pub fn parse_asn1_sig(asn1: &[u8], curve: Curve) -> Result<Vec<u8>> {
     let signature = der_parser::parse_ber(asn1)?.1;
     let numbers = signature.as_sequence()?;
-    let r = numbers[0].as_bigint()?.to_bytes_be();
-    let s = numbers[1].as_bigint()?.to_bytes_be();
-    Ok([r.1, s.1].concat())
+    if numbers.len() != 2 {
+        return Err(anyhow!(
+            "Expecting signature to contain 2 numbers, found {}",
+            numbers.len()
+        ));
+    }
+    let mut raw = Vec::with_capacity(curve.key_size() * 2);
+    for number in numbers {
+        // The minimal big-endian encoding, without sign, is left-padded to the
+        // key size, since r and s may have leading zeros.
+        let (_, bytes) = number.as_bigint()?.to_bytes_be();
+        if bytes.len() > curve.key_size() {
+            return Err(anyhow!(
+                "Expecting signature numbers to be at most {} bytes, found {}",
+                curve.key_size(),
+                bytes.len()
+            ));
+        }
+        raw.resize(raw.len() + curve.key_size() - bytes.len(), 0);
+        raw.extend_from_slice(&bytes);
+    }
+    Ok(raw)
 }
 
 pub fn raw_sig_to_asn1(raw: Vec<u8>) -> Result<Vec<u8>> {
-    const NUMBER_LENGTH: usize = 32; // 256 bit is 32 bytes.
-    const SIG_LENGTH: usize = NUMBER_LENGTH * 2; // A signature contains two numbers;
-    if raw.len() != SIG_LENGTH {
+    // A signature contains two numbers, each of 32 bytes (256 bit) on P-256
+    // or 48 bytes (384 bit) on P-384.
+    const SIG_LENGTHS: [usize; 2] = [32 * 2, 48 * 2];
+    if !SIG_LENGTHS.contains(&raw.len()) {
         return Err(Error::msg(format!(
-            "Expecting signature length to be {}, found {}.",
-            SIG_LENGTH,
+            "Expecting signature length to be one of {:?}, found {}.",
+            SIG_LENGTHS,
             raw.len()
         )));
     }
+    let number_length = raw.len() / 2;
     let mut r = raw;
-    let mut s = r.split_off(NUMBER_LENGTH);
+    let mut s = r.split_off(number_length);
     ensure_positive(&mut r);
     ensure_positive(&mut s);
     let asn1 = BerObject::from_obj(BerObjectContent::Sequence(vec![
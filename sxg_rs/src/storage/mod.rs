@@ -20,6 +20,7 @@ use anyhow::Result;
 use async_trait::async_trait;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -27,6 +28,13 @@ use tokio::sync::RwLock;
 pub trait Storage: MaybeSend + MaybeSync {
     async fn read(&self, k: &str) -> Result<Option<String>>;
     async fn write(&self, k: &str, v: &str) -> Result<()>;
+    /// Like `write`, but the storage may drop the value after `ttl`, e.g. to
+    /// clean up after retired certificates. By default, it is kept like any
+    /// other value.
+    async fn write_with_ttl(&self, k: &str, v: &str, ttl: Duration) -> Result<()> {
+        let _ = ttl;
+        self.write(k, v).await
+    }
 }
 
 pub struct InMemoryStorage(Arc<RwLock<HashMap<String, String>>>);
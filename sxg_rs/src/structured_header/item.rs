@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use anyhow::{anyhow, Result};
 use std::borrow::Cow;
 use std::fmt;
 
@@ -22,6 +23,52 @@ pub enum ShItem<'a> {
     String(Cow<'a, str>),
 }
 
+impl<'a> ShItem<'a> {
+    // https://tools.ietf.org/html/draft-ietf-httpbis-header-structure-10#section-4.2.7
+    // Parses an item from the start of `input`, and advances `input` past it.
+    pub(super) fn parse(input: &mut &'a str) -> Result<Self> {
+        let s: &'a str = *input;
+        match s.chars().next() {
+            Some('*') => {
+                let end = s[1..]
+                    .find('*')
+                    .ok_or_else(|| anyhow!("Byte sequence is not terminated"))?;
+                let bytes = ::base64::decode(&s[1..1 + end])?;
+                *input = &s[end + 2..];
+                Ok(ShItem::ByteSequence(bytes.into()))
+            }
+            Some('"') => {
+                let mut value = String::new();
+                let mut chars = s.char_indices().skip(1);
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => match chars.next() {
+                            Some((_, c @ ('\\' | '"'))) => value.push(c),
+                            _ => return Err(anyhow!("Invalid escape in string")),
+                        },
+                        '"' => {
+                            *input = &s[i + 1..];
+                            return Ok(ShItem::String(value.into()));
+                        }
+                        '\u{20}'..='\u{7e}' => value.push(c),
+                        _ => return Err(anyhow!("Invalid character in string")),
+                    }
+                }
+                Err(anyhow!("String is not terminated"))
+            }
+            Some('-' | '0'..='9') => {
+                let end = s[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map_or(s.len(), |end| end + 1);
+                let number = s[..end].parse()?;
+                *input = &s[end..];
+                Ok(ShItem::Integer(number))
+            }
+            _ => Err(anyhow!("Expecting an item, found {:?}", s)),
+        }
+    }
+}
+
 // should be https://tools.ietf.org/html/draft-ietf-httpbis-header-structure-10#section-4.1.5
 impl<'a> fmt::Display for ShItem<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
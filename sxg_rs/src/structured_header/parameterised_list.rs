@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use anyhow::{anyhow, Result};
 use std::borrow::Cow;
 use std::fmt;
 use std::ops::{Deref, DerefMut};
@@ -42,6 +43,57 @@ impl<'a> ShParamList<'a> {
     }
 }
 
+impl<'a> ShParamList<'a> {
+    // https://tools.ietf.org/html/draft-ietf-httpbis-header-structure-10#section-4.2.5
+    pub fn parse(input: &'a str) -> Result<Self> {
+        const OWS: &[char] = &[' ', '\t'];
+        let mut list = ShParamList::new();
+        let mut input = input.trim_matches(OWS);
+        loop {
+            let mut item = ParamItem::new(parse_key(&mut input)?);
+            loop {
+                input = input.trim_start_matches(OWS);
+                if !input.starts_with(';') {
+                    break;
+                }
+                input = input[1..].trim_start_matches(OWS);
+                let name = parse_key(&mut input)?;
+                let value = if input.starts_with('=') {
+                    input = &input[1..];
+                    Some(ShItem::parse(&mut input)?)
+                } else {
+                    None
+                };
+                item.push((name.into(), value));
+            }
+            list.push(item);
+            if input.is_empty() {
+                return Ok(list);
+            }
+            if !input.starts_with(',') {
+                return Err(anyhow!("Expecting a comma, found {:?}", input));
+            }
+            input = input[1..].trim_start_matches(OWS);
+        }
+    }
+}
+
+// https://tools.ietf.org/html/draft-ietf-httpbis-header-structure-10#section-4.2.2
+fn parse_key<'a>(input: &mut &'a str) -> Result<&'a str> {
+    let s: &'a str = *input;
+    let end = s
+        .find(|c: char| {
+            !(c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '*'))
+        })
+        .unwrap_or(s.len());
+    if !s.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return Err(anyhow!("Expecting a key, found {:?}", s));
+    }
+    let (key, rest) = s.split_at(end);
+    *input = rest;
+    Ok(key)
+}
+
 impl<'a> Default for ShParamList<'a> {
     fn default() -> Self {
         Self::new()
@@ -93,3 +145,39 @@ impl<'a> fmt::Display for ShParamList<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_inverts_display() {
+        let mut list = ShParamList::new();
+        let mut param = ParamItem::new("sig");
+        param.push((
+            "sig".into(),
+            Some(ShItem::ByteSequence(b"\x00\xff"[..].into())),
+        ));
+        param.push(("cert-url".into(), Some(ShItem::String("a \"b\\c".into()))));
+        param.push(("date".into(), Some(ShItem::Integer(-123))));
+        param.push(("flag".into(), None));
+        list.push(param);
+        list.push(ParamItem::new("other"));
+        let serialized = format!("{}", list);
+        let parsed = ShParamList::parse(&serialized).unwrap();
+        assert_eq!(format!("{}", parsed), serialized);
+        assert_eq!(parsed[0].primary_id, "sig");
+        assert_eq!(
+            parsed[0][1],
+            ("cert-url".into(), Some(ShItem::String("a \"b\\c".into())))
+        );
+        assert_eq!(parsed[1].primary_id, "other");
+    }
+    #[test]
+    fn parse_errors() {
+        assert!(ShParamList::parse("").is_err());
+        assert!(ShParamList::parse("sig;date=").is_err());
+        assert!(ShParamList::parse("sig;url=\"unterminated").is_err());
+        assert!(ShParamList::parse("sig;sig=*AAAA").is_err());
+        assert!(ShParamList::parse("sig sig").is_err());
+    }
+}
@@ -12,7 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::{Error, Result};
+use crate::cbor::DataItem;
+use anyhow::{anyhow, Error, Result};
+use std::collections::HashMap;
+
+// The magic string of b3, the SXG version labeled by the content type
+// `application/signed-exchange;v=b3`. It is the only version that browsers
+// accept, and the earlier b1 and b2 drafts differ in more than their labels,
+// so the version isn't configurable.
+const MAGIC: &[u8] = b"sxg1-b3\0";
 
 // https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#application-signed-exchange
 pub fn build(
@@ -39,7 +47,7 @@ pub fn build(
         )));
     }
     Ok([
-        "sxg1-b3\0".as_bytes(),
+        MAGIC,
         &(fallback_url.len() as u16).to_be_bytes(),
         fallback_url.as_bytes(),
         (sig_len as u32).to_be_bytes().get(1..4).unwrap(),
@@ -50,3 +58,114 @@ pub fn build(
     ]
     .concat())
 }
+
+/// The fields of an SXG, which are the inputs of [`build`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Parts<'a> {
+    pub fallback_url: &'a str,
+    pub signature: &'a [u8],
+    pub signed_headers: &'a [u8],
+    pub payload_body: &'a [u8],
+}
+
+/// Splits an SXG into its fields, without validating them.
+pub fn parse(sxg: &[u8]) -> Result<Parts<'_>> {
+    fn take<'a>(input: &mut &'a [u8], size: usize, name: &str) -> Result<&'a [u8]> {
+        if input.len() < size {
+            return Err(anyhow!("The SXG is truncated in {}", name));
+        }
+        let (bytes, rest) = input.split_at(size);
+        *input = rest;
+        Ok(bytes)
+    }
+    fn be_number(bytes: &[u8]) -> usize {
+        bytes
+            .iter()
+            .fold(0, |number, &byte| (number << 8) | byte as usize)
+    }
+    let mut input = sxg;
+    if take(&mut input, MAGIC.len(), "magic")? != MAGIC {
+        return Err(anyhow!("The SXG doesn't start with {:?}", MAGIC));
+    }
+    let fallback_url_len = be_number(take(&mut input, 2, "fallbackUrlLength")?);
+    let fallback_url = std::str::from_utf8(take(&mut input, fallback_url_len, "fallbackUrl")?)
+        .map_err(|e| Error::new(e).context("The fallback URL is not UTF-8"))?;
+    let sig_len = be_number(take(&mut input, 3, "sigLength")?);
+    let header_len = be_number(take(&mut input, 3, "headerLength")?);
+    let signature = take(&mut input, sig_len, "signature")?;
+    let signed_headers = take(&mut input, header_len, "signedHeaders")?;
+    Ok(Parts {
+        fallback_url,
+        signature,
+        signed_headers,
+        payload_body: input,
+    })
+}
+
+/// Parses the signed headers of an SXG, which must be a CBOR map of byte
+/// strings, including `:status` and a MICE `digest`.
+pub fn parse_signed_headers(signed_headers: &[u8]) -> Result<HashMap<&[u8], &[u8]>> {
+    let (map, rest) = DataItem::parse(signed_headers)?;
+    if !rest.is_empty() {
+        return Err(anyhow!("There are {} bytes after the CBOR map", rest.len()));
+    }
+    let fields = match map {
+        DataItem::Map(fields) => fields,
+        _ => return Err(anyhow!("The signed headers are not a CBOR map")),
+    };
+    let mut headers = HashMap::new();
+    for field in fields {
+        match field {
+            (DataItem::ByteString(name), DataItem::ByteString(value)) => {
+                headers.insert(name, value);
+            }
+            _ => return Err(anyhow!("The signed headers contain a non-bytestring field")),
+        }
+    }
+    if !headers.contains_key(&b":status"[..]) {
+        return Err(anyhow!("The :status header is missing"));
+    }
+    mice_digest(&headers)?;
+    Ok(headers)
+}
+
+/// Returns the MICE integrity of the payload, from the `digest` header.
+pub fn mice_digest(headers: &HashMap<&[u8], &[u8]>) -> Result<Vec<u8>> {
+    let digest = headers
+        .get(&b"digest"[..])
+        .ok_or_else(|| anyhow!("The digest header is missing"))?;
+    let digest = std::str::from_utf8(digest)?
+        .strip_prefix("mi-sha256-03=")
+        .ok_or_else(|| anyhow!("The digest header is not mi-sha256-03"))?;
+    Ok(::base64::decode(digest)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_inverts_build() {
+        let sxg = build("https://example.com/", b"sig", b"headers", b"body").unwrap();
+        assert_eq!(
+            parse(&sxg).unwrap(),
+            Parts {
+                fallback_url: "https://example.com/",
+                signature: b"sig",
+                signed_headers: b"headers",
+                payload_body: b"body",
+            }
+        );
+    }
+    #[test]
+    fn parse_rejects_truncated() {
+        let sxg = build("https://example.com/", b"sig", b"headers", b"").unwrap();
+        assert!(parse(&sxg[..sxg.len() - 1]).is_err());
+        assert!(parse(b"sxg1-b2\0").is_err());
+    }
+    #[test]
+    fn build_starts_with_b3_magic() {
+        let sxg = build("https://example.com/", b"", b"", b"").unwrap();
+        assert!(sxg.starts_with(b"sxg1-b3\0"));
+        assert_eq!(parse(&sxg).unwrap().fallback_url, "https://example.com/");
+    }
+}
@@ -14,7 +14,8 @@
 
 use crate::header_integrity::HeaderIntegrityFetcher;
 use crate::headers::Headers;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Error, Result};
+use std::io::Read;
 use url::Url;
 
 #[cfg(all(target_family = "wasm", feature = "wasm"))]
@@ -91,7 +92,7 @@ pub trait MaybeSync: Sync {}
 #[cfg(not(feature = "wasm"))]
 impl<T: Sync> MaybeSync for T {}
 
-// #[warn(clippy::too_many_arguments)]
+#[allow(clippy::too_many_arguments)]
 pub async fn signed_headers_and_payload(
     fallback_url: &Url,
     status_code: u16,
@@ -99,27 +100,164 @@ pub async fn signed_headers_and_payload(
     payload_body: &[u8],
     header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
     skip_process_link: bool,
+    brotli_level: Option<u32>,
+    mice_record_size: usize,
 ) -> Result<(Vec<u8>, Vec<u8>)> {
-    if status_code != 200 {
-        return Err(anyhow!("The resource status code is {}.", status_code));
-    }
-    // 16384 is the max mice record size allowed by SXG spec.
-    // https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#section-3.5-7.9.1
-    let (mice_digest, payload_body) = crate::mice::calculate(payload_body, 16384);
+    let decoded = decode_content(
+        payload_headers.inner().get("content-encoding"),
+        payload_body,
+    )?;
+    signed_headers_and_decoded_payload(
+        fallback_url,
+        status_code,
+        payload_headers,
+        decoded.as_deref().unwrap_or(payload_body),
+        header_integrity_fetcher,
+        skip_process_link,
+        brotli_level,
+        mice_record_size,
+    )
+    .await
+}
+
+// Like `signed_headers_and_payload`, but for a payload body whose
+// content-encoding has already been undone by `decode_content`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn signed_headers_and_decoded_payload(
+    fallback_url: &Url,
+    status_code: u16,
+    payload_headers: &Headers,
+    payload_body: &[u8],
+    header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
+    skip_process_link: bool,
+    brotli_level: Option<u32>,
+    mice_record_size: usize,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    check_status_code(status_code)?;
+    let brotli_level = brotli_level.filter(|_| payload_headers.is_compressible());
+    let compressed;
+    let payload_body = match brotli_level {
+        Some(level) => {
+            compressed = brotli_compress(payload_body, level)?;
+            &compressed[..]
+        }
+        None => payload_body,
+    };
+    let (mice_digest, payload_body) = crate::mice::calculate(payload_body, mice_record_size);
     let signed_headers = payload_headers
         .get_signed_headers_bytes(
             fallback_url,
             status_code,
             &mice_digest,
+            payload_body.len(),
             header_integrity_fetcher,
             skip_process_link,
+            brotli_level.is_some(),
         )
         .await;
     Ok((signed_headers, payload_body))
 }
 
+// Returns an error for a status code that can't be signed. A 1xx response
+// isn't final, a 206 covers only part of the resource, and a 304 has no body
+// of its own. Whether a 4xx or 5xx response is signed is up to the config;
+// see `SxgWorker::should_sign_status`.
+fn check_status_code(status_code: u16) -> Result<()> {
+    if matches!(status_code, 200..=599) && !matches!(status_code, 206 | 304) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "The resource status code is {}, which cannot be signed.",
+            status_code
+        ))
+    }
+}
+
+// Undoes the content codings that the upstream applied, as listed in its
+// `content-encoding` header, or returns None if there are none. The upstream
+// header isn't signed, so MICE must be computed over the decoded bytes for the
+// signed `content-encoding` to describe them. The decoded payload is limited
+// to MAX_PAYLOAD_SIZE.
+pub(crate) fn decode_content(
+    content_encoding: Option<&String>,
+    body: &[u8],
+) -> Result<Option<Vec<u8>>> {
+    let codings: Vec<String> = match content_encoding {
+        Some(value) => value
+            .split(',')
+            .map(|coding| coding.trim().to_ascii_lowercase())
+            .filter(|coding| !coding.is_empty() && coding != "identity")
+            .collect(),
+        None => vec![],
+    };
+    if codings.is_empty() {
+        return Ok(None);
+    }
+    let limit = crate::MAX_PAYLOAD_SIZE as u64;
+    let mut decoded = body.to_vec();
+    // Codings are listed in the order they were applied, so they are undone in
+    // reverse.
+    for coding in codings.iter().rev() {
+        let mut output = vec![];
+        decoder(coding, &decoded)?
+            .take(limit + 1)
+            .read_to_end(&mut output)
+            .map_err(|e| Error::new(e).context(format!("Failed to decode {} payload", coding)))?;
+        if output.len() as u64 > limit {
+            return Err(anyhow!(
+                "The decoded payload exceeds the limit {}.",
+                crate::MAX_PAYLOAD_SIZE
+            ));
+        }
+        decoded = output;
+    }
+    Ok(Some(decoded))
+}
+
+// Returns a reader of `input` with the content coding `coding` undone.
+#[cfg(feature = "content_coding")]
+fn decoder<'a>(coding: &str, input: &'a [u8]) -> Result<Box<dyn Read + 'a>> {
+    match coding {
+        "gzip" | "x-gzip" => Ok(Box::new(flate2::read::GzDecoder::new(input))),
+        // The HTTP "deflate" coding is the zlib format.
+        "deflate" => Ok(Box::new(flate2::read::ZlibDecoder::new(input))),
+        "br" => Ok(Box::new(brotli::Decompressor::new(input, 4096))),
+        _ => Err(anyhow!(
+            "The content-encoding {} is not supported, so the payload cannot be signed.",
+            coding
+        )),
+    }
+}
+
+#[cfg(not(feature = "content_coding"))]
+fn decoder<'a>(coding: &str, _input: &'a [u8]) -> Result<Box<dyn Read + 'a>> {
+    Err(anyhow!(
+        "The content-encoding {} needs the content_coding feature, so the payload cannot be signed.",
+        coding
+    ))
+}
+
+// Compresses with the given quality (0-11), which is deterministic, so that
+// header-integrity can be computed by recompressing.
+#[cfg(feature = "content_coding")]
+fn brotli_compress(input: &[u8], quality: u32) -> Result<Vec<u8>> {
+    let params = brotli::enc::BrotliEncoderParams {
+        quality: quality as i32,
+        ..Default::default()
+    };
+    let mut output = vec![];
+    brotli::BrotliCompress(&mut &*input, &mut output, &params)?;
+    Ok(output)
+}
+
+#[cfg(not(feature = "content_coding"))]
+fn brotli_compress(_input: &[u8], _quality: u32) -> Result<Vec<u8>> {
+    Err(anyhow!("brotli_level needs the content_coding feature."))
+}
+
 #[cfg(test)]
 pub mod tests {
+    use super::*;
     use futures::{
         future::{BoxFuture, Future},
         task::{Context, Poll, Waker},
@@ -151,6 +289,70 @@ SFfkmh8Fc2QXpbbaK5AQfnQpkDHV
     //   openssl x509 -in - -outform DER | openssl dgst -sha256 -binary | base64 | tr /+ _- | tr -d =
     pub const SELF_SIGNED_CERT_SHA256: &str = "Lz2EMcys4NR9FP0yYnuS5Uw8xM3gbVAOM2lwSBU9qX0";
 
+    // The SHA-256 of the public key of the above cert, generated using:
+    //   openssl x509 -pubkey -noout -in - | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64 | tr /+ _- | tr -d =
+    pub const SELF_SIGNED_PUBLIC_KEY_SHA256: &str = "E9nWs0er0sTGTwK5vCAHtIwj3KNQMiHQZVowURPfrPU";
+
+    // A certificate whose private key is also checked in, for tests that verify signatures.
+    // Generated like SELF_SIGNED_CERT_PEM above, but keeping the key and extracting its
+    // private scalar with:
+    //   openssl ec -in "$KEY" -outform DER | tail -c +8 | head -c 32 | base64
+    pub const SIGNING_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIBsTCCAVegAwIBAgIURjAk4mIwIrynNtqMB0D31hlnvXAwCgYIKoZIzj0EAwIw
+MjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYT
+AlVTMB4XDTI2MTAxNDA1MjA1NVoXDTI3MDExMjA1MjA1NVowMjEUMBIGA1UEAwwL
+ZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYTAlVTMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEWpxFASoBrkOKVcdKTW60fF5l91wI2eodenO15EPf
+2EFIpof5jftxHxVn+LrB+pkC48BvjY4HZ6sml5Htdhr3JaNLMEkwEAYKKwYBBAHW
+eQIBFgQCBQAwFgYDVR0RBA8wDYILZXhhbXBsZS5vcmcwHQYDVR0OBBYEFBvUyZ+a
+zqqbRGOCuh+Y1wp8osr8MAoGCCqGSM49BAMCA0gAMEUCIG3/Z3UMSubVvWgJII3n
+dJGHumYz1sL0dBzX683gSnjiAiEAkOG6V6xs3CUNyTmelFSTeCnUtB587KcVzYKn
+tM4oz6g=
+-----END CERTIFICATE-----
+    ";
+    pub const SIGNING_PRIVATE_KEY_BASE64: &str = "Q714JzYPYxqPwajTtDPs5ho+SUk3TlknvXzaqihejIo=";
+
+    // A certificate with an OCSP responder in its AIA extension, for tests of OCSP failures.
+    // Expires 11 minutes after SIGNING_CERT_PEM, and has its key, so that both can be added to
+    // one worker. Generated like SELF_SIGNED_CERT_PEM above, but with the key of
+    // SIGNING_CERT_PEM, "-not_before 20261014053147Z -not_after 20270112053147Z", and
+    // "authorityInfoAccess=OCSP;URI:http://ocsp.example.org" in the extensions.
+    pub const OCSP_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIBzzCCAXSgAwIBAgIUB4Q0H3ITrTypt0eFBiqC/1DtfdwwCgYIKoZIzj0EAwIw
+MjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYT
+AlVTMB4XDTI2MTAxNDA1MzE0N1oXDTI3MDExMjA1MzE0N1owMjEUMBIGA1UEAwwL
+ZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYTAlVTMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEWpxFASoBrkOKVcdKTW60fF5l91wI2eodenO15EPf
+2EFIpof5jftxHxVn+LrB+pkC48BvjY4HZ6sml5Htdhr3JaNoMGYwEAYKKwYBBAHW
+eQIBFgQCBQAwMwYIKwYBBQUHAQEEJzAlMCMGCCsGAQUFBzABhhdodHRwOi8vb2Nz
+cC5leGFtcGxlLm9yZzAdBgNVHQ4EFgQUG9TJn5rOqptEY4K6H5jXCnyiyvwwCgYI
+KoZIzj0EAwIDSQAwRgIhAPt5DZ88OjwWzB3shPuht8u4HJW+3PbfnKoEJul/oG7I
+AiEAllEzHsf9jHkco5Kz91seZK3JpDYTdeb8r9FHQSjbOVg=
+-----END CERTIFICATE-----
+    ";
+
+    // An OCSP Must-Staple certificate with the same OCSP responder as OCSP_CERT_PEM. Generated
+    // like SELF_SIGNED_CERT_PEM above, but with
+    // "authorityInfoAccess=OCSP;URI:http://ocsp.example.org\ntlsfeature=status_request" in the
+    // extensions.
+    pub const MUST_STAPLE_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIB+jCCAaGgAwIBAgIUMmNK3smd1apxh2IuvEFkxiHM6gEwCgYIKoZIzj0EAwIw
+MjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYT
+AlVTMB4XDTI2MTAxNDA3MzY0OVoXDTI3MDExMjA3MzY0OVowMjEUMBIGA1UEAwwL
+ZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYTAlVTMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAE4TrwXw42QBXALkkGwHno9xDH+iAxTZP6DwDV0Qca
+2T2fO5ClOhZLTmVayv0Mc4yycgYVx6YCejs5vjyxHMg0V6OBlDCBkTAQBgorBgEE
+AdZ5AgEWBAIFADAWBgNVHREEDzANggtleGFtcGxlLm9yZzAzBggrBgEFBQcBAQQn
+MCUwIwYIKwYBBQUHMAGGF2h0dHA6Ly9vY3NwLmV4YW1wbGUub3JnMBEGCCsGAQUF
+BwEYBAUwAwIBBTAdBgNVHQ4EFgQUkhkTzaU+SDeggbXEbeA2WTQ9tuswCgYIKoZI
+zj0EAwIDRwAwRAIgcygzYpn6HuU5m5ls/gRLQau0SMTNikiMjPtoakDqFDkCIFHp
+6jViBsYi/YLgbUW1H8UFZiKnW//Y/c0wxwqHlZBd
+-----END CERTIFICATE-----
+    ";
+
     // Returns a future for the given state object. If multiple futures are created from the same
     // shared state, the first to be polled resolves after the second.
     pub fn out_of_order<'a, T: 'a, F: 'a + Fn() -> T + Send>(
@@ -198,4 +400,187 @@ SFfkmh8Fc2QXpbbaK5AQfnQpkDHV
             }
         }
     }
+
+    // Returns the content-encoding signed header, and the body decoded from MICE.
+    async fn sign(headers: Vec<(&str, &str)>, brotli_level: Option<u32>) -> (String, Vec<u8>) {
+        sign_body(headers, HTML, brotli_level).await
+    }
+
+    // Like `sign`, but with the given upstream payload.
+    async fn sign_body(
+        headers: Vec<(&str, &str)>,
+        payload_body: &[u8],
+        brotli_level: Option<u32>,
+    ) -> (String, Vec<u8>) {
+        let (content_encoding, body, _) = sign_with_record_size(
+            headers,
+            payload_body,
+            brotli_level,
+            crate::mice::MAX_RECORD_SIZE,
+        )
+        .await;
+        (content_encoding, body)
+    }
+
+    // Like `sign`, but also returns the MICE-encoded payload.
+    async fn sign_with_record_size(
+        headers: Vec<(&str, &str)>,
+        payload_body: &[u8],
+        brotli_level: Option<u32>,
+        mice_record_size: usize,
+    ) -> (String, Vec<u8>, Vec<u8>) {
+        use crate::cbor::DataItem;
+        use std::collections::BTreeSet;
+        let headers = Headers::new(
+            headers
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            &BTreeSet::new(),
+        );
+        let (signed_headers, payload) = super::signed_headers_and_payload(
+            &Url::parse("https://foo.com/").unwrap(),
+            200,
+            &headers,
+            payload_body,
+            &mut crate::header_integrity::tests::null_integrity_fetcher(),
+            false,
+            brotli_level,
+            mice_record_size,
+        )
+        .await
+        .unwrap();
+        let fields = match DataItem::parse(&signed_headers).unwrap().0 {
+            DataItem::Map(fields) => fields,
+            _ => panic!("signed headers are not a map"),
+        };
+        let field = |name: &[u8]| {
+            fields
+                .iter()
+                .find_map(|field| match field {
+                    (DataItem::ByteString(k), DataItem::ByteString(v)) if *k == name => {
+                        Some(String::from_utf8(v.to_vec()).unwrap())
+                    }
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let digest =
+            base64::decode(field(b"digest").strip_prefix("mi-sha256-03=").unwrap()).unwrap();
+        let body = crate::mice::decode(&digest, &payload).unwrap();
+        (field(b"content-encoding"), body, payload)
+    }
+
+    const HTML: &[u8] = b"<html><body>Hello, hello, hello, hello, hello!</body></html>";
+
+    #[cfg(feature = "content_coding")]
+    #[tokio::test]
+    async fn brotli_compresses_payload() {
+        let (content_encoding, body) = sign(vec![("content-type", "text/html")], Some(5)).await;
+        assert_eq!(content_encoding, "br, mi-sha256-03");
+        // MICE is computed over the compressed bytes.
+        assert_ne!(body, HTML);
+        let mut decompressed = vec![];
+        brotli::BrotliDecompress(&mut &*body, &mut decompressed).unwrap();
+        assert_eq!(decompressed, HTML);
+    }
+    #[tokio::test]
+    async fn brotli_skips_incompressible_payload() {
+        for headers in [
+            vec![("content-type", "image/jpeg")],
+            vec![("content-type", "video/mp4")],
+        ] {
+            let (content_encoding, body) = sign(headers, Some(5)).await;
+            assert_eq!(content_encoding, "mi-sha256-03");
+            assert_eq!(body, HTML);
+        }
+    }
+    #[cfg(feature = "content_coding")]
+    fn gzip(input: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(input).unwrap();
+        encoder.finish().unwrap()
+    }
+    #[cfg(feature = "content_coding")]
+    #[tokio::test]
+    async fn decodes_gzip_payload() {
+        let headers = || vec![("content-type", "text/html"), ("content-encoding", "gzip")];
+        // MICE is computed over the decoded bytes, as the signed
+        // content-encoding says.
+        let (content_encoding, body) = sign_body(headers(), &gzip(HTML), None).await;
+        assert_eq!(content_encoding, "mi-sha256-03");
+        assert_eq!(body, HTML);
+        // The decoded bytes can be Brotli-compressed.
+        let (content_encoding, body) = sign_body(headers(), &gzip(HTML), Some(5)).await;
+        assert_eq!(content_encoding, "br, mi-sha256-03");
+        let mut decompressed = vec![];
+        brotli::BrotliDecompress(&mut &*body, &mut decompressed).unwrap();
+        assert_eq!(decompressed, HTML);
+    }
+    #[cfg(feature = "content_coding")]
+    #[test]
+    fn decode_content_codings() {
+        let header = |value: &str| Some(value.to_string());
+        assert_eq!(super::decode_content(None, HTML).unwrap(), None);
+        assert_eq!(
+            super::decode_content(header("identity").as_ref(), HTML).unwrap(),
+            None
+        );
+        assert_eq!(
+            super::decode_content(header("X-Gzip").as_ref(), &gzip(HTML)).unwrap(),
+            Some(HTML.to_vec())
+        );
+        // Applied in order, so undone in reverse.
+        assert_eq!(
+            super::decode_content(header("gzip, gzip").as_ref(), &gzip(&gzip(HTML))).unwrap(),
+            Some(HTML.to_vec())
+        );
+        assert!(super::decode_content(header("gzip").as_ref(), HTML).is_err());
+        assert_eq!(
+            super::decode_content(header("zstd").as_ref(), HTML)
+                .unwrap_err()
+                .to_string(),
+            "The content-encoding zstd is not supported, so the payload cannot be signed."
+        );
+    }
+    #[tokio::test]
+    async fn mice_record_sizes() {
+        let headers = || vec![("content-type", "text/html")];
+        // Each record after the first is preceded by a 32-byte proof, after
+        // the 8-byte record size.
+        let records = |payload: &[u8]| (payload.len() - 8 - HTML.len()) / 32 + 1;
+        let (_, body, large) = sign_with_record_size(headers(), HTML, None, 16384).await;
+        assert_eq!(body, HTML);
+        assert_eq!(records(&large), 1);
+        let (_, body, small) = sign_with_record_size(headers(), HTML, None, 16).await;
+        assert_eq!(body, HTML);
+        assert_eq!(records(&small), (HTML.len() + 15) / 16);
+        assert_ne!(large, small);
+    }
+    #[test]
+    fn status_codes() {
+        for status_code in [200, 203, 301, 302, 307, 308, 404, 410, 500, 503] {
+            assert!(
+                super::check_status_code(status_code).is_ok(),
+                "{status_code}"
+            );
+        }
+        for status_code in [0, 100, 101, 103, 206, 304, 600] {
+            assert!(
+                super::check_status_code(status_code).is_err(),
+                "{status_code}"
+            );
+        }
+        assert_eq!(
+            super::check_status_code(206).unwrap_err().to_string(),
+            "The resource status code is 206, which cannot be signed."
+        );
+    }
+    #[tokio::test]
+    async fn brotli_disabled() {
+        let (content_encoding, body) = sign(vec![("content-type", "text/html")], None).await;
+        assert_eq!(content_encoding, "mi-sha256-03");
+        assert_eq!(body, HTML);
+    }
 }
@@ -0,0 +1,627 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks that an SXG is well-formed and correctly signed, e.g. before
+//! deploying a generated one, without serving it.
+
+use crate::crypto::CertificateChain;
+use crate::signature::{signed_message, SEVEN_DAYS};
+use crate::structured_header::{ParamItem, ShItem, ShParamList};
+use crate::{cbor, mice, sxg};
+use anyhow::{anyhow, Error, Result};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// One of the checks done by [`validate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// The fallback URL is an absolute https URL.
+    FallbackUrl,
+    /// The signature header parses, and has the parameters `sig`,
+    /// `integrity`, `cert-sha256`, `date` and `expires`.
+    SignatureHeader,
+    /// The `cert-url` parameter is an absolute https or data URL.
+    CertUrl,
+    /// The `validity-url` parameter is an absolute https URL.
+    ValidityUrl,
+    /// The signature is currently valid, and lasts at most 7 days.
+    Expiry,
+    /// The signed headers are a CBOR map with `:status` and a MICE `digest`.
+    SignedHeaders,
+    /// The keys of the signed headers are in canonical CBOR order, as
+    /// browsers require.
+    CanonicalCbor,
+    /// The payload body matches the MICE `digest` header.
+    Mice,
+    /// The `sig` parameter is signed by the certificate matching
+    /// `cert-sha256`.
+    Signature,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Check::FallbackUrl => "fallback-url",
+            Check::SignatureHeader => "signature-header",
+            Check::CertUrl => "cert-url",
+            Check::ValidityUrl => "validity-url",
+            Check::Expiry => "expiry",
+            Check::SignedHeaders => "signed-headers",
+            Check::CanonicalCbor => "canonical-cbor",
+            Check::Mice => "mice",
+            Check::Signature => "signature",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The outcome of one [`Check`].
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub check: Check,
+    /// The reason why the check failed, or `None` if it passed.
+    pub error: Option<String>,
+}
+
+/// The outcomes of all checks, in the order of [`Check`].
+#[derive(Debug)]
+pub struct SxgValidation {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl SxgValidation {
+    pub fn is_valid(&self) -> bool {
+        self.diagnostics.iter().all(|d| d.error.is_none())
+    }
+    /// Returns the reason why `check` failed, or `None` if it passed.
+    pub fn error(&self, check: Check) -> Option<&str> {
+        self.diagnostics
+            .iter()
+            .find(|d| d.check == check)
+            .and_then(|d| d.error.as_deref())
+    }
+}
+
+impl fmt::Display for SxgValidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for Diagnostic { check, error } in &self.diagnostics {
+            match error {
+                None => writeln!(f, "PASS {}", check)?,
+                Some(error) => writeln!(f, "FAIL {}: {}", check, error)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator {
+    fn record<T>(&mut self, check: Check, result: Result<T>) -> Option<T> {
+        let (value, error) = match result {
+            Ok(value) => (Some(value), None),
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        self.diagnostics.push(Diagnostic { check, error });
+        value
+    }
+    fn skip(&mut self, check: Check, dependency: Check) {
+        self.diagnostics.push(Diagnostic {
+            check,
+            error: Some(format!("Skipped, because {} failed", dependency)),
+        });
+    }
+}
+
+// The required parameters of a signature.
+struct SignatureParams {
+    sig: Vec<u8>,
+    cert_sha256: Vec<u8>,
+    date: u64,
+    expires: u64,
+}
+
+fn get_param<'a>(item: &'a ParamItem, name: &str) -> Result<&'a ShItem<'a>> {
+    item.iter()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, value)| value.as_ref())
+        .ok_or_else(|| anyhow!("The {} parameter is missing", name))
+}
+
+fn get_bytes<'a>(item: &'a ParamItem, name: &str) -> Result<&'a [u8]> {
+    match get_param(item, name)? {
+        ShItem::ByteSequence(bytes) => Ok(&**bytes),
+        _ => Err(anyhow!("The {} parameter is not a byte sequence", name)),
+    }
+}
+
+fn get_string<'a>(item: &'a ParamItem, name: &str) -> Result<&'a str> {
+    match get_param(item, name)? {
+        ShItem::String(s) => Ok(&**s),
+        _ => Err(anyhow!("The {} parameter is not a string", name)),
+    }
+}
+
+fn get_time(item: &ParamItem, name: &str) -> Result<u64> {
+    match get_param(item, name)? {
+        ShItem::Integer(n) if *n >= 0 => Ok(*n as u64),
+        _ => Err(anyhow!(
+            "The {} parameter is not a non-negative integer",
+            name
+        )),
+    }
+}
+
+// Returns the first signature in the signature header.
+fn parse_signature_header(signature: &[u8]) -> Result<ParamItem<'_>> {
+    let signature = std::str::from_utf8(signature)?;
+    ShParamList::parse(signature)?
+        .0
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("The signature header is empty"))
+}
+
+fn parse_signature_params(item: &ParamItem) -> Result<SignatureParams> {
+    let integrity = get_string(item, "integrity")?;
+    if integrity != "digest/mi-sha256-03" {
+        return Err(anyhow!(
+            r#"The integrity parameter is "{}", not "digest/mi-sha256-03""#,
+            integrity
+        ));
+    }
+    let cert_sha256 = get_bytes(item, "cert-sha256")?;
+    if cert_sha256.len() != 32 {
+        return Err(anyhow!(
+            "The cert-sha256 parameter is {} bytes, not 32",
+            cert_sha256.len()
+        ));
+    }
+    Ok(SignatureParams {
+        sig: get_bytes(item, "sig")?.to_vec(),
+        cert_sha256: cert_sha256.to_vec(),
+        date: get_time(item, "date")?,
+        expires: get_time(item, "expires")?,
+    })
+}
+
+fn check_url(url: &str, schemes: &[&str]) -> Result<()> {
+    let parsed =
+        Url::parse(url).map_err(|e| Error::new(e).context(format!("Invalid URL {:?}", url)))?;
+    if !schemes.contains(&parsed.scheme()) {
+        return Err(anyhow!(
+            "The URL {:?} has scheme {}, not one of {:?}",
+            url,
+            parsed.scheme(),
+            schemes
+        ));
+    }
+    Ok(())
+}
+
+fn check_expiry(params: &SignatureParams, now: SystemTime) -> Result<()> {
+    let now = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    if params.expires <= params.date {
+        return Err(anyhow!(
+            "The signature expires ({}) before its date ({})",
+            params.expires,
+            params.date
+        ));
+    }
+    if params.expires - params.date > SEVEN_DAYS.as_secs() {
+        return Err(anyhow!(
+            "The signature lasts {} seconds, which is longer than 7 days",
+            params.expires - params.date
+        ));
+    }
+    if now < params.date {
+        return Err(anyhow!(
+            "The signature date ({}) is in the future (now is {})",
+            params.date,
+            now
+        ));
+    }
+    if now >= params.expires {
+        return Err(anyhow!(
+            "The signature expired at {} (now is {})",
+            params.expires,
+            now
+        ));
+    }
+    Ok(())
+}
+
+fn check_signature(
+    certificates: &[&CertificateChain],
+    params: &SignatureParams,
+    validity_url: &str,
+    parts: &sxg::Parts,
+) -> Result<()> {
+    let certificate = certificates
+        .iter()
+        .find(|c| c.end_entity_sha256 == params.cert_sha256)
+        .ok_or_else(|| anyhow!("The cert-sha256 doesn't match any known certificate"))?;
+    let message = signed_message(
+        &params.cert_sha256,
+        validity_url,
+        params.date,
+        params.expires,
+        parts.fallback_url,
+        parts.signed_headers,
+    );
+    certificate
+        .end_entity_public_key()?
+        .verify(&message, &params.sig)
+        .map_err(|e| e.context("The signature doesn't verify"))
+}
+
+/// Checks the SXG against the given certificates, of which the one matching
+/// `cert-sha256` is expected to have signed it. Returns an error only if the
+/// SXG can't be split into its fields; otherwise every failed check is
+/// reported in the returned diagnostics.
+pub fn validate(
+    sxg: &[u8],
+    certificates: &[&CertificateChain],
+    now: SystemTime,
+) -> Result<SxgValidation> {
+    let parts = sxg::parse(sxg)?;
+    let mut v = Validator {
+        diagnostics: vec![],
+    };
+    v.record(
+        Check::FallbackUrl,
+        check_url(parts.fallback_url, &["https"]),
+    );
+    let signature = parse_signature_header(parts.signature).and_then(|item| {
+        let params = parse_signature_params(&item)?;
+        Ok((item, params))
+    });
+    let (validity_url, params) = match v.record(Check::SignatureHeader, signature) {
+        Some((item, params)) => {
+            let cert_url = get_string(&item, "cert-url");
+            v.record(
+                Check::CertUrl,
+                cert_url.and_then(|url| check_url(url, &["https", "data"])),
+            );
+            let validity_url = get_string(&item, "validity-url")
+                .and_then(|url| check_url(url, &["https"]).map(|_| url.to_string()));
+            (v.record(Check::ValidityUrl, validity_url), Some(params))
+        }
+        None => {
+            v.skip(Check::CertUrl, Check::SignatureHeader);
+            v.skip(Check::ValidityUrl, Check::SignatureHeader);
+            (None, None)
+        }
+    };
+    match &params {
+        Some(params) => {
+            v.record(Check::Expiry, check_expiry(params, now));
+        }
+        None => v.skip(Check::Expiry, Check::SignatureHeader),
+    }
+    let headers = v.record(
+        Check::SignedHeaders,
+        sxg::parse_signed_headers(parts.signed_headers),
+    );
+    v.record(
+        Check::CanonicalCbor,
+        cbor::check_canonical_order(parts.signed_headers),
+    );
+    match headers {
+        Some(headers) => {
+            let decoded = sxg::mice_digest(&headers)
+                .and_then(|digest| mice::decode(&digest, parts.payload_body));
+            v.record(Check::Mice, decoded);
+        }
+        None => v.skip(Check::Mice, Check::SignedHeaders),
+    }
+    match (&params, &validity_url) {
+        (Some(params), Some(validity_url)) => {
+            v.record(
+                Check::Signature,
+                check_signature(certificates, params, validity_url, &parts),
+            );
+        }
+        (None, _) => v.skip(Check::Signature, Check::SignatureHeader),
+        (_, None) => v.skip(Check::Signature, Check::ValidityUrl),
+    }
+    Ok(SxgValidation {
+        diagnostics: v.diagnostics,
+    })
+}
+
+/// Returns an outline of the fields of an SXG, one value per line: the
+/// signatures with their parameters, the signed response headers, and the
+/// MICE records of the payload. Nothing is verified beyond parsing, so that
+/// SXGs that fail [`validate`] can be compared, e.g. with `diff`.
+pub fn dump(sxg: &[u8]) -> Result<String> {
+    let parts = sxg::parse(sxg)?;
+    let mut lines = vec![
+        "format: application/signed-exchange;v=b3".to_string(),
+        format!("fallback-url: {}", parts.fallback_url),
+    ];
+    let signature = std::str::from_utf8(parts.signature)?;
+    let signatures =
+        ShParamList::parse(signature).map_err(|e| e.context("The signature header is invalid"))?;
+    for item in signatures.iter() {
+        lines.push(format!("signature: {}", item.primary_id));
+        for (name, value) in item.iter() {
+            match value {
+                Some(value) => lines.push(format!("  {}: {}", name, value)),
+                None => lines.push(format!("  {}", name)),
+            }
+        }
+    }
+    let (headers, rest) = cbor::DataItem::parse(parts.signed_headers)
+        .map_err(|e| e.context("The signed headers are invalid"))?;
+    if !rest.is_empty() {
+        return Err(anyhow!("There are {} bytes after the CBOR map", rest.len()));
+    }
+    lines.push("signed-headers:".into());
+    headers.outline(1, &mut lines);
+    lines.push("payload:".into());
+    lines.push(format!("  length: {}", parts.payload_body.len()));
+    if !parts.payload_body.is_empty() {
+        let (record_size, records) = mice::count_records(parts.payload_body)?;
+        lines.push(format!("  mice-record-size: {}", record_size));
+        lines.push(format!("  mice-records: {}", records));
+    }
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_cache::NullCache;
+    use crate::runtime::Runtime;
+    use crate::utils::tests::{SIGNING_CERT_PEM, SIGNING_PRIVATE_KEY_BASE64};
+    use crate::{CreateSignedExchangeParams, SxgWorker};
+
+    fn new_worker() -> SxgWorker {
+        let yaml = format!(
+            r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: my_domain.com
+private_key_base64: "{}"
+reserved_path: ".sxg"
+respond_debug_info: false
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity/"
+            "#,
+            SIGNING_PRIVATE_KEY_BASE64
+        );
+        let mut worker = SxgWorker::new(&yaml).unwrap();
+        worker
+            .add_certificate(
+                CertificateChain::from_pem_files(&[SIGNING_CERT_PEM, SIGNING_CERT_PEM]).unwrap(),
+            )
+            .unwrap();
+        worker
+    }
+    async fn create_sxg(worker: &SxgWorker, now: SystemTime) -> Vec<u8> {
+        create_sxg_from(worker, now, vec![], b"<h1>Hello</h1>").await
+    }
+    async fn create_sxg_from(
+        worker: &SxgWorker,
+        now: SystemTime,
+        payload_headers: Vec<(String, String)>,
+        payload_body: &[u8],
+    ) -> Vec<u8> {
+        let runtime = Runtime {
+            now,
+            sxg_signer: Box::new(worker.create_rust_signer().unwrap()),
+            ..Default::default()
+        };
+        worker
+            .create_signed_exchange(
+                &runtime,
+                CreateSignedExchangeParams {
+                    fallback_url: "https://my_domain.com/",
+                    cert_origin: "https://my_domain.com",
+                    payload_body,
+                    payload_headers: worker.transform_payload_headers(payload_headers).unwrap(),
+                    skip_process_link: false,
+                    status_code: 200,
+                    header_integrity_cache: NullCache {},
+                    mice_record_size: None,
+                },
+            )
+            .await
+            .unwrap()
+            .body
+    }
+    fn failed_checks(validation: &SxgValidation) -> Vec<Check> {
+        validation
+            .diagnostics
+            .iter()
+            .filter(|d| d.error.is_some())
+            .map(|d| d.check)
+            .collect()
+    }
+    #[tokio::test]
+    async fn valid_sxg() {
+        let worker = new_worker();
+        let sxg = create_sxg(&worker, SystemTime::now()).await;
+        let validation = worker.validate_sxg(&sxg).unwrap();
+        assert!(validation.is_valid(), "{}", validation);
+        assert_eq!(validation.diagnostics.len(), 9);
+        assert!(validation.to_string().starts_with("PASS fallback-url\n"));
+    }
+    #[tokio::test]
+    async fn dumps_sxg() {
+        let worker = new_worker();
+        let sxg = create_sxg(&worker, SystemTime::now()).await;
+        let outline = dump(&sxg).unwrap();
+        assert!(outline.starts_with(
+            "format: application/signed-exchange;v=b3\n\
+             fallback-url: https://my_domain.com/\n\
+             signature: sig\n"
+        ));
+        assert!(outline.contains("\n  ':status': '200'\n"));
+        assert!(outline.ends_with("  mice-records: 1\n"), "{}", outline);
+        // Truncated in the signature.
+        assert!(dump(&sxg[..40]).is_err());
+    }
+    #[cfg(feature = "content_coding")]
+    #[tokio::test]
+    async fn gzip_payload() {
+        use std::io::Write;
+        let worker = new_worker();
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(b"<h1>Hello</h1>").unwrap();
+        let sxg = create_sxg_from(
+            &worker,
+            SystemTime::now(),
+            vec![
+                ("content-type".into(), "text/html".into()),
+                ("content-encoding".into(), "gzip".into()),
+            ],
+            &gzip.finish().unwrap(),
+        )
+        .await;
+        let validation = worker.validate_sxg(&sxg).unwrap();
+        assert!(validation.is_valid(), "{}", validation);
+        // The signed content-encoding describes the MICE-decoded payload.
+        let parts = sxg::parse(&sxg).unwrap();
+        let headers = sxg::parse_signed_headers(parts.signed_headers).unwrap();
+        assert_eq!(
+            headers.get(&b"content-encoding"[..]),
+            Some(&&b"mi-sha256-03"[..])
+        );
+        let digest = sxg::mice_digest(&headers).unwrap();
+        assert_eq!(
+            mice::decode(&digest, parts.payload_body).unwrap(),
+            b"<h1>Hello</h1>".to_vec()
+        );
+    }
+    #[tokio::test]
+    async fn bad_mice() {
+        let worker = new_worker();
+        let mut sxg = create_sxg(&worker, SystemTime::now()).await;
+        *sxg.last_mut().unwrap() ^= 1;
+        let validation = worker.validate_sxg(&sxg).unwrap();
+        assert_eq!(failed_checks(&validation), vec![Check::Mice]);
+        assert_eq!(
+            validation.error(Check::Mice),
+            Some("The integrity of the payload doesn't match")
+        );
+    }
+    #[tokio::test]
+    async fn expired_signature() {
+        let worker = new_worker();
+        let now = SystemTime::now();
+        let sxg = create_sxg(&worker, now).await;
+        let certificates: Vec<_> = worker.certificates.iter().collect();
+        let validation = validate(&sxg, &certificates, now + SEVEN_DAYS).unwrap();
+        assert_eq!(failed_checks(&validation), vec![Check::Expiry]);
+        assert!(validation
+            .error(Check::Expiry)
+            .unwrap()
+            .starts_with("The signature expired at"));
+    }
+    #[tokio::test]
+    async fn missing_validity_url() {
+        let worker = new_worker();
+        let sxg = create_sxg(&worker, SystemTime::now()).await;
+        let parts = sxg::parse(&sxg).unwrap();
+        let mut signature =
+            ShParamList::parse(std::str::from_utf8(parts.signature).unwrap()).unwrap();
+        signature[0].retain(|(name, _)| name != "validity-url");
+        let signature = signature.to_string();
+        let sxg = sxg::build(
+            parts.fallback_url,
+            signature.as_bytes(),
+            parts.signed_headers,
+            parts.payload_body,
+        )
+        .unwrap();
+        let validation = worker.validate_sxg(&sxg).unwrap();
+        assert_eq!(
+            failed_checks(&validation),
+            vec![Check::ValidityUrl, Check::Signature]
+        );
+        assert_eq!(
+            validation.error(Check::ValidityUrl),
+            Some("The validity-url parameter is missing")
+        );
+        assert_eq!(
+            validation.error(Check::Signature),
+            Some("Skipped, because validity-url failed")
+        );
+    }
+    #[tokio::test]
+    async fn unknown_certificate() {
+        let worker = new_worker();
+        let sxg = create_sxg(&worker, SystemTime::now()).await;
+        let validation = validate(&sxg, &[], SystemTime::now()).unwrap();
+        assert_eq!(failed_checks(&validation), vec![Check::Signature]);
+    }
+    #[tokio::test]
+    async fn tampered_signed_headers() {
+        let worker = new_worker();
+        let mut sxg = create_sxg(&worker, SystemTime::now()).await;
+        // Changes the signed content-type from text/html to text/htmm.
+        let index = sxg.windows(9).position(|w| w == b"text/html").unwrap();
+        sxg[index + 8] = b'm';
+        let validation = worker.validate_sxg(&sxg).unwrap();
+        assert_eq!(failed_checks(&validation), vec![Check::Signature]);
+    }
+    #[tokio::test]
+    async fn misordered_signed_headers() {
+        use crate::cbor::DataItem;
+        let worker = new_worker();
+        let sxg = create_sxg(&worker, SystemTime::now()).await;
+        let parts = sxg::parse(&sxg).unwrap();
+        // Re-encodes the signed headers with their keys in reverse order.
+        let fields = match DataItem::parse(parts.signed_headers).unwrap().0 {
+            DataItem::Map(fields) => fields,
+            _ => panic!("The signed headers are not a map"),
+        };
+        let mut signed_headers = vec![0xa0 + fields.len() as u8];
+        for (key, value) in fields.iter().rev() {
+            signed_headers.extend(key.serialize());
+            signed_headers.extend(value.serialize());
+        }
+        let sxg = sxg::build(
+            parts.fallback_url,
+            parts.signature,
+            &signed_headers,
+            parts.payload_body,
+        )
+        .unwrap();
+        let validation = worker.validate_sxg(&sxg).unwrap();
+        assert_eq!(
+            failed_checks(&validation),
+            vec![Check::CanonicalCbor, Check::Signature]
+        );
+        assert!(validation
+            .error(Check::CanonicalCbor)
+            .unwrap()
+            .contains("is not after"));
+    }
+    #[test]
+    fn not_an_sxg() {
+        let worker = new_worker();
+        assert!(worker.validate_sxg(b"<html>").is_err());
+    }
+}
@@ -60,7 +60,9 @@ impl WasmWorker {
         if let Some(certificate_pem) = certificate_pem {
             let certificate =
                 CertificateChain::from_pem_files(&[&certificate_pem]).map_err(to_js_error)?;
-            sxg_worker.add_certificate(certificate);
+            sxg_worker
+                .add_certificate(certificate)
+                .map_err(to_js_error)?;
         }
         Ok(WasmWorker(Arc::new(RwLock::new(sxg_worker))))
     }
@@ -186,6 +188,7 @@ impl WasmWorker {
                         payload_headers,
                         status_code: options.status_code(),
                         header_integrity_cache,
+                        mice_record_size: None,
                         skip_process_link: options.skip_process_link(),
                     },
                 )
@@ -0,0 +1,127 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signs with a signer and fetcher implemented in JavaScript, as a
+//! Cloudflare Worker does, without a tokio runtime. Run with:
+//!
+//!   wasm-pack test --node sxg_rs --features wasm -- --test wasm
+
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use js_sys::{Function, Reflect};
+use std::time::{Duration, UNIX_EPOCH};
+use sxg_rs::{
+    crypto::CertificateChain, fetcher::js_fetcher::JsFetcher, http_cache::NullCache,
+    runtime::Runtime, signature::js_signer::JsSigner, CreateSignedExchangeParams, SxgWorker,
+};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+// The same certificate as `SIGNING_CERT_PEM` in the unit tests. Its private
+// key is not needed, because the signature is mocked.
+const CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIBsTCCAVegAwIBAgIURjAk4mIwIrynNtqMB0D31hlnvXAwCgYIKoZIzj0EAwIw
+MjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYT
+AlVTMB4XDTI2MTAxNDA1MjA1NVoXDTI3MDExMjA1MjA1NVowMjEUMBIGA1UEAwwL
+ZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYTAlVTMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEWpxFASoBrkOKVcdKTW60fF5l91wI2eodenO15EPf
+2EFIpof5jftxHxVn+LrB+pkC48BvjY4HZ6sml5Htdhr3JaNLMEkwEAYKKwYBBAHW
+eQIBFgQCBQAwFgYDVR0RBA8wDYILZXhhbXBsZS5vcmcwHQYDVR0OBBYEFBvUyZ+a
+zqqbRGOCuh+Y1wp8osr8MAoGCCqGSM49BAMCA0gAMEUCIG3/Z3UMSubVvWgJII3n
+dJGHumYz1sL0dBzX683gSnjiAiEAkOG6V6xs3CUNyTmelFSTeCnUtB587KcVzYKn
+tM4oz6g=
+-----END CERTIFICATE-----
+";
+
+fn new_worker() -> SxgWorker {
+    let yaml = r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: example.org
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity/"
+    "#;
+    let mut worker = SxgWorker::new(yaml).unwrap();
+    worker
+        .add_certificate(CertificateChain::from_pem_files(&[CERT_PEM, CERT_PEM]).unwrap())
+        .unwrap();
+    worker
+}
+
+// Returns the number stored in the JavaScript global `name`.
+fn global_count(name: &str) -> f64 {
+    Reflect::get(&js_sys::global(), &JsValue::from_str(name))
+        .unwrap()
+        .as_f64()
+        .unwrap_or(0.0)
+}
+
+#[wasm_bindgen_test]
+async fn signs_with_js_signer_and_fetcher() {
+    let worker = new_worker();
+    // Returns an all-zero raw signature, counting the calls.
+    let signer = Function::new_with_args(
+        "message",
+        "globalThis.signs = (globalThis.signs || 0) + 1;
+         return Promise.resolve(new Uint8Array(64));",
+    );
+    // Answers the subresource fetch for the preload link with a 404, so that
+    // the link is dropped from the signed headers.
+    let fetcher = Function::new_with_args(
+        "request",
+        "globalThis.fetches = (globalThis.fetches || 0) + 1;
+         return Promise.resolve({ status: 404, headers: [], body: [] });",
+    );
+    let runtime = Runtime {
+        now: UNIX_EPOCH + Duration::from_secs(1_800_000_000),
+        fetcher: Box::new(JsFetcher::new(fetcher)),
+        sxg_signer: Box::new(JsSigner::from_raw_signer(signer)),
+        ..Default::default()
+    };
+    let sxg = worker
+        .create_signed_exchange(
+            &runtime,
+            CreateSignedExchangeParams {
+                fallback_url: "https://example.org/index.html",
+                cert_origin: "https://example.org",
+                payload_body: b"<h1>Hello</h1>",
+                payload_headers: worker
+                    .transform_payload_headers(vec![
+                        ("content-type".into(), "text/html".into()),
+                        (
+                            "link".into(),
+                            "<https://example.org/style.css>;rel=preload;as=style".into(),
+                        ),
+                    ])
+                    .unwrap(),
+                skip_process_link: false,
+                status_code: 200,
+                header_integrity_cache: NullCache {},
+                mice_record_size: None,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sxg.status, 200);
+    assert!(sxg.headers.contains(&(
+        "content-type".to_string(),
+        "application/signed-exchange;v=b3".to_string()
+    )));
+    assert!(sxg.body.starts_with(b"sxg1-b3\0"));
+    assert_eq!(global_count("signs"), 1.0);
+    assert!(global_count("fetches") >= 1.0);
+}
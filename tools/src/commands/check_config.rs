@@ -0,0 +1,220 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Error, Result};
+use clap::Parser;
+use std::fs;
+use sxg_rs::{config::Config, crypto::CertificateChain, SxgWorker};
+
+/// Checks a config.yaml of http_server without starting it: that it parses,
+/// that every value is in range, and that the certificates it refers to load
+/// and are SXG-compatible. Prints every problem found.
+#[derive(Parser)]
+pub struct Opts {
+    /// The config.yaml to check.
+    config: String,
+    /// The PEM certificate of html_host, as passed to http_server's --cert.
+    #[clap(long, requires = "issuer")]
+    cert: Option<String>,
+    /// The PEM issuer of the certificate, as passed to http_server's
+    /// --issuer.
+    #[clap(long, requires = "cert")]
+    issuer: Option<String>,
+}
+
+pub fn main(opts: Opts) -> Result<()> {
+    let problems = check(&opts);
+    for problem in &problems {
+        println!("{:#}", problem);
+    }
+    if problems.is_empty() {
+        println!("{} is valid", opts.config);
+        Ok(())
+    } else {
+        Err(anyhow!("{} has {} problem(s)", opts.config, problems.len()))
+    }
+}
+
+// Returns every problem of the config and the files it refers to.
+fn check(opts: &Opts) -> Vec<Error> {
+    let yaml = match fs::read_to_string(&opts.config) {
+        Ok(yaml) => yaml,
+        Err(e) => return vec![anyhow!("error reading {}: {}", opts.config, e)],
+    };
+    let config: Config = match serde_yaml::from_str(&yaml) {
+        Ok(config) => config,
+        Err(e) => return vec![Error::new(e).context(format!("error parsing {}", opts.config))],
+    };
+    let mut problems = vec![];
+    // The file references are checked even if a value is out of range.
+    let hosts = config.hosts.clone();
+    let worker = match config.validate_all() {
+        Ok(config) => Some(SxgWorker::from_parsed(config)),
+        Err(errors) => {
+            problems.extend(errors);
+            None
+        }
+    };
+    if let Some(worker) = &worker {
+        if worker.config().private_key_base64.is_some() {
+            if let Err(e) = worker.create_rust_signer() {
+                problems.push(e.context("private_key_base64 is invalid"));
+            }
+        }
+    }
+    if let (Some(cert), Some(issuer)) = (&opts.cert, &opts.issuer) {
+        if let Err(e) = load_certificate(cert, issuer) {
+            problems.push(e.context("--cert and --issuer"));
+        }
+    }
+    for (host, host_config) in &hosts {
+        match (&host_config.cert_file, &host_config.issuer_file) {
+            (Some(cert), Some(issuer)) => {
+                if let Err(e) = load_certificate(cert, issuer) {
+                    problems.push(e.context(format!("hosts.{}", host)));
+                }
+            }
+            _ => problems.push(anyhow!(
+                "hosts.{} needs both cert_file and issuer_file",
+                host
+            )),
+        }
+    }
+    problems
+}
+
+// Reads the certificate and its issuer, and checks that they form a chain
+// that can sign SXGs.
+fn load_certificate(cert: &str, issuer: &str) -> Result<CertificateChain> {
+    let read =
+        |path: &str| fs::read_to_string(path).map_err(|e| anyhow!("error reading {}: {}", path, e));
+    let certificate = CertificateChain::from_pem_files(&[&read(cert)?, &read(issuer)?])?;
+    certificate.validate().map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        anyhow!("Invalid certificate chain: {}", errors.join("; "))
+    })?;
+    Ok(certificate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    const VALID_CONFIG: &str = r#"
+cert_url_dirname: ".well-known/sxg-certs"
+forward_request_headers: []
+html_host: example.com
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+"#;
+    // Writes the files into a new temporary directory, and returns its path.
+    fn temp_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "sxg-rs-check-config-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+    fn problems(dir: &Path) -> Vec<String> {
+        check(&Opts {
+            config: dir.join("config.yaml").to_str().unwrap().into(),
+            cert: None,
+            issuer: None,
+        })
+        .iter()
+        .map(|e| format!("{:#}", e))
+        .collect()
+    }
+    #[test]
+    fn valid_config() {
+        let dir = temp_dir("valid", &[("config.yaml", VALID_CONFIG)]);
+        assert_eq!(problems(&dir), Vec::<String>::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn missing_or_malformed_config() {
+        let dir = temp_dir("malformed", &[]);
+        let missing = problems(&dir);
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].starts_with("error reading"), "{}", missing[0]);
+        fs::write(dir.join("config.yaml"), "html_host: [").unwrap();
+        let malformed = problems(&dir);
+        assert_eq!(malformed.len(), 1);
+        assert!(
+            malformed[0].starts_with("error parsing"),
+            "{}",
+            malformed[0]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn reports_every_problem() {
+        let config = format!(
+            "{}{}",
+            VALID_CONFIG,
+            r#"
+brotli_level: 12
+cert_url_base: "https://example.com/certs"
+signature_duration_seconds: 604801
+hosts:
+  blog.example.com:
+    cert_file: "missing.pem"
+    issuer_file: "missing.pem"
+  shop.example.com:
+    cert_file: "cert.pem"
+"#
+        );
+        let dir = temp_dir("invalid", &[("config.yaml", config.as_str())]);
+        let problems = problems(&dir);
+        assert_eq!(problems.len(), 5, "{:#?}", problems);
+        assert!(problems[0].starts_with("brotli_level is 12"));
+        assert!(problems[1].starts_with("cert_url_base"));
+        assert!(problems[2].starts_with("signature_duration_seconds is 604801"));
+        assert!(problems[3].starts_with("hosts.blog.example.com: error reading missing.pem"));
+        assert_eq!(
+            problems[4],
+            "hosts.shop.example.com needs both cert_file and issuer_file"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn checks_private_key_and_certificate() {
+        let config = format!("{}private_key_base64: \"AAAA\"\n", VALID_CONFIG);
+        let dir = temp_dir(
+            "certificate",
+            &[("config.yaml", config.as_str()), ("cert.pem", "not a PEM")],
+        );
+        let cert = dir.join("cert.pem").to_str().unwrap().to_string();
+        let problems: Vec<String> = check(&Opts {
+            config: dir.join("config.yaml").to_str().unwrap().into(),
+            cert: Some(cert.clone()),
+            issuer: Some(cert),
+        })
+        .iter()
+        .map(|e| format!("{:#}", e))
+        .collect();
+        assert_eq!(problems.len(), 2, "{:#?}", problems);
+        assert!(problems[0].starts_with("private_key_base64 is invalid"));
+        assert!(problems[1].starts_with("--cert and --issuer"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
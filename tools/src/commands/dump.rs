@@ -0,0 +1,49 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::fs;
+use sxg_rs::validation;
+
+/// Prints the parsed fields of an SXG file, one value per line, e.g. to diff
+/// an SXG that a browser rejects against one that it accepts.
+#[derive(Parser)]
+pub struct Opts {
+    /// The SXG file to dump.
+    sxg: String,
+}
+
+pub fn main(opts: Opts) -> Result<()> {
+    let sxg = fs::read(&opts.sxg).map_err(|e| anyhow!("error reading {}: {}", opts.sxg, e))?;
+    let dump =
+        validation::dump(&sxg).map_err(|e| e.context(format!("error parsing {}", opts.sxg)))?;
+    print!("{}", dump);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    #[test]
+    fn dumps_fixture_sxg() {
+        // page.sxg contains page.html in 64-byte MICE records, with a
+        // placeholder signature.
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let sxg = fs::read(testdata.join("page.sxg")).unwrap();
+        let expected = fs::read_to_string(testdata.join("page.sxg.txt")).unwrap();
+        assert_eq!(validation::dump(&sxg).unwrap(), expected);
+    }
+}
@@ -147,8 +147,16 @@ pub fn main(
     };
     let mut routes = cloudflare_input.routes.clone();
     routes.extend(vec![
-        format!("{}/.well-known/sxg-certs/*", sxg_input.html_host),
-        format!("{}/.well-known/sxg-validity/*", sxg_input.html_host),
+        format!(
+            "{}/{}/*",
+            sxg_input.html_host,
+            sxg_input.cert_url_dirname.trim_matches('/')
+        ),
+        format!(
+            "{}/{}/*",
+            sxg_input.html_host,
+            sxg_input.validity_url_dirname.trim_matches('/')
+        ),
         format!("{}/.well-known/acme-challenge/*", sxg_input.html_host),
     ]);
     let wrangler_toml_output = WranglerManifest {
@@ -1,11 +1,33 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use sxg_rs::config::Config as SxgConfig;
 use sxg_rs::crypto::EcPrivateKey;
 
 const CONFIG_YAML: &str = "http_server/config.yaml";
 const PRIVKEY_PEM: &str = "credentials/privkey.pem";
 
-pub(crate) fn main(mut config: SxgConfig) -> Result<()> {
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HttpServerSpecificInput {
+    // The DNS resolver of http_server's fetchers, copied as is into
+    // config.yaml; see http_server/src/dns.rs for its format.
+    pub dns: Option<serde_yaml::Value>,
+    // The TLS client certificate of http_server's fetchers, copied as is into
+    // config.yaml; see http_server/src/client_auth.rs for its format.
+    pub client_auth: Option<serde_yaml::Value>,
+}
+
+// The format of http_server/config.yaml.
+#[derive(Serialize)]
+struct HttpServerConfig<'a> {
+    #[serde(flatten)]
+    sxg_worker: SxgConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dns: Option<&'a serde_yaml::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_auth: Option<&'a serde_yaml::Value>,
+}
+
+pub(crate) fn main(mut config: SxgConfig, input: Option<&HttpServerSpecificInput>) -> Result<()> {
     config.private_key_base64 = Some(base64::encode(
         EcPrivateKey::from_sec1_pem(&std::fs::read_to_string(PRIVKEY_PEM)?)?.d,
     ));
@@ -16,7 +38,11 @@ pub(crate) fn main(mut config: SxgConfig) -> Result<()> {
             # Please note that anything you modify won't be preserved\n\
             # at the next time you run \"cargo run -p tools -- -gen-config\".\n\
             {}",
-            serde_yaml::to_string(&config)?,
+            serde_yaml::to_string(&HttpServerConfig {
+                sxg_worker: config,
+                dns: input.and_then(|input| input.dns.as_ref()),
+                client_auth: input.and_then(|input| input.client_auth.as_ref()),
+            })?,
         ),
     )?;
     Ok(())
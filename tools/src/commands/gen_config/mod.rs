@@ -23,10 +23,11 @@ use anyhow::{Error, Result};
 use clap::{ArgEnum, Parser};
 use cloudflare::CloudlareSpecificInput;
 use fastly::FastlySpecificInput;
+use http_server::HttpServerSpecificInput;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use sxg_rs::acme::{directory::Directory as AcmeDirectory, Account as AcmeAccount};
-use sxg_rs::crypto::EcPrivateKey;
+use sxg_rs::crypto::{Curve, EcPrivateKey};
 use tools::Artifact;
 
 #[derive(ArgEnum, Clone, Debug, Eq, PartialEq)]
@@ -59,6 +60,7 @@ pub struct Config {
     certificates: SxgCertConfig,
     cloudflare: Option<CloudlareSpecificInput>,
     fastly: Option<FastlySpecificInput>,
+    http_server: Option<HttpServerSpecificInput>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -109,7 +111,8 @@ async fn create_acme_key_and_account(
     domain_name: &str,
 ) -> Result<(EcPrivateKey, AcmeAccount)> {
     let acme_private_key = {
-        let pem = generate_private_key_pem()?;
+        // ACME requests are signed with ES256, which requires P-256.
+        let pem = generate_private_key_pem(Curve::P256)?;
         EcPrivateKey::from_sec1_pem(&pem)?
     };
     let runtime = sxg_rs::runtime::Runtime {
@@ -198,7 +201,7 @@ pub fn main(opts: Opts) -> Result<()> {
             )?;
         }
         Some(Platform::HttpServer) => {
-            http_server::main(input.sxg_worker)?;
+            http_server::main(input.sxg_worker, input.http_server.as_ref())?;
         }
         Some(Platform::Fastly) => {
             fastly::main(
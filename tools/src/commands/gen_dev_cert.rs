@@ -15,16 +15,22 @@
 use anyhow::Result;
 use clap::Parser;
 use std::fs::write;
+use sxg_rs::crypto::Curve;
 
 use crate::linux_commands::{
     create_certificate, create_certificate_request_pem, get_certificate_sha256,
-    read_or_create_private_key_pem, write_new_file,
+    read_or_create_private_key_pem, sxg_ext_file_content, write_new_file,
 };
 
 #[derive(Parser)]
 pub struct Opts {
-    #[clap(long)]
-    domain: String,
+    /// A domain of the certificate. May be repeated to add SubjectAltNames;
+    /// the first is also the Common Name.
+    #[clap(long = "domain", required = true)]
+    domains: Vec<String>,
+    /// The curve of the generated private key; either p256 or p384.
+    #[clap(long, default_value = "p256")]
+    curve: Curve,
 }
 
 pub fn main(opts: Opts) -> Result<()> {
@@ -34,15 +40,17 @@ pub fn main(opts: Opts) -> Result<()> {
     const CERT_FILE: &str = "cert.pem";
     const ISSUER_FILE: &str = "issuer.pem";
     const CERT_SHA256_FILE: &str = "cert_sha256.txt";
-    read_or_create_private_key_pem(PRIVKEY_FILE)?;
-    create_certificate_request_pem(&opts.domain, PRIVKEY_FILE, CERT_CSR_FILE)?;
-    write(
-        EXT_FILE,
-        format!(
-            "1.3.6.1.4.1.11129.2.1.22 = ASN1:NULL\nsubjectAltName=DNS:{}\n",
-            &opts.domain,
-        ),
-    )?;
+    if !opts.curve.is_sxg_compatible() {
+        println!(
+            "Warning: SXGs signed with {} are rejected by browsers; the SXG spec requires {}.",
+            opts.curve.signature_scheme(),
+            Curve::P256.signature_scheme(),
+        );
+    }
+    let domains: Vec<&str> = opts.domains.iter().map(String::as_str).collect();
+    read_or_create_private_key_pem(PRIVKEY_FILE, opts.curve)?;
+    create_certificate_request_pem(&domains, PRIVKEY_FILE, CERT_CSR_FILE)?;
+    write(EXT_FILE, sxg_ext_file_content(&domains)?)?;
     let cert_pem = create_certificate(PRIVKEY_FILE, CERT_CSR_FILE, EXT_FILE, CERT_FILE)?;
     write_new_file(ISSUER_FILE, &cert_pem)?;
     write_new_file(
@@ -37,7 +37,7 @@ pub async fn main(opts: Opts) -> Result<()> {
         &fs::read_to_string(opts.cert_pem).unwrap(),
         &fs::read_to_string(opts.issuer_pem).unwrap(),
     ])?;
-    worker.add_certificate(certificate);
+    worker.add_certificate(certificate)?;
     fs::write(
         opts.out_cert_cbor,
         &worker.create_cert_cbor(
@@ -65,6 +65,7 @@ pub async fn main(opts: Opts) -> Result<()> {
             skip_process_link: false,
             status_code: 200,
             header_integrity_cache: NullCache {},
+            mice_record_size: None,
         },
     );
     let sxg = sxg.await;
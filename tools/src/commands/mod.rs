@@ -13,9 +13,14 @@
 // limitations under the License.
 
 mod apply_acme_cert;
+mod check_config;
+mod dump;
 mod gen_config;
 mod gen_dev_cert;
 mod gen_sxg;
+mod selftest;
+mod sign_file;
+mod validate;
 
 use super::tokio_block_on as block_on;
 use anyhow::Result;
@@ -23,10 +28,16 @@ use clap::Parser;
 
 #[derive(Parser)]
 enum SubCommand {
+    #[clap(alias = "acme-setup")]
     ApplyAcmeCert(apply_acme_cert::Opts),
+    CheckConfig(check_config::Opts),
+    Dump(dump::Opts),
     GenConfig(gen_config::Opts),
     GenDevCert(gen_dev_cert::Opts),
     GenSxg(gen_sxg::Opts),
+    Selftest(selftest::Opts),
+    SignFile(sign_file::Opts),
+    Validate(validate::Opts),
 }
 
 #[derive(Parser)]
@@ -38,8 +49,13 @@ struct Opts {
 pub fn main() -> Result<()> {
     match Opts::parse().sub_command {
         SubCommand::ApplyAcmeCert(opts) => block_on(apply_acme_cert::main(opts)),
+        SubCommand::CheckConfig(opts) => check_config::main(opts),
+        SubCommand::Dump(opts) => dump::main(opts),
         SubCommand::GenConfig(opts) => gen_config::main(opts),
         SubCommand::GenSxg(opts) => block_on(gen_sxg::main(opts)),
         SubCommand::GenDevCert(opts) => gen_dev_cert::main(opts),
+        SubCommand::Selftest(opts) => block_on(selftest::main(opts)),
+        SubCommand::SignFile(opts) => block_on(sign_file::main(opts)),
+        SubCommand::Validate(opts) => validate::main(opts),
     }
 }
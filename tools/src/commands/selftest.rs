@@ -0,0 +1,118 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Error, Result};
+use clap::Parser;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+use sxg_rs::{
+    crypto::{CertificateChain, Curve},
+    fetcher::NULL_FETCHER,
+    http_cache::NullCache,
+    runtime::Runtime,
+    signature::rust_signer::RustSigner,
+    CreateSignedExchangeParams, SxgWorker,
+};
+
+use crate::linux_commands::{
+    create_certificate, create_certificate_request_pem, generate_private_key_pem,
+    sxg_ext_file_content, write_new_file,
+};
+
+/// Generates an ephemeral key and self-signed certificate, signs a small HTML
+/// page with them, and validates the resulting SXG. Exits with an error if any
+/// step fails, e.g. because openssl is missing.
+#[derive(Parser)]
+pub struct Opts {
+    /// The domain of the certificate and the signed page.
+    #[clap(long, default_value = "selftest.example")]
+    domain: String,
+}
+
+pub async fn main(opts: Opts) -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("sxg-selftest-{}", std::process::id()));
+    fs::create_dir_all(&dir)
+        .map_err(|e| Error::new(e).context(format!("Failed to create {:?}", dir)))?;
+    let result = selftest(&opts.domain, &dir).await;
+    // The generated key is worthless, but is removed regardless.
+    let _ = fs::remove_dir_all(&dir);
+    result.map_err(|e| e.context("Selftest failed"))?;
+    println!("Selftest passed.");
+    Ok(())
+}
+
+async fn selftest(domain: &str, dir: &Path) -> Result<()> {
+    let privkey_file = dir.join("privkey.pem");
+    let cert_csr_file = dir.join("cert.csr");
+    let ext_file = dir.join("ext.txt");
+    let cert_file = dir.join("cert.pem");
+    // The same steps as `gen-dev-cert`, in a temporary directory.
+    let privkey_pem = generate_private_key_pem(Curve::P256)?;
+    write_new_file(&privkey_file, &privkey_pem)?;
+    create_certificate_request_pem(&[domain], &privkey_file, &cert_csr_file)?;
+    fs::write(&ext_file, sxg_ext_file_content(&[domain])?)?;
+    let cert_pem = create_certificate(&privkey_file, &cert_csr_file, &ext_file, &cert_file)?;
+    println!("Generated a P-256 key and a self-signed certificate.");
+
+    let mut worker = SxgWorker::new(&format!(
+        r#"
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers: []
+html_host: {domain}
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity/"
+        "#
+    ))?;
+    // The certificate is also its own issuer.
+    worker
+        .add_certificate(CertificateChain::from_pem_files(&[&cert_pem, &cert_pem])?)
+        .map_err(|e| e.context("The generated certificate is not SXG-compatible"))?;
+    let runtime = Runtime {
+        now: SystemTime::now(),
+        fetcher: Box::new(NULL_FETCHER),
+        sxg_signer: Box::new(RustSigner::from_sec1_pem(&privkey_pem)?),
+        ..Default::default()
+    };
+    let fallback_url = format!("https://{domain}/");
+    let cert_origin = format!("https://{domain}");
+    let sxg = worker
+        .create_signed_exchange(
+            &runtime,
+            CreateSignedExchangeParams {
+                fallback_url: &fallback_url,
+                cert_origin: &cert_origin,
+                payload_body: b"<!DOCTYPE html><h1>sxg-rs selftest</h1>",
+                payload_headers: worker
+                    .transform_payload_headers(vec![("content-type".into(), "text/html".into())])?,
+                skip_process_link: false,
+                status_code: 200,
+                header_integrity_cache: NullCache {},
+                mice_record_size: None,
+            },
+        )
+        .await
+        .map_err(|e| e.context("Failed to sign"))?;
+    println!("Signed a {}-byte SXG.", sxg.body.len());
+
+    let validation = worker.validate_sxg(&sxg.body)?;
+    print!("{}", validation);
+    if validation.is_valid() {
+        Ok(())
+    } else {
+        Err(anyhow!("The generated SXG is invalid"))
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::fs;
+use std::time::SystemTime;
+use sxg_rs::{
+    crypto::CertificateChain, fetcher::NULL_FETCHER, http_cache::NullCache, runtime::Runtime,
+    CreateSignedExchangeParams, SxgWorker,
+};
+use url::Url;
+
+use crate::linux_commands::write_new_file;
+
+/// Signs a local file as the response to a URL, and writes the SXG to disk,
+/// e.g. to upload prerendered pages to a cache without running a server.
+#[derive(Parser)]
+pub struct Opts {
+    /// The file to sign, such as page.html.
+    #[clap(long)]
+    input: String,
+    /// The https URL that the file is served at, which becomes the fallback
+    /// URL of the SXG.
+    #[clap(long)]
+    url: String,
+    /// The SXG file to write, which must not exist yet.
+    #[clap(long)]
+    output: String,
+    /// The content-type of the file.
+    #[clap(long, default_value = "text/html;charset=utf-8")]
+    content_type: String,
+    /// The config of the worker, as read by http_server, whose
+    /// private_key_base64 signs the SXG.
+    #[clap(long, default_value = "config.yaml")]
+    config: String,
+    /// The PEM certificate of the private key.
+    #[clap(long, default_value = "cert.pem")]
+    cert: String,
+    /// The PEM issuer of the certificate.
+    #[clap(long, default_value = "issuer.pem")]
+    issuer: String,
+}
+
+pub async fn main(opts: Opts) -> Result<()> {
+    let worker = load_worker(&opts.config, &opts.cert, &opts.issuer)?;
+    let payload_body =
+        fs::read(&opts.input).map_err(|e| anyhow!("error reading {}: {}", opts.input, e))?;
+    let sxg = sign(&worker, &opts.url, &opts.content_type, &payload_body).await?;
+    write_new_file(&opts.output, &sxg)?;
+    println!("Wrote a {}-byte SXG to {}.", sxg.len(), opts.output);
+    Ok(())
+}
+
+// Reads the config and the certificate into a new worker.
+fn load_worker(config: &str, cert: &str, issuer: &str) -> Result<SxgWorker> {
+    let read =
+        |path: &str| fs::read_to_string(path).map_err(|e| anyhow!("error reading {}: {}", path, e));
+    let mut worker = SxgWorker::new(&read(config)?)
+        .map_err(|e| e.context(format!("error parsing {}", config)))?;
+    worker.add_certificate(CertificateChain::from_pem_files(&[
+        &read(cert)?,
+        &read(issuer)?,
+    ])?)?;
+    Ok(worker)
+}
+
+// Returns the SXG of `payload_body`, served at `url` with `content_type`.
+async fn sign(
+    worker: &SxgWorker,
+    url: &str,
+    content_type: &str,
+    payload_body: &[u8],
+) -> Result<Vec<u8>> {
+    let url = Url::parse(url).map_err(|e| anyhow!("error parsing --url {}: {}", url, e))?;
+    if url.scheme() != "https" {
+        return Err(anyhow!("--url {} is not an https URL", url));
+    }
+    let runtime = Runtime {
+        now: SystemTime::now(),
+        fetcher: Box::new(NULL_FETCHER),
+        sxg_signer: Box::new(worker.create_rust_signer()?),
+        ..Default::default()
+    };
+    let sxg = worker
+        .create_signed_exchange(
+            &runtime,
+            CreateSignedExchangeParams {
+                fallback_url: url.as_str(),
+                cert_origin: &url.origin().ascii_serialization(),
+                payload_body,
+                payload_headers: worker.transform_payload_headers(vec![(
+                    "content-type".into(),
+                    content_type.into(),
+                )])?,
+                skip_process_link: false,
+                status_code: 200,
+                header_integrity_cache: NullCache {},
+                mice_record_size: None,
+            },
+        )
+        .await
+        .map_err(|e| e.context("Failed to sign"))?;
+    Ok(sxg.body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    // The private key of testdata/cert.pem, which is SIGNING_CERT_PEM of
+    // sxg_rs/src/utils.rs.
+    const PRIVATE_KEY_BASE64: &str = "Q714JzYPYxqPwajTtDPs5ho+SUk3TlknvXzaqihejIo=";
+    #[tokio::test]
+    async fn signs_fixture_html() {
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let dir = std::env::temp_dir().join(format!("sxg-rs-sign-file-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let config = dir.join("config.yaml");
+        fs::write(
+            &config,
+            format!(
+                r#"
+cert_url_dirname: ".well-known/sxg-certs"
+forward_request_headers: []
+html_host: example.org
+private_key_base64: "{}"
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+validity_url_dirname: ".well-known/sxg-validity"
+"#,
+                PRIVATE_KEY_BASE64
+            ),
+        )
+        .unwrap();
+        let cert = testdata.join("cert.pem");
+        let cert = cert.to_str().unwrap();
+        let worker = load_worker(config.to_str().unwrap(), cert, cert).unwrap();
+        let html = fs::read(testdata.join("page.html")).unwrap();
+        let sxg = sign(&worker, "https://example.org/page", "text/html", &html)
+            .await
+            .unwrap();
+
+        let validation = worker.validate_sxg(&sxg).unwrap();
+        assert!(validation.is_valid(), "{}", validation);
+        let response = SxgWorker::unwrap_sxg(&sxg).unwrap();
+        assert_eq!(response.body, html);
+        assert!(sign(&worker, "http://example.org/page", "text/html", &html)
+            .await
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
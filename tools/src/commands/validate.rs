@@ -0,0 +1,48 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::fs;
+use std::time::SystemTime;
+use sxg_rs::{crypto::CertificateChain, validation};
+
+/// Checks that an SXG file is well-formed and correctly signed, and prints
+/// the result of each check.
+#[derive(Parser)]
+pub struct Opts {
+    /// The SXG file to validate.
+    sxg: String,
+    /// The PEM certificate that is expected to have signed the SXG.
+    #[clap(long, default_value = "cert.pem")]
+    cert: String,
+    /// The PEM issuer of the certificate.
+    #[clap(long, default_value = "issuer.pem")]
+    issuer: String,
+}
+
+pub fn main(opts: Opts) -> Result<()> {
+    let sxg = fs::read(&opts.sxg)?;
+    let certificate = CertificateChain::from_pem_files(&[
+        &fs::read_to_string(&opts.cert)?,
+        &fs::read_to_string(&opts.issuer)?,
+    ])?;
+    let validation = validation::validate(&sxg, &[&certificate], SystemTime::now())?;
+    print!("{}", validation);
+    if validation.is_valid() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} is not a valid SXG", opts.sxg))
+    }
+}
@@ -15,6 +15,7 @@
 use anyhow::{Error, Result};
 use std::path::Path;
 use std::process::Command;
+use sxg_rs::crypto::Curve;
 
 /// Executes a command, checks the exit code, and returns the stdout as bytes.
 pub fn execute(command: &mut Command) -> Result<Vec<u8>> {
@@ -54,29 +55,31 @@ pub fn write_new_file(path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> Resu
     }
 }
 
-/// Generates a private key, and returns it without writing to any files.
+/// Generates a private key on the given curve, and returns it without writing
+/// to any files.
 /// Care should be taken to prevent the private key being lost.
-pub fn generate_private_key_pem() -> Result<String> {
+pub fn generate_private_key_pem(curve: Curve) -> Result<String> {
     execute_and_parse_stdout(
         Command::new("openssl")
             .arg("ecparam")
             .arg("-outform")
             .arg("pem")
             .arg("-name")
-            .arg("prime256v1")
+            .arg(curve.openssl_name())
             .arg("-genkey"),
     )
     .map_err(|e| e.context("Failed to use openssl to generate private key"))
 }
 
 /// Tries to read the contents of given file; if the file does not exist,
-/// generates a private key, and writes PEM to the file, and returns it.
-pub fn read_or_create_private_key_pem(file: impl AsRef<Path>) -> Result<String> {
+/// generates a private key on the given curve, and writes PEM to the file, and
+/// returns it.
+pub fn read_or_create_private_key_pem(file: impl AsRef<Path>, curve: Curve) -> Result<String> {
     if file.as_ref().exists() {
         println!("Reading private key from file {:?}", file.as_ref());
         std::fs::read_to_string(file).map_err(Error::new)
     } else {
-        let privkey_pem = generate_private_key_pem()?;
+        let privkey_pem = generate_private_key_pem(curve)?;
         println!(
             "Writing private key to file {:?}, please keep it in a safe place.",
             file.as_ref()
@@ -86,14 +89,37 @@ pub fn read_or_create_private_key_pem(file: impl AsRef<Path>) -> Result<String>
     }
 }
 
+/// Returns the value of a subjectAltName extension listing each of `domains`
+/// as a DNS name, for use in openssl config. Returns error if `domains` is
+/// empty.
+pub fn subject_alt_name(domains: &[&str]) -> Result<String> {
+    if domains.is_empty() {
+        return Err(Error::msg("At least one domain is required."));
+    }
+    let names: Vec<String> = domains.iter().map(|d| format!("DNS:{}", d)).collect();
+    Ok(names.join(","))
+}
+
+/// Returns the content of the `ext_file` for `create_certificate`, with the
+/// CanSignHttpExchanges extension and a subjectAltName of `domains`.
+pub fn sxg_ext_file_content(domains: &[&str]) -> Result<String> {
+    Ok(format!(
+        "1.3.6.1.4.1.11129.2.1.22 = ASN1:NULL\nsubjectAltName={}\n",
+        subject_alt_name(domains)?
+    ))
+}
+
 /// Generates a certificate request, and returns it in PEM format.
+/// The first of `domains` is the Common Name, and all of them are requested
+/// as SubjectAltNames.
 /// Writes PEM to `output_file`.
 /// Overwrites if `output_file` already exists.
 pub fn create_certificate_request_pem(
-    domain: &str,
+    domains: &[&str],
     private_key_file: impl AsRef<Path>,
     output_file: impl AsRef<Path>,
 ) -> Result<String> {
+    let subject_alt_name = subject_alt_name(domains)?;
     let cert_csr_pem = execute_and_parse_stdout(
         Command::new("openssl")
             .arg("req")
@@ -102,7 +128,9 @@ pub fn create_certificate_request_pem(
             .arg("-key")
             .arg(private_key_file.as_ref().as_os_str())
             .arg("-subj")
-            .arg(format!("/CN={}/O=Test/C=US", domain)),
+            .arg(format!("/CN={}/O=Test/C=US", domains[0]))
+            .arg("-addext")
+            .arg(format!("subjectAltName={}", subject_alt_name)),
     )?;
     std::fs::write(output_file, &cert_csr_pem)?;
     Ok(cert_csr_pem)
@@ -136,15 +164,69 @@ pub fn create_certificate(
     Ok(cert_pem)
 }
 
+/// Returns the SHA-256 of the DER-encoded SubjectPublicKeyInfo of the
+/// certificate in the given PEM file. This is parsed in Rust, so that it does
+/// not require openssl to be installed.
 pub fn get_certificate_sha256(certificate_file: impl AsRef<Path>) -> Result<Vec<u8>> {
-    let public_key_pem = execute_and_parse_stdout(
-        Command::new("openssl")
-            .arg("x509")
-            .arg("-pubkey")
-            .arg("-noout")
-            .arg("-in")
-            .arg(certificate_file.as_ref().as_os_str()),
-    )?;
-    let public_key_der = sxg_rs::crypto::get_der_from_pem(&public_key_pem, "PUBLIC KEY")?;
-    Ok(sxg_rs::crypto::HashAlgorithm::Sha256.digest(&public_key_der))
+    let certificate_pem = std::fs::read_to_string(certificate_file)?;
+    sxg_rs::crypto::CertificateChain::from_pem_files(&[&certificate_pem])?.public_key_sha256()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::prelude::{FromDer, GeneralName, ParsedExtension, X509CertificationRequest};
+    #[test]
+    fn certificate_sha256() {
+        // Equals the output of
+        // `openssl x509 -pubkey -noout -in cert.pem | openssl pkey -pubin -outform der | sha256sum`.
+        const EXPECTED: &str = "aa413db980b416e69815efe5ea8bb50f72563050f47c3ff857464cf50da8e9c0";
+        let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/cert.pem");
+        let sha256 = get_certificate_sha256(file).unwrap();
+        let hex: String = sha256.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, EXPECTED);
+    }
+    #[test]
+    fn subject_alt_name_requires_a_domain() {
+        assert_eq!(
+            subject_alt_name(&["a.example", "b.example"]).unwrap(),
+            "DNS:a.example,DNS:b.example"
+        );
+        assert!(subject_alt_name(&[]).is_err());
+        assert!(create_certificate_request_pem(&[], "privkey.pem", "cert.csr").is_err());
+    }
+    #[test]
+    fn certificate_request_has_subject_alt_names() {
+        let dir = std::env::temp_dir().join(format!("sxg-rs-csr-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let privkey_file = dir.join("privkey.pem");
+        std::fs::write(
+            &privkey_file,
+            generate_private_key_pem(Curve::P256).unwrap(),
+        )
+        .unwrap();
+        let csr_pem = create_certificate_request_pem(
+            &["a.example", "b.example"],
+            &privkey_file,
+            dir.join("cert.csr"),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let csr_der = sxg_rs::crypto::get_der_from_pem(&csr_pem, "CERTIFICATE REQUEST").unwrap();
+        let (_, csr) = X509CertificationRequest::from_der(&csr_der).unwrap();
+        let names: Vec<&str> = csr
+            .requested_extensions()
+            .unwrap()
+            .flat_map(|ext| match ext {
+                ParsedExtension::SubjectAlternativeName(san) => san.general_names.iter().collect(),
+                _ => vec![],
+            })
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some(*name),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["a.example", "b.example"]);
+    }
 }
@@ -33,7 +33,7 @@ export type AcceptLevel = 'RejectsSxg' | 'PrefersSxg' | 'AcceptsSxg';
 export interface WasmRequest {
   body: number[];
   headers: HeaderFields;
-  method: 'Get' | 'Post';
+  method: 'Delete' | 'Get' | 'Head' | 'Options' | 'Post' | 'Put';
   url: string;
 }
 